@@ -0,0 +1,80 @@
+// Lexing, reading and evaluating a few kinds of program. Run with
+// `cargo bench`; `cargo bench -- read` runs one group.
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use tuple_gd::{
+    arena::ExprArena,
+    interpreter::{Backend, Interpreter},
+    lexer::lex,
+    parser::read_all_spanned,
+};
+
+// Forms nested nearly as deep as the reader allows.
+fn deep_nesting() -> String {
+    let form = "(+ 1 ".repeat(100) + "1" + &")".repeat(100) + "\n";
+    form.repeat(200)
+}
+
+// A definition for each of thousands of distinct names.
+fn many_symbols() -> String {
+    (0..5000)
+        .map(|i| format!("(define name-{i} (list 'a-{i} :key-{i} \"text {i}\"))\n"))
+        .collect()
+}
+
+const NUMERIC_LOOP: &str = "
+(define (sum n acc)
+  (if (= n 0) acc (sum (- n 1) (+ acc (* n 1.5)))))
+(sum 20000 0)";
+
+fn sources() -> [(&'static str, String); 3] {
+    [
+        ("deep nesting", deep_nesting()),
+        ("many symbols", many_symbols()),
+        ("numeric loop", NUMERIC_LOOP.repeat(50)),
+    ]
+}
+
+fn lexing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lex");
+    for (name, source) in sources() {
+        group.bench_function(name, |b| b.iter(|| lex(black_box(&source)).unwrap()));
+    }
+    group.finish();
+}
+
+fn reading(c: &mut Criterion) {
+    let mut group = c.benchmark_group("read");
+    for (name, source) in sources() {
+        group.bench_function(format!("{name}/spanned"), |b| {
+            b.iter(|| read_all_spanned(black_box(&source)).unwrap())
+        });
+        group.bench_function(format!("{name}/arena"), |b| {
+            b.iter(|| ExprArena::read(black_box(&source)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn evaluating(c: &mut Criterion) {
+    let mut group = c.benchmark_group("eval");
+    let programs = [
+        ("deep nesting", deep_nesting()),
+        ("many symbols", many_symbols()),
+        ("numeric loop", NUMERIC_LOOP.to_owned()),
+    ];
+    for (name, source) in programs {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            group.bench_function(format!("{name}/{backend:?}"), |b| {
+                b.iter(|| {
+                    Interpreter::with_backend(backend)
+                        .eval_str(black_box(&source))
+                        .unwrap()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lexing, reading, evaluating);
+criterion_main!(benches);
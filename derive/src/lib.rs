@@ -0,0 +1,82 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as Tokens;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, GenericParam, Generics};
+
+// #[derive(TasksValue)] on a struct with named fields implements IntoValue
+// and FromValue for it, through tuple_gd::convert's struct_into_value and
+// struct_field: the struct is a map from keywords named after its fields,
+// with _ written as -, to their values. Type parameters are required to
+// convert too.
+#[proc_macro_derive(TasksValue)]
+pub fn derive_tasks_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<Tokens> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(unsupported(input)),
+        },
+        _ => return Err(unsupported(input)),
+    };
+    let idents: Vec<_> = fields
+        .iter()
+        .filter_map(|field| field.ident.as_ref())
+        .collect();
+    let keys: Vec<String> = idents
+        .iter()
+        .map(|ident| {
+            let name = ident.to_string();
+            name.trim_start_matches("r#").replace('_', "-")
+        })
+        .collect();
+    let name = &input.ident;
+    let expected = name.to_string();
+    let into = bounded(&input.generics, quote!(::tuple_gd::convert::IntoValue));
+    let from = bounded(&input.generics, quote!(::tuple_gd::convert::FromValue));
+    let (into_impl, ty, into_where) = into.split_for_impl();
+    let (from_impl, _, from_where) = from.split_for_impl();
+
+    Ok(quote! {
+        impl #into_impl ::tuple_gd::convert::IntoValue for #name #ty #into_where {
+            fn into_value(self) -> ::tuple_gd::Value {
+                ::tuple_gd::convert::struct_into_value([
+                    #((#keys, ::tuple_gd::convert::IntoValue::into_value(self.#idents)),)*
+                ])
+            }
+        }
+
+        impl #from_impl ::tuple_gd::convert::FromValue for #name #ty #from_where {
+            fn from_value(
+                value: ::tuple_gd::Value,
+            ) -> ::core::result::Result<Self, ::tuple_gd::EvalError> {
+                ::core::result::Result::Ok(#name {
+                    #(#idents: ::tuple_gd::convert::struct_field(&value, #expected, #keys)?,)*
+                })
+            }
+        }
+    })
+}
+
+// generics with bound added to each type parameter.
+fn bounded(generics: &Generics, bound: Tokens) -> Generics {
+    let mut generics = generics.clone();
+    for param in generics.params.iter_mut() {
+        if let GenericParam::Type(param) = param {
+            param.bounds.push(syn::parse2(bound.clone()).unwrap());
+        }
+    }
+    generics
+}
+
+fn unsupported(input: &DeriveInput) -> Error {
+    Error::new_spanned(
+        &input.ident,
+        "TasksValue can only be derived for structs with named fields",
+    )
+}
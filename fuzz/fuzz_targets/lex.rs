@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tuple_gd::lexer::lex;
+
+fuzz_target!(|input: &str| {
+    let _ = lex(input);
+});
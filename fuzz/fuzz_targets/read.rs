@@ -0,0 +1,6 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// What tuple_gd::fuzz_check checks, of whatever bytes libFuzzer makes.
+fuzz_target!(|input: &[u8]| tuple_gd::fuzz_check(input));
@@ -0,0 +1,50 @@
+/* The C interface of tuple-gd, built with the capi feature. See
+ * src/capi.rs for who owns what. */
+#ifndef TUPLE_GD_H
+#define TUPLE_GD_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct TasksInterpreter TasksInterpreter;
+typedef struct TasksValue TasksValue;
+
+enum {
+    TASKS_NIL = 0,
+    TASKS_BOOL = 1,
+    TASKS_INTEGER = 2,
+    TASKS_FLOAT = 3,
+    TASKS_STRING = 4,
+    TASKS_SYMBOL = 5,
+    TASKS_LIST = 6,
+    TASKS_OTHER = 7,
+};
+
+TasksInterpreter *tasks_new(void);
+void tasks_free(TasksInterpreter *interpreter);
+
+/* NULL on failure, with the reason in tasks_last_error. */
+TasksValue *tasks_eval(TasksInterpreter *interpreter, const char *source);
+const char *tasks_last_error(const TasksInterpreter *interpreter);
+
+void tasks_value_free(TasksValue *value);
+void tasks_string_free(char *string);
+
+int32_t tasks_value_type(const TasksValue *value);
+int32_t tasks_value_bool(const TasksValue *value);
+int64_t tasks_value_integer(const TasksValue *value);
+double tasks_value_float(const TasksValue *value);
+char *tasks_value_string(const TasksValue *value);
+char *tasks_value_print(const TasksValue *value);
+size_t tasks_value_list_len(const TasksValue *value);
+TasksValue *tasks_value_list_get(const TasksValue *value, size_t index);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,1799 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt;
+
+use crate::{
+    builtins,
+    lexer::{lex_token, skip_trivia, Span, Token},
+    parser::{check_depth, lex_failure, recover, Expr, ReadError, SpannedExpr},
+    symbol::{self, SymbolId, SPECIAL_FORMS},
+};
+
+// What an editor wants to know about a source file as it's edited, for a
+// language server to build on: the forms and the problems reading them,
+// an outline of the definitions, where a name was defined and what to
+// show on hovering over it. Spans are byte offsets into the text.
+//
+// The text is kept as its top-level forms, and an edit reads again only
+// the forms it could have changed. Reading from where a form starts
+// depends only on the text from there on, so once a form read after an
+// edit starts where one from before it did, that one and the rest are as
+// they were, moved by however much the edit grew or shrank the text.
+pub struct Document {
+    text: String,
+    forms: Vec<Form>,
+}
+
+// One top-level form as it was read: the text from its first token to its
+// last, how far the lexer could have looked to find the last one, which
+// is up to the next whitespace, and what reading the tokens gave.
+#[derive(Clone, Debug)]
+struct Form {
+    span: Span,
+    looked_to: usize,
+    exprs: Vec<SpannedExpr>,
+    errors: Vec<ReadError>,
+}
+
+impl Form {
+    fn shift(&mut self, delta: isize) {
+        self.span = shift_span(self.span, delta);
+        self.looked_to = self.looked_to.wrapping_add_signed(delta);
+        for expr in &mut self.exprs {
+            shift_expr(expr, delta);
+        }
+        for error in &mut self.errors {
+            shift_error(error, delta);
+        }
+    }
+}
+
+impl Document {
+    pub fn new(text: impl Into<String>) -> Self {
+        let mut document = Document {
+            text: text.into(),
+            forms: Vec::new(),
+        };
+        document.read_from(0, Vec::new());
+        document
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    // Replaces range with replacement, panicking as String::replace_range
+    // does if range isn't within the text on character boundaries, and
+    // reads again what that could have changed. Returns the span of the
+    // new text that was read again.
+    pub fn edit(&mut self, range: Span, replacement: &str) -> Span {
+        self.text.replace_range(range.start..range.end, replacement);
+        let delta = replacement.len() as isize - (range.end - range.start) as isize;
+        let first = self
+            .forms
+            .partition_point(|form| form.looked_to < range.start);
+        let start = first.checked_sub(1).map_or(0, |i| self.forms[i].span.end);
+        let after = self
+            .forms
+            .split_off(first)
+            .into_iter()
+            .filter(|form| form.span.start >= range.end)
+            .map(|mut form| {
+                form.shift(delta);
+                form
+            })
+            .collect();
+        let end = self.read_from(start, after);
+        Span::new(start, end)
+    }
+
+    // Reads forms from start until one starts where one of after does, and
+    // keeps that one and the rest of after instead of reading them. Returns
+    // where reading stopped.
+    fn read_from(&mut self, mut start: usize, after: Vec<Form>) -> usize {
+        let mut after = after.into_iter().peekable();
+        while let Some(form) = read_form(&self.text, start) {
+            while after
+                .next_if(|old| old.span.start < form.span.start)
+                .is_some()
+            {}
+            if after
+                .peek()
+                .is_some_and(|old| old.span.start == form.span.start)
+            {
+                self.forms.extend(after);
+                break;
+            }
+            start = form.span.end;
+            self.forms.push(form);
+        }
+        start
+    }
+
+    // The forms at the top level, leaving out those that didn't read.
+    pub fn forms(&self) -> impl Iterator<Item = &SpannedExpr> {
+        self.forms.iter().flat_map(|form| &form.exprs)
+    }
+
+    // Everything that didn't read, and names the top level defines more
+    // than once, in the order they come in the text.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics: Vec<_> = self
+            .forms
+            .iter()
+            .flat_map(|form| form.errors.iter().cloned().map(Diagnostic::Read))
+            .collect();
+        let mut seen: Vec<(SymbolId, Span)> = Vec::new();
+        for (name, span) in definitions(self.forms()) {
+            match seen.iter().find(|&&(defined, _)| defined == name) {
+                Some(&(_, first)) => diagnostics.push(Diagnostic::Redefined { name, span, first }),
+                None => seen.push((name, span)),
+            }
+        }
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span().start);
+        diagnostics
+    }
+
+    // The diagnostics and what a look at the names finds without running
+    // anything: symbols that aren't bound, set!s of them, calls with the
+    // wrong number of arguments to functions defined here, local bindings
+    // nothing uses and bindings that hide a builtin. globals are the names
+    // bound before the text runs, as Env::names gives them for a new
+    // interpreter. A text that loads a file, or imports a module it
+    // doesn't define, can bind anything, so then nothing is unbound.
+    pub fn lint(&self, globals: &[SymbolId]) -> Vec<Diagnostic> {
+        let forms: Vec<_> = self.forms().collect();
+        let mut linter = Linter {
+            globals,
+            unbound: !binds_unknown(&forms),
+            scopes: Vec::new(),
+            diagnostics: self.diagnostics(),
+        };
+        linter.top_level(&forms);
+        let mut diagnostics = linter.diagnostics;
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span().start);
+        diagnostics
+    }
+
+    // The definitions at the top level, including those in a begin, with
+    // those in a module's body under it.
+    pub fn symbols(&self) -> Vec<DocumentSymbol> {
+        let mut symbols = Vec::new();
+        outline(&self.text, self.forms(), &mut symbols);
+        symbols
+    }
+
+    // Where the variable at offset is bound: by a define at the top level
+    // or in a body, as a parameter, or by let, match or catch. A binding
+    // is its own definition.
+    pub fn definition(&self, offset: usize) -> Option<Span> {
+        self.resolve(offset)?.definition
+    }
+
+    // What to show on hovering over the symbol at offset: for a definition
+    // in the outline its signature and doc, otherwise whether it's local or
+    // a builtin.
+    pub fn hover(&self, offset: usize) -> Option<Hover> {
+        let reference = self.resolve(offset)?;
+        let contents = match reference.definition {
+            Some(definition) => match find_symbol(&self.symbols(), definition) {
+                Some(symbol) => {
+                    let mut contents = symbol
+                        .detail
+                        .clone()
+                        .unwrap_or_else(|| symbol.name.to_string());
+                    let doc = self.doc(symbol);
+                    if !doc.is_empty() {
+                        contents.push_str("\n\n");
+                        contents.push_str(&doc);
+                    }
+                    contents
+                }
+                None => format!("local {}", reference.name),
+            },
+            None if builtins::find(reference.name.as_str()).is_some() => {
+                format!("builtin {}", reference.name)
+            }
+            None => return None,
+        };
+        Some(Hover {
+            span: reference.span,
+            contents,
+        })
+    }
+
+    // What a definition in the outline says about itself: the ; comments
+    // on the lines just above it, then the string a function's or macro's
+    // body starts with, if more of the body follows it.
+    pub fn doc(&self, symbol: &DocumentSymbol) -> String {
+        let mut doc = doc_comment(&self.text, symbol.span.start);
+        if let Some(string) = find_form(self.forms(), symbol.span).and_then(docstring) {
+            if !doc.is_empty() {
+                doc.push_str("\n\n");
+            }
+            doc.push_str(string);
+        }
+        doc
+    }
+
+    fn resolve(&self, offset: usize) -> Option<Reference> {
+        let mut resolver = Resolver {
+            offset,
+            scopes: vec![definitions(self.forms())],
+            found: None,
+        };
+        for form in self.forms() {
+            resolver.expr(form);
+        }
+        resolver.found
+    }
+
+    // The byte offset of a 1-based line and column, counting characters as
+    // Span::line_col does, or None if the line or the text ends before it.
+    pub fn offset(&self, line: usize, column: usize) -> Option<usize> {
+        let start = match line.checked_sub(1)? {
+            0 => 0,
+            before => self.text.match_indices('\n').nth(before - 1)?.0 + 1,
+        };
+        let rest = &self.text[start..];
+        let text = &rest[..rest.find('\n').unwrap_or(rest.len())];
+        let column = column.checked_sub(1)?;
+        match text.char_indices().nth(column) {
+            Some((i, _)) => Some(start + i),
+            None if column == text.chars().count() => Some(start + text.len()),
+            None => None,
+        }
+    }
+}
+
+// Reads the form whose first token is the first after start, or returns
+// None if there is only trivia left. The form ends with the token that
+// closes what it opened, or at the end of the text if nothing does. A
+// token that doesn't lex ends it instead, with just that error, at the
+// next whitespace, so that the rest of the token isn't read as another.
+fn read_form(text: &str, start: usize) -> Option<Form> {
+    let mut rest = &text[start..];
+    let mut tokens = Vec::new();
+    let mut depth: usize = 0;
+    let mut begin = None;
+    let mut complete = false;
+    let error = loop {
+        let next = match skip_trivia(rest) {
+            Ok((next, ())) => next,
+            Err(e) => break Some(lex_failure(text, e)),
+        };
+        if next.is_empty() {
+            break None;
+        }
+        begin.get_or_insert(text.len() - next.len());
+        let token = match lex_token(text)(next) {
+            Ok((next, token)) => {
+                rest = next;
+                token
+            }
+            Err(e) => break Some(lex_failure(text, e)),
+        };
+        let waiting = match token.value {
+            Token::LParan | Token::LBracket | Token::LBrace => {
+                depth += 1;
+                true
+            }
+            Token::RParan | Token::RBracket | Token::RBrace => {
+                depth = depth.saturating_sub(1);
+                false
+            }
+            Token::Quote
+            | Token::Quasiquote
+            | Token::Unquote
+            | Token::UnquoteSplicing
+            | Token::DatumComment
+            | Token::Dispatch(..) => true,
+            _ => false,
+        };
+        tokens.push(token);
+        if depth == 0 && !waiting {
+            complete = true;
+            break None;
+        }
+    };
+    let (span, exprs, errors) = match error {
+        Some(error) => {
+            let error_span = error.span();
+            let begin = begin.unwrap_or(error_span.start);
+            let end = next_whitespace(text, error_span.end);
+            (Span::new(begin, end), Vec::new(), vec![error])
+        }
+        None if tokens.is_empty() => return None,
+        None => {
+            let end = match complete {
+                true => tokens[tokens.len() - 1].span.end,
+                false => text.len(),
+            };
+            let span = Span::new(begin.unwrap_or(end), end);
+            let (exprs, errors) = match check_depth(&tokens) {
+                Ok(()) => recover(tokens, Span::new(end, end)),
+                Err(error) => (Vec::new(), vec![error]),
+            };
+            (span, exprs, errors)
+        }
+    };
+    Some(Form {
+        span,
+        looked_to: next_whitespace(text, span.end),
+        exprs,
+        errors,
+    })
+}
+
+fn next_whitespace(text: &str, from: usize) -> usize {
+    text[from..]
+        .find([' ', '\t', '\r', '\n'])
+        .map_or(text.len(), |i| from + i)
+}
+
+fn shift_span(span: Span, delta: isize) -> Span {
+    Span::new(
+        span.start.wrapping_add_signed(delta),
+        span.end.wrapping_add_signed(delta),
+    )
+}
+
+fn shift_expr(expr: &mut SpannedExpr, delta: isize) {
+    match expr {
+        SpannedExpr::Atom(atom) => atom.span = shift_span(atom.span, delta),
+        SpannedExpr::List(list) | SpannedExpr::Vector(list) => {
+            list.span = shift_span(list.span, delta);
+            for item in &mut list.value {
+                shift_expr(item, delta);
+            }
+        }
+        SpannedExpr::DottedList(list) => {
+            list.span = shift_span(list.span, delta);
+            for item in &mut list.value.0 {
+                shift_expr(item, delta);
+            }
+            shift_expr(&mut list.value.1, delta);
+        }
+        SpannedExpr::Map(map) => {
+            map.span = shift_span(map.span, delta);
+            for (key, value) in &mut map.value {
+                shift_expr(key, delta);
+                shift_expr(value, delta);
+            }
+        }
+    }
+}
+
+fn shift_error(error: &mut ReadError, delta: isize) {
+    match error {
+        ReadError::Lex { span, .. }
+        | ReadError::Parse { span, .. }
+        | ReadError::TrailingInput { span, .. }
+        | ReadError::TooDeep { span }
+        | ReadError::Dispatch { span, .. } => *span = shift_span(*span, delta),
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Diagnostic {
+    Read(ReadError),
+    // A name the top level defines again: span is where it's defined
+    // again, first where it was defined before.
+    Redefined {
+        name: SymbolId,
+        span: Span,
+        first: Span,
+    },
+    // What lint finds. A symbol nothing in scope or among the globals
+    // binds, and a set! of one.
+    Unbound {
+        name: SymbolId,
+        span: Span,
+    },
+    SetUnbound {
+        name: SymbolId,
+        span: Span,
+    },
+    // A call, spanning the whole form, to a function defined in the text
+    // with a number of arguments it doesn't take.
+    Arity {
+        name: SymbolId,
+        span: Span,
+        expected: Arity,
+        found: usize,
+    },
+    // A parameter or local binding that nothing refers to.
+    Unused {
+        name: SymbolId,
+        span: Span,
+    },
+    // A binding of a global's name, which hides the global where it's in
+    // scope.
+    Shadows {
+        name: SymbolId,
+        span: Span,
+    },
+}
+
+// How many arguments a function takes: required ones, and any number more
+// with a rest parameter.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Arity {
+    pub required: usize,
+    pub rest: bool,
+}
+
+impl Arity {
+    fn accepts(self, found: usize) -> bool {
+        found == self.required || (self.rest && found > self.required)
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.rest {
+            true => write!(f, "at least {}", self.required),
+            false => write!(f, "{}", self.required),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        })
+    }
+}
+
+impl Diagnostic {
+    pub fn span(&self) -> Span {
+        match self {
+            Diagnostic::Read(error) => error.span(),
+            Diagnostic::Redefined { span, .. }
+            | Diagnostic::Unbound { span, .. }
+            | Diagnostic::SetUnbound { span, .. }
+            | Diagnostic::Arity { span, .. }
+            | Diagnostic::Unused { span, .. }
+            | Diagnostic::Shadows { span, .. } => *span,
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Diagnostic::Read(_)
+            | Diagnostic::Unbound { .. }
+            | Diagnostic::SetUnbound { .. }
+            | Diagnostic::Arity { .. } => Severity::Error,
+            Diagnostic::Redefined { .. }
+            | Diagnostic::Unused { .. }
+            | Diagnostic::Shadows { .. } => Severity::Warning,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::Read(error) => write!(f, "{error}"),
+            Diagnostic::Redefined { name, .. } => write!(f, "`{name}` is already defined"),
+            Diagnostic::Unbound { name, .. } => write!(f, "unbound symbol `{name}`"),
+            Diagnostic::SetUnbound { name, .. } => write!(f, "set! of unbound symbol `{name}`"),
+            Diagnostic::Arity {
+                name,
+                expected,
+                found,
+                ..
+            } => write!(f, "`{name}` expects {expected} arguments, found {found}"),
+            Diagnostic::Unused { name, .. } => write!(f, "`{name}` is never used"),
+            Diagnostic::Shadows { name, .. } => write!(f, "`{name}` shadows a builtin"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SymbolKind {
+    Function,
+    Variable,
+    Macro,
+    Module,
+}
+
+// A definition in the outline. Span is the whole form and name_span the
+// name in it. A function's or macro's detail is how a call to it looks,
+// as in (name a b), and a module's is its export list.
+#[derive(Clone, PartialEq, Debug)]
+pub struct DocumentSymbol {
+    pub name: SymbolId,
+    pub kind: SymbolKind,
+    pub span: Span,
+    pub name_span: Span,
+    pub detail: Option<String>,
+    pub children: Vec<DocumentSymbol>,
+}
+
+// Span is the symbol hovered over.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Hover {
+    pub span: Span,
+    pub contents: String,
+}
+
+fn outline<'a>(
+    text: &str,
+    forms: impl IntoIterator<Item = &'a SpannedExpr>,
+    symbols: &mut Vec<DocumentSymbol>,
+) {
+    for form in forms {
+        let SpannedExpr::List(list) = form else {
+            continue;
+        };
+        let items = list.value.as_slice();
+        let Some((head, _)) = items.first().and_then(symbol) else {
+            continue;
+        };
+        let mut children = Vec::new();
+        let definition = match (head, &items[1..]) {
+            (symbol::BEGIN, body) => {
+                outline(text, body, symbols);
+                continue;
+            }
+            (symbol::DEFINE, [SpannedExpr::List(signature), ..]) => {
+                signature.value.first().map(|name| {
+                    (
+                        name,
+                        SymbolKind::Function,
+                        Some(source(text, signature.span)),
+                    )
+                })
+            }
+            (symbol::DEFINE, [SpannedExpr::DottedList(signature), ..]) => {
+                signature.value.0.first().map(|name| {
+                    let detail = source(text, signature.span);
+                    (name, SymbolKind::Function, Some(detail))
+                })
+            }
+            (symbol::DEFINE, [name, value, ..]) => match lambda_params(value) {
+                Some(params) => {
+                    let detail = signature(text, name, params);
+                    Some((name, SymbolKind::Function, Some(detail)))
+                }
+                None => Some((name, SymbolKind::Variable, None)),
+            },
+            (symbol::DEFINE, [name]) => Some((name, SymbolKind::Variable, None)),
+            (symbol::DEFMACRO, [name, params, ..]) => {
+                Some((name, SymbolKind::Macro, Some(signature(text, name, params))))
+            }
+            (_, [name, export, body @ ..]) if head.as_str() == "module" => {
+                outline(text, body, &mut children);
+                Some((name, SymbolKind::Module, Some(source(text, export.span()))))
+            }
+            _ => None,
+        };
+        let Some((name, kind, detail)) = definition else {
+            continue;
+        };
+        let Some((name, name_span)) = symbol(name) else {
+            continue;
+        };
+        symbols.push(DocumentSymbol {
+            name,
+            kind,
+            span: list.span,
+            name_span,
+            detail,
+            children,
+        });
+    }
+}
+
+// The names that forms define as variables, as a body's scope has them.
+fn definitions<'a>(forms: impl IntoIterator<Item = &'a SpannedExpr>) -> Vec<(SymbolId, Span)> {
+    let mut symbols = Vec::new();
+    outline("", forms, &mut symbols);
+    symbols
+        .into_iter()
+        .filter(|symbol| symbol.kind != SymbolKind::Module)
+        .map(|symbol| (symbol.name, symbol.name_span))
+        .collect()
+}
+
+fn find_symbol(symbols: &[DocumentSymbol], name_span: Span) -> Option<&DocumentSymbol> {
+    symbols
+        .iter()
+        .find_map(|symbol| match symbol.name_span == name_span {
+            true => Some(symbol),
+            false => find_symbol(&symbol.children, name_span),
+        })
+}
+
+// The list among forms, or inside one of them, that span is of.
+fn find_form<'a>(
+    forms: impl IntoIterator<Item = &'a SpannedExpr>,
+    span: Span,
+) -> Option<&'a SpannedExpr> {
+    forms.into_iter().find_map(|form| match form {
+        SpannedExpr::List(list) if list.span == span => Some(form),
+        SpannedExpr::List(list) if list.span.start <= span.start && span.end <= list.span.end => {
+            find_form(&list.value, span)
+        }
+        _ => None,
+    })
+}
+
+fn docstring(form: &SpannedExpr) -> Option<&str> {
+    let SpannedExpr::List(list) = form else {
+        return None;
+    };
+    let (head, rest) = list.value.split_first()?;
+    let body = match (symbol(head)?.0, rest) {
+        (symbol::DEFINE, [SpannedExpr::List(_) | SpannedExpr::DottedList(_), body @ ..]) => body,
+        (symbol::DEFINE, [_, SpannedExpr::List(value)]) => match value.value.as_slice() {
+            [head, _, body @ ..]
+                if symbol(head).is_some_and(|(head, _)| head == symbol::LAMBDA) =>
+            {
+                body
+            }
+            _ => return None,
+        },
+        (symbol::DEFMACRO, [_, _, body @ ..]) => body,
+        _ => return None,
+    };
+    match body {
+        [SpannedExpr::Atom(atom), _, ..] => match &atom.value {
+            Expr::String(string) => Some(string),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn lambda_params(value: &SpannedExpr) -> Option<&SpannedExpr> {
+    let SpannedExpr::List(list) = value else {
+        return None;
+    };
+    match list.value.as_slice() {
+        [head, params, ..] if symbol(head).is_some_and(|(head, _)| head == symbol::LAMBDA) => {
+            Some(params)
+        }
+        _ => None,
+    }
+}
+
+// (name params...) for a name and the parameter list it takes.
+fn signature(text: &str, name: &SpannedExpr, params: &SpannedExpr) -> String {
+    let name = source(text, name.span());
+    let params = source(text, params.span());
+    match params.strip_prefix('(').and_then(|p| p.strip_suffix(')')) {
+        Some("") => format!("({name})"),
+        Some(params) => format!("({name} {params})"),
+        None => format!("({name} . {params})"),
+    }
+}
+
+// The text of span with its whitespace run together, so that it fits on
+// one line. definitions passes no text, since it only wants the names.
+fn source(text: &str, span: Span) -> String {
+    text.get(span.start..span.end)
+        .unwrap_or_default()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// The ; comments on the lines just above start, if nothing comes before
+// start on its own line, without the semicolons and a space after them.
+fn doc_comment(text: &str, start: usize) -> String {
+    let mut lines = text[..start].split('\n').rev();
+    if !lines.next().is_some_and(|line| line.trim().is_empty()) {
+        return String::new();
+    }
+    let mut doc: Vec<&str> = lines
+        .map(str::trim)
+        .take_while(|line| line.starts_with(';'))
+        .map(|line| {
+            let line = line.trim_start_matches(';');
+            line.strip_prefix(' ').unwrap_or(line)
+        })
+        .collect();
+    doc.reverse();
+    doc.join("\n")
+}
+
+fn symbol(expr: &SpannedExpr) -> Option<(SymbolId, Span)> {
+    match expr {
+        SpannedExpr::Atom(atom) => match atom.value {
+            Expr::Symbol(name) => Some((name, atom.span)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// A span holds an offset just past its end too, where the cursor is after
+// typing a name.
+fn contains(span: Span, offset: usize) -> bool {
+    span.start <= offset && offset <= span.end
+}
+
+// A symbol in the text and the binding it refers to, if the text has one.
+struct Reference {
+    name: SymbolId,
+    span: Span,
+    definition: Option<Span>,
+}
+
+// Walks down to the symbol at offset, keeping track of the names in scope
+// on the way, innermost last.
+struct Resolver {
+    offset: usize,
+    scopes: Vec<Vec<(SymbolId, Span)>>,
+    found: Option<Reference>,
+}
+
+impl Resolver {
+    fn lookup(&self, name: SymbolId) -> Option<Span> {
+        self.scopes.iter().rev().find_map(|scope| {
+            scope
+                .iter()
+                .find(|&&(bound, _)| bound == name)
+                .map(|&(_, span)| span)
+        })
+    }
+
+    fn binding(&mut self, name: SymbolId, span: Span) {
+        if contains(span, self.offset) {
+            self.found = Some(Reference {
+                name,
+                span,
+                definition: Some(span),
+            });
+        }
+    }
+
+    fn bind(&mut self, names: Vec<(SymbolId, Span)>) {
+        for &(name, span) in &names {
+            self.binding(name, span);
+        }
+        self.scopes.push(names);
+    }
+
+    fn expr(&mut self, expr: &SpannedExpr) {
+        if self.found.is_some() || !contains(expr.span(), self.offset) {
+            return;
+        }
+        match expr {
+            SpannedExpr::Atom(_) => {
+                if let Some((name, span)) = symbol(expr) {
+                    self.found = Some(Reference {
+                        name,
+                        span,
+                        definition: self.lookup(name),
+                    });
+                }
+            }
+            SpannedExpr::List(list) => self.list(&list.value),
+            SpannedExpr::DottedList(list) => {
+                list.value.0.iter().for_each(|item| self.expr(item));
+                self.expr(&list.value.1);
+            }
+            SpannedExpr::Vector(vector) => vector.value.iter().for_each(|item| self.expr(item)),
+            SpannedExpr::Map(map) => {
+                for (key, value) in &map.value {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+        }
+    }
+
+    fn list(&mut self, items: &[SpannedExpr]) {
+        let head = items.first().and_then(symbol).map(|(head, _)| head);
+        let depth = self.scopes.len();
+        match (head, items) {
+            (Some(symbol::QUOTE), _) => {}
+            (Some(symbol::QUASIQUOTE), [_, template]) => self.template(template),
+            (Some(symbol::DEFINE), [_, signature, body @ ..]) => match signature {
+                SpannedExpr::List(list) => {
+                    let [name, params @ ..] = list.value.as_slice() else {
+                        return;
+                    };
+                    self.function(name, params_of(params, None), body);
+                }
+                SpannedExpr::DottedList(list) => {
+                    let (items, rest) = &list.value;
+                    let [name, params @ ..] = items.as_slice() else {
+                        return;
+                    };
+                    self.function(name, params_of(params, Some(rest)), body);
+                }
+                name => {
+                    if let Some((name, span)) = symbol(name) {
+                        self.binding(name, span);
+                    }
+                    body.iter().for_each(|value| self.expr(value));
+                }
+            },
+            (Some(symbol::DEFMACRO), [_, name, params, body @ ..]) => {
+                self.function(name, params_list(params), body)
+            }
+            (Some(symbol::LAMBDA), [_, params, body @ ..]) => {
+                self.bind(params_list(params));
+                self.body(body);
+            }
+            (Some(symbol::LET | symbol::LET_STAR), [_, SpannedExpr::List(bindings), body @ ..]) => {
+                let mut names = Vec::new();
+                for binding in &bindings.value {
+                    let SpannedExpr::List(parts) = binding else {
+                        continue;
+                    };
+                    let [name, value] = parts.value.as_slice() else {
+                        continue;
+                    };
+                    self.expr(value);
+                    let Some(name) = symbol(name) else {
+                        continue;
+                    };
+                    match head == Some(symbol::LET_STAR) {
+                        true => self.bind(vec![name]),
+                        false => names.push(name),
+                    }
+                }
+                self.bind(names);
+                self.body(body);
+            }
+            (Some(symbol::LET_VALUES), [_, SpannedExpr::List(bindings), body @ ..]) => {
+                let mut names = Vec::new();
+                for binding in &bindings.value {
+                    let SpannedExpr::List(parts) = binding else {
+                        continue;
+                    };
+                    if let [params, value] = parts.value.as_slice() {
+                        self.expr(value);
+                        names.extend(params_list(params));
+                    }
+                }
+                self.bind(names);
+                self.body(body);
+            }
+            (Some(symbol::MATCH), [_, subject, clauses @ ..]) => {
+                self.expr(subject);
+                for clause in clauses {
+                    let SpannedExpr::List(parts) = clause else {
+                        continue;
+                    };
+                    let [pattern, body @ ..] = parts.value.as_slice() else {
+                        continue;
+                    };
+                    if !contains(clause.span(), self.offset) {
+                        continue;
+                    }
+                    let mut names = Vec::new();
+                    pattern_names(pattern, &mut names);
+                    self.bind(names);
+                    self.body(body);
+                }
+            }
+            (Some(symbol::TRY), [_, body @ .., SpannedExpr::List(clause)]) => {
+                body.iter().for_each(|form| self.expr(form));
+                if let [catch, name, handler @ ..] = clause.value.as_slice() {
+                    if symbol(catch).is_some_and(|(catch, _)| catch == symbol::CATCH) {
+                        self.bind(symbol(name).into_iter().collect());
+                        self.body(handler);
+                    }
+                }
+            }
+            (Some(head), [_, _, export, body @ ..]) if head.as_str() == "module" => {
+                self.scopes.push(definitions(body));
+                if let SpannedExpr::List(export) = export {
+                    export.value.iter().skip(1).for_each(|name| self.expr(name));
+                }
+                body.iter().for_each(|form| self.expr(form));
+            }
+            _ => items.iter().for_each(|item| self.expr(item)),
+        }
+        self.scopes.truncate(depth);
+    }
+
+    // A define with a parameter list, or a defmacro.
+    fn function(
+        &mut self,
+        name: &SpannedExpr,
+        params: Vec<(SymbolId, Span)>,
+        body: &[SpannedExpr],
+    ) {
+        if let Some((name, span)) = symbol(name) {
+            self.binding(name, span);
+        }
+        self.bind(params);
+        self.body(body);
+    }
+
+    // A body's own defines are in scope all through it.
+    fn body(&mut self, body: &[SpannedExpr]) {
+        self.scopes.push(definitions(body));
+        body.iter().for_each(|form| self.expr(form));
+    }
+
+    // Only what's unquoted in a quasiquote template is code.
+    fn template(&mut self, template: &SpannedExpr) {
+        if !contains(template.span(), self.offset) {
+            return;
+        }
+        match template {
+            SpannedExpr::Atom(_) => {}
+            SpannedExpr::List(list) => match list.value.as_slice() {
+                [head, value]
+                    if symbol(head).is_some_and(|(head, _)| {
+                        head == symbol::UNQUOTE || head == symbol::UNQUOTE_SPLICING
+                    }) =>
+                {
+                    self.expr(value)
+                }
+                items => items.iter().for_each(|item| self.template(item)),
+            },
+            SpannedExpr::DottedList(list) => {
+                list.value.0.iter().for_each(|item| self.template(item));
+                self.template(&list.value.1);
+            }
+            SpannedExpr::Vector(vector) => vector.value.iter().for_each(|item| self.template(item)),
+            SpannedExpr::Map(map) => {
+                for (key, value) in &map.value {
+                    self.template(key);
+                    self.template(value);
+                }
+            }
+        }
+    }
+}
+
+// A name in scope as lint sees it. Only a local binding is reported when
+// nothing uses it, as a top-level definition can be used from elsewhere.
+struct Binding {
+    name: SymbolId,
+    span: Span,
+    // For a function defined with a parameter list the arguments it
+    // takes, unless it has keys.
+    arity: Option<Arity>,
+    is_macro: bool,
+    local: bool,
+    used: bool,
+}
+
+impl Binding {
+    fn new(name: SymbolId, span: Span, local: bool) -> Self {
+        Binding {
+            name,
+            span,
+            arity: None,
+            is_macro: false,
+            local,
+            used: false,
+        }
+    }
+}
+
+// Walks all of the text as Resolver walks down to an offset, keeping the
+// names in scope on the way, innermost last, and what it finds.
+struct Linter<'a> {
+    globals: &'a [SymbolId],
+    // Whether a symbol nothing binds is reported.
+    unbound: bool,
+    scopes: Vec<Vec<Binding>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Linter<'_> {
+    // The top level has its definitions, a module's exports qualified by
+    // its name and the exports of the modules it imports in scope.
+    fn top_level(&mut self, forms: &[&SpannedExpr]) {
+        let mut scope = Vec::new();
+        for form in forms {
+            defined(form, false, &mut scope);
+        }
+        let modules: Vec<_> = forms.iter().filter_map(|form| module_form(form)).collect();
+        for &(module, exports) in &modules {
+            for (export, span) in exports.iter().filter_map(symbol) {
+                for qualified in [format!("{module}/{export}"), format!("{module}::{export}")] {
+                    scope.push(Binding::new(SymbolId::new(&qualified), span, false));
+                }
+            }
+        }
+        for name in forms.iter().filter_map(|form| import_form(form)) {
+            if let Some(&(_, exports)) = modules.iter().find(|&&(module, _)| module == name) {
+                let exports = exports.iter().filter_map(symbol);
+                scope.extend(exports.map(|(export, span)| Binding::new(export, span, false)));
+            }
+        }
+        self.enter(scope);
+        forms.iter().for_each(|form| self.expr(form));
+        self.leave(0);
+    }
+
+    fn enter(&mut self, scope: Vec<Binding>) {
+        for binding in &scope {
+            if self.globals.contains(&binding.name) {
+                self.diagnostics.push(Diagnostic::Shadows {
+                    name: binding.name,
+                    span: binding.span,
+                });
+            }
+        }
+        self.scopes.push(scope);
+    }
+
+    // Drops the scopes above depth, reporting what nothing in them used.
+    fn leave(&mut self, depth: usize) {
+        for scope in self.scopes.split_off(depth) {
+            let unused = scope.into_iter().filter(|binding| {
+                binding.local && !binding.used && !binding.name.as_str().starts_with('_')
+            });
+            self.diagnostics
+                .extend(unused.map(|binding| Diagnostic::Unused {
+                    name: binding.name,
+                    span: binding.span,
+                }));
+        }
+    }
+
+    fn bind(&mut self, names: Vec<(SymbolId, Span)>) {
+        let scope = names
+            .into_iter()
+            .map(|(name, span)| Binding::new(name, span, true))
+            .collect();
+        self.enter(scope);
+    }
+
+    fn lookup(&mut self, name: SymbolId) -> Option<&mut Binding> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.iter_mut().rev().find(|binding| binding.name == name))
+    }
+
+    fn is_unbound(&mut self, name: SymbolId) -> bool {
+        self.unbound && !self.globals.contains(&name) && self.lookup(name).is_none()
+    }
+
+    fn reference(&mut self, name: SymbolId, span: Span) {
+        if self.is_unbound(name) {
+            self.diagnostics.push(Diagnostic::Unbound { name, span });
+        }
+        if let Some(binding) = self.lookup(name) {
+            binding.used = true;
+        }
+    }
+
+    fn expr(&mut self, expr: &SpannedExpr) {
+        match expr {
+            SpannedExpr::Atom(_) => {
+                if let Some((name, span)) = symbol(expr) {
+                    self.reference(name, span);
+                }
+            }
+            SpannedExpr::List(list) => self.list(list.span, &list.value),
+            SpannedExpr::DottedList(list) => {
+                list.value.0.iter().for_each(|item| self.expr(item));
+                self.expr(&list.value.1);
+            }
+            SpannedExpr::Vector(vector) => vector.value.iter().for_each(|item| self.expr(item)),
+            SpannedExpr::Map(map) => {
+                for (key, value) in &map.value {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+        }
+    }
+
+    fn list(&mut self, span: Span, items: &[SpannedExpr]) {
+        let head = items.first().and_then(symbol).map(|(head, _)| head);
+        let depth = self.scopes.len();
+        match (head, items) {
+            (Some(symbol::QUOTE), _) => {}
+            (Some(symbol::QUASIQUOTE), [_, template]) => self.template(template),
+            (Some(symbol::DEFINE), [_, signature, body @ ..]) => match signature {
+                SpannedExpr::List(list) => {
+                    if let [name, params @ ..] = list.value.as_slice() {
+                        self.function(name, params_of(params, None), body);
+                    }
+                }
+                SpannedExpr::DottedList(list) => {
+                    let (items, rest) = &list.value;
+                    if let [name, params @ ..] = items.as_slice() {
+                        self.function(name, params_of(params, Some(rest)), body);
+                    }
+                }
+                name => {
+                    self.declare(name);
+                    body.iter().for_each(|value| self.expr(value));
+                }
+            },
+            (Some(symbol::DEFMACRO), [_, name, params, body @ ..]) => {
+                self.function(name, params_list(params), body)
+            }
+            (Some(symbol::LAMBDA), [_, params, body @ ..]) => {
+                self.bind(params_list(params));
+                self.body(body);
+            }
+            (Some(symbol::LET | symbol::LET_STAR), [_, SpannedExpr::List(bindings), body @ ..]) => {
+                let mut names = Vec::new();
+                for binding in &bindings.value {
+                    let SpannedExpr::List(parts) = binding else {
+                        continue;
+                    };
+                    let [name, value] = parts.value.as_slice() else {
+                        continue;
+                    };
+                    self.expr(value);
+                    let Some(name) = symbol(name) else {
+                        continue;
+                    };
+                    match head == Some(symbol::LET_STAR) {
+                        true => self.bind(vec![name]),
+                        false => names.push(name),
+                    }
+                }
+                self.bind(names);
+                self.body(body);
+            }
+            (Some(symbol::LET_VALUES), [_, SpannedExpr::List(bindings), body @ ..]) => {
+                let mut names = Vec::new();
+                for binding in &bindings.value {
+                    let SpannedExpr::List(parts) = binding else {
+                        continue;
+                    };
+                    if let [params, value] = parts.value.as_slice() {
+                        self.expr(value);
+                        names.extend(params_list(params));
+                    }
+                }
+                self.bind(names);
+                self.body(body);
+            }
+            (Some(symbol::MATCH), [_, subject, clauses @ ..]) => {
+                self.expr(subject);
+                for clause in clauses {
+                    let SpannedExpr::List(parts) = clause else {
+                        continue;
+                    };
+                    let [pattern, body @ ..] = parts.value.as_slice() else {
+                        continue;
+                    };
+                    let mut names = Vec::new();
+                    pattern_names(pattern, &mut names);
+                    self.bind(names);
+                    self.body(body);
+                    self.leave(depth);
+                }
+            }
+            (Some(symbol::TRY), [_, body @ .., SpannedExpr::List(clause)]) => {
+                body.iter().for_each(|form| self.expr(form));
+                if let [catch, name, handler @ ..] = clause.value.as_slice() {
+                    if symbol(catch).is_some_and(|(catch, _)| catch == symbol::CATCH) {
+                        self.bind(symbol(name).into_iter().collect());
+                        self.body(handler);
+                    }
+                }
+            }
+            (Some(symbol::SET), [_, name, value]) => {
+                if let Some((name, span)) = symbol(name) {
+                    if self.is_unbound(name) {
+                        self.diagnostics.push(Diagnostic::SetUnbound { name, span });
+                    }
+                }
+                self.expr(value);
+            }
+            (Some(symbol::COND), [_, clauses @ ..]) => {
+                for clause in clauses {
+                    let SpannedExpr::List(parts) = clause else {
+                        self.expr(clause);
+                        continue;
+                    };
+                    let test = parts.value.first().and_then(symbol);
+                    let skip = usize::from(test.is_some_and(|(test, _)| test == symbol::ELSE));
+                    parts.value[skip..].iter().for_each(|part| self.expr(part));
+                }
+            }
+            (Some(symbol::GENERATOR), [_, body @ ..]) => self.body(body),
+            (Some(head), [_, _, export, body @ ..]) if head.as_str() == "module" => {
+                let mut scope = Vec::new();
+                for form in body {
+                    defined(form, false, &mut scope);
+                }
+                self.enter(scope);
+                if let SpannedExpr::List(export) = export {
+                    export.value.iter().skip(1).for_each(|name| self.expr(name));
+                }
+                body.iter().for_each(|form| self.expr(form));
+            }
+            (Some(head), _) if head.as_str() == "import" => {}
+            (Some(head), [_, args @ ..])
+                if head == symbol::YIELD || SPECIAL_FORMS.contains(&head.as_str()) =>
+            {
+                args.iter().for_each(|arg| self.expr(arg))
+            }
+            (Some(head), [callee, args @ ..]) => {
+                let binding = self
+                    .lookup(head)
+                    .map(|binding| (binding.is_macro, binding.arity));
+                match binding {
+                    // What a macro is given isn't code until it's expanded.
+                    Some((true, _)) => self.expr(callee),
+                    Some((false, Some(expected))) if !expected.accepts(args.len()) => {
+                        self.diagnostics.push(Diagnostic::Arity {
+                            name: head,
+                            span,
+                            expected,
+                            found: args.len(),
+                        });
+                        items.iter().for_each(|item| self.expr(item));
+                    }
+                    _ => items.iter().for_each(|item| self.expr(item)),
+                }
+            }
+            _ => items.iter().for_each(|item| self.expr(item)),
+        }
+        self.leave(depth);
+    }
+
+    // A define with a parameter list, or a defmacro.
+    fn function(
+        &mut self,
+        name: &SpannedExpr,
+        params: Vec<(SymbolId, Span)>,
+        body: &[SpannedExpr],
+    ) {
+        self.declare(name);
+        self.bind(params);
+        self.body(body);
+    }
+
+    // A define where a body's definitions aren't looked for, as in a when,
+    // binds its name from there on.
+    fn declare(&mut self, name: &SpannedExpr) {
+        let Some((name, span)) = symbol(name) else {
+            return;
+        };
+        if self.lookup(name).is_none() {
+            if let Some(scope) = self.scopes.last_mut() {
+                scope.push(Binding::new(name, span, false));
+            }
+        }
+    }
+
+    fn body(&mut self, body: &[SpannedExpr]) {
+        let mut scope = Vec::new();
+        for form in body {
+            defined(form, true, &mut scope);
+        }
+        self.enter(scope);
+        body.iter().for_each(|form| self.expr(form));
+    }
+
+    fn template(&mut self, template: &SpannedExpr) {
+        match template {
+            SpannedExpr::Atom(_) => {}
+            SpannedExpr::List(list) => match list.value.as_slice() {
+                [head, value]
+                    if symbol(head).is_some_and(|(head, _)| {
+                        head == symbol::UNQUOTE || head == symbol::UNQUOTE_SPLICING
+                    }) =>
+                {
+                    self.expr(value)
+                }
+                items => items.iter().for_each(|item| self.template(item)),
+            },
+            SpannedExpr::DottedList(list) => {
+                list.value.0.iter().for_each(|item| self.template(item));
+                self.template(&list.value.1);
+            }
+            SpannedExpr::Vector(vector) => vector.value.iter().for_each(|item| self.template(item)),
+            SpannedExpr::Map(map) => {
+                for (key, value) in &map.value {
+                    self.template(key);
+                    self.template(value);
+                }
+            }
+        }
+    }
+}
+
+// What a define or defmacro binds, with the arguments a function takes,
+// or what the forms in a begin do.
+fn defined(form: &SpannedExpr, local: bool, scope: &mut Vec<Binding>) {
+    let SpannedExpr::List(list) = form else {
+        return;
+    };
+    let items = list.value.as_slice();
+    let Some((head, _)) = items.first().and_then(symbol) else {
+        return;
+    };
+    let (name, arity, is_macro) = match (head, &items[1..]) {
+        (symbol::BEGIN, body) => {
+            body.iter().for_each(|form| defined(form, local, scope));
+            return;
+        }
+        (symbol::DEFINE, [SpannedExpr::List(signature), ..]) => {
+            let Some((name, params)) = signature.value.split_first() else {
+                return;
+            };
+            (name, arity(params, false), false)
+        }
+        (symbol::DEFINE, [SpannedExpr::DottedList(signature), ..]) => {
+            let Some((name, params)) = signature.value.0.split_first() else {
+                return;
+            };
+            (name, arity(params, true), false)
+        }
+        (symbol::DEFINE, [name, value @ ..]) => {
+            let params = value.first().and_then(lambda_params);
+            (name, params.and_then(params_arity), false)
+        }
+        (symbol::DEFMACRO, [name, ..]) => (name, None, true),
+        _ => return,
+    };
+    if let Some((name, span)) = symbol(name) {
+        scope.push(Binding {
+            arity,
+            is_macro,
+            ..Binding::new(name, span, local)
+        });
+    }
+}
+
+fn arity(params: &[SpannedExpr], rest: bool) -> Option<Arity> {
+    let keys = params
+        .iter()
+        .any(|param| symbol(param).is_some_and(|(name, _)| name == symbol::AND_KEY));
+    match keys {
+        true => None,
+        false => Some(Arity {
+            required: params.len(),
+            rest,
+        }),
+    }
+}
+
+fn params_arity(params: &SpannedExpr) -> Option<Arity> {
+    match params {
+        SpannedExpr::List(list) => arity(&list.value, false),
+        SpannedExpr::DottedList(list) => arity(&list.value.0, true),
+        rest => symbol(rest).map(|_| Arity {
+            required: 0,
+            rest: true,
+        }),
+    }
+}
+
+// (module name (export names...) body...) as its name and exports.
+fn module_form(form: &SpannedExpr) -> Option<(SymbolId, &[SpannedExpr])> {
+    let SpannedExpr::List(list) = form else {
+        return None;
+    };
+    match list.value.as_slice() {
+        [head, name, SpannedExpr::List(export), ..]
+            if symbol(head).is_some_and(|(head, _)| head.as_str() == "module") =>
+        {
+            Some((symbol(name)?.0, export.value.get(1..)?))
+        }
+        _ => None,
+    }
+}
+
+fn import_form(form: &SpannedExpr) -> Option<SymbolId> {
+    let SpannedExpr::List(list) = form else {
+        return None;
+    };
+    match list.value.as_slice() {
+        [head, name] if symbol(head).is_some_and(|(head, _)| head.as_str() == "import") => {
+            Some(symbol(name)?.0)
+        }
+        _ => None,
+    }
+}
+
+// Whether forms load a file or import a module they don't define, either
+// of which binds names the text doesn't show.
+fn binds_unknown(forms: &[&SpannedExpr]) -> bool {
+    let modules: Vec<_> = forms
+        .iter()
+        .filter_map(|form| module_form(form))
+        .map(|(module, _)| module)
+        .collect();
+    forms.iter().any(|form| loads(form, &modules))
+}
+
+fn loads(expr: &SpannedExpr, modules: &[SymbolId]) -> bool {
+    if let Some(name) = import_form(expr) {
+        return !modules.contains(&name);
+    }
+    match expr {
+        SpannedExpr::Atom(_) => false,
+        SpannedExpr::List(list) => match list.value.first().and_then(symbol) {
+            Some((head, _)) if matches!(head.as_str(), "load" | "load-compiled") => true,
+            _ => list.value.iter().any(|item| loads(item, modules)),
+        },
+        SpannedExpr::DottedList(list) => {
+            list.value.0.iter().any(|item| loads(item, modules)) || loads(&list.value.1, modules)
+        }
+        SpannedExpr::Vector(vector) => vector.value.iter().any(|item| loads(item, modules)),
+        SpannedExpr::Map(map) => map
+            .value
+            .iter()
+            .any(|(key, value)| loads(key, modules) || loads(value, modules)),
+    }
+}
+
+// The names a parameter list binds: (a b), (a b . rest), a lone rest, with
+// keys after &key written as name or (name default).
+fn params_list(params: &SpannedExpr) -> Vec<(SymbolId, Span)> {
+    match params {
+        SpannedExpr::List(list) => params_of(&list.value, None),
+        SpannedExpr::DottedList(list) => params_of(&list.value.0, Some(&list.value.1)),
+        rest => symbol(rest).into_iter().collect(),
+    }
+}
+
+fn params_of(params: &[SpannedExpr], rest: Option<&SpannedExpr>) -> Vec<(SymbolId, Span)> {
+    params
+        .iter()
+        .chain(rest)
+        .filter_map(|param| match param {
+            SpannedExpr::List(key) => key.value.first().and_then(symbol),
+            param => symbol(param),
+        })
+        .filter(|&(name, _)| name != symbol::AND_KEY)
+        .collect()
+}
+
+// The names a match pattern binds: its symbols other than _, leaving out
+// quoted data and the keys of a map pattern.
+fn pattern_names(pattern: &SpannedExpr, names: &mut Vec<(SymbolId, Span)>) {
+    match pattern {
+        SpannedExpr::Atom(_) => {
+            names.extend(symbol(pattern).filter(|&(name, _)| name != symbol::WILDCARD))
+        }
+        SpannedExpr::List(list) => {
+            let quoted = list
+                .value
+                .first()
+                .and_then(symbol)
+                .is_some_and(|(head, _)| head == symbol::QUOTE);
+            if !quoted {
+                list.value
+                    .iter()
+                    .for_each(|item| pattern_names(item, names));
+            }
+        }
+        SpannedExpr::DottedList(list) => {
+            list.value
+                .0
+                .iter()
+                .for_each(|item| pattern_names(item, names));
+            pattern_names(&list.value.1, names);
+        }
+        SpannedExpr::Vector(vector) => vector
+            .value
+            .iter()
+            .for_each(|item| pattern_names(item, names)),
+        SpannedExpr::Map(map) => map
+            .value
+            .iter()
+            .for_each(|(_, value)| pattern_names(value, names)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::borrow::ToOwned;
+
+    fn read_like_new(document: &Document) {
+        let fresh = Document::new(document.text());
+        assert_eq!(
+            document.forms().collect::<Vec<_>>(),
+            fresh.forms().collect::<Vec<_>>()
+        );
+        assert_eq!(document.diagnostics(), fresh.diagnostics());
+    }
+
+    #[test]
+    fn edit_test() {
+        let source = "(define (f x) (* x 2))\n(define y 1)\n(f y)\n";
+        let mut document = Document::new(source);
+        assert_eq!(document.forms().count(), 3);
+
+        // Only the form that was edited is read again.
+        let at = source.find("1)").unwrap();
+        assert_eq!(
+            document.edit(Span::new(at, at + 1), "10"),
+            Span::new(22, 36)
+        );
+        assert_eq!(
+            document.text(),
+            "(define (f x) (* x 2))\n(define y 10)\n(f y)\n"
+        );
+        read_like_new(&document);
+
+        // Opening a list swallows what follows until the end of the text,
+        // and closing it again gives the other forms back.
+        assert_eq!(document.edit(Span::new(0, 0), "("), Span::new(0, 44));
+        assert_eq!(document.forms().count(), 1);
+        assert_eq!(document.diagnostics().len(), 1);
+        read_like_new(&document);
+        assert_eq!(document.edit(Span::new(0, 1), ""), Span::new(0, 42));
+        assert_eq!(document.forms().count(), 3);
+        read_like_new(&document);
+
+        // So does a block comment left open.
+        let end = document.text().len();
+        document.edit(Span::new(23, 23), "#|");
+        assert_eq!(document.forms().count(), 1);
+        document.edit(Span::new(end + 2, end + 2), "|#");
+        assert_eq!(document.forms().count(), 1);
+        read_like_new(&document);
+
+        // Text added right after a token can become part of it.
+        let mut document = Document::new("1e (x)");
+        document.edit(Span::new(2, 2), "5");
+        read_like_new(&document);
+        let mut document = Document::new("abc \"un");
+        document.edit(Span::new(7, 7), "closed\" def");
+        assert_eq!(document.forms().count(), 3);
+        read_like_new(&document);
+    }
+
+    #[test]
+    fn diagnostics_test() {
+        let document = Document::new("(define x 1) ) (define x 2) #\\bogus\n(ok) (\"open");
+        let diagnostics = document.diagnostics();
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|diagnostic| (diagnostic.span(), diagnostic.severity()))
+                .collect::<Vec<_>>(),
+            [
+                (Span::new(13, 14), Severity::Error),
+                (Span::new(23, 24), Severity::Warning),
+                (Span::new(28, 29), Severity::Error),
+                (Span::new(42, 47), Severity::Error),
+            ]
+        );
+        assert_eq!(
+            diagnostics[1],
+            Diagnostic::Redefined {
+                name: "x".into(),
+                span: Span::new(23, 24),
+                first: Span::new(8, 9),
+            }
+        );
+        assert_eq!(diagnostics[1].to_string(), "`x` is already defined");
+        // What came after the bad character still reads.
+        assert!(document
+            .forms()
+            .any(|form| form.to_expr().to_string() == "(ok)"));
+    }
+
+    #[test]
+    fn lint_test() {
+        let source = "(define (area w h) (* w h))
+(define (f x y) (+ x z))
+(area 1 2 3)
+(set! nowhere 1)
+(define (g list) (car list))
+(let ((unused 1) (_ignored 2)) 'done)
+(cond ((area 1 2) 'a) (else 'b))
+(defmacro swap (a b) (list b a))
+(swap not code)
+(module shapes (export circle) (define (circle r) (* r r)))
+(shapes/circle 1)
+(define (v . xs) xs)
+(v)
+(match 1 ((h . t) h) (_ 0))
+(try (car 1) (catch e e))
+(when #t (define late 1))
+late";
+        let document = Document::new(source);
+        let globals: Vec<SymbolId> = ["*", "+", "car", "list"].map(SymbolId::from).into();
+        let found: Vec<_> = document
+            .lint(&globals)
+            .iter()
+            .map(|diagnostic| {
+                let span = diagnostic.span();
+                (&source[span.start..span.end], diagnostic.to_string())
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("y", "`y` is never used".to_owned()),
+                ("z", "unbound symbol `z`".to_owned()),
+                (
+                    "(area 1 2 3)",
+                    "`area` expects 2 arguments, found 3".to_owned()
+                ),
+                ("nowhere", "set! of unbound symbol `nowhere`".to_owned()),
+                ("list", "`list` shadows a builtin".to_owned()),
+                ("unused", "`unused` is never used".to_owned()),
+                ("t", "`t` is never used".to_owned()),
+            ]
+        );
+        assert_eq!(
+            Document::new("(define (f) 1) (f 2)").lint(&globals)[0],
+            Diagnostic::Arity {
+                name: "f".into(),
+                span: Span::new(15, 20),
+                expected: Arity {
+                    required: 0,
+                    rest: false
+                },
+                found: 1,
+            }
+        );
+        // A loaded file could define anything.
+        assert!(Document::new("(load \"lib.lisp\") (helper)")
+            .lint(&globals)
+            .is_empty());
+        assert_eq!(Document::new("(import elsewhere) x").lint(&globals), []);
+        assert_eq!(
+            Document::new("(module m (export x) (define x 1)) (import m) x y")
+                .lint(&globals)
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            ["unbound symbol `y`"]
+        );
+    }
+
+    #[test]
+    fn symbols_test() {
+        let source = "(define (area w h) (* w h))
+(define pi 3.14)
+(define square (lambda (x) (* x x)))
+(define (log . args) args)
+(begin (define twice (lambda args args)))
+(defmacro swap (a b) (list b a))
+(module shapes (export circle)
+  (define (circle r) (* pi r r)))
+(display pi)";
+        let symbols = Document::new(source).symbols();
+        let outline: Vec<_> = symbols
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.kind, symbol.detail.as_deref()))
+            .collect();
+        assert_eq!(
+            outline,
+            [
+                ("area", SymbolKind::Function, Some("(area w h)")),
+                ("pi", SymbolKind::Variable, None),
+                ("square", SymbolKind::Function, Some("(square x)")),
+                ("log", SymbolKind::Function, Some("(log . args)")),
+                ("twice", SymbolKind::Function, Some("(twice . args)")),
+                ("swap", SymbolKind::Macro, Some("(swap a b)")),
+                ("shapes", SymbolKind::Module, Some("(export circle)")),
+            ]
+        );
+        let area = &symbols[0];
+        assert_eq!(area.span, Span::new(0, 27));
+        assert_eq!(area.name_span, Span::new(9, 13));
+        assert_eq!(symbols[6].children.len(), 1);
+        assert_eq!(symbols[6].children[0].detail.as_deref(), Some("(circle r)"));
+    }
+
+    #[test]
+    fn definition_test() {
+        let source = "(define n 10)
+(define (f x &key (scale 2) . rest)
+  (define inner x)
+  (let ((x (+ x n)) (y inner))
+    (let* ((a x) (b a))
+      (match b ((h . t) h) (_ y)))))
+(try (f n) (catch e e))
+(lambda (n) `(n ,n))
+'n
+(module m (export g) (define (g) n))";
+        let document = Document::new(source);
+        let at = |text: &str| source.find(text).unwrap();
+        let defined = |text: &str, name: &str| {
+            let start = at(text) + text.rfind(name).unwrap();
+            document.definition(start)
+        };
+        let span = |text: &str, name: &str| {
+            let start = at(text) + text.rfind(name).unwrap();
+            Some(Span::new(start, start + name.len()))
+        };
+        let n = span("(define n", "n");
+        assert_eq!(defined("(define n", "n"), n);
+        assert_eq!(defined("(+ x n)", "n"), n);
+        assert_eq!(defined("(f n)", "f"), span("(f x", "f"));
+        assert_eq!(defined("(f n)", "n"), n);
+        assert_eq!(defined("(define inner x)", "x"), span("(f x", "x"));
+        assert_eq!(
+            defined("(y inner)", "inner"),
+            span("(define inner", "inner")
+        );
+        // let's inits see the x outside it, and its body the x it binds.
+        assert_eq!(defined("(+ x n)", "x"), span("(f x", "x"));
+        assert_eq!(defined("((a x)", "x"), span("((x (+", "x"));
+        assert_eq!(defined("(b a)", "a"), span("((a x)", "a"));
+        assert_eq!(defined("t) h)", "h)"), span("((h . t)", "h"));
+        assert_eq!(defined("(_ y)", "y"), span("(y inner)", "y"));
+        assert_eq!(defined("(catch e e)", "e)"), span("(catch e", "e"));
+        assert_eq!(defined("(lambda (n) `(n ,n", ",n"), None);
+        assert_eq!(
+            document.definition(at("(n ,n") + 4),
+            span("(lambda (n", "n")
+        );
+        assert_eq!(document.definition(at("(n ,n") + 1), None);
+        assert_eq!(document.definition(at("'n") + 1), None);
+        assert_eq!(defined("(export g)", "g"), span("(define (g", "g"));
+        assert_eq!(defined("(define (g) n)", "n)"), n);
+        assert_eq!(
+            defined("(f x &key (scale", "scale"),
+            span("(scale", "scale")
+        );
+        assert_eq!(defined(". rest", "rest"), span(". rest", "rest"));
+        assert_eq!(document.definition(at("(try") + 1), None);
+    }
+
+    #[test]
+    fn hover_test() {
+        let source =
+            "; Doubles x.\n; Twice over.\n(define (double x) (* x 2))\n(double (car '(1)))";
+        let document = Document::new(source);
+        let hover = |text: &str| document.hover(source.rfind(text).unwrap());
+        assert_eq!(
+            hover("double"),
+            Some(Hover {
+                span: Span::new(56, 62),
+                contents: "(double x)\n\nDoubles x.\nTwice over.".to_owned(),
+            })
+        );
+        assert_eq!(hover("x)").unwrap().contents, "local x");
+        assert_eq!(hover("car").unwrap().contents, "builtin car");
+        assert_eq!(hover("1)"), None);
+        let source = "; Halves x.\n(define (half x) \"Rounding down.\" (quotient x 2))\n\
+                      (define third (lambda (x) \"Rounding down.\" (quotient x 3)))\n\
+                      (define (name) \"half\")";
+        let document = Document::new(source);
+        let docs: Vec<String> = document
+            .symbols()
+            .iter()
+            .map(|symbol| document.doc(symbol))
+            .collect();
+        assert_eq!(docs, ["Halves x.\n\nRounding down.", "Rounding down.", ""]);
+        assert_eq!(
+            Document::new("(+ 1 2) (define z 1)")
+                .hover(16)
+                .unwrap()
+                .contents,
+            "z"
+        );
+    }
+
+    #[test]
+    fn offset_test() {
+        let document = Document::new("ab\nλx\n");
+        assert_eq!(document.offset(1, 1), Some(0));
+        assert_eq!(document.offset(1, 3), Some(2));
+        assert_eq!(document.offset(2, 2), Some(5));
+        assert_eq!(document.offset(3, 1), Some(7));
+        assert_eq!(document.offset(1, 4), None);
+        assert_eq!(document.offset(4, 1), None);
+        assert_eq!(document.offset(0, 1), None);
+        let span = Span::new(5, 6);
+        assert_eq!(span.line_col(document.text()), (2, 2));
+    }
+}
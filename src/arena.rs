@@ -0,0 +1,559 @@
+#[cfg(feature = "bigint")]
+use alloc::boxed::Box;
+use alloc::{string::ToString, vec::Vec};
+
+use crate::{
+    lexer::{Span, Spanned, Token},
+    parser::{lex_source, Expected, Expr, ReadError, SpannedExpr},
+    reader,
+    symbol::{self, SymbolId},
+};
+
+// A node's index in its ExprArena.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ExprId(u32);
+
+// Where a node's children are in the arena: len ids from start.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Children {
+    start: u32,
+    len: u32,
+}
+
+// Like SpannedExpr, but children are ids rather than nested trees. A map's
+// keys and values alternate.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Node {
+    Atom(Expr),
+    List(Children),
+    DottedList(Children, ExprId),
+    Vector(Children),
+    Map(Children),
+}
+
+// Source read into three flat vectors, whatever its size or shape: the
+// nodes, their spans and the ids of their children, which are stored
+// together for each node. Reading a large file this way allocates for the
+// vectors as they grow and for string atoms, rather than once or twice per
+// node as SpannedExpr does.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ExprArena {
+    nodes: Vec<Node>,
+    spans: Vec<Span>,
+    children: Vec<ExprId>,
+    forms: Vec<ExprId>,
+}
+
+// A form still being read, innermost last. The offsets are into the
+// pending children.
+enum Frame {
+    List {
+        start: usize,
+        open: Span,
+        dot: Option<usize>,
+    },
+    Vector {
+        start: usize,
+        open: Span,
+    },
+    Map {
+        start: usize,
+        open: Span,
+    },
+    // 'x and the like, waiting for x.
+    Prefix {
+        name: SymbolId,
+        span: Span,
+    },
+    // #;, waiting for the datum to drop, and how big the arena was before
+    // it.
+    Comment {
+        nodes: usize,
+        children: usize,
+    },
+    // A read table prefix, waiting for the datum to hand to its handler,
+    // which is then dropped for what the handler makes of it.
+    Dispatch {
+        lead: char,
+        sub: char,
+        span: Span,
+        nodes: usize,
+        children: usize,
+    },
+}
+
+impl ExprArena {
+    pub fn new() -> Self {
+        ExprArena::default()
+    }
+
+    // Reads every form in input, as read_all_spanned does and with the
+    // same errors.
+    pub fn read(input: &str) -> Result<Self, ReadError> {
+        build(&lex_source(input)?, Span::new(input.len(), input.len()))
+    }
+
+    pub fn from_spanned(forms: &[SpannedExpr]) -> Self {
+        let mut arena = ExprArena::new();
+        arena.forms = forms.iter().map(|form| arena.push_spanned(form)).collect();
+        arena
+    }
+
+    fn push_spanned(&mut self, form: &SpannedExpr) -> ExprId {
+        let children = |arena: &mut Self, items: &mut dyn Iterator<Item = &SpannedExpr>| {
+            let ids: Vec<_> = items.map(|item| arena.push_spanned(item)).collect();
+            arena.push_children(ids)
+        };
+        let node = match form {
+            SpannedExpr::Atom(atom) => Node::Atom(atom.value.clone()),
+            SpannedExpr::List(list) => Node::List(children(self, &mut list.value.iter())),
+            SpannedExpr::DottedList(list) => {
+                let (items, tail) = &list.value;
+                let items = children(self, &mut items.iter());
+                Node::DottedList(items, self.push_spanned(tail))
+            }
+            SpannedExpr::Vector(vector) => Node::Vector(children(self, &mut vector.value.iter())),
+            SpannedExpr::Map(map) => {
+                let mut entries = map.value.iter().flat_map(|(key, value)| [key, value]);
+                Node::Map(children(self, &mut entries))
+            }
+        };
+        self.push(node, form.span())
+    }
+
+    fn push(&mut self, node: Node, span: Span) -> ExprId {
+        self.nodes.push(node);
+        self.spans.push(span);
+        ExprId(self.nodes.len() as u32 - 1)
+    }
+
+    fn push_children(&mut self, ids: impl IntoIterator<Item = ExprId>) -> Children {
+        let start = self.children.len();
+        self.children.extend(ids);
+        Children {
+            start: start as u32,
+            len: (self.children.len() - start) as u32,
+        }
+    }
+
+    // The top-level forms, in the order they were written.
+    pub fn forms(&self) -> &[ExprId] {
+        &self.forms
+    }
+
+    pub fn node(&self, id: ExprId) -> &Node {
+        &self.nodes[id.0 as usize]
+    }
+
+    pub fn span(&self, id: ExprId) -> Span {
+        self.spans[id.0 as usize]
+    }
+
+    pub fn children(&self, children: Children) -> &[ExprId] {
+        let start = children.start as usize;
+        &self.children[start..start + children.len as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    // The node as the evaluator takes it, built the way
+    // SpannedExpr::to_expr builds one.
+    pub fn to_expr(&self, id: ExprId) -> Expr {
+        let exprs = |children| self.children(children).iter().map(|&id| self.to_expr(id));
+        match self.node(id) {
+            Node::Atom(atom) => atom.clone(),
+            Node::List(items) => Expr::List(exprs(*items).collect()),
+            Node::DottedList(items, tail) => exprs(*items)
+                .rev()
+                .fold(self.to_expr(*tail), |cdr, car| Expr::cons(car, cdr)),
+            Node::Vector(items) => Expr::Vector(exprs(*items).collect()),
+            Node::Map(entries) => Expr::Map(
+                self.children(*entries)
+                    .chunks(2)
+                    .map(|entry| (self.to_expr(entry[0]), self.to_expr(entry[1])))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+fn build(tokens: &[Spanned<Token>], eof: Span) -> Result<ExprArena, ReadError> {
+    let mut arena = ExprArena {
+        nodes: Vec::with_capacity(tokens.len()),
+        spans: Vec::with_capacity(tokens.len()),
+        ..ExprArena::default()
+    };
+    let mut builder = Builder {
+        arena: &mut arena,
+        frames: Vec::new(),
+        pending: Vec::new(),
+    };
+    for token in tokens {
+        builder.token(&token.value, token.span)?;
+    }
+    builder.finish(eof)?;
+    Ok(arena)
+}
+
+// Reads tokens with a stack of the forms they are inside instead of by
+// recursing, so it accepts exactly the nesting the parser does without
+// needing the parser's stack. It stops at the same token the parser
+// would, and says what it expected there the way the parser does.
+struct Builder<'a> {
+    arena: &'a mut ExprArena,
+    frames: Vec<Frame>,
+    // The children read so far of every open list, vector and map, each
+    // one's after those of the ones it is inside.
+    pending: Vec<ExprId>,
+}
+
+impl Builder<'_> {
+    fn token(&mut self, token: &Token, span: Span) -> Result<(), ReadError> {
+        let start = self.pending.len();
+        let unexpected = |expected| ReadError::Parse {
+            span,
+            expected,
+            found: Some(token.clone().into_owned()),
+        };
+        // Only a `)` or a comment can follow the datum after a dot.
+        if self.after_tail()
+            && !matches!(
+                token,
+                Token::RParan | Token::RBracket | Token::RBrace | Token::DatumComment
+            )
+        {
+            return Err(unexpected(Expected::RParan));
+        }
+        match token {
+            Token::LParan => self.frames.push(Frame::List {
+                start,
+                open: span,
+                dot: None,
+            }),
+            Token::LBracket => self.frames.push(Frame::Vector { start, open: span }),
+            Token::LBrace => self.frames.push(Frame::Map { start, open: span }),
+            Token::Quote | Token::Quasiquote | Token::Unquote | Token::UnquoteSplicing => {
+                let name = match token {
+                    Token::Quote => symbol::QUOTE,
+                    Token::Quasiquote => symbol::QUASIQUOTE,
+                    Token::Unquote => symbol::UNQUOTE,
+                    _ => symbol::UNQUOTE_SPLICING,
+                };
+                self.frames.push(Frame::Prefix { name, span });
+            }
+            Token::DatumComment => self.frames.push(Frame::Comment {
+                nodes: self.arena.nodes.len(),
+                children: self.arena.children.len(),
+            }),
+            &Token::Dispatch(lead, sub) => self.frames.push(Frame::Dispatch {
+                lead,
+                sub,
+                span,
+                nodes: self.arena.nodes.len(),
+                children: self.arena.children.len(),
+            }),
+            // (a b . c) has at least one element before the dot.
+            Token::Dot => match self.frames.last_mut() {
+                Some(Frame::List { start, dot, .. })
+                    if dot.is_none() && self.pending.len() > *start =>
+                {
+                    *dot = Some(self.pending.len());
+                }
+                _ => return Err(unexpected(self.expected())),
+            },
+            Token::RParan | Token::RBracket | Token::RBrace => {
+                let node = self.close(token).map_err(unexpected)?;
+                let open = match self.frames.pop() {
+                    Some(
+                        Frame::List { open, .. }
+                        | Frame::Vector { open, .. }
+                        | Frame::Map { open, .. },
+                    ) => open,
+                    _ => unreachable!(),
+                };
+                let id = self.arena.push(node, open.to(span));
+                self.complete(id)?;
+            }
+            atom => {
+                let id = self.arena.push(Node::Atom(atom_expr(atom)), span);
+                self.complete(id)?;
+            }
+        }
+        Ok(())
+    }
+
+    // The node the innermost form makes once token closes it, or what was
+    // expected instead of token.
+    fn close(&mut self, token: &Token) -> Result<Node, Expected> {
+        let node = match (self.frames.last(), token) {
+            (
+                Some(&Frame::List {
+                    start, dot: None, ..
+                }),
+                Token::RParan,
+            ) => Node::List(self.take_children(start)),
+            // And exactly one after it.
+            (
+                Some(&Frame::List {
+                    start,
+                    dot: Some(dot),
+                    ..
+                }),
+                Token::RParan,
+            ) => {
+                if self.pending.len() != dot + 1 {
+                    return Err(Expected::Expr);
+                }
+                let tail = self.pending.pop().unwrap();
+                Node::DottedList(self.take_children(start), tail)
+            }
+            (Some(&Frame::Vector { start, .. }), Token::RBracket) => {
+                Node::Vector(self.take_children(start))
+            }
+            (Some(&Frame::Map { start, .. }), Token::RBrace) => {
+                if (self.pending.len() - start) % 2 == 1 {
+                    return Err(Expected::Expr);
+                }
+                Node::Map(self.take_children(start))
+            }
+            _ => return Err(self.expected()),
+        };
+        Ok(node)
+    }
+
+    // What the innermost form needs next, for when it gets something else.
+    fn expected(&self) -> Expected {
+        match self.frames.last() {
+            Some(&Frame::List { dot: Some(dot), .. }) if self.pending.len() == dot => {
+                Expected::Expr
+            }
+            Some(Frame::List { .. }) => Expected::RParan,
+            Some(Frame::Vector { .. }) => Expected::RBracket,
+            Some(Frame::Map { .. }) => Expected::RBrace,
+            _ => Expected::Expr,
+        }
+    }
+
+    // Whether the innermost form is a dotted list that already has its
+    // tail.
+    fn after_tail(&self) -> bool {
+        matches!(
+            self.frames.last(),
+            Some(&Frame::List { dot: Some(dot), .. }) if self.pending.len() > dot
+        )
+    }
+
+    fn take_children(&mut self, start: usize) -> Children {
+        let ids = self.pending.drain(start..);
+        self.arena.push_children(ids)
+    }
+
+    // Hands a finished datum to whatever it is inside.
+    fn complete(&mut self, mut id: ExprId) -> Result<(), ReadError> {
+        loop {
+            match self.frames.last() {
+                Some(&Frame::Prefix { name, span }) => {
+                    self.frames.pop();
+                    let symbol = self.arena.push(Node::Atom(Expr::Symbol(name)), span);
+                    let items = self.arena.push_children([symbol, id]);
+                    let span = span.to(self.arena.span(id));
+                    id = self.arena.push(Node::List(items), span);
+                }
+                Some(&Frame::Comment { nodes, children }) => {
+                    self.frames.pop();
+                    self.truncate(nodes, children);
+                    return Ok(());
+                }
+                Some(&Frame::Dispatch {
+                    lead,
+                    sub,
+                    span: prefix,
+                    nodes,
+                    children,
+                }) => {
+                    self.frames.pop();
+                    let datum = self.arena.to_expr(id);
+                    let span = prefix.to(self.arena.span(id));
+                    self.truncate(nodes, children);
+                    let expr = reader::dispatch(lead, sub, datum).map_err(|message| {
+                        ReadError::Dispatch {
+                            span: prefix,
+                            message,
+                        }
+                    })?;
+                    id = self.arena.push(Node::Atom(expr), span);
+                }
+                Some(_) => {
+                    self.pending.push(id);
+                    return Ok(());
+                }
+                None => {
+                    self.arena.forms.push(id);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn truncate(&mut self, nodes: usize, children: usize) {
+        self.arena.nodes.truncate(nodes);
+        self.arena.spans.truncate(nodes);
+        self.arena.children.truncate(children);
+    }
+
+    fn finish(&self, eof: Span) -> Result<(), ReadError> {
+        match self.frames.is_empty() {
+            true => Ok(()),
+            false => Err(ReadError::Parse {
+                span: eof,
+                expected: self.expected(),
+                found: None,
+            }),
+        }
+    }
+}
+
+fn atom_expr(token: &Token) -> Expr {
+    match token {
+        Token::Bool(b) => Expr::Bool(*b),
+        Token::Integer(i) => Expr::Integer(*i),
+        #[cfg(feature = "bigint")]
+        Token::BigInteger(i) => Expr::BigInteger(Box::new(i.clone())),
+        Token::Rational(r) => Expr::Rational(*r),
+        Token::Float(f) => Expr::Float(*f),
+        Token::Char(c) => Expr::Char(*c),
+        Token::String(s) => Expr::String(s.to_string()),
+        Token::Symbol(s) => Expr::Symbol(SymbolId::new(s)),
+        Token::Keyword(s) => Expr::Keyword(SymbolId::new(s)),
+        _ => unreachable!("brackets and prefixes are handled by the builder"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        parser::{read_all, read_all_spanned},
+        reader::{with_read_table, ReadTable},
+    };
+    use alloc::string::String;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn arena_test() {
+        let source = "(define (f x) (* x 2)) [1 \"s\" #\\a] {:k 'v} (a b . c) #;(gone) `(,x ,@y)";
+        let arena = ExprArena::read(source).unwrap();
+        let forms: Vec<_> = arena.forms().iter().map(|&id| arena.to_expr(id)).collect();
+        assert_eq!(forms, read_all(source).unwrap());
+        assert_eq!(arena.forms().len(), 5);
+        let define = arena.forms()[0];
+        assert_eq!(arena.span(define), Span::new(0, 22));
+        let Node::List(items) = arena.node(define) else {
+            panic!("{:?}", arena.node(define));
+        };
+        assert_eq!(arena.children(*items).len(), 3);
+        let spans: Vec<_> = read_all_spanned(source)
+            .unwrap()
+            .iter()
+            .map(SpannedExpr::span)
+            .collect();
+        let arena_spans: Vec<_> = arena.forms().iter().map(|&id| arena.span(id)).collect();
+        assert_eq!(arena_spans, spans);
+        // The commented-out datum leaves nothing behind.
+        assert!(arena
+            .nodes
+            .iter()
+            .all(|node| *node != Node::Atom(Expr::Symbol("gone".into()))));
+    }
+
+    #[test]
+    fn arena_error_test() {
+        for source in [
+            "(a . b c)",
+            "(. a)",
+            "[a . b]",
+            "{:k}",
+            "(a",
+            "a)",
+            "'",
+            "#;",
+            "(a #;)",
+            "(a . #;b)",
+            "(a]",
+            "[a)",
+            "{a b)",
+            "(a . ]",
+            "(a . b]",
+            "(a . b 'c)",
+            "(a . b (c))",
+            "(a . . b)",
+            "(a . b . c)",
+            "(a #;. b)",
+            "'. a",
+            "')",
+            "{(a)}",
+            "({:k})",
+            "[a",
+            "{a",
+            "(a .",
+            "(a . b",
+            "(a . b #;",
+            "a #;",
+            "#x",
+            "#;(a",
+            "#;(a ]",
+            "#;(a . b c)",
+        ] {
+            assert_eq!(
+                ExprArena::read(source),
+                Err(read_all_spanned(source).unwrap_err()),
+                "{source}"
+            );
+        }
+        assert_eq!(
+            ExprArena::read("(a . #;b c #;d)").map(|arena| arena.to_expr(arena.forms()[0])),
+            Ok(Expr::cons(
+                Expr::Symbol("a".into()),
+                Expr::Symbol("c".into())
+            ))
+        );
+        assert_eq!(ExprArena::read("#;#;a b"), Ok(ExprArena::new()));
+        for source in [
+            "'#;a b",
+            "#;'a b",
+            "(a #;b . #;c d)",
+            "[#;[a] b] {#;c :k #;d v #;e}",
+            "`(,@a . ,b)",
+            "",
+        ] {
+            assert!(ExprArena::read(source).is_ok(), "{source}");
+        }
+    }
+
+    #[test]
+    fn arena_dispatch_test() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let mut table = ReadTable::new();
+        table.set_dispatch('#', 'x', |datum| {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            match datum {
+                Expr::Symbol(_) => Ok(datum),
+                _ => Err(String::from("expected a symbol")),
+            }
+        });
+        // Each handler runs once, even when a later form is wrong.
+        for source in ["#xa (#xb", "#xa #x1", "#xa (#x1 #xb)"] {
+            CALLS.store(0, Ordering::Relaxed);
+            let read = with_read_table(&table, || ExprArena::read(source));
+            assert_eq!(CALLS.load(Ordering::Relaxed), 2, "{source}");
+            let parsed = with_read_table(&table, || read_all_spanned(source));
+            assert_eq!(read, Err(parsed.unwrap_err()), "{source}");
+        }
+    }
+}
@@ -0,0 +1,2134 @@
+use alloc::boxed::Box;
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::cmp::Ordering;
+
+#[cfg(feature = "bigint")]
+use crate::bigint::BigInt;
+#[cfg(feature = "std")]
+use crate::profile::Profile;
+use crate::{
+    compile,
+    eval::{apply, is_truthy, Env, EvalError},
+    gc, hooks,
+    io::{self, Port},
+    limits,
+    parser::Expr,
+    rational::Rational,
+    value::{Atom, Builtin, ErrorObject, NativeFn, Value},
+    vm::Generator,
+};
+
+const BUILTINS: &[(&str, Builtin)] = &[
+    ("+", add),
+    ("-", sub),
+    ("*", mul),
+    ("/", div),
+    ("quotient", quotient),
+    ("exact->inexact", exact_to_inexact),
+    ("exact?", is_exact),
+    ("=", num_eq),
+    ("<", lt),
+    (">", gt),
+    ("<=", le),
+    (">=", ge),
+    ("eq?", is_eq),
+    ("eqv?", is_eqv),
+    ("equal?", is_equal),
+    ("cons", cons),
+    ("car", car),
+    ("cdr", cdr),
+    ("list", list),
+    ("length", length),
+    ("append", append),
+    ("apply", apply_builtin),
+    ("values", values),
+    ("call-with-values", call_with_values),
+    ("map", map),
+    ("for-each", for_each),
+    ("filter", filter),
+    ("fold-left", fold_left),
+    ("fold-right", fold_right),
+    ("range", range),
+    ("null?", is_null),
+    ("pair?", is_pair),
+    ("number?", is_number),
+    ("symbol?", is_symbol),
+    ("keyword?", is_keyword),
+    ("char?", is_char),
+    ("vector?", is_vector),
+    ("vector", vector),
+    ("vector-length", vector_length),
+    ("vector-ref", vector_ref),
+    ("map?", is_map),
+    ("map-get", map_get),
+    ("box", make_atom),
+    ("atom", make_atom),
+    ("atom?", is_atom),
+    ("deref", deref),
+    ("reset!", reset),
+    ("swap!", swap),
+    ("error", error),
+    ("raise", raise),
+    ("error-object?", is_error_object),
+    ("error-object-message", error_object_message),
+    ("error-object-irritants", error_object_irritants),
+    ("with-exception-handler", with_exception_handler),
+    ("exit", exit),
+    ("char->integer", char_to_integer),
+    ("integer->char", integer_to_char),
+    ("string-append", string_append),
+    ("string-length", string_length),
+    ("substring", substring),
+    ("string->symbol", string_to_symbol),
+    ("symbol->string", symbol_to_string),
+    ("number->string", number_to_string),
+    ("string->number", string_to_number),
+    ("split", split),
+    ("string-join", string_join),
+    ("format", format),
+    ("open-input-file", open_input_file),
+    ("open-output-file", open_output_file),
+    ("open-input-string", open_input_string),
+    ("open-output-string", open_output_string),
+    ("get-output-string", get_output_string),
+    ("close-port", close_port),
+    ("input-port?", is_input_port),
+    ("output-port?", is_output_port),
+    ("current-input-port", current_input_port),
+    ("current-output-port", current_output_port),
+    ("read-line", read_line),
+    ("write", write),
+    ("display", display),
+    ("newline", newline),
+    ("generator?", is_generator),
+    ("next", next),
+    ("generator-done?", is_generator_done),
+    ("generator->list", generator_to_list),
+    ("gc", collect_garbage),
+    ("trace", trace),
+    ("untrace", untrace),
+    ("disassemble", disassemble),
+];
+
+// Builtins that run on other threads, which only sync values can go to.
+#[cfg(feature = "sync")]
+const PARALLEL: &[(&str, Builtin)] = &[("pmap", pmap)];
+#[cfg(not(feature = "sync"))]
+const PARALLEL: &[(&str, Builtin)] = &[];
+
+// Builtins that need the clock.
+#[cfg(feature = "std")]
+const TIMED: &[(&str, Builtin)] = &[("profile", profile)];
+#[cfg(not(feature = "std"))]
+const TIMED: &[(&str, Builtin)] = &[];
+
+fn all() -> impl Iterator<Item = &'static (&'static str, Builtin)> {
+    BUILTINS.iter().chain(PARALLEL).chain(TIMED)
+}
+
+pub fn register(env: &Env) {
+    for &(name, func) in all() {
+        env.define(name, Value::Native(NativeFn::new(name, func)));
+    }
+}
+
+// The builtin registered under name, for callers that want to run one
+// without an environment.
+pub(crate) fn find(name: &str) -> Option<Builtin> {
+    all()
+        .find(|&&(builtin, _)| builtin == name)
+        .map(|&(_, func)| func)
+}
+
+pub fn global_env() -> Env {
+    let env = Env::new();
+    register(&env);
+    env
+}
+
+fn arity(args: &[Value], expected: usize) -> Result<(), EvalError> {
+    if args.len() == expected {
+        Ok(())
+    } else {
+        Err(EvalError::ArityMismatch {
+            expected,
+            found: args.len(),
+        })
+    }
+}
+
+// Integers and rationals stay exact until a float is involved, at which
+// point the operation is carried out in floating point. Rationals are made
+// of i64s, so an exact result too large for one is an overflow error,
+// except that with the bigint feature an integer result becomes a
+// BigInteger instead. A rational result with a BigInteger involved is an
+// overflow too. Results are always in their simplest form: a
+// rational that comes out whole is an Integer and a BigInteger that fits
+// is too.
+#[derive(Clone)]
+enum Number {
+    Integer(i64),
+    #[cfg(feature = "bigint")]
+    Big(BigInt),
+    Rational(Rational),
+    Float(f64),
+}
+
+impl Number {
+    fn from_expr(expr: &Value) -> Result<Self, EvalError> {
+        match expr {
+            Value::Integer(i) => Ok(Number::Integer(*i)),
+            #[cfg(feature = "bigint")]
+            Value::BigInteger(i) => Ok(Number::Big((**i).clone())),
+            Value::Rational(r) => Ok(Number::Rational(*r)),
+            Value::Float(f) => Ok(Number::Float(*f)),
+            other => Err(EvalError::TypeMismatch {
+                expected: "number",
+                found: other.clone(),
+            }),
+        }
+    }
+
+    #[cfg(feature = "bigint")]
+    fn from_big(i: BigInt) -> Self {
+        match i.to_i64() {
+            Some(i) => Number::Integer(i),
+            None => Number::Big(i),
+        }
+    }
+
+    fn from_rational(r: Rational) -> Self {
+        match r.to_integer() {
+            Some(i) => Number::Integer(i),
+            None => Number::Rational(r),
+        }
+    }
+
+    fn as_rational(&self) -> Option<Rational> {
+        match self {
+            Number::Integer(i) => Some(Rational::from(*i)),
+            Number::Rational(r) => Some(*r),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "bigint")]
+    fn as_big(&self) -> Option<BigInt> {
+        match self {
+            Number::Integer(i) => Some(BigInt::from(*i)),
+            Number::Big(i) => Some(i.clone()),
+            _ => None,
+        }
+    }
+
+    fn as_f64(&self) -> f64 {
+        match self {
+            Number::Integer(i) => *i as f64,
+            #[cfg(feature = "bigint")]
+            Number::Big(i) => i.to_f64(),
+            Number::Rational(r) => r.to_f64(),
+            Number::Float(f) => *f,
+        }
+    }
+
+    fn into_expr(self) -> Value {
+        match self {
+            Number::Integer(i) => Value::Integer(i),
+            #[cfg(feature = "bigint")]
+            Number::Big(i) => Value::BigInteger(Box::new(i)),
+            Number::Rational(r) => Value::Rational(r),
+            Number::Float(f) => Value::Float(f),
+        }
+    }
+}
+
+// One arithmetic operation for each kind of number. The integer version
+// returns None when the result isn't an integer that fits, and the exact
+// ones when they have no exact result; either way the next kind up is
+// tried.
+struct Op {
+    int: fn(i64, i64) -> Option<i64>,
+    ratio: fn(Rational, Rational) -> Option<Rational>,
+    #[cfg(feature = "bigint")]
+    big: fn(&BigInt, &BigInt) -> Option<Number>,
+    float: fn(f64, f64) -> f64,
+}
+
+const ADD: Op = Op {
+    int: i64::checked_add,
+    ratio: Rational::checked_add,
+    #[cfg(feature = "bigint")]
+    big: |a, b| Some(Number::from_big(a + b)),
+    float: |a, b| a + b,
+};
+
+const SUB: Op = Op {
+    int: i64::checked_sub,
+    ratio: Rational::checked_sub,
+    #[cfg(feature = "bigint")]
+    big: |a, b| Some(Number::from_big(a - b)),
+    float: |a, b| a - b,
+};
+
+const MUL: Op = Op {
+    int: i64::checked_mul,
+    ratio: Rational::checked_mul,
+    #[cfg(feature = "bigint")]
+    big: |a, b| Some(Number::from_big(a * b)),
+    float: |a, b| a * b,
+};
+
+// Dividing integers that don't divide evenly gives a rational. Division by
+// zero is ruled out before the operation is reached.
+const DIV: Op = Op {
+    int: |a, b| a.checked_rem(b).filter(|&r| r == 0).and(a.checked_div(b)),
+    ratio: Rational::checked_div,
+    #[cfg(feature = "bigint")]
+    big: big_div,
+    float: |a, b| a / b,
+};
+
+// A quotient of BigIntegers that isn't whole is reduced first, and is a
+// rational if both its parts then fit in an i64.
+#[cfg(feature = "bigint")]
+fn big_div(a: &BigInt, b: &BigInt) -> Option<Number> {
+    let (quotient, remainder) = a.div_rem(b)?;
+    if remainder.is_zero() {
+        return Some(Number::from_big(quotient));
+    }
+    let divisor = a.gcd(b);
+    let (numerator, _) = a.div_rem(&divisor)?;
+    let (denominator, _) = b.div_rem(&divisor)?;
+    Rational::new(numerator.to_i64()?, denominator.to_i64()?).map(Number::from_rational)
+}
+
+fn apply_op(op: &Op, a: Number, b: Number) -> Result<Number, EvalError> {
+    if let (Number::Integer(x), Number::Integer(y)) = (&a, &b) {
+        if let Some(i) = (op.int)(*x, *y) {
+            return Ok(Number::Integer(i));
+        }
+    }
+    if let (Some(x), Some(y)) = (a.as_rational(), b.as_rational()) {
+        if let Some(r) = (op.ratio)(x, y) {
+            return Ok(Number::from_rational(r));
+        }
+        if x.to_integer().is_none() || y.to_integer().is_none() {
+            return Err(EvalError::RationalOverflow);
+        }
+        if !cfg!(feature = "bigint") {
+            return Err(EvalError::IntegerOverflow);
+        }
+    }
+    #[cfg(feature = "bigint")]
+    if let (Some(x), Some(y)) = (a.as_big(), b.as_big()) {
+        if let Some(n) = (op.big)(&x, &y) {
+            return Ok(n);
+        }
+    }
+    // Exact numbers with no exact result, such as a rational and a
+    // BigInteger, don't quietly become inexact.
+    if !matches!(a, Number::Float(_)) && !matches!(b, Number::Float(_)) {
+        return Err(EvalError::RationalOverflow);
+    }
+    Ok(Number::Float((op.float)(a.as_f64(), b.as_f64())))
+}
+
+fn fold(args: &[Value], init: Option<Number>, op: &Op) -> Result<Value, EvalError> {
+    let mut numbers = args.iter().map(Number::from_expr);
+    let first = match init {
+        Some(n) => n,
+        None => numbers.next().ok_or(EvalError::ArityMismatch {
+            expected: 1,
+            found: 0,
+        })??,
+    };
+    numbers
+        .try_fold(first, |acc, n| apply_op(op, acc, n?))
+        .map(Number::into_expr)
+}
+
+fn add(args: &[Value]) -> Result<Value, EvalError> {
+    fold(args, Some(Number::Integer(0)), &ADD)
+}
+
+fn mul(args: &[Value]) -> Result<Value, EvalError> {
+    fold(args, Some(Number::Integer(1)), &MUL)
+}
+
+// (- x) is x negated. Taking x from 0 is exact for exact numbers, but
+// for floats would give 0.0 rather than -0.0.
+fn sub(args: &[Value]) -> Result<Value, EvalError> {
+    if let [x] = args {
+        return match Number::from_expr(x)? {
+            Number::Float(x) => Ok(Value::Float(-x)),
+            x => apply_op(&SUB, Number::Integer(0), x).map(Number::into_expr),
+        };
+    }
+    fold(args, None, &SUB)
+}
+
+// (/ x) is 1/x, as in Scheme.
+fn div(args: &[Value]) -> Result<Value, EvalError> {
+    let divisors = if args.len() == 1 { args } else { &args[1..] };
+    if divisors.iter().any(|arg| *arg == Value::Integer(0)) {
+        return Err(EvalError::DivisionByZero);
+    }
+    let init = (args.len() == 1).then_some(Number::Integer(1));
+    fold(args, init, &DIV)
+}
+
+// Integer division, rounding toward zero. With the bigint feature a
+// quotient too large for an i64, and a BigInteger argument, are fine.
+fn quotient(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 2)?;
+    let integer = |value: &Value| match value {
+        Value::Integer(_) => Number::from_expr(value),
+        #[cfg(feature = "bigint")]
+        Value::BigInteger(_) => Number::from_expr(value),
+        other => Err(EvalError::TypeMismatch {
+            expected: "integer",
+            found: other.clone(),
+        }),
+    };
+    let (a, b) = (integer(&args[0])?, integer(&args[1])?);
+    // Checked before either path, since a BigInteger dividend would
+    // otherwise take the big one.
+    if matches!(b, Number::Integer(0)) {
+        return Err(EvalError::DivisionByZero);
+    }
+    if let (Number::Integer(a), Number::Integer(b)) = (&a, &b) {
+        if let Some(q) = a.checked_div(*b) {
+            return Ok(Value::Integer(q));
+        }
+    }
+    #[cfg(feature = "bigint")]
+    if let (Some(a), Some(b)) = (a.as_big(), b.as_big()) {
+        if let Some((q, _)) = a.div_rem(&b) {
+            return Ok(Number::from_big(q).into_expr());
+        }
+    }
+    Err(EvalError::IntegerOverflow)
+}
+
+fn exact_to_inexact(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Float(Number::from_expr(&args[0])?.as_f64()))
+}
+
+fn is_exact(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Bool(!matches!(
+        Number::from_expr(&args[0])?,
+        Number::Float(_)
+    )))
+}
+
+// Numbers are compared exactly where both are exact, so 1/3 is less than
+// 33333333333333333/100000000000000000 even though as floats they're
+// equal. Any comparison with NaN is false.
+fn compare(args: &[Value], holds: fn(Ordering) -> bool) -> Result<Value, EvalError> {
+    let numbers = args
+        .iter()
+        .map(Number::from_expr)
+        .collect::<Result<Vec<_>, _>>()?;
+    if numbers.is_empty() {
+        return Err(EvalError::ArityMismatch {
+            expected: 1,
+            found: 0,
+        });
+    }
+    let holds = numbers.windows(2).all(|pair| {
+        let (a, b) = (&pair[0], &pair[1]);
+        if let (Some(x), Some(y)) = (a.as_rational(), b.as_rational()) {
+            return holds(x.cmp(&y));
+        }
+        #[cfg(feature = "bigint")]
+        if let (Some(x), Some(y)) = (a.as_big(), b.as_big()) {
+            return holds(x.cmp(&y));
+        }
+        a.as_f64().partial_cmp(&b.as_f64()).is_some_and(holds)
+    });
+    Ok(Value::Bool(holds))
+}
+
+fn num_eq(args: &[Value]) -> Result<Value, EvalError> {
+    compare(args, Ordering::is_eq)
+}
+
+fn lt(args: &[Value]) -> Result<Value, EvalError> {
+    compare(args, Ordering::is_lt)
+}
+
+fn gt(args: &[Value]) -> Result<Value, EvalError> {
+    compare(args, Ordering::is_gt)
+}
+
+fn le(args: &[Value]) -> Result<Value, EvalError> {
+    compare(args, Ordering::is_le)
+}
+
+fn ge(args: &[Value]) -> Result<Value, EvalError> {
+    compare(args, Ordering::is_ge)
+}
+
+fn is_eq(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 2)?;
+    Ok(Value::Bool(args[0].is_eq(&args[1])))
+}
+
+fn is_eqv(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 2)?;
+    Ok(Value::Bool(args[0].is_eqv(&args[1])))
+}
+
+fn is_equal(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 2)?;
+    Ok(Value::Bool(args[0].is_equal(&args[1])))
+}
+
+// Lists are stored as vectors, so nil and () are both the empty list.
+// Consing onto anything but a list makes an improper list, a Pair, which
+// the list functions other than car and cdr don't accept.
+fn list_items(expr: &Value) -> Result<&[Value], EvalError> {
+    match expr {
+        Value::Nil => Ok(&[]),
+        Value::List(items) => Ok(items),
+        other => Err(EvalError::TypeMismatch {
+            expected: "list",
+            found: other.clone(),
+        }),
+    }
+}
+
+fn non_empty(expr: &Value) -> Result<&[Value], EvalError> {
+    match list_items(expr)? {
+        [] => Err(EvalError::TypeMismatch {
+            expected: "pair",
+            found: expr.clone(),
+        }),
+        items => Ok(items),
+    }
+}
+
+fn cons(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 2)?;
+    Ok(Value::cons(args[0].clone(), args[1].clone()))
+}
+
+fn car(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    match &args[0] {
+        Value::Pair(car, _) => Ok((**car).clone()),
+        list => Ok(non_empty(list)?[0].clone()),
+    }
+}
+
+fn cdr(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    match &args[0] {
+        Value::Pair(_, cdr) => Ok((**cdr).clone()),
+        list => Ok(Value::List(non_empty(list)?[1..].to_vec())),
+    }
+}
+
+fn list(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::List(args.to_vec()))
+}
+
+fn length(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Integer(list_items(&args[0])?.len() as i64))
+}
+
+// As in Scheme the last argument can be anything, and becomes the tail of
+// the result.
+fn append(args: &[Value]) -> Result<Value, EvalError> {
+    let Some((last, init)) = args.split_last() else {
+        return Ok(Value::List(Vec::new()));
+    };
+    let lists = init.iter().map(list_items).collect::<Result<Vec<_>, _>>()?;
+    let len: usize = lists.iter().map(|items| items.len()).sum::<usize>()
+        + list_items(last).map_or(0, <[_]>::len);
+    limits::allocate(len.saturating_mul(size_of::<Value>()))?;
+    let mut items = Vec::with_capacity(len);
+    for list in lists {
+        items.extend_from_slice(list);
+    }
+    match last {
+        Value::Nil | Value::List(_) => {
+            items.extend_from_slice(list_items(last)?);
+            Ok(Value::List(items))
+        }
+        tail => Ok(items
+            .into_iter()
+            .rev()
+            .fold(tail.clone(), |cdr, car| Value::cons(car, cdr))),
+    }
+}
+
+// (apply f args... list) calls f with args followed by the items of list.
+fn apply_builtin(args: &[Value]) -> Result<Value, EvalError> {
+    let [func, rest @ .., last] = args else {
+        return Err(EvalError::ArityMismatch {
+            expected: 2,
+            found: args.len(),
+        });
+    };
+    let mut call_args = rest.to_vec();
+    call_args.extend_from_slice(list_items(last)?);
+    apply(func, call_args)
+}
+
+// (values x) is just x; any other number of values is kept together for
+// call-with-values or let-values to take apart.
+fn values(args: &[Value]) -> Result<Value, EvalError> {
+    match args {
+        [value] => Ok(value.clone()),
+        _ => Ok(Value::Values(args.into())),
+    }
+}
+
+// (call-with-values producer consumer) calls consumer with the values
+// producer returns when called with none.
+fn call_with_values(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 2)?;
+    apply(&args[1], apply(&args[0], vec![])?.into_values())
+}
+
+// The function and lists of (map f lists...) and the like, where f is
+// called with an item from each list and the shortest list decides how
+// many calls there are. They loop here rather than recurse in the
+// language, so a long list nests evaluation no deeper than one call of f.
+fn lists_arg(args: &[Value], skip: usize) -> Result<(&Value, Vec<&[Value]>, usize), EvalError> {
+    if args.len() < skip + 2 {
+        return Err(EvalError::ArityMismatch {
+            expected: skip + 2,
+            found: args.len(),
+        });
+    }
+    let lists = args[skip + 1..]
+        .iter()
+        .map(list_items)
+        .collect::<Result<Vec<_>, _>>()?;
+    let len = lists.iter().map(|list| list.len()).min().unwrap_or(0);
+    Ok((&args[0], lists, len))
+}
+
+fn nth(lists: &[&[Value]], i: usize) -> Vec<Value> {
+    lists.iter().map(|list| list[i].clone()).collect()
+}
+
+// (map f lists...) is the list of f's results.
+fn map(args: &[Value]) -> Result<Value, EvalError> {
+    let (func, lists, len) = lists_arg(args, 0)?;
+    let items = (0..len)
+        .map(|i| apply(func, nth(&lists, i)))
+        .collect::<Result<_, _>>()?;
+    Ok(Value::List(items))
+}
+
+// (pmap f lists...) is map with the calls spread over rayon's threads, so
+// f shouldn't depend on the order they run in. They write to the caller's
+// ports and their steps together count against the caller's, but a hook
+// or trace doesn't hear about them.
+#[cfg(feature = "sync")]
+fn pmap(args: &[Value]) -> Result<Value, EvalError> {
+    use rayon::prelude::*;
+
+    let (func, lists, len) = lists_arg(args, 0)?;
+    let io = io::current();
+    let budget = limits::fork();
+    let (results, tracked): (Vec<_>, Vec<_>) = (0..len)
+        .into_par_iter()
+        .map(|i| {
+            let mut budget = budget.clone();
+            gc::tracking(|| {
+                io::with_io(io.clone(), || {
+                    limits::with_budget(&mut budget, || apply(func, nth(&lists, i)))
+                })
+            })
+        })
+        .unzip();
+    tracked.into_iter().for_each(gc::adopt);
+    limits::join(budget);
+    let items = results.into_iter().collect::<Result<_, _>>()?;
+    Ok(Value::List(items))
+}
+
+// (for-each f lists...) calls f for its effects and returns ().
+fn for_each(args: &[Value]) -> Result<Value, EvalError> {
+    let (func, lists, len) = lists_arg(args, 0)?;
+    for i in 0..len {
+        apply(func, nth(&lists, i))?;
+    }
+    Ok(Value::Nil)
+}
+
+// (filter pred list) keeps the items for which pred is true.
+fn filter(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 2)?;
+    let mut kept = Vec::new();
+    for item in list_items(&args[1])? {
+        if is_truthy(&apply(&args[0], vec![item.clone()])?) {
+            kept.push(item.clone());
+        }
+    }
+    Ok(Value::List(kept))
+}
+
+// (fold-left f init lists...) calls (f acc items...) from the first
+// items on, and (fold-right f init lists...) calls (f items... acc) from
+// the last, each time with acc the result so far.
+fn fold_left(args: &[Value]) -> Result<Value, EvalError> {
+    let (func, lists, len) = lists_arg(args, 1)?;
+    (0..len).try_fold(args[1].clone(), |acc, i| {
+        let mut call_args = vec![acc];
+        call_args.extend(nth(&lists, i));
+        apply(func, call_args)
+    })
+}
+
+fn fold_right(args: &[Value]) -> Result<Value, EvalError> {
+    let (func, lists, len) = lists_arg(args, 1)?;
+    (0..len).rev().try_fold(args[1].clone(), |acc, i| {
+        let mut call_args = nth(&lists, i);
+        call_args.push(acc);
+        apply(func, call_args)
+    })
+}
+
+// (range end), (range start end) or (range start end step): the integers
+// from start, 0 by default, up to but not including end, counting by
+// step, which may be negative but not 0.
+fn range(args: &[Value]) -> Result<Value, EvalError> {
+    let integer = |value: &Value| match value {
+        Value::Integer(i) => Ok(*i),
+        other => Err(EvalError::TypeMismatch {
+            expected: "integer",
+            found: other.clone(),
+        }),
+    };
+    let (start, end, step) = match args {
+        [end] => (0, integer(end)?, 1),
+        [start, end] => (integer(start)?, integer(end)?, 1),
+        [start, end, step] => (integer(start)?, integer(end)?, integer(step)?),
+        _ => {
+            return Err(EvalError::ArityMismatch {
+                expected: 3,
+                found: args.len(),
+            })
+        }
+    };
+    if step == 0 {
+        return Err(EvalError::TypeMismatch {
+            expected: "non-zero step",
+            found: args[2].clone(),
+        });
+    }
+    let (from, to, by) = (i128::from(start), i128::from(end), i128::from(step));
+    let len = if by > 0 {
+        (to - from + by - 1) / by
+    } else {
+        (from - to - by - 1) / -by
+    };
+    let len = usize::try_from(len.max(0)).unwrap_or(usize::MAX);
+    limits::allocate(len.saturating_mul(size_of::<Value>()))?;
+    let mut items = Vec::with_capacity(len);
+    let mut i = start;
+    while (step > 0 && i < end) || (step < 0 && i > end) {
+        items.push(Value::Integer(i));
+        match i.checked_add(step) {
+            Some(next) => i = next,
+            None => break,
+        }
+    }
+    Ok(Value::List(items))
+}
+
+fn is_null(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Bool(
+        list_items(&args[0]).is_ok_and(<[Value]>::is_empty),
+    ))
+}
+
+fn is_pair(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Bool(
+        matches!(args[0], Value::Pair(..)) || non_empty(&args[0]).is_ok(),
+    ))
+}
+
+fn is_number(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Bool(Number::from_expr(&args[0]).is_ok()))
+}
+
+fn is_symbol(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Bool(matches!(args[0], Value::Symbol(_))))
+}
+
+fn is_keyword(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Bool(matches!(args[0], Value::Keyword(_))))
+}
+
+fn is_char(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Bool(matches!(args[0], Value::Char(_))))
+}
+
+fn is_vector(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Bool(matches!(args[0], Value::Vector(_))))
+}
+
+fn vector(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Vector(args.to_vec()))
+}
+
+fn vector_items(value: &Value) -> Result<&[Value], EvalError> {
+    match value {
+        Value::Vector(items) => Ok(items),
+        other => Err(EvalError::TypeMismatch {
+            expected: "vector",
+            found: other.clone(),
+        }),
+    }
+}
+
+fn vector_length(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Integer(vector_items(&args[0])?.len() as i64))
+}
+
+fn vector_ref(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 2)?;
+    let items = vector_items(&args[0])?;
+    let item = match args[1] {
+        Value::Integer(i) => usize::try_from(i).ok().and_then(|i| items.get(i)),
+        _ => None,
+    };
+    item.cloned().ok_or_else(|| EvalError::TypeMismatch {
+        expected: "vector index",
+        found: args[1].clone(),
+    })
+}
+
+fn is_map(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Bool(matches!(args[0], Value::Map(_))))
+}
+
+// (map-get map key default) gives default, or () without one, when the
+// key isn't there. A key written twice finds its first value.
+fn map_get(args: &[Value]) -> Result<Value, EvalError> {
+    let (map, key, default) = match args {
+        [map, key] => (map, key, Value::Nil),
+        [map, key, default] => (map, key, default.clone()),
+        _ => {
+            return Err(EvalError::ArityMismatch {
+                expected: 2,
+                found: args.len(),
+            })
+        }
+    };
+    let Value::Map(entries) = map else {
+        return Err(EvalError::TypeMismatch {
+            expected: "map",
+            found: map.clone(),
+        });
+    };
+    Ok(entries
+        .iter()
+        .find(|(k, _)| k.is_equal(key))
+        .map_or(default, |(_, v)| v.clone()))
+}
+
+fn make_atom(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Atom(Atom::new(args[0].clone())))
+}
+
+fn is_atom(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Bool(matches!(args[0], Value::Atom(_))))
+}
+
+fn atom_arg(value: &Value) -> Result<&Atom, EvalError> {
+    match value {
+        Value::Atom(atom) => Ok(atom),
+        other => Err(EvalError::TypeMismatch {
+            expected: "atom",
+            found: other.clone(),
+        }),
+    }
+}
+
+fn deref(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(atom_arg(&args[0])?.get())
+}
+
+// Returns the new value.
+fn reset(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 2)?;
+    atom_arg(&args[0])?.set(args[1].clone());
+    Ok(args[1].clone())
+}
+
+// (swap! atom f args...) sets atom to (f value args...) and returns it.
+// f runs without the atom locked, so it may read the atom itself, and the
+// result is stored with compare_and_set. If the atom changed in the
+// meantime, on another thread or in f, f is called again with its new
+// value, so f shouldn't have side effects.
+fn swap(args: &[Value]) -> Result<Value, EvalError> {
+    let [atom, func, rest @ ..] = args else {
+        return Err(EvalError::ArityMismatch {
+            expected: 2,
+            found: args.len(),
+        });
+    };
+    let atom = atom_arg(atom)?;
+    loop {
+        let current = atom.get();
+        let mut call_args = vec![current.clone()];
+        call_args.extend_from_slice(rest);
+        let value = apply(func, call_args)?;
+        if atom.compare_and_set(&current, value.clone()) {
+            return Ok(value);
+        }
+    }
+}
+
+// (error message irritants...) raises a new error object.
+fn error(args: &[Value]) -> Result<Value, EvalError> {
+    match args {
+        [Value::String(message), irritants @ ..] => Err(EvalError::Raised(Value::error(
+            message.clone(),
+            irritants.to_vec(),
+        ))),
+        [other, ..] => Err(EvalError::TypeMismatch {
+            expected: "string",
+            found: other.clone(),
+        }),
+        [] => Err(EvalError::ArityMismatch {
+            expected: 1,
+            found: 0,
+        }),
+    }
+}
+
+// Any value can be raised, not only error objects.
+fn raise(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Err(EvalError::Raised(args[0].clone()))
+}
+
+fn is_error_object(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Bool(matches!(args[0], Value::Error(_))))
+}
+
+fn error_object(value: &Value) -> Result<&ErrorObject, EvalError> {
+    match value {
+        Value::Error(error) => Ok(error),
+        other => Err(EvalError::TypeMismatch {
+            expected: "error object",
+            found: other.clone(),
+        }),
+    }
+}
+
+fn error_object_message(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::String(error_object(&args[0])?.message.clone()))
+}
+
+fn error_object_irritants(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::List(error_object(&args[0])?.irritants.clone()))
+}
+
+// (with-exception-handler handler thunk) calls thunk, and if it raises or
+// fails, returns what handler gives for the error instead. Running into a
+// limit, being cancelled or exiting isn't handled, as with try.
+fn with_exception_handler(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 2)?;
+    match apply(&args[1], vec![]) {
+        Err(error @ (EvalError::LimitExceeded(_) | EvalError::Cancelled | EvalError::Exit(_))) => {
+            Err(error)
+        }
+        Err(error) => apply(&args[0], vec![error.into_value()]),
+        result => result,
+    }
+}
+
+// (exit) and (exit #t) end the program with status 0, (exit #f) with 1
+// and (exit n) with n. The host decides what ending it means: the command
+// line exits the process, while eval_str just fails with EvalError::Exit.
+fn exit(args: &[Value]) -> Result<Value, EvalError> {
+    let status = match args {
+        [] | [Value::Bool(true)] => 0,
+        [Value::Bool(false)] => 1,
+        [Value::Integer(n)] => i32::try_from(*n).map_err(|_| EvalError::IntegerOverflow)?,
+        [found] => {
+            return Err(EvalError::TypeMismatch {
+                expected: "integer or boolean",
+                found: found.clone(),
+            })
+        }
+        _ => {
+            return Err(EvalError::ArityMismatch {
+                expected: 1,
+                found: args.len(),
+            })
+        }
+    };
+    Err(EvalError::Exit(status))
+}
+
+fn char_to_integer(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    match args[0] {
+        Value::Char(c) => Ok(Value::Integer(c as i64)),
+        _ => Err(EvalError::TypeMismatch {
+            expected: "character",
+            found: args[0].clone(),
+        }),
+    }
+}
+
+// Surrogates and numbers past U+10FFFF aren't characters.
+fn integer_to_char(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    let code = match args[0] {
+        Value::Integer(i) => u32::try_from(i).ok().and_then(char::from_u32),
+        _ => None,
+    };
+    code.map(Value::Char)
+        .ok_or_else(|| EvalError::TypeMismatch {
+            expected: "character code",
+            found: args[0].clone(),
+        })
+}
+
+fn string_arg(value: &Value) -> Result<&str, EvalError> {
+    match value {
+        Value::String(s) => Ok(s),
+        other => Err(EvalError::TypeMismatch {
+            expected: "string",
+            found: other.clone(),
+        }),
+    }
+}
+
+fn string_append(args: &[Value]) -> Result<Value, EvalError> {
+    let strings = args.iter().map(string_arg).collect::<Result<Vec<_>, _>>()?;
+    let len = strings.iter().map(|s| s.len()).sum();
+    limits::allocate(len)?;
+    Ok(Value::String(strings.concat()))
+}
+
+// Lengths and indices count characters, not bytes.
+fn string_length(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Integer(string_arg(&args[0])?.chars().count() as i64))
+}
+
+// (substring s start end) is the characters from start up to but not
+// including end, which defaults to the end of s.
+fn substring(args: &[Value]) -> Result<Value, EvalError> {
+    let (s, start, end) = match args {
+        [s, start] => (string_arg(s)?, start, None),
+        [s, start, end] => (string_arg(s)?, start, Some(end)),
+        _ => {
+            return Err(EvalError::ArityMismatch {
+                expected: 3,
+                found: args.len(),
+            })
+        }
+    };
+    let len = s.chars().count();
+    let index = |value: &Value, min: usize| match value {
+        Value::Integer(i) => usize::try_from(*i)
+            .ok()
+            .filter(|i| (min..=len).contains(i))
+            .ok_or_else(|| EvalError::TypeMismatch {
+                expected: "string index",
+                found: value.clone(),
+            }),
+        other => Err(EvalError::TypeMismatch {
+            expected: "string index",
+            found: other.clone(),
+        }),
+    };
+    let start = index(start, 0)?;
+    let end = end.map_or(Ok(len), |end| index(end, start))?;
+    Ok(Value::String(s.chars().skip(start).take(end - start).collect()))
+}
+
+fn string_to_symbol(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    let name = string_arg(&args[0])?;
+    limits::allocate(name.len())?;
+    Ok(Value::Symbol(name.into()))
+}
+
+fn symbol_to_string(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    match &args[0] {
+        Value::Symbol(name) => Ok(Value::String(name.as_str().into())),
+        other => Err(EvalError::TypeMismatch {
+            expected: "symbol",
+            found: other.clone(),
+        }),
+    }
+}
+
+fn number_to_string(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Number::from_expr(&args[0])?;
+    Ok(Value::String(args[0].to_string()))
+}
+
+// Reads the number the way the reader does, including rationals and
+// floats, and gives #f for a string that isn't exactly one number.
+fn string_to_number(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    let number = crate::parser::read(string_arg(&args[0])?)
+        .ok()
+        .map(|expr| Value::from(&expr))
+        .filter(|value| Number::from_expr(value).is_ok());
+    Ok(number.unwrap_or(Value::Bool(false)))
+}
+
+// (split s) splits at runs of whitespace; (split s sep) at every sep,
+// keeping the empty strings between separators that touch.
+fn split(args: &[Value]) -> Result<Value, EvalError> {
+    let parts: Vec<&str> = match args {
+        [s] => string_arg(s)?.split_whitespace().collect(),
+        [s, sep] => match string_arg(sep)? {
+            "" => {
+                return Err(EvalError::TypeMismatch {
+                    expected: "non-empty separator",
+                    found: sep.clone(),
+                })
+            }
+            sep => string_arg(s)?.split(sep).collect(),
+        },
+        _ => {
+            return Err(EvalError::ArityMismatch {
+                expected: 2,
+                found: args.len(),
+            })
+        }
+    };
+    Ok(Value::List(
+        parts
+            .into_iter()
+            .map(|part| Value::String(part.into()))
+            .collect(),
+    ))
+}
+
+// (string-join strings) puts a space between the strings, undoing split,
+// and (string-join strings sep) puts sep between them. The result is
+// built once, so joining the pieces of a loop is linear where repeated
+// string-append is quadratic.
+fn string_join(args: &[Value]) -> Result<Value, EvalError> {
+    let (strings, sep) = match args {
+        [strings] => (strings, " "),
+        [strings, sep] => (strings, string_arg(sep)?),
+        _ => {
+            return Err(EvalError::ArityMismatch {
+                expected: 2,
+                found: args.len(),
+            })
+        }
+    };
+    let strings = list_items(strings)?
+        .iter()
+        .map(string_arg)
+        .collect::<Result<Vec<_>, _>>()?;
+    let len = strings.iter().map(|s| s.len()).sum::<usize>()
+        + sep.len() * strings.len().saturating_sub(1);
+    limits::allocate(len)?;
+    Ok(Value::String(strings.join(sep)))
+}
+
+// (format template args...) fills in template: ~a writes the next
+// argument as display would, ~s as the printer would, ~% is a newline and
+// ~~ a tilde. There must be exactly one argument for each ~a and ~s.
+fn format(args: &[Value]) -> Result<Value, EvalError> {
+    let [template, values @ ..] = args else {
+        return Err(EvalError::ArityMismatch {
+            expected: 1,
+            found: 0,
+        });
+    };
+    let template = string_arg(template)?;
+    let mut result = String::new();
+    let mut values = values.iter();
+    let mut used = 0;
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '~' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(directive @ ('a' | 's')) => {
+                used += 1;
+                let Some(value) = values.next() else {
+                    continue;
+                };
+                match directive {
+                    'a' => result.push_str(&display_string(value)),
+                    _ => result.push_str(&value.to_string()),
+                }
+            }
+            Some('%') => result.push('\n'),
+            Some('~') => result.push('~'),
+            other => {
+                return Err(EvalError::TypeMismatch {
+                    expected: "format directive",
+                    found: Value::String(other.map_or("~".into(), |c| ['~', c].iter().collect())),
+                })
+            }
+        }
+    }
+    if used != args.len() - 1 {
+        return Err(EvalError::ArityMismatch {
+            expected: used + 1,
+            found: args.len(),
+        });
+    }
+    Ok(Value::String(result))
+}
+
+// Strings, characters and symbols are written as they are, without
+// quotes, #\ or bars, as display does in Scheme; anything else is written
+// the way the printer would.
+fn display_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Char(c) => c.to_string(),
+        Value::Symbol(s) => s.to_string(),
+        other => other.to_string(),
+    }
+}
+
+// Ports are opened, read and written through the I/O backend of the
+// interpreter running the code, which by default is the process's own
+// standard input and output and file system.
+fn open_input_file(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    io::open_input_file(string_arg(&args[0])?).map(Value::Port)
+}
+
+// Creates the file, or empties it if it exists.
+fn open_output_file(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    io::open_output_file(string_arg(&args[0])?).map(Value::Port)
+}
+
+// String ports read from and write to memory, without the backend.
+fn open_input_string(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Port(Port::input_string(string_arg(&args[0])?)))
+}
+
+fn open_output_string(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 0)?;
+    Ok(Value::Port(Port::output_string()))
+}
+
+fn get_output_string(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    let port = port_arg(&args[0])?;
+    port.output_text()
+        .map(Value::String)
+        .ok_or_else(|| EvalError::TypeMismatch {
+            expected: "open string output port",
+            found: args[0].clone(),
+        })
+}
+
+fn port_arg(value: &Value) -> Result<&Port, EvalError> {
+    match value {
+        Value::Port(port) => Ok(port),
+        other => Err(EvalError::TypeMismatch {
+            expected: "port",
+            found: other.clone(),
+        }),
+    }
+}
+
+// Closing a port again does nothing.
+fn close_port(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    port_arg(&args[0])?.close();
+    Ok(Value::Nil)
+}
+
+fn is_input_port(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Bool(matches!(&args[0], Value::Port(port) if port.is_input())))
+}
+
+fn is_output_port(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Bool(matches!(&args[0], Value::Port(port) if !port.is_input())))
+}
+
+fn current_input_port(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 0)?;
+    Ok(Value::Port(io::current_input()))
+}
+
+fn current_output_port(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 0)?;
+    Ok(Value::Port(io::current_output()))
+}
+
+// The port an I/O builtin was given after its first `before` arguments,
+// or the current one when it was left out.
+fn port_or(args: &[Value], before: usize, current: fn() -> Port) -> Result<Port, EvalError> {
+    match &args[before.min(args.len())..] {
+        [] => Ok(current()),
+        [port] => port_arg(port).cloned(),
+        _ => Err(EvalError::ArityMismatch {
+            expected: before + 1,
+            found: args.len(),
+        }),
+    }
+}
+
+// (read-line port) gives the next line without its line ending, or #f
+// when the input is used up.
+fn read_line(args: &[Value]) -> Result<Value, EvalError> {
+    let line = port_or(args, 0, io::current_input)?.read_line()?;
+    Ok(line.map_or(Value::Bool(false), Value::String))
+}
+
+fn output_arg(args: &[Value]) -> Result<(&Value, Port), EvalError> {
+    match args.first() {
+        Some(value) => Ok((value, port_or(args, 1, io::current_output)?)),
+        None => Err(EvalError::ArityMismatch {
+            expected: 1,
+            found: 0,
+        }),
+    }
+}
+
+// (write value port) writes value as the printer does, so the reader can
+// read it back.
+fn write(args: &[Value]) -> Result<Value, EvalError> {
+    let (value, port) = output_arg(args)?;
+    port.write_str(&value.to_string())?;
+    Ok(Value::Nil)
+}
+
+fn display(args: &[Value]) -> Result<Value, EvalError> {
+    let (value, port) = output_arg(args)?;
+    port.write_str(&display_string(value))?;
+    Ok(Value::Nil)
+}
+
+fn newline(args: &[Value]) -> Result<Value, EvalError> {
+    port_or(args, 0, io::current_output)?.write_str("\n")?;
+    Ok(Value::Nil)
+}
+
+fn is_generator(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Bool(matches!(args[0], Value::Generator(_))))
+}
+
+fn generator_arg(value: &Value) -> Result<&Generator, EvalError> {
+    match value {
+        Value::Generator(generator) => Ok(generator),
+        other => Err(EvalError::TypeMismatch {
+            expected: "generator",
+            found: other.clone(),
+        }),
+    }
+}
+
+// (next g default) runs g to its next yield and gives the value yielded,
+// or default, () without one, once g has finished.
+fn next(args: &[Value]) -> Result<Value, EvalError> {
+    let (generator, default) = match args {
+        [generator] => (generator, Value::Nil),
+        [generator, default] => (generator, default.clone()),
+        _ => {
+            return Err(EvalError::ArityMismatch {
+                expected: 2,
+                found: args.len(),
+            })
+        }
+    };
+    Ok(generator_arg(generator)?.resume()?.unwrap_or(default))
+}
+
+fn is_generator_done(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    Ok(Value::Bool(generator_arg(&args[0])?.is_done()))
+}
+
+// Everything left for g to yield, which never ends if g doesn't.
+fn generator_to_list(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    let generator = generator_arg(&args[0])?;
+    let mut items = Vec::new();
+    while let Some(item) = generator.resume()? {
+        items.push(item);
+    }
+    Ok(Value::List(items))
+}
+
+// (gc) frees the frames and atoms kept alive only by cycles and returns
+// how many there were. Collections also happen on their own as the heap
+// grows.
+fn collect_garbage(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 0)?;
+    Ok(Value::Integer(gc::collect_garbage() as i64))
+}
+
+// (trace f) makes every later call to f, and the value it returns, print
+// to stderr. (untrace f) stops that; untracing a procedure that isn't
+// traced does nothing.
+fn trace(args: &[Value]) -> Result<Value, EvalError> {
+    hooks::trace(procedure(args)?.clone());
+    Ok(Value::Nil)
+}
+
+fn untrace(args: &[Value]) -> Result<Value, EvalError> {
+    hooks::untrace(procedure(args)?);
+    Ok(Value::Nil)
+}
+
+// (profile thunk) calls thunk, writes a table of how long the calls it
+// made took to stderr and returns what thunk did. Nothing else is told
+// about those calls, not even a debugger.
+#[cfg(feature = "std")]
+fn profile(args: &[Value]) -> Result<Value, EvalError> {
+    arity(args, 1)?;
+    let profile = Profile::new();
+    let result = profile.during(|| apply(&args[0], vec![]));
+    io::current_error().write_str(&profile.table())?;
+    result
+}
+
+// (disassemble f) writes f's bytecode to the current output port. A
+// procedure the tree-walker made is compiled for the listing.
+fn disassemble(args: &[Value]) -> Result<Value, EvalError> {
+    let function = match procedure(args)? {
+        Value::Compiled(closure) => Some(closure.function.clone()),
+        Value::Closure(closure) => {
+            let lambda = Expr::Lambda(Box::new(closure.lambda.clone()));
+            let compiled = compile::compile(&lambda).ok();
+            compiled.map(|top| top.functions[0].clone())
+        }
+        _ => None,
+    };
+    let function = function.ok_or_else(|| EvalError::TypeMismatch {
+        expected: "procedure the VM can compile",
+        found: args[0].clone(),
+    })?;
+    io::current_output().write_str(&function.to_string())?;
+    Ok(Value::Nil)
+}
+
+fn procedure(args: &[Value]) -> Result<&Value, EvalError> {
+    arity(args, 1)?;
+    if !args[0].is_procedure() {
+        return Err(EvalError::TypeMismatch {
+            expected: "procedure",
+            found: args[0].clone(),
+        });
+    }
+    Ok(&args[0])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{eval::eval, parser::Expr};
+    use alloc::{borrow::ToOwned, format};
+
+    fn call(op: &str, args: Vec<Expr>) -> Expr {
+        let mut items = vec![Expr::Symbol(op.into())];
+        items.extend(args);
+        Expr::List(items)
+    }
+
+    fn int_exprs(values: &[i64]) -> Vec<Expr> {
+        values.iter().copied().map(Expr::Integer).collect()
+    }
+
+    fn ints(values: &[i64]) -> Vec<Value> {
+        values.iter().copied().map(Value::Integer).collect()
+    }
+
+    #[test]
+    fn arithmetic_test() {
+        let mut env = global_env();
+        assert_eq!(
+            eval(&call("+", int_exprs(&[1, 2, 3])), &mut env),
+            Ok(Value::Integer(6))
+        );
+        assert_eq!(eval(&call("+", vec![]), &mut env), Ok(Value::Integer(0)));
+        assert_eq!(
+            eval(&call("-", int_exprs(&[5])), &mut env),
+            Ok(Value::Integer(-5))
+        );
+        assert_eq!(
+            eval(&call("-", int_exprs(&[10, 3, 2])), &mut env),
+            Ok(Value::Integer(5))
+        );
+        let negated = eval(&call("-", vec![Expr::Float(0.0)]), &mut env);
+        assert!(matches!(negated, Ok(Value::Float(x)) if x == 0.0 && x.is_sign_negative()));
+        assert_eq!(
+            eval(&call("-", vec![Expr::Float(-1.5)]), &mut env),
+            Ok(Value::Float(1.5))
+        );
+        assert_eq!(
+            eval(&call("*", int_exprs(&[2, 3, 4])), &mut env),
+            Ok(Value::Integer(24))
+        );
+        assert_eq!(
+            eval(&call("/", int_exprs(&[8, 2])), &mut env),
+            Ok(Value::Integer(4))
+        );
+        assert_eq!(
+            eval(&call("quotient", int_exprs(&[7, 2])), &mut env),
+            Ok(Value::Integer(3))
+        );
+        assert_eq!(
+            eval(
+                &call("*", vec![Expr::Integer(2), call("+", int_exprs(&[1, 2]))]),
+                &mut env
+            ),
+            Ok(Value::Integer(6))
+        );
+        assert_eq!(
+            eval(&call("/", int_exprs(&[1, 0])), &mut env),
+            Err(EvalError::DivisionByZero)
+        );
+        #[cfg(not(feature = "bigint"))]
+        assert_eq!(
+            eval(&call("+", int_exprs(&[i64::MAX, 1])), &mut env),
+            Err(EvalError::IntegerOverflow)
+        );
+        assert_eq!(
+            add(&[Value::Integer(1), Value::Bool(true)]),
+            Err(EvalError::TypeMismatch {
+                expected: "number",
+                found: Value::Bool(true)
+            })
+        );
+    }
+
+    #[test]
+    fn float_arithmetic_test() {
+        assert_eq!(
+            add(&[Value::Integer(1), Value::Float(0.5)]),
+            Ok(Value::Float(1.5))
+        );
+        assert_eq!(
+            div(&[Value::Float(1.), Value::Integer(4)]),
+            Ok(Value::Float(0.25))
+        );
+        assert_eq!(
+            div(&[Value::Float(1.), Value::Float(0.)]),
+            Ok(Value::Float(f64::INFINITY))
+        );
+    }
+
+    #[cfg(feature = "bigint")]
+    #[test]
+    fn bigint_arithmetic_test() {
+        let mut env = global_env();
+        let mut run = |source: &str| {
+            eval(&crate::parser::read(source).unwrap(), &mut env).map(|v| v.to_string())
+        };
+        assert_eq!(
+            run("(+ 9223372036854775807 1)"),
+            Ok("9223372036854775808".to_owned())
+        );
+        assert_eq!(
+            run("(* 4294967296 4294967296 -4294967296)"),
+            Ok("-79228162514264337593543950336".to_owned())
+        );
+        assert_eq!(
+            run("(- 100000000000000000000 99999999999999999999)"),
+            Ok("1".to_owned())
+        );
+        assert_eq!(
+            run("(/ -9223372036854775808 -1)"),
+            Ok("9223372036854775808".to_owned())
+        );
+        assert_eq!(run("(+ 1e0 100000000000000000000)"), Ok("1e20".to_owned()));
+        assert_eq!(
+            run("(+ 1/3 100000000000000000000)"),
+            Err(EvalError::RationalOverflow)
+        );
+        assert_eq!(
+            run("(/ 100000000000000000000 3)"),
+            Err(EvalError::RationalOverflow)
+        );
+        assert_eq!(
+            run("(/ 200000000000000000000 300000000000000000000)"),
+            Ok("2/3".to_owned())
+        );
+        assert_eq!(
+            run("(/ -100000000000000000000 300000000000000000000)"),
+            Ok("-1/3".to_owned())
+        );
+        assert_eq!(
+            run("(/ 3 100000000000000000000)"),
+            Err(EvalError::RationalOverflow)
+        );
+        assert_eq!(
+            run("(quotient -9223372036854775808 -1)"),
+            Ok("9223372036854775808".to_owned())
+        );
+        assert_eq!(
+            run("(quotient 100000000000000000000 -7)"),
+            Ok("-14285714285714285714".to_owned())
+        );
+        assert_eq!(
+            run("(quotient 100000000000000000000 100000000000000000000)"),
+            Ok("1".to_owned())
+        );
+        assert_eq!(
+            run("(quotient 100000000000000000000 0)"),
+            Err(EvalError::DivisionByZero)
+        );
+        assert_eq!(
+            run("(< 1 100000000000000000000 100000000000000000001)"),
+            Ok("#t".to_owned())
+        );
+        assert_eq!(run("(number? 100000000000000000000)"), Ok("#t".to_owned()));
+        assert_eq!(
+            eval(
+                &crate::parser::read("(- 100000000000000000000 1 99999999999999999999)").unwrap(),
+                &mut env
+            ),
+            Ok(Value::Integer(0))
+        );
+    }
+
+    #[test]
+    fn rational_arithmetic_test() {
+        let mut env = global_env();
+        let mut run = |source: &str| {
+            eval(&crate::parser::read(source).unwrap(), &mut env).map(|v| v.to_string())
+        };
+        assert_eq!(run("(/ 7 2)"), Ok("7/2".to_owned()));
+        assert_eq!(run("(/ 4)"), Ok("1/4".to_owned()));
+        assert_eq!(run("(+ 1/3 1/6)"), Ok("1/2".to_owned()));
+        assert_eq!(run("(* 2/3 3/2)"), Ok("1".to_owned()));
+        assert_eq!(run("(- 1/2 1)"), Ok("-1/2".to_owned()));
+        assert_eq!(run("(+ 1/2 0.25)"), Ok("0.75".to_owned()));
+        assert_eq!(run("(exact->inexact 1/4)"), Ok("0.25".to_owned()));
+        assert_eq!(run("(exact? 1/4)"), Ok("#t".to_owned()));
+        assert_eq!(run("(exact? 0.25)"), Ok("#f".to_owned()));
+        assert_eq!(
+            run("(= 1/3 33333333333333333/100000000000000000)"),
+            Ok("#f".to_owned())
+        );
+        assert_eq!(run("(= 1/2 0.5)"), Ok("#t".to_owned()));
+        assert_eq!(run("(< 1/3 1/2 1)"), Ok("#t".to_owned()));
+        assert_eq!(run("(/ 1/2 0)"), Err(EvalError::DivisionByZero));
+        assert_eq!(
+            run("(* 1/9223372036854775807 1/2)"),
+            Err(EvalError::RationalOverflow)
+        );
+        assert!(run("(quotient 1/2 1)").is_err());
+        #[cfg(not(feature = "bigint"))]
+        assert_eq!(
+            run("(quotient -9223372036854775808 -1)"),
+            Err(EvalError::IntegerOverflow)
+        );
+    }
+
+    #[test]
+    fn comparison_test() {
+        assert_eq!(lt(&ints(&[1, 2, 3])), Ok(Value::Bool(true)));
+        assert_eq!(lt(&ints(&[1, 3, 2])), Ok(Value::Bool(false)));
+        assert_eq!(
+            num_eq(&[Value::Integer(2), Value::Float(2.)]),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(ge(&ints(&[3, 3, 1])), Ok(Value::Bool(true)));
+        assert_eq!(gt(&ints(&[1])), Ok(Value::Bool(true)));
+        assert!(le(&[]).is_err());
+    }
+
+    #[test]
+    fn equality_test() {
+        let mut env = global_env();
+        let cases = [
+            ("(eq? 'a 'a)", [true, true, true]),
+            ("(eq? :k :k)", [true, true, true]),
+            ("(eq? '() ())", [true, true, true]),
+            ("(eq? #\\a #\\a)", [true, true, true]),
+            ("(eq? 2 2)", [true, true, true]),
+            ("(eq? 2.5 2.5)", [false, true, true]),
+            ("(eq? 1/2 1/2)", [false, true, true]),
+            ("(eq? 2 2.0)", [false, false, false]),
+            ("(eq? 0.0 -0.0)", [false, false, false]),
+            ("(eq? \"\" \"\")", [true, true, true]),
+            ("(eq? \"ab\" \"ab\")", [false, false, true]),
+            ("(eq? '(1 (2)) (list 1 (list 2)))", [false, false, true]),
+            ("(eq? '(1 . 2) (cons 1 2))", [false, false, true]),
+            ("(eq? [1 \"a\"] [1 \"a\"])", [false, false, true]),
+            ("(eq? {:a 1 :b 2} {:b 2 :a 1})", [false, false, true]),
+            ("(eq? {:a 1} {:a 2})", [false, false, false]),
+            ("(eq? car car)", [true, true, true]),
+            ("(eq? (atom 1) (atom 1))", [false, false, false]),
+            (
+                "(eq? (try (error \"e\" 1) (catch e e)) (try (error \"e\" 1) (catch e e)))",
+                [false, false, true],
+            ),
+        ];
+        for (source, expected) in cases {
+            for (predicate, expected) in ["eq?", "eqv?", "equal?"].into_iter().zip(expected) {
+                let source = source.replacen("eq?", predicate, 1);
+                let expr = crate::parser::read(&source).unwrap();
+                assert_eq!(eval(&expr, &mut env), Ok(Value::Bool(expected)), "{source}");
+            }
+        }
+        let expr = crate::parser::read(
+            "(begin (define (f) 1) (define a (atom f)) (list (eq? f f) (eq? a a) (eqv? f (deref a))))",
+        )
+        .unwrap();
+        assert_eq!(
+            eval(&expr, &mut env),
+            Ok(Value::List(vec![Value::Bool(true); 3]))
+        );
+        assert!(is_eq(&ints(&[1])).is_err());
+    }
+
+    #[test]
+    fn list_test() {
+        let items = Value::List(ints(&[1, 2, 3]));
+        assert_eq!(
+            cons(&[Value::Integer(0), Value::Nil]),
+            Ok(Value::List(ints(&[0])))
+        );
+        assert_eq!(car(core::slice::from_ref(&items)), Ok(Value::Integer(1)));
+        assert_eq!(
+            cdr(core::slice::from_ref(&items)),
+            Ok(Value::List(ints(&[2, 3])))
+        );
+        assert_eq!(length(core::slice::from_ref(&items)), Ok(Value::Integer(3)));
+        assert_eq!(
+            append(&[items.clone(), Value::Nil, Value::List(ints(&[4]))]),
+            Ok(Value::List(ints(&[1, 2, 3, 4])))
+        );
+        assert_eq!(list(&ints(&[1, 2, 3])), Ok(items));
+        assert!(car(&[Value::Nil]).is_err());
+        let pair = cons(&ints(&[1, 2])).unwrap();
+        assert_eq!(pair.to_string(), "(1 . 2)");
+        assert_eq!(car(core::slice::from_ref(&pair)), Ok(Value::Integer(1)));
+        assert_eq!(cdr(core::slice::from_ref(&pair)), Ok(Value::Integer(2)));
+        assert_eq!(is_pair(core::slice::from_ref(&pair)), Ok(Value::Bool(true)));
+        assert!(length(&[pair]).is_err());
+        assert_eq!(
+            append(&[Value::List(ints(&[1, 2])), Value::Integer(3)])
+                .unwrap()
+                .to_string(),
+            "(1 2 . 3)"
+        );
+        assert_eq!(append(&[]), Ok(Value::List(vec![])));
+    }
+
+    #[test]
+    fn values_test() {
+        let mut env = global_env();
+        let mut run = |source: &str| {
+            eval(&crate::parser::read(source).unwrap(), &mut env).map(|v| v.to_string())
+        };
+        assert_eq!(run("(values 1)"), Ok("1".to_owned()));
+        assert_eq!(run("(values 1 \"a\")"), Ok("1 \"a\"".to_owned()));
+        assert_eq!(run("(values)"), Ok(String::new()));
+        assert_eq!(
+            run("(call-with-values (lambda () (values 1 2 3)) list)"),
+            Ok("(1 2 3)".to_owned())
+        );
+        assert_eq!(
+            run("(call-with-values (lambda () 4) (lambda (x) (* x x)))"),
+            Ok("16".to_owned())
+        );
+        assert_eq!(run("(call-with-values values list)"), Ok("()".to_owned()));
+        assert!(run("(call-with-values (lambda () (values 1 2)) car)").is_err());
+        assert!(run("(call-with-values list)").is_err());
+        // Where one value is kept, several are refused rather than ending
+        // up in data.
+        for source in [
+            "(list (values 1 2))",
+            "(define v (values 1 2))",
+            "(begin (define v 0) (set! v (values)))",
+            "(let ((v (values 1 2))) v)",
+            "[(values 1 2)]",
+            "{:a (values 1 2)}",
+        ] {
+            assert!(
+                matches!(
+                    run(source),
+                    Err(EvalError::TypeMismatch {
+                        expected: "one value",
+                        ..
+                    })
+                ),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn iteration_test() {
+        let mut env = global_env();
+        let mut run = |source: &str| {
+            let mut result = Ok(Value::Nil);
+            for expr in crate::parser::read_all(source).unwrap() {
+                result = eval(&expr, &mut env);
+            }
+            result.map(|v| v.to_string())
+        };
+        assert_eq!(
+            run("(map (lambda (x) (* x x)) '(1 2 3))"),
+            Ok("(1 4 9)".to_owned())
+        );
+        assert_eq!(run("(map + '(1 2 3) '(10 20))"), Ok("(11 22)".to_owned()));
+        assert_eq!(run("(map car ())"), Ok("()".to_owned()));
+        assert_eq!(
+            run("(filter (lambda (x) (< x 3)) '(1 5 2 4))"),
+            Ok("(1 2)".to_owned())
+        );
+        assert_eq!(
+            run("(fold-left cons () '(1 2 3))"),
+            Ok("(((() . 1) . 2) . 3)".to_owned())
+        );
+        assert_eq!(
+            run("(fold-right cons () '(1 2 3))"),
+            Ok("(1 2 3)".to_owned())
+        );
+        assert_eq!(
+            run("(fold-left (lambda (acc a b) (+ acc (* a b))) 0 '(1 2) '(3 4))"),
+            Ok("11".to_owned())
+        );
+        assert_eq!(
+            run("(define seen (atom ()))
+                 (for-each (lambda (x y) (swap! seen (lambda (l) (cons (+ x y) l)))) '(1 2) '(3 4))
+                 (deref seen)"),
+            Ok("(6 4)".to_owned())
+        );
+        assert_eq!(run("(for-each car ())"), Ok("()".to_owned()));
+        assert_eq!(run("(apply + 1 2 '(3 4))"), Ok("10".to_owned()));
+        assert_eq!(run("(apply list ())"), Ok("()".to_owned()));
+        assert_eq!(run("(range 4)"), Ok("(0 1 2 3)".to_owned()));
+        assert_eq!(run("(range 2 5)"), Ok("(2 3 4)".to_owned()));
+        assert_eq!(run("(range 5 0 -2)"), Ok("(5 3 1)".to_owned()));
+        assert_eq!(run("(range 3 1)"), Ok("()".to_owned()));
+        assert_eq!(
+            run("(range 9223372036854775806 9223372036854775807 5)"),
+            Ok("(9223372036854775806)".to_owned())
+        );
+        // However long the list, f is called one at a time.
+        assert_eq!(
+            run("(fold-left + 0 (map (lambda (x) (* 2 x)) (range 100000)))"),
+            Ok("9999900000".to_owned())
+        );
+        assert!(run("(range 1 2 0)").is_err());
+        assert!(run("(range 1.5)").is_err());
+        assert!(run("(map car)").is_err());
+        assert!(run("(map car 1)").is_err());
+        assert!(run("(apply +)").is_err());
+        assert!(run("(apply + 1)").is_err());
+        assert!(run("(filter 1 '(1))").is_err());
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn pmap_test() {
+        let mut env = global_env();
+        let mut run = |source: &str| {
+            let mut result = Ok(Value::Nil);
+            for expr in crate::parser::read_all(source).unwrap() {
+                result = eval(&expr, &mut env);
+            }
+            result.map(|v| v.to_string())
+        };
+        assert_eq!(
+            run(
+                "(define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))
+                 (pmap fib (range 15))"
+            ),
+            Ok("(0 1 1 2 3 5 8 13 21 34 55 89 144 233 377)".to_owned())
+        );
+        assert_eq!(run("(pmap + '(1 2 3) '(10 20))"), Ok("(11 22)".to_owned()));
+        assert_eq!(run("(pmap car ())"), Ok("()".to_owned()));
+        assert_eq!(
+            run("(pmap (lambda (x) (if (= x 3) (raise 'three) x)) (range 8))"),
+            Err(EvalError::Raised(Value::Symbol("three".into())))
+        );
+        assert!(run("(pmap car 1)").is_err());
+        // No update is lost to another call swapping at the same time.
+        assert_eq!(
+            run("(define (spin n) (if (= n 0) n (spin (- n 1))))
+                 (define n (atom 0))
+                 (pmap (lambda (x) (swap! n (lambda (v) (spin 10) (+ v 1)))) (range 1000))
+                 (deref n)"),
+            Ok("1000".to_owned())
+        );
+    }
+
+    #[test]
+    fn predicate_test() {
+        assert_eq!(is_null(&[Value::List(vec![])]), Ok(Value::Bool(true)));
+        assert_eq!(is_null(&[Value::Integer(0)]), Ok(Value::Bool(false)));
+        assert_eq!(is_pair(&[Value::List(ints(&[1]))]), Ok(Value::Bool(true)));
+        assert_eq!(is_pair(&[Value::Nil]), Ok(Value::Bool(false)));
+        assert_eq!(is_number(&[Value::Float(1.)]), Ok(Value::Bool(true)));
+        assert_eq!(
+            is_symbol(&[Value::Symbol("x".into())]),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(is_char(&[Value::Char('a')]), Ok(Value::Bool(true)));
+        assert_eq!(
+            is_keyword(&[Value::Keyword("a".into())]),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            is_keyword(&[Value::Symbol("a".into())]),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(
+            is_char(&[Value::String("a".into())]),
+            Ok(Value::Bool(false))
+        );
+    }
+
+    #[test]
+    fn char_test() {
+        assert_eq!(
+            char_to_integer(&[Value::Char('λ')]),
+            Ok(Value::Integer(0x3bb))
+        );
+        assert_eq!(integer_to_char(&[Value::Integer(65)]), Ok(Value::Char('A')));
+        assert!(integer_to_char(&[Value::Integer(0xd800)]).is_err());
+        assert!(integer_to_char(&[Value::Integer(-1)]).is_err());
+        assert_eq!(display_string(&Value::Char('x')), "x");
+    }
+
+    #[test]
+    fn vector_map_test() {
+        let mut env = global_env();
+        let run = |source: &str, env: &mut Env| {
+            eval(&crate::parser::read(source).unwrap(), env).map(|v| v.to_string())
+        };
+        assert_eq!(
+            run("(vector 1 (vector))", &mut env),
+            Ok("[1 []]".to_owned())
+        );
+        assert_eq!(run("(vector-length [1 2 3])", &mut env), Ok("3".to_owned()));
+        assert_eq!(run("(vector-ref [1 2 3] 2)", &mut env), Ok("3".to_owned()));
+        assert!(run("(vector-ref [1 2 3] 3)", &mut env).is_err());
+        assert!(run("(vector-length '(1))", &mut env).is_err());
+        assert_eq!(run("(vector? [])", &mut env), Ok("#t".to_owned()));
+        assert_eq!(run("(map? [])", &mut env), Ok("#f".to_owned()));
+        assert_eq!(
+            run("(map-get {:a 1 :a 2} :a)", &mut env),
+            Ok("1".to_owned())
+        );
+        assert_eq!(run("(map-get {:a 1} :b)", &mut env), Ok("()".to_owned()));
+        assert_eq!(run("(map-get {:a 1} :b 0)", &mut env), Ok("0".to_owned()));
+        assert!(run("(map-get [] :b)", &mut env).is_err());
+        assert_eq!(
+            run(
+                "(map-get {'(1 2) :list \"s\" :string} (list 1 2))",
+                &mut env
+            ),
+            Ok(":list".to_owned())
+        );
+    }
+
+    #[test]
+    fn atom_test() {
+        let mut env = global_env();
+        let mut run = |source: &str| {
+            let mut result = Ok(Value::Nil);
+            for expr in crate::parser::read_all(source).unwrap() {
+                result = eval(&expr, &mut env);
+            }
+            result.map(|v| v.to_string())
+        };
+        assert_eq!(
+            run("(define a (atom 1)) (define b a) (reset! b 2) (deref a)"),
+            Ok("2".to_owned())
+        );
+        assert_eq!(run("(swap! a + 10 20)"), Ok("32".to_owned()));
+        assert_eq!(run("(deref b)"), Ok("32".to_owned()));
+        assert_eq!(run("a"), Ok("#<atom 32>".to_owned()));
+        // f sees the atom's value again when it changed the atom itself.
+        assert_eq!(
+            run("(swap! a (lambda (x) (list x (deref a) (reset! a 5))))"),
+            Ok("(5 5 5)".to_owned())
+        );
+        assert_eq!(
+            run("(list (atom? (box 1)) (atom? 1))"),
+            Ok("(#t #f)".to_owned())
+        );
+        assert!(run("(deref 1)").is_err());
+        assert!(run("(swap! a)").is_err());
+        let atom = Atom::new(Value::Nil);
+        assert_eq!(atom, atom.clone());
+        assert_ne!(atom, Atom::new(Value::Nil));
+        assert!(!atom.compare_and_set(&Value::Bool(false), Value::Integer(1)));
+        assert!(atom.compare_and_set(&Value::Nil, Value::Integer(1)));
+        assert_eq!(atom.get(), Value::Integer(1));
+    }
+
+    #[test]
+    fn error_test() {
+        let mut env = global_env();
+        let mut run = |source: &str| {
+            eval(&crate::parser::read(source).unwrap(), &mut env).map(|v| v.to_string())
+        };
+        assert_eq!(
+            run("(error \"bad value:\" 1 'x)"),
+            Err(EvalError::Raised(Value::error(
+                "bad value:",
+                vec![Value::Integer(1), Value::Symbol("x".into())]
+            )))
+        );
+        assert_eq!(
+            run("(error \"bad value:\" 1 'x)").unwrap_err().to_string(),
+            "bad value: 1 x"
+        );
+        assert_eq!(
+            run("(raise 'oops)").unwrap_err().to_string(),
+            "uncaught oops"
+        );
+        assert_eq!(
+            run("(with-exception-handler
+                   (lambda (e) (list (error-object-message e) (error-object-irritants e)))
+                   (lambda () (error \"failed\" 1 2)))"),
+            Ok("(\"failed\" (1 2))".to_owned())
+        );
+        assert_eq!(
+            run("(with-exception-handler (lambda (e) (list 'caught e)) (lambda () (raise 5)))"),
+            Ok("(caught 5)".to_owned())
+        );
+        assert_eq!(
+            run("(with-exception-handler (lambda (e) 0) (lambda () 1))"),
+            Ok("1".to_owned())
+        );
+        assert_eq!(
+            run("(error-object? (with-exception-handler (lambda (e) e) (lambda () (car 1))))"),
+            Ok("#t".to_owned())
+        );
+        assert!(run("(error 1)").is_err());
+    }
+
+    #[test]
+    fn string_test() {
+        let mut env = global_env();
+        let mut run = |source: &str| {
+            eval(&crate::parser::read(source).unwrap(), &mut env).map(|v| v.to_string())
+        };
+        assert_eq!(
+            run("(string-append \"ab\" \"\" \"λc\")"),
+            Ok("\"abλc\"".to_owned())
+        );
+        assert_eq!(run("(string-append)"), Ok("\"\"".to_owned()));
+        assert_eq!(run("(string-length \"aλb\")"), Ok("3".to_owned()));
+        assert_eq!(run("(substring \"hello\" 1 3)"), Ok("\"el\"".to_owned()));
+        assert_eq!(run("(substring \"hλllo\" 1)"), Ok("\"λllo\"".to_owned()));
+        assert_eq!(run("(substring \"abc\" 3 3)"), Ok("\"\"".to_owned()));
+        assert!(run("(substring \"abc\" 2 1)").is_err());
+        assert!(run("(substring \"abc\" 0 4)").is_err());
+        assert!(run("(substring \"abc\" -1)").is_err());
+        assert_eq!(run("(string->symbol \"foo\")"), Ok("foo".to_owned()));
+        assert_eq!(run("(symbol->string 'foo)"), Ok("\"foo\"".to_owned()));
+        assert!(run("(symbol->string \"foo\")").is_err());
+        assert_eq!(run("(number->string 3/4)"), Ok("\"3/4\"".to_owned()));
+        assert_eq!(run("(number->string 2.5)"), Ok("\"2.5\"".to_owned()));
+        assert!(run("(number->string 'x)").is_err());
+        assert_eq!(run("(string->number \"-12\")"), Ok("-12".to_owned()));
+        assert_eq!(run("(string->number \"6/4\")"), Ok("3/2".to_owned()));
+        assert_eq!(run("(string->number \"1e3\")"), Ok("1000.0".to_owned()));
+        for input in ["abc", "", "1 2", "(1)", "\\\"1\\\""] {
+            assert_eq!(
+                run(&format!("(string->number \"{input}\")")),
+                Ok("#f".to_owned()),
+                "{input}"
+            );
+        }
+        assert_eq!(
+            run("(split \"  a b\\tc \")"),
+            Ok("(\"a\" \"b\" \"c\")".to_owned())
+        );
+        assert_eq!(
+            run("(split \"a,,b\" \",\")"),
+            Ok("(\"a\" \"\" \"b\")".to_owned())
+        );
+        assert!(run("(split \"a\" \"\")").is_err());
+        assert_eq!(
+            run("(string-join (split \"a b  c\"))"),
+            Ok("\"a b c\"".to_owned())
+        );
+        assert_eq!(
+            run("(string-join '(\"a\" \"\" \"b\") \",\")"),
+            Ok("\"a,,b\"".to_owned())
+        );
+        assert_eq!(run("(string-join '())"), Ok("\"\"".to_owned()));
+        assert!(run("(string-join '(\"a\" 1))").is_err());
+    }
+
+    #[test]
+    fn format_test() {
+        let mut env = global_env();
+        let mut run = |source: &str| {
+            eval(&crate::parser::read(source).unwrap(), &mut env).map(|v| v.to_string())
+        };
+        assert_eq!(
+            run("(format \"~a and ~s, ~a~~~%\" \"x\" \"y\" '(1 #\\z))"),
+            Ok("\"x and \\\"y\\\", (1 #\\\\z)~\\n\"".to_owned())
+        );
+        assert_eq!(run("(format \"plain\")"), Ok("\"plain\"".to_owned()));
+        assert_eq!(
+            run("(format \"~a ~a\" 1)"),
+            Err(EvalError::ArityMismatch {
+                expected: 3,
+                found: 2
+            })
+        );
+        assert!(run("(format \"~a\" 1 2)").is_err());
+        assert!(run("(format \"~q\" 1)").is_err());
+        assert!(run("(format \"~\")").is_err());
+        assert!(run("(format 1)").is_err());
+    }
+
+    #[test]
+    fn display_string_test() {
+        assert_eq!(display_string(&Value::String("two".to_owned())), "two");
+        assert_eq!(
+            display_string(&Value::List(vec![
+                Value::Integer(1),
+                Value::List(vec![Value::Bool(true), Value::Float(3.)]),
+            ])),
+            "(1 (#t 3.0))"
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use crate::{
+    compile::{CaptureSource, Function, Op},
+    parser::{read_all, Expr},
+    symbol::SymbolId,
+    sync::Shared,
+    value::Value,
+};
+
+// Bumped whenever the instructions or the layout below change, so a file
+// written by another version is refused instead of misread.
+pub const VERSION: i64 = 1;
+
+const MAGIC: &str = "tuple-gd-compiled";
+
+// What a compiled file holds for each top-level form of its source: the
+// form's bytecode, or, for a form the VM can't compile, the form itself
+// with its macros expanded, for the evaluator to run.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Form {
+    Compiled(Shared<Function>),
+    Source(Expr),
+}
+
+// A source file read, expanded and compiled ahead of time. The file is
+// written as data, which the reader takes back in:
+//
+//   (tuple-gd-compiled VERSION "source path" CHECKSUM FORM ...)
+//
+// where a FORM is (source EXPR) or (function PARAMS REST SLOTS (OP ...)
+// (CONSTANT ...) (FUNCTION ...) (CAPTURE ...)), and each nested FUNCTION
+// is laid out the same way without the head.
+#[derive(Clone, PartialEq, Debug)]
+pub struct CompiledFile {
+    pub source: String,
+    pub checksum: u64,
+    pub forms: Vec<Form>,
+}
+
+// FNV-1a over the source text, to tell whether a compiled file is out of
+// date. It isn't meant to stand up to anyone trying to collide it.
+pub fn checksum(source: &str) -> u64 {
+    source.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl CompiledFile {
+    pub fn to_text(&self) -> String {
+        let mut items = vec![
+            symbol(MAGIC),
+            Expr::Integer(VERSION),
+            Expr::String(self.source.clone()),
+            // Kept as the same 64 bits, which is all a comparison needs.
+            Expr::Integer(self.checksum as i64),
+        ];
+        items.extend(self.forms.iter().map(|form| match form {
+            Form::Compiled(function) => function_to_expr(function, true),
+            Form::Source(expr) => Expr::List(vec![symbol("source"), expr.clone()]),
+        }));
+        format!("{}\n", Expr::List(items))
+    }
+
+    // Err says what is wrong with text. Every index an instruction uses is
+    // checked to be in range, so a damaged file is refused rather than
+    // left to fail in the middle of a run.
+    pub fn from_text(text: &str) -> Result<CompiledFile, String> {
+        let malformed = || "not a compiled file".to_string();
+        let forms = read_all(text).map_err(|_| malformed())?;
+        let [Expr::List(items)] = forms.as_slice() else {
+            return Err(malformed());
+        };
+        let [Expr::Symbol(magic), Expr::Integer(version), header @ ..] = items.as_slice() else {
+            return Err(malformed());
+        };
+        if magic.as_str() != MAGIC {
+            return Err(malformed());
+        }
+        if *version != VERSION {
+            return Err(format!(
+                "compiled by version {version} of the compiler, not {VERSION}"
+            ));
+        }
+        let [Expr::String(source), Expr::Integer(checksum), forms @ ..] = header else {
+            return Err(malformed());
+        };
+        let forms = forms
+            .iter()
+            .map(|form| form_from_expr(form).ok_or_else(malformed))
+            .collect::<Result<_, _>>()?;
+        Ok(CompiledFile {
+            source: source.clone(),
+            checksum: *checksum as u64,
+            forms,
+        })
+    }
+}
+
+fn symbol(name: &str) -> Expr {
+    Expr::Symbol(SymbolId::new(name))
+}
+
+fn items(expr: &Expr) -> Option<&[Expr]> {
+    match expr {
+        // () reads back as nil.
+        Expr::Nil => Some(&[]),
+        Expr::List(items) => Some(items),
+        _ => None,
+    }
+}
+
+fn function_to_expr(function: &Function, top: bool) -> Expr {
+    let mut items = Vec::new();
+    if top {
+        items.push(symbol("function"));
+    }
+    items.extend([
+        Expr::Integer(function.params as i64),
+        Expr::Bool(function.rest),
+        Expr::Integer(function.slots as i64),
+        Expr::List(function.code.iter().map(op_to_expr).collect()),
+        // Constants are made from the literals and quoted data of the
+        // source, so they always have a written form.
+        Expr::List(
+            function
+                .constants
+                .iter()
+                .map(|constant| Expr::try_from(constant.clone()).unwrap_or(Expr::Nil))
+                .collect(),
+        ),
+        Expr::List(
+            function
+                .functions
+                .iter()
+                .map(|nested| function_to_expr(nested, false))
+                .collect(),
+        ),
+        Expr::List(
+            function
+                .captures
+                .iter()
+                .map(|capture| match capture {
+                    CaptureSource::Local(slot) => {
+                        Expr::List(vec![symbol("local"), Expr::Integer(i64::from(*slot))])
+                    }
+                    CaptureSource::Capture(i) => {
+                        Expr::List(vec![symbol("capture"), Expr::Integer(i64::from(*i))])
+                    }
+                })
+                .collect(),
+        ),
+    ]);
+    Expr::List(items)
+}
+
+fn op_to_expr(op: &Op) -> Expr {
+    let (name, operand) = match *op {
+        Op::Const(n) => ("const", Some(n)),
+        Op::Nil => ("nil", None),
+        Op::Local(n) => ("local", Some(n)),
+        Op::LocalBox(n) => ("local-box", Some(n)),
+        Op::SetLocal(n) => ("set-local", Some(n)),
+        Op::MakeBox(n) => ("make-box", Some(n)),
+        Op::SetBox(n) => ("set-box", Some(n)),
+        Op::Capture(n) => ("capture", Some(n)),
+        Op::CaptureBox(n) => ("capture-box", Some(n)),
+        Op::SetCaptureBox(n) => ("set-capture-box", Some(n)),
+        Op::Global(name) => return Expr::List(vec![symbol("global"), Expr::Symbol(name)]),
+        Op::SetGlobal(name) => return Expr::List(vec![symbol("set-global"), Expr::Symbol(name)]),
+        Op::DefineGlobal(name) => {
+            return Expr::List(vec![symbol("define-global"), Expr::Symbol(name)])
+        }
+        Op::Pop => ("pop", None),
+        Op::Dup => ("dup", None),
+        Op::Jump(n) => ("jump", Some(n)),
+        Op::JumpIfFalse(n) => ("jump-if-false", Some(n)),
+        Op::Closure(n) => ("closure", Some(n)),
+        Op::Call(n) => ("call", Some(n)),
+        Op::TailCall(n) => ("tail-call", Some(n)),
+        Op::Return => ("return", None),
+        Op::Vector(n) => ("vector", Some(n)),
+        Op::Map(n) => ("map", Some(n)),
+        Op::Generator(n) => ("generator", Some(n)),
+        Op::Yield => ("yield", None),
+    };
+    let mut items = vec![symbol(name)];
+    items.extend(operand.map(|n| Expr::Integer(i64::from(n))));
+    Expr::List(items)
+}
+
+fn form_from_expr(expr: &Expr) -> Option<Form> {
+    match items(expr)? {
+        [Expr::Symbol(head), expr] if head.as_str() == "source" => Some(Form::Source(expr.clone())),
+        [Expr::Symbol(head), function @ ..] if head.as_str() == "function" => {
+            // A top-level form runs as a function of no arguments with
+            // nothing captured.
+            let function = function_from_exprs(function, None)?;
+            (function.params == 0 && !function.rest && function.captures.is_empty())
+                .then(|| Form::Compiled(Shared::new(function)))
+        }
+        _ => None,
+    }
+}
+
+// parent is how many slots and captures has the function that makes
+// closures of this one, which they capture from; a top-level form has no
+// parent.
+fn function_from_exprs(fields: &[Expr], parent: Option<(usize, usize)>) -> Option<Function> {
+    let [params, rest, slots, code, constants, functions, captures] = fields else {
+        return None;
+    };
+    let (Expr::Integer(params), Expr::Bool(rest), Expr::Integer(slots)) = (params, rest, slots)
+    else {
+        return None;
+    };
+    let captures = items(captures)?
+        .iter()
+        .map(|capture| match items(capture)? {
+            [Expr::Symbol(kind), Expr::Integer(n)] => {
+                let n = u32::try_from(*n).ok()?;
+                let (slots, captured) = parent?;
+                match kind.as_str() {
+                    "local" if (n as usize) < slots => Some(CaptureSource::Local(n)),
+                    "capture" if (n as usize) < captured => Some(CaptureSource::Capture(n)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let mut function = Function {
+        params: usize::try_from(*params).ok()?,
+        rest: *rest,
+        slots: usize::try_from(*slots).ok()?,
+        code: items(code)?
+            .iter()
+            .map(op_from_expr)
+            .collect::<Option<_>>()?,
+        constants: items(constants)?.iter().map(Value::from).collect(),
+        functions: Vec::new(),
+        captures,
+    };
+    let parent = Some((function.slots, function.captures.len()));
+    function.functions = items(functions)?
+        .iter()
+        .map(|nested| Some(Shared::new(function_from_exprs(items(nested)?, parent)?)))
+        .collect::<Option<_>>()?;
+    let in_range = function.params + usize::from(function.rest) <= function.slots
+        && function.code.last() == Some(&Op::Return)
+        && function
+            .code
+            .iter()
+            .all(|op| operand_in_range(op, &function));
+    in_range.then_some(function)
+}
+
+fn operand_in_range(op: &Op, function: &Function) -> bool {
+    let below = |n: u32, len: usize| (n as usize) < len;
+    match *op {
+        Op::Const(n) => below(n, function.constants.len()),
+        Op::Local(n) | Op::LocalBox(n) | Op::SetLocal(n) | Op::MakeBox(n) | Op::SetBox(n) => {
+            below(n, function.slots)
+        }
+        Op::Capture(n) | Op::CaptureBox(n) | Op::SetCaptureBox(n) => {
+            below(n, function.captures.len())
+        }
+        Op::Jump(n) | Op::JumpIfFalse(n) => below(n, function.code.len()),
+        Op::Closure(n) | Op::Generator(n) => below(n, function.functions.len()),
+        _ => true,
+    }
+}
+
+fn op_from_expr(expr: &Expr) -> Option<Op> {
+    let index = |n: &Expr| match n {
+        Expr::Integer(n) => u32::try_from(*n).ok(),
+        _ => None,
+    };
+    let (Expr::Symbol(name), operands) = items(expr)?.split_first()? else {
+        return None;
+    };
+    Some(match (name.as_str(), operands) {
+        ("nil", []) => Op::Nil,
+        ("pop", []) => Op::Pop,
+        ("dup", []) => Op::Dup,
+        ("return", []) => Op::Return,
+        ("yield", []) => Op::Yield,
+        ("global", [Expr::Symbol(name)]) => Op::Global(*name),
+        ("set-global", [Expr::Symbol(name)]) => Op::SetGlobal(*name),
+        ("define-global", [Expr::Symbol(name)]) => Op::DefineGlobal(*name),
+        ("const", [n]) => Op::Const(index(n)?),
+        ("local", [n]) => Op::Local(index(n)?),
+        ("local-box", [n]) => Op::LocalBox(index(n)?),
+        ("set-local", [n]) => Op::SetLocal(index(n)?),
+        ("make-box", [n]) => Op::MakeBox(index(n)?),
+        ("set-box", [n]) => Op::SetBox(index(n)?),
+        ("capture", [n]) => Op::Capture(index(n)?),
+        ("capture-box", [n]) => Op::CaptureBox(index(n)?),
+        ("set-capture-box", [n]) => Op::SetCaptureBox(index(n)?),
+        ("jump", [n]) => Op::Jump(index(n)?),
+        ("jump-if-false", [n]) => Op::JumpIfFalse(index(n)?),
+        ("closure", [n]) => Op::Closure(index(n)?),
+        ("call", [n]) => Op::Call(index(n)?),
+        ("tail-call", [n]) => Op::TailCall(index(n)?),
+        ("vector", [n]) => Op::Vector(index(n)?),
+        ("map", [n]) => Op::Map(index(n)?),
+        ("generator", [n]) => Op::Generator(index(n)?),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{compile::compile, parser::read};
+
+    fn compiled(sources: &[&str]) -> CompiledFile {
+        let forms = sources
+            .iter()
+            .map(|source| {
+                let expr = read(source).unwrap();
+                match compile(&expr) {
+                    Ok(function) => Form::Compiled(function),
+                    Err(_) => Form::Source(expr),
+                }
+            })
+            .collect();
+        CompiledFile {
+            source: "lib.lisp".to_string(),
+            checksum: checksum("(source)"),
+            forms,
+        }
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let file = compiled(&[
+            "(define (adder n) (lambda (x) (set! n (+ n x)) n))",
+            "(define v [1 \"two\" '(3 . 4) {:k 2.5}])",
+            "(define g (generator (yield 1)))",
+            "(try (f) (catch e e))",
+        ]);
+        assert!(matches!(file.forms[3], Form::Source(_)));
+        assert_eq!(CompiledFile::from_text(&file.to_text()), Ok(file));
+    }
+
+    #[test]
+    fn refuse_test() {
+        let text = compiled(&["(+ 1 2)"]).to_text();
+        let other_version = text.replacen(&format!("compiled {VERSION}"), "compiled 0", 1);
+        assert_eq!(
+            CompiledFile::from_text(&other_version),
+            Err(format!(
+                "compiled by version 0 of the compiler, not {VERSION}"
+            ))
+        );
+        for damaged in [
+            "",
+            "(+ 1 2)",
+            "(tuple-gd-compiled",
+            &text.replacen("(const 0)", "(const 7)", 1),
+            &text.replacen("(return)", "(pop)", 1),
+            &text.replacen("(global +)", "(global 1)", 1),
+        ] {
+            assert_eq!(
+                CompiledFile::from_text(damaged),
+                Err("not a compiled file".to_string()),
+                "{damaged}"
+            );
+        }
+    }
+
+    #[test]
+    fn checksum_test() {
+        assert_eq!(checksum(""), 0xcbf2_9ce4_8422_2325);
+        assert_ne!(checksum("(define x 1)"), checksum("(define x 2)"));
+    }
+}
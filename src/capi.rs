@@ -0,0 +1,279 @@
+// The interpreter over a C ABI, for C and C++ hosts. Build it as a shared
+// library with
+//
+//   cargo rustc --lib --crate-type cdylib --release --features capi
+//
+// and include include/tuple_gd.h. An interpreter and the values it gives
+// out belong to the thread that made them, as Values aren't Send without
+// the sync feature.
+//
+// Strings going in are NUL-terminated UTF-8. Strings coming out are
+// owned by the caller, who frees them with tasks_string_free, except for
+// tasks_last_error's, which the interpreter keeps until its next call.
+#![allow(clippy::missing_safety_doc)]
+
+use core::{ffi::c_char, ptr};
+use std::ffi::{CStr, CString};
+
+use crate::{interpreter::Interpreter, value::Value};
+
+pub struct TasksInterpreter {
+    interpreter: Interpreter,
+    error: Option<CString>,
+}
+
+pub struct TasksValue(Value);
+
+// What tasks_value_type returns for each kind of value.
+pub const TASKS_NIL: i32 = 0;
+pub const TASKS_BOOL: i32 = 1;
+pub const TASKS_INTEGER: i32 = 2;
+pub const TASKS_FLOAT: i32 = 3;
+pub const TASKS_STRING: i32 = 4;
+pub const TASKS_SYMBOL: i32 = 5;
+pub const TASKS_LIST: i32 = 6;
+pub const TASKS_OTHER: i32 = 7;
+
+#[no_mangle]
+pub extern "C" fn tasks_new() -> *mut TasksInterpreter {
+    Box::into_raw(Box::new(TasksInterpreter {
+        interpreter: Interpreter::new(),
+        error: None,
+    }))
+}
+
+// SAFETY: interpreter is null or came from tasks_new and hasn't been
+// freed; the values it gave out stay valid.
+#[no_mangle]
+pub unsafe extern "C" fn tasks_free(interpreter: *mut TasksInterpreter) {
+    if !interpreter.is_null() {
+        drop(unsafe { Box::from_raw(interpreter) });
+    }
+}
+
+// Runs every form in source and returns the value of the last one, to be
+// freed with tasks_value_free, or null on failure, with the reason left
+// for tasks_last_error.
+//
+// SAFETY: interpreter came from tasks_new and source is a NUL-terminated
+// string.
+#[no_mangle]
+pub unsafe extern "C" fn tasks_eval(
+    interpreter: *mut TasksInterpreter,
+    source: *const c_char,
+) -> *mut TasksValue {
+    let interpreter = unsafe { &mut *interpreter };
+    let result = match unsafe { CStr::from_ptr(source) }.to_str() {
+        Ok(source) => interpreter
+            .interpreter
+            .eval_str(source)
+            .map_err(|e| e.to_string()),
+        Err(_) => Err("source is not UTF-8".to_string()),
+    };
+    match result {
+        Ok(value) => {
+            interpreter.error = None;
+            Box::into_raw(Box::new(TasksValue(value)))
+        }
+        Err(message) => {
+            interpreter.error = Some(c_string(message));
+            ptr::null_mut()
+        }
+    }
+}
+
+// Why the last tasks_eval failed, or null if it didn't.
+//
+// SAFETY: interpreter came from tasks_new.
+#[no_mangle]
+pub unsafe extern "C" fn tasks_last_error(interpreter: *const TasksInterpreter) -> *const c_char {
+    match unsafe { &(*interpreter).error } {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+// SAFETY: value is null or came from this API and hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn tasks_value_free(value: *mut TasksValue) {
+    if !value.is_null() {
+        drop(unsafe { Box::from_raw(value) });
+    }
+}
+
+// SAFETY: string is null or came from this API and hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn tasks_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(unsafe { CString::from_raw(string) });
+    }
+}
+
+// The accessors below take a value that came from this API and hasn't
+// been freed. Asked for the wrong kind, they give 0, 0.0 or null.
+
+#[no_mangle]
+pub unsafe extern "C" fn tasks_value_type(value: *const TasksValue) -> i32 {
+    match unsafe { &(*value).0 } {
+        Value::Nil => TASKS_NIL,
+        Value::Bool(_) => TASKS_BOOL,
+        Value::Integer(_) => TASKS_INTEGER,
+        Value::Float(_) => TASKS_FLOAT,
+        Value::String(_) => TASKS_STRING,
+        Value::Symbol(_) => TASKS_SYMBOL,
+        Value::List(_) => TASKS_LIST,
+        _ => TASKS_OTHER,
+    }
+}
+
+// 1 for #t, 0 for anything else.
+#[no_mangle]
+pub unsafe extern "C" fn tasks_value_bool(value: *const TasksValue) -> i32 {
+    i32::from(matches!(unsafe { &(*value).0 }, Value::Bool(true)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tasks_value_integer(value: *const TasksValue) -> i64 {
+    match unsafe { &(*value).0 } {
+        Value::Integer(i) => *i,
+        _ => 0,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tasks_value_float(value: *const TasksValue) -> f64 {
+    match unsafe { &(*value).0 } {
+        Value::Float(x) => *x,
+        _ => 0.0,
+    }
+}
+
+// The text of a string or the name of a symbol. A NUL inside a string
+// ends it early.
+#[no_mangle]
+pub unsafe extern "C" fn tasks_value_string(value: *const TasksValue) -> *mut c_char {
+    match unsafe { &(*value).0 } {
+        Value::String(s) => c_string(s.clone()).into_raw(),
+        Value::Symbol(s) => c_string(s.as_str().to_string()).into_raw(),
+        _ => ptr::null_mut(),
+    }
+}
+
+// Any value written as the REPL would print it.
+#[no_mangle]
+pub unsafe extern "C" fn tasks_value_print(value: *const TasksValue) -> *mut c_char {
+    c_string(unsafe { &(*value).0 }.to_string()).into_raw()
+}
+
+// The length of a list, 0 for nil and anything else.
+#[no_mangle]
+pub unsafe extern "C" fn tasks_value_list_len(value: *const TasksValue) -> usize {
+    match unsafe { &(*value).0 } {
+        Value::List(items) => items.len(),
+        _ => 0,
+    }
+}
+
+// A list's item at index, to be freed with tasks_value_free, or null if
+// there is none.
+#[no_mangle]
+pub unsafe extern "C" fn tasks_value_list_get(
+    value: *const TasksValue,
+    index: usize,
+) -> *mut TasksValue {
+    match unsafe { &(*value).0 } {
+        Value::List(items) if index < items.len() => {
+            Box::into_raw(Box::new(TasksValue(items[index].clone())))
+        }
+        _ => ptr::null_mut(),
+    }
+}
+
+fn c_string(text: String) -> CString {
+    let end = text.find('\0').unwrap_or(text.len());
+    let mut bytes = text.into_bytes();
+    bytes.truncate(end);
+    CString::new(bytes).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Runs source and hands back the printed value or the error.
+    fn eval(interpreter: *mut TasksInterpreter, source: &str) -> Result<String, String> {
+        let source = CString::new(source).unwrap();
+        unsafe {
+            let value = tasks_eval(interpreter, source.as_ptr());
+            if value.is_null() {
+                let error = CStr::from_ptr(tasks_last_error(interpreter));
+                return Err(error.to_str().unwrap().to_string());
+            }
+            let printed = tasks_value_print(value);
+            let text = CStr::from_ptr(printed).to_str().unwrap().to_string();
+            tasks_string_free(printed);
+            tasks_value_free(value);
+            Ok(text)
+        }
+    }
+
+    #[test]
+    fn eval_test() {
+        let interpreter = tasks_new();
+        assert_eq!(
+            eval(interpreter, "(define (sq x) (* x x)) (list (sq 4) \"a\")"),
+            Ok("(16 \"a\")".to_string())
+        );
+        assert_eq!(
+            eval(interpreter, "(car ())"),
+            Err("error: expected pair, found ()".to_string())
+        );
+        assert_eq!(eval(interpreter, "(sq 3)"), Ok("9".to_string()));
+        assert!(unsafe { tasks_last_error(interpreter) }.is_null());
+        unsafe { tasks_free(interpreter) };
+    }
+
+    #[test]
+    fn value_test() {
+        let interpreter = tasks_new();
+        let source = CString::new("(list 1 2.5 \"s\" 'sym #t car)").unwrap();
+        unsafe {
+            let list = tasks_eval(interpreter, source.as_ptr());
+            assert_eq!(tasks_value_type(list), TASKS_LIST);
+            assert_eq!(tasks_value_list_len(list), 6);
+            assert!(tasks_value_list_get(list, 6).is_null());
+            let item = |i| tasks_value_list_get(list, i);
+            let types: Vec<i32> = (0..6)
+                .map(|i| {
+                    let value = item(i);
+                    let kind = tasks_value_type(value);
+                    tasks_value_free(value);
+                    kind
+                })
+                .collect();
+            assert_eq!(
+                types,
+                [
+                    TASKS_INTEGER,
+                    TASKS_FLOAT,
+                    TASKS_STRING,
+                    TASKS_SYMBOL,
+                    TASKS_BOOL,
+                    TASKS_OTHER
+                ]
+            );
+            let (integer, float, string) = (item(0), item(1), item(2));
+            assert_eq!(tasks_value_integer(integer), 1);
+            assert_eq!(tasks_value_integer(float), 0);
+            assert_eq!(tasks_value_float(float), 2.5);
+            let text = tasks_value_string(string);
+            assert_eq!(CStr::from_ptr(text).to_str(), Ok("s"));
+            assert!(tasks_value_string(integer).is_null());
+            tasks_string_free(text);
+            for value in [integer, float, string, list] {
+                tasks_value_free(value);
+            }
+            tasks_free(interpreter);
+        }
+    }
+}
@@ -0,0 +1,1484 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self, Read, Write},
+    iter,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    analysis::{Diagnostic, Document, Severity},
+    compile::{compile, Unsupported},
+    coverage::Coverage,
+    debug::{Backtrace, Debugger},
+    docs,
+    eval::EvalError,
+    format::format_source,
+    interpreter::{Interpreter, InterpreterError},
+    json::{expr_to_json, write_json_string},
+    lexer::{Span, Spanned, Token},
+    parser::{lex_source, read_all_spanned, ReadError, SpannedExpr},
+    profile::Profile,
+    repl,
+    report::{did_you_mean, snippet},
+    symbol::SymbolId,
+    testing,
+    value::Value,
+};
+
+const USAGE: &str = "usage: tuple-gd [run [--coverage LCOV] [--profile] [--folded OUT] [--watch] \
+FILE [-- ARG...] | debug FILE [-- ARG...] | \
+[eval] -e EXPR | - | check FILE... | \
+lint [--json] FILE... | test [--coverage LCOV] [--watch] [PATH...] | doc PATH... -o DIR | expand [--json] FILE | dump (--tokens | --ast) [--json] FILE | \
+fmt FILE | fmt --check FILE... | disasm FILE]";
+
+// What the binary was asked to do.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Command {
+    Repl,
+    // The script and the arguments after --, which (command-line) gives
+    // it; where to write its coverage, if anywhere; whether to print its
+    // profile, and where to write it folded; and whether to run it again
+    // each time it changes.
+    Run {
+        path: String,
+        args: Vec<String>,
+        coverage: Option<String>,
+        profile: bool,
+        folded: Option<String>,
+        watch: bool,
+    },
+    // Runs a script as Run does, under the debugger.
+    Debug {
+        path: String,
+        args: Vec<String>,
+    },
+    Eval(String),
+    // The program on standard input.
+    Stdin,
+    Check(Vec<String>),
+    Lint {
+        json: bool,
+        paths: Vec<String>,
+    },
+    // The test files at the paths, or under the current directory.
+    Test {
+        paths: Vec<String>,
+        coverage: Option<String>,
+        watch: bool,
+    },
+    // Markdown pages for the source files at the paths, written to the
+    // directory out.
+    Doc {
+        paths: Vec<String>,
+        out: String,
+    },
+    // The forms of a file with their macros expanded.
+    Expand {
+        json: bool,
+        path: String,
+    },
+    Dump {
+        what: Dump,
+        json: bool,
+        path: String,
+    },
+    Fmt(String),
+    // Only says which files fmt would change.
+    FmtCheck(Vec<String>),
+    Disasm(String),
+    #[cfg(feature = "lsp")]
+    Lsp,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Dump {
+    Tokens,
+    Ast,
+}
+
+// The arguments after the program name. Err is the usage message.
+pub fn parse_args(args: &[String]) -> Result<Command, &'static str> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        [] => Ok(Command::Repl),
+        // run's options come before the file.
+        ["run", rest @ ..] => {
+            let (mut coverage, mut profile, mut folded, mut rest) = (None, false, None, rest);
+            let mut watch = false;
+            loop {
+                match rest {
+                    ["--coverage", lcov, more @ ..] if coverage.is_none() => {
+                        (coverage, rest) = (Some(lcov.to_string()), more)
+                    }
+                    ["--profile", more @ ..] if !profile => (profile, rest) = (true, more),
+                    ["--folded", out, more @ ..] if folded.is_none() => {
+                        (folded, rest) = (Some(out.to_string()), more)
+                    }
+                    ["--watch", more @ ..] if !watch => (watch, rest) = (true, more),
+                    _ => break,
+                }
+            }
+            let (path, args) = match rest {
+                [path] => (path, &[][..]),
+                [path, "--", args @ ..] => (path, args),
+                _ => return Err(USAGE),
+            };
+            Ok(Command::Run {
+                path: path.to_string(),
+                args: args.iter().map(|arg| arg.to_string()).collect(),
+                coverage,
+                profile,
+                folded,
+                watch,
+            })
+        }
+        ["debug", path] => Ok(Command::Debug {
+            path: path.to_string(),
+            args: Vec::new(),
+        }),
+        ["debug", path, "--", args @ ..] => Ok(Command::Debug {
+            path: path.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        }),
+        ["eval", "-e", source] | ["-e", source] => Ok(Command::Eval(source.to_string())),
+        ["-"] => Ok(Command::Stdin),
+        ["check", paths @ ..] if !paths.is_empty() => Ok(Command::Check(
+            paths.iter().map(|p| p.to_string()).collect(),
+        )),
+        ["lint", options @ ..] => {
+            let json = options.first() == Some(&"--json");
+            match &options[usize::from(json)..] {
+                [] => Err(USAGE),
+                paths => Ok(Command::Lint {
+                    json,
+                    paths: paths.iter().map(|p| p.to_string()).collect(),
+                }),
+            }
+        }
+        ["test", rest @ ..] => {
+            let (mut coverage, mut watch, mut rest) = (None, false, rest);
+            loop {
+                match rest {
+                    ["--coverage", lcov, more @ ..] if coverage.is_none() => {
+                        (coverage, rest) = (Some(lcov.to_string()), more)
+                    }
+                    ["--watch", more @ ..] if !watch => (watch, rest) = (true, more),
+                    _ => break,
+                }
+            }
+            let paths = match rest {
+                [] => vec![".".to_owned()],
+                paths => paths.iter().map(|p| p.to_string()).collect(),
+            };
+            Ok(Command::Test {
+                paths,
+                coverage,
+                watch,
+            })
+        }
+        ["doc", "-o", out, paths @ ..] | ["doc", paths @ .., "-o", out] if !paths.is_empty() => {
+            Ok(Command::Doc {
+                paths: paths.iter().map(|p| p.to_string()).collect(),
+                out: out.to_string(),
+            })
+        }
+        ["expand", path] => Ok(Command::Expand {
+            json: false,
+            path: path.to_string(),
+        }),
+        ["expand", "--json", path] => Ok(Command::Expand {
+            json: true,
+            path: path.to_string(),
+        }),
+        ["dump", options @ .., path] => {
+            let mut what = None;
+            let mut json = false;
+            for option in options {
+                match *option {
+                    "--tokens" if what.is_none() => what = Some(Dump::Tokens),
+                    "--ast" if what.is_none() => what = Some(Dump::Ast),
+                    "--json" => json = true,
+                    _ => return Err(USAGE),
+                }
+            }
+            Ok(Command::Dump {
+                what: what.ok_or(USAGE)?,
+                json,
+                path: path.to_string(),
+            })
+        }
+        ["fmt", "--check", paths @ ..] if !paths.is_empty() => Ok(Command::FmtCheck(
+            paths.iter().map(|p| p.to_string()).collect(),
+        )),
+        ["fmt", path] => Ok(Command::Fmt(path.to_string())),
+        ["disasm", path] => Ok(Command::Disasm(path.to_string())),
+        #[cfg(feature = "lsp")]
+        ["lsp"] => Ok(Command::Lsp),
+        _ => Err(USAGE),
+    }
+}
+
+// Runs the command line and returns the exit status: 0 on success, 1 when
+// a program fails to read or run, n when it calls (exit n), and 2 for a
+// bad command line. Errors that name a place in a file are written
+// path:line:column.
+pub fn run(args: &[String], out: impl Write, mut err: impl Write) -> io::Result<i32> {
+    let command = match parse_args(args) {
+        Ok(command) => command,
+        Err(usage) => {
+            writeln!(err, "{usage}")?;
+            return Ok(2);
+        }
+    };
+    match command {
+        Command::Run { watch: true, .. } | Command::Test { watch: true, .. } => {
+            watch(&command, out, err)
+        }
+        command => execute(command, out, err, &mut Vec::new()),
+    }
+}
+
+// Does what command asks, adding the files run and test read to files.
+fn execute(
+    command: Command,
+    mut out: impl Write,
+    mut err: impl Write,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<i32> {
+    match command {
+        Command::Repl => repl::run(io::stdin().lock(), out),
+        // The one hook tells the backtrace and whatever else was asked for,
+        // and what they recorded is written even if the script fails.
+        Command::Run {
+            path,
+            args,
+            coverage,
+            profile,
+            folded,
+            ..
+        } => {
+            let mut interpreter = script_interpreter(&path, &args);
+            let backtrace = Backtrace::new();
+            if let Ok(source) = fs::read_to_string(&path) {
+                backtrace.set_source(&path, &source);
+            }
+            let counts = coverage.as_ref().map(|_| Coverage::new());
+            let timings = (profile || folded.is_some()).then(Profile::new);
+            let observers = (backtrace.clone(), counts.clone(), timings.clone());
+            interpreter.set_hook(move |event| {
+                let (backtrace, counts, timings) = &observers;
+                backtrace.observe(event);
+                if let Some(counts) = counts {
+                    counts.observe(event);
+                }
+                if let Some(timings) = timings {
+                    timings.observe(event);
+                }
+            });
+            let status = run_script(&mut interpreter, &path, Some(&backtrace), &mut err)?;
+            files.push(PathBuf::from(&path));
+            files.extend(interpreter.files_run());
+            if let (true, Some(timings)) = (profile, &timings) {
+                write!(err, "{}", timings.table())?;
+            }
+            let reports = [
+                (coverage, counts.map(|counts| counts.lcov())),
+                (folded, timings.map(|timings| timings.folded())),
+            ];
+            for (path, report) in reports {
+                if let (Some(path), Some(report)) = (path, report) {
+                    if let Err(message) = write_report(&path, &report) {
+                        return failed(&mut err, message);
+                    }
+                }
+            }
+            Ok(status)
+        }
+        Command::Debug { path, args } => {
+            let mut interpreter = script_interpreter(&path, &args);
+            let mut debugger = Debugger::new(io::BufReader::new(io::stdin()), io::stdout());
+            if let Ok(source) = fs::read_to_string(&path) {
+                debugger.set_source(&path, &source);
+            }
+            debugger.install(&mut interpreter);
+            run_script(&mut interpreter, &path, None, &mut err)
+        }
+        Command::Eval(source) => eval(&source, out, err),
+        Command::Stdin => {
+            let mut source = String::new();
+            match io::stdin().read_to_string(&mut source) {
+                Ok(_) => eval(&source, out, err),
+                Err(e) => failed(&mut err, format!("standard input: {e}")),
+            }
+        }
+        // A file that reads is also linted, for the errors lint would find,
+        // and expanded, in an interpreter of its own, for those its macros
+        // raise.
+        Command::Check(paths) => {
+            let globals = script_interpreter("", &[]).env().names();
+            let mut status = 0;
+            for path in &paths {
+                let source = match fs::read_to_string(path) {
+                    Ok(source) => source,
+                    Err(e) => {
+                        writeln!(err, "{path}: {e}")?;
+                        status = 1;
+                        continue;
+                    }
+                };
+                if let Err(e) = read_all_spanned(&source) {
+                    writeln!(err, "{}", located(path, &source, &e))?;
+                    status = 1;
+                    continue;
+                }
+                let mut found = lint(path, &source, &globals);
+                found.retain(is_error);
+                if !found.is_empty() {
+                    write!(err, "{}", diagnostics_to_text(&found))?;
+                    status = 1;
+                }
+                if let Err(e) = script_interpreter(path, &[]).expand_file(path) {
+                    writeln!(err, "{}", run_error(&e))?;
+                    status = 1;
+                }
+            }
+            Ok(status)
+        }
+        Command::Lint { json, paths } => {
+            let globals = script_interpreter("", &[]).env().names();
+            let mut status = 0;
+            let mut found = Vec::new();
+            for path in &paths {
+                let source = match fs::read_to_string(path) {
+                    Ok(source) => source,
+                    Err(e) => {
+                        writeln!(err, "{path}: {e}")?;
+                        status = 1;
+                        continue;
+                    }
+                };
+                let linted = lint(path, &source, &globals);
+                if linted.iter().any(is_error) {
+                    status = 1;
+                }
+                found.extend(linted);
+            }
+            let report = match json {
+                true => diagnostics_to_json(&found),
+                false => diagnostics_to_text(&found),
+            };
+            write!(out, "{report}").map(|()| status)
+        }
+        // Each file runs in an interpreter of its own, then its tests do.
+        // A file that fails to run fails as a whole. A directory is watched
+        // as well as the files found in it, for the test files added to it.
+        Command::Test {
+            paths, coverage, ..
+        } => {
+            files.extend(paths.iter().map(PathBuf::from));
+            let mut test_files = Vec::new();
+            for path in &paths {
+                match discover(Path::new(path), testing::SUFFIXES) {
+                    Ok(found) => test_files.extend(found),
+                    Err(e) => return failed(&mut err, format!("{path}: {e}")),
+                }
+            }
+            let (mut passed, mut failures) = (0, 0);
+            let counts = Coverage::new();
+            for path in test_files {
+                let mut interpreter = script_interpreter(&path, &[]);
+                if coverage.is_some() {
+                    counts.install(&mut interpreter);
+                }
+                let tests = testing::install(&mut interpreter);
+                let ran = interpreter.eval_file(&path);
+                files.push(PathBuf::from(&path));
+                files.extend(interpreter.files_run());
+                if let Err(e) = ran {
+                    writeln!(err, "{}", run_error(&e))?;
+                    failures += 1;
+                    continue;
+                }
+                for outcome in tests.run(&mut interpreter) {
+                    match outcome.failure {
+                        None => {
+                            writeln!(out, "{path}: {} ok", outcome.name)?;
+                            passed += 1;
+                        }
+                        Some(failure) => {
+                            writeln!(out, "{path}: {} FAILED: {failure}", outcome.name)?;
+                            failures += 1;
+                        }
+                    }
+                }
+            }
+            writeln!(out, "{passed} passed, {failures} failed")?;
+            match coverage.map(|lcov| write_report(&lcov, &counts.lcov())) {
+                Some(Err(message)) => failed(&mut err, message),
+                _ => Ok(i32::from(failures > 0)),
+            }
+        }
+        // Each page goes where its file is under the directory it was found
+        // in, with .md in place of the file's suffix, and index.md lists
+        // them.
+        Command::Doc { paths, out } => {
+            let out = Path::new(&out);
+            let mut index = String::from("# Documentation\n\n");
+            for root in &paths {
+                let root = Path::new(root);
+                let files = match discover(root, docs::SUFFIXES) {
+                    Ok(files) => files,
+                    Err(e) => return failed(&mut err, format!("{}: {e}", root.display())),
+                };
+                for file in files {
+                    let file = Path::new(&file);
+                    let name = match root.is_dir() {
+                        true => file.strip_prefix(root).unwrap_or(file),
+                        false => Path::new(file.file_name().unwrap_or_default()),
+                    };
+                    let source = match fs::read_to_string(file) {
+                        Ok(source) => source,
+                        Err(e) => return failed(&mut err, format!("{}: {e}", file.display())),
+                    };
+                    let title = name.display().to_string();
+                    let page = name.with_extension("md");
+                    let markdown = docs::markdown(&title, &Document::new(source));
+                    let written = out.join(&page);
+                    let result = fs::create_dir_all(written.parent().unwrap_or(out))
+                        .and_then(|()| fs::write(&written, markdown));
+                    if let Err(e) = result {
+                        return failed(&mut err, format!("{}: {e}", written.display()));
+                    }
+                    writeln!(index, "- [{title}]({})", page.display()).unwrap();
+                }
+            }
+            let written = out.join("index.md");
+            match fs::write(&written, index) {
+                Ok(()) => Ok(0),
+                Err(e) => failed(&mut err, format!("{}: {e}", written.display())),
+            }
+        }
+        // The forms are written out and formatted as fmt would, so that
+        // each goes over as many lines as it needs.
+        Command::Expand { json, path } => {
+            let forms = match script_interpreter(&path, &[]).expand_file(&path) {
+                Ok(forms) => forms,
+                Err(e) => return failed(&mut err, run_error(&e)),
+            };
+            let expanded = match json {
+                true => {
+                    let forms: Vec<String> = forms.iter().map(expr_to_json).collect();
+                    format!("[{}]\n", forms.join(","))
+                }
+                false => {
+                    let text: String = forms.iter().map(|form| format!("{form}\n")).collect();
+                    format_source(&text).unwrap_or(text)
+                }
+            };
+            write!(out, "{expanded}").map(|()| 0)
+        }
+        Command::Dump { what, json, path } => {
+            let source = match fs::read_to_string(&path) {
+                Ok(source) => source,
+                Err(e) => return failed(&mut err, format!("{path}: {e}")),
+            };
+            let dumped = match what {
+                Dump::Tokens => lex_source(&source).map(|tokens| match json {
+                    true => tokens_to_json(&source, &tokens),
+                    false => tokens_to_text(&source, &tokens),
+                }),
+                Dump::Ast => read_all_spanned(&source).map(|forms| match json {
+                    true => forms_to_json(&forms),
+                    false => forms_to_text(&source, &forms),
+                }),
+            };
+            match dumped {
+                Ok(text) => write!(out, "{text}").map(|()| 0),
+                Err(e) => failed(&mut err, located(&path, &source, &e)),
+            }
+        }
+        Command::Fmt(path) => {
+            let source = match fs::read_to_string(&path) {
+                Ok(source) => source,
+                Err(e) => return failed(&mut err, format!("{path}: {e}")),
+            };
+            match format_source(&source) {
+                Ok(formatted) => write!(out, "{formatted}").map(|()| 0),
+                Err(e) => failed(&mut err, located(&path, &source, &e)),
+            }
+        }
+        Command::FmtCheck(paths) => {
+            let mut status = 0;
+            for path in paths {
+                let message = match fs::read_to_string(&path) {
+                    Ok(source) => match format_source(&source) {
+                        Ok(formatted) if formatted == source => continue,
+                        Ok(_) => format!("{path}: not formatted"),
+                        Err(e) => located(&path, &source, &e),
+                    },
+                    Err(e) => format!("{path}: {e}"),
+                };
+                writeln!(err, "{message}")?;
+                status = 1;
+            }
+            Ok(status)
+        }
+        Command::Disasm(path) => {
+            let source = match fs::read_to_string(&path) {
+                Ok(source) => source,
+                Err(e) => return failed(&mut err, format!("{path}: {e}")),
+            };
+            match read_all_spanned(&source) {
+                Ok(forms) => write!(out, "{}", disassemble(&source, &forms)).map(|()| 0),
+                Err(e) => failed(&mut err, located(&path, &source, &e)),
+            }
+        }
+        #[cfg(feature = "lsp")]
+        Command::Lsp => crate::lsp::run(io::stdin().lock(), out),
+    }
+}
+
+// How often watch looks at the files it's watching.
+const POLL: Duration = Duration::from_millis(200);
+
+// Runs command, then again each time one of the files it ran changes,
+// until the process is interrupted. Each run clears the screen first, and
+// ends with how long it took.
+fn watch(command: &Command, mut out: impl Write, mut err: impl Write) -> io::Result<i32> {
+    loop {
+        write!(out, "\x1b[2J\x1b[H")?;
+        out.flush()?;
+        let mut files = Vec::new();
+        let started = Instant::now();
+        let status = execute(command.clone(), &mut out, &mut err, &mut files)?;
+        let mut files: Vec<PathBuf> = files
+            .into_iter()
+            .map(|file| fs::canonicalize(&file).unwrap_or(file))
+            .collect();
+        files.sort();
+        files.dedup();
+        writeln!(
+            err,
+            "exited {status} after {:.1?}; watching {} files",
+            started.elapsed(),
+            files.len()
+        )?;
+        let seen = modified(&files);
+        while modified(&files) == seen {
+            thread::sleep(POLL);
+        }
+    }
+}
+
+// When each file was last changed, or None if it can't be found.
+fn modified(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| fs::metadata(file).and_then(|data| data.modified()).ok())
+        .collect()
+}
+
+// Prints the value of the last form in source as the printer writes it.
+fn eval(source: &str, mut out: impl Write, mut err: impl Write) -> io::Result<i32> {
+    match Interpreter::new().eval_str(source) {
+        Ok(value) => writeln!(out, "{value}").map(|()| 0),
+        Err(e) => match exit_status(&e) {
+            Some(status) => Ok(status),
+            None => failed(&mut err, e),
+        },
+    }
+}
+
+// An interpreter set up as run sets one up for path, with the arguments
+// after -- for (command-line) to give.
+fn script_interpreter(path: &str, args: &[String]) -> Interpreter {
+    let mut interpreter = Interpreter::new();
+    let line: Vec<Value> = iter::once(path)
+        .chain(args.iter().map(String::as_str))
+        .map(|arg| Value::String(arg.to_string()))
+        .collect();
+    interpreter.register_fn("command-line", move |args| match args {
+        [] => Ok(Value::List(line.clone())),
+        _ => Err(EvalError::ArityMismatch {
+            expected: 0,
+            found: args.len(),
+        }),
+    });
+    interpreter
+}
+
+// An error that went uncaught out of the calls backtrace saw is followed by
+// a line for each of them, innermost first.
+fn run_script(
+    interpreter: &mut Interpreter,
+    path: &str,
+    backtrace: Option<&Backtrace>,
+    err: &mut impl Write,
+) -> io::Result<i32> {
+    let e = match interpreter.eval_file(path) {
+        Ok(_) => return Ok(0),
+        Err(e) => e,
+    };
+    if let Some(status) = exit_status(&e) {
+        return Ok(status);
+    }
+    writeln!(err, "{}", run_error(&e))?;
+    let mut inner = &e;
+    while let InterpreterError::InFile { error, .. } = inner {
+        inner = error;
+    }
+    let InterpreterError::Eval(e) = inner else {
+        return Ok(1);
+    };
+    if let Some(backtrace) = backtrace {
+        let span = backtrace.span(e);
+        if let (Some(span), Ok(source)) = (span, fs::read_to_string(path)) {
+            write!(err, "{}", snippet(&source, span))?;
+        }
+    }
+    if let EvalError::UnboundSymbol(name) = e {
+        if let Some(hint) = did_you_mean(name, &interpreter.env().names()) {
+            writeln!(err, "help: {hint}")?;
+        }
+    }
+    if let Some(backtrace) = backtrace {
+        for line in backtrace.lines(e) {
+            writeln!(err, "  {line}")?;
+        }
+    }
+    Ok(1)
+}
+
+fn failed(err: &mut impl Write, message: impl std::fmt::Display) -> io::Result<i32> {
+    writeln!(err, "{message}")?;
+    Ok(1)
+}
+
+// The files at path, itself if it's a file, or those ending in one of
+// suffixes in the directory and under it if it's a directory, in order by
+// path.
+fn discover(path: &Path, suffixes: &[&str]) -> io::Result<Vec<String>> {
+    if !path.is_dir() {
+        return Ok(vec![path.display().to_string()]);
+    }
+    let mut files = Vec::new();
+    let mut entries: Vec<_> = fs::read_dir(path)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|entry| entry.path());
+    for entry in entries {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if path.is_dir() && !name.starts_with('.') && name != "target" {
+            files.extend(discover(&path, suffixes)?);
+        } else if suffixes.iter().any(|suffix| name.ends_with(suffix)) {
+            files.push(path.display().to_string());
+        }
+    }
+    Ok(files)
+}
+
+// A report that can't be written, such as coverage, fails the command
+// however the program went.
+fn write_report(path: &str, report: &str) -> Result<(), String> {
+    fs::write(path, report).map_err(|e| format!("{path}: {e}"))
+}
+
+// The status the program asked to end with, from wherever it did.
+fn exit_status(e: &InterpreterError) -> Option<i32> {
+    match e {
+        InterpreterError::Eval(EvalError::Exit(status)) => Some(*status),
+        InterpreterError::InFile { error, .. } => exit_status(error),
+        _ => None,
+    }
+}
+
+// A read error in the file being run, or one it loaded, is located in
+// that file the way check locates it.
+fn run_error(e: &InterpreterError) -> String {
+    let (mut inner, mut file) = (e, None);
+    while let InterpreterError::InFile { path, error } = inner {
+        (inner, file) = (error, Some(path));
+    }
+    if let (Some(file), InterpreterError::Read(read)) = (file, inner) {
+        if let Ok(source) = fs::read_to_string(file) {
+            return located(&file.display().to_string(), &source, read);
+        }
+    }
+    e.to_string()
+}
+
+// The error's place and message, and the line it's on marked under it.
+fn located(path: &str, source: &str, e: &ReadError) -> String {
+    let (line, column) = e.span().line_col(source);
+    let shown = snippet(source, e.span());
+    format!("{path}:{line}:{column}: {e}\n{}", shown.trim_end())
+}
+
+// A diagnostic in a file, after the line and column it starts at, and
+// before the snippet and hint shown under it.
+type Found<'a> = (&'a String, usize, usize, Diagnostic, String);
+
+// What Document::lint finds in the file at path, with source in it.
+fn lint<'a>(path: &'a String, source: &str, globals: &[SymbolId]) -> Vec<Found<'a>> {
+    let mut found = Vec::new();
+    for diagnostic in Document::new(source).lint(globals) {
+        let (line, column) = diagnostic.span().line_col(source);
+        let mut shown = snippet(source, diagnostic.span());
+        if let Diagnostic::Unbound { name, .. } | Diagnostic::SetUnbound { name, .. } = diagnostic {
+            if let Some(hint) = did_you_mean(name.as_str(), globals) {
+                writeln!(shown, "help: {hint}").unwrap();
+            }
+        }
+        found.push((path, line, column, diagnostic, shown));
+    }
+    found
+}
+
+fn is_error(found: &Found) -> bool {
+    found.3.severity() == Severity::Error
+}
+
+// What lint found, located as check locates a read error.
+fn diagnostics_to_text(found: &[Found]) -> String {
+    let mut out = String::new();
+    for (path, line, column, diagnostic, shown) in found {
+        let severity = diagnostic.severity();
+        write!(
+            out,
+            "{path}:{line}:{column}: {severity}: {diagnostic}\n{shown}"
+        )
+        .unwrap();
+    }
+    out
+}
+
+// An array of {"path", "line", "column", "start", "end", "severity",
+// "message"} objects, where start and end are byte offsets.
+fn diagnostics_to_json(found: &[Found]) -> String {
+    let mut out = String::from("[");
+    for (i, (path, line, column, diagnostic, _)) in found.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str("{\"path\":");
+        write_json_string(&mut out, path);
+        let Span { start, end } = diagnostic.span();
+        write!(
+            out,
+            ",\"line\":{line},\"column\":{column},\"start\":{start},\"end\":{end},\"severity\":\"{}\",\"message\":",
+            diagnostic.severity()
+        )
+        .unwrap();
+        write_json_string(&mut out, &diagnostic.to_string());
+        out.push('}');
+    }
+    out.push_str("]\n");
+    out
+}
+
+// One token a line, after the line and column it starts at.
+fn tokens_to_text(source: &str, tokens: &[Spanned<Token>]) -> String {
+    let mut out = String::new();
+    for token in tokens {
+        let (line, column) = token.span.line_col(source);
+        writeln!(out, "{line}:{column} {}", token.value).unwrap();
+    }
+    out
+}
+
+// An array of {"kind", "text", "start", "end"} objects, where text is the
+// token as written and start and end are byte offsets.
+fn tokens_to_json(source: &str, tokens: &[Spanned<Token>]) -> String {
+    let mut out = String::from("[");
+    for (i, token) in tokens.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let Span { start, end } = token.span;
+        write!(out, "{{\"kind\":\"{}\",\"text\":", token_kind(&token.value)).unwrap();
+        write_json_string(&mut out, &source[start..end]);
+        write!(out, ",\"start\":{start},\"end\":{end}}}").unwrap();
+    }
+    out.push_str("]\n");
+    out
+}
+
+fn token_kind(token: &Token) -> &'static str {
+    match token {
+        Token::LParan => "lparen",
+        Token::RParan => "rparen",
+        Token::LBracket => "lbracket",
+        Token::RBracket => "rbracket",
+        Token::LBrace => "lbrace",
+        Token::RBrace => "rbrace",
+        Token::Dot => "dot",
+        Token::Quote => "quote",
+        Token::Quasiquote => "quasiquote",
+        Token::Unquote => "unquote",
+        Token::UnquoteSplicing => "unquote-splicing",
+        Token::DatumComment => "datum-comment",
+        Token::Dispatch(..) => "dispatch",
+        Token::Bool(_) => "bool",
+        Token::Integer(_) => "integer",
+        #[cfg(feature = "bigint")]
+        Token::BigInteger(_) => "integer",
+        Token::Rational(_) => "rational",
+        Token::Float(_) => "float",
+        Token::Char(_) => "char",
+        Token::String(_) => "string",
+        Token::Symbol(_) => "symbol",
+        Token::Keyword(_) => "keyword",
+    }
+}
+
+// The tree the parser built, a node a line and indented by depth, each
+// after the line and column it starts at.
+fn forms_to_text(source: &str, forms: &[SpannedExpr]) -> String {
+    let mut out = String::new();
+    for form in forms {
+        write_node(&mut out, source, form, 0);
+    }
+    out
+}
+
+fn write_node(out: &mut String, source: &str, node: &SpannedExpr, depth: usize) {
+    let (line, column) = node.span().line_col(source);
+    write!(out, "{:indent$}{line}:{column} ", "", indent = depth * 2).unwrap();
+    match node {
+        SpannedExpr::Atom(atom) => writeln!(out, "{}", atom.value).unwrap(),
+        SpannedExpr::List(list) => {
+            out.push_str("list\n");
+            for item in &list.value {
+                write_node(out, source, item, depth + 1);
+            }
+        }
+        SpannedExpr::DottedList(list) => {
+            out.push_str("dotted list\n");
+            let (items, tail) = &list.value;
+            for item in items.iter().chain([&**tail]) {
+                write_node(out, source, item, depth + 1);
+            }
+        }
+        SpannedExpr::Vector(vector) => {
+            out.push_str("vector\n");
+            for item in &vector.value {
+                write_node(out, source, item, depth + 1);
+            }
+        }
+        SpannedExpr::Map(map) => {
+            out.push_str("map\n");
+            for (key, value) in &map.value {
+                write_node(out, source, key, depth + 1);
+                write_node(out, source, value, depth + 1);
+            }
+        }
+    }
+}
+
+// Each form's bytecode after the line and column it starts at. Forms are
+// compiled as they were read, without running the file, so macros it
+// defines aren't expanded.
+fn disassemble(source: &str, forms: &[SpannedExpr]) -> String {
+    let mut out = String::new();
+    for form in forms {
+        let (line, column) = form.span().line_col(source);
+        match compile(&form.to_expr()) {
+            Ok(function) => write!(out, "{line}:{column}\n{function}").unwrap(),
+            Err(Unsupported(expr)) => {
+                writeln!(out, "{line}:{column} not compiled: {expr}").unwrap()
+            }
+        }
+    }
+    out
+}
+
+// An array of the forms in the encoding of json::expr_to_json.
+fn forms_to_json(forms: &[SpannedExpr]) -> String {
+    let forms: Vec<String> = forms
+        .iter()
+        .map(|form| expr_to_json(&form.to_expr()))
+        .collect();
+    format!("[{}]\n", forms.join(","))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    // The exit status and what was written to standard output and error.
+    fn run_cli(arguments: &[&str]) -> (i32, String, String) {
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let status = run(&args(arguments), &mut out, &mut err).unwrap();
+        let text = |bytes| String::from_utf8(bytes).unwrap();
+        (status, text(out), text(err))
+    }
+
+    fn write_temp(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("tuple-gd-cli-{}-{name}", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn parse_args_test() {
+        assert_eq!(parse_args(&[]), Ok(Command::Repl));
+        assert_eq!(
+            parse_args(&args(&["eval", "-e", "(+ 1 2)"])),
+            Ok(Command::Eval("(+ 1 2)".to_owned()))
+        );
+        assert_eq!(
+            parse_args(&args(&["dump", "--json", "--ast", "a.lisp"])),
+            Ok(Command::Dump {
+                what: Dump::Ast,
+                json: true,
+                path: "a.lisp".to_owned()
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["check", "a.lisp", "b.lisp"])),
+            Ok(Command::Check(args(&["a.lisp", "b.lisp"])))
+        );
+        assert_eq!(
+            parse_args(&args(&["lint", "--json", "a.lisp"])),
+            Ok(Command::Lint {
+                json: true,
+                paths: args(&["a.lisp"])
+            })
+        );
+        assert!(parse_args(&args(&["lint", "--json"])).is_err());
+        assert_eq!(
+            parse_args(&args(&["doc", "src", "lib", "-o", "docs"])),
+            Ok(Command::Doc {
+                paths: args(&["src", "lib"]),
+                out: "docs".to_owned()
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["doc", "-o", "docs", "src"])),
+            parse_args(&args(&["doc", "src", "-o", "docs"]))
+        );
+        assert!(parse_args(&args(&["doc", "-o", "docs"])).is_err());
+        assert_eq!(
+            parse_args(&args(&["test"])),
+            Ok(Command::Test {
+                paths: args(&["."]),
+                coverage: None,
+                watch: false
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["test", "--watch", "--coverage", "lcov.info", "t"])),
+            Ok(Command::Test {
+                paths: args(&["t"]),
+                coverage: Some("lcov.info".to_owned()),
+                watch: true
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["expand", "--json", "a.lisp"])),
+            Ok(Command::Expand {
+                json: true,
+                path: "a.lisp".to_owned(),
+            })
+        );
+        assert!(parse_args(&args(&["dump", "--json", "a.lisp"])).is_err());
+        assert!(parse_args(&args(&["dump", "--tokens", "--ast", "a.lisp"])).is_err());
+        assert_eq!(
+            parse_args(&args(&["disasm", "a.lisp"])),
+            Ok(Command::Disasm("a.lisp".to_owned()))
+        );
+        assert_eq!(
+            parse_args(&args(&["run", "a.lisp", "--", "-v", "--", "x"])),
+            Ok(Command::Run {
+                path: "a.lisp".to_owned(),
+                args: args(&["-v", "--", "x"]),
+                coverage: None,
+                profile: false,
+                folded: None,
+                watch: false
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["run", "--coverage", "c.info", "a.lisp", "--", "x"])),
+            Ok(Command::Run {
+                path: "a.lisp".to_owned(),
+                args: args(&["x"]),
+                coverage: Some("c.info".to_owned()),
+                profile: false,
+                folded: None,
+                watch: false
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&[
+                "run",
+                "--folded",
+                "out",
+                "--profile",
+                "--watch",
+                "a.lisp"
+            ])),
+            Ok(Command::Run {
+                path: "a.lisp".to_owned(),
+                args: Vec::new(),
+                coverage: None,
+                profile: true,
+                folded: Some("out".to_owned()),
+                watch: true
+            })
+        );
+        assert!(parse_args(&args(&["run", "--profile", "--profile", "a.lisp"])).is_err());
+        assert!(parse_args(&args(&["run", "a.lisp", "x"])).is_err());
+        assert!(parse_args(&args(&["run", "--coverage", "lcov.info"])).is_err());
+        assert!(parse_args(&args(&["run", "--coverage", "a", "--coverage", "b", "c"])).is_err());
+        assert_eq!(
+            parse_args(&args(&["debug", "a.lisp", "--", "x"])),
+            Ok(Command::Debug {
+                path: "a.lisp".to_owned(),
+                args: args(&["x"])
+            })
+        );
+        assert!(parse_args(&args(&["check"])).is_err());
+        assert_eq!(
+            parse_args(&args(&["-e", "(+ 1 2)"])),
+            Ok(Command::Eval("(+ 1 2)".to_owned()))
+        );
+        assert_eq!(parse_args(&args(&["-"])), Ok(Command::Stdin));
+        assert!(parse_args(&args(&["eval", "(+ 1 2)"])).is_err());
+    }
+
+    #[test]
+    fn eval_test() {
+        assert_eq!(
+            run_cli(&["eval", "-e", "(+ 1 2)"]),
+            (0, "3\n".to_owned(), String::new())
+        );
+        assert_eq!(
+            run_cli(&["-e", "(list \"a\" #\\b)"]),
+            (0, "(\"a\" #\\b)\n".to_owned(), String::new())
+        );
+        let (status, _, err) = run_cli(&["eval", "-e", "missing"]);
+        assert_eq!(
+            (status, err.as_str()),
+            (1, "error: unbound symbol `missing`\n")
+        );
+        assert_eq!(run_cli(&["bogus"]).0, 2);
+    }
+
+    #[test]
+    fn run_and_check_test() {
+        let good = write_temp("good.lisp", "(define x 1)\n(+ x 1)\n");
+        let bad = write_temp("bad.lisp", "(define x 1)\n(+ x\n");
+        assert_eq!(run_cli(&["run", &good]), (0, String::new(), String::new()));
+        assert_eq!(run_cli(&["check", &good]).0, 0);
+        let (status, _, err) = run_cli(&["check", &good, &bad]);
+        assert_eq!(status, 1);
+        assert!(err.starts_with(&format!("{bad}:3:1: expected")), "{err}");
+        let (status, _, err) = run_cli(&["run", &bad]);
+        assert_eq!(status, 1);
+        assert!(err.starts_with(&format!("{bad}:3:1: expected")), "{err}");
+        assert_eq!(run_cli(&["check", "no/such/file.lisp"]).0, 1);
+        // Only lint's errors, not its warnings, and what expanding raises.
+        let wrong = write_temp(
+            "wrong.lisp",
+            "(defmacro one (a) a)\n(one 1 2)\n(define (f list) (lenght list))\n",
+        );
+        let (status, _, err) = run_cli(&["check", &wrong]);
+        assert_eq!(status, 1);
+        assert_eq!(
+            err,
+            format!(
+                "{wrong}:3:19: error: unbound symbol `lenght`\n\
+                 3 | (define (f list) (lenght list))\n  \
+                 |                   ^^^^^^\n\
+                 help: did you mean `length`?\n\
+                 {wrong}: error: expected 1 arguments, found 2\n"
+            )
+        );
+
+        let failing = write_temp(
+            "failing.lisp",
+            "(define (fact n)\n  (if (= n 0) (car n) (* n (fact (- n 1)))))\n(fact 1)\n",
+        );
+        let (status, _, err) = run_cli(&["run", &failing]);
+        assert_eq!(status, 1);
+        assert!(
+            err.ends_with(&format!(
+                "\n  at (car n) {failing}:2:15\n  \
+                 at (fact (- n 1)) {failing}:2:28\n  \
+                 at (fact 1) {failing}:3:1\n"
+            )),
+            "{err}"
+        );
+        let typo = write_temp("typo.lisp", "(define (f) (lenght '()))\n(f)\n");
+        assert_eq!(
+            run_cli(&["run", &typo]),
+            (
+                1,
+                String::new(),
+                format!(
+                    "{typo}: error: unbound symbol `lenght`\n2 | (f)\n  | ^^^\n\
+                     help: did you mean `length`?\n  at (f) {typo}:2:1\n"
+                )
+            )
+        );
+
+        let script = write_temp(
+            "args.lisp",
+            "(define args (cdr (command-line)))\n\
+             (try (exit (length args)) (catch e (exit 99)))",
+        );
+        assert_eq!(
+            run_cli(&["run", &script]),
+            (0, String::new(), String::new())
+        );
+        assert_eq!(run_cli(&["run", &script, "--", "a", "b"]).0, 2);
+        assert_eq!(
+            run_cli(&["eval", "-e", "(exit #f)"]),
+            (1, String::new(), String::new())
+        );
+    }
+
+    #[test]
+    fn fmt_test() {
+        let messy = write_temp("messy.lisp", "(define (f x)\n(+ x\n 1))\n");
+        let (status, formatted, _) = run_cli(&["fmt", &messy]);
+        assert_eq!(
+            (status, formatted.as_str()),
+            (0, "(define (f x) (+ x 1))\n")
+        );
+        let tidy = write_temp("tidy.lisp", &formatted);
+        assert_eq!(
+            run_cli(&["fmt", "--check", &tidy]),
+            (0, String::new(), String::new())
+        );
+        let bad = write_temp("bad-fmt.lisp", "(f\n");
+        let (status, _, err) = run_cli(&["fmt", "--check", &tidy, &messy, &bad]);
+        assert_eq!(status, 1);
+        assert_eq!(
+            err.lines().collect::<Vec<_>>(),
+            [
+                format!("{messy}: not formatted"),
+                format!("{bad}:2:1: expected `)`, found end of input"),
+                "2 |".to_owned(),
+                "  | ^".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn lint_test() {
+        let path = write_temp(
+            "lint.lisp",
+            "(define (f list) (car (command-line)))\n(f 1 2)\n(lenght '())",
+        );
+        assert_eq!(
+            run_cli(&["lint", &path]),
+            (
+                1,
+                format!(
+                    "{path}:1:12: warning: `list` shadows a builtin\n\
+                     1 | (define (f list) (car (command-line)))\n  |            ^^^^\n\
+                     {path}:1:12: warning: `list` is never used\n\
+                     1 | (define (f list) (car (command-line)))\n  |            ^^^^\n\
+                     {path}:2:1: error: `f` expects 1 arguments, found 2\n\
+                     2 | (f 1 2)\n  | ^^^^^^^\n\
+                     {path}:3:2: error: unbound symbol `lenght`\n\
+                     3 | (lenght '())\n  |  ^^^^^^\nhelp: did you mean `length`?\n"
+                ),
+                String::new()
+            )
+        );
+        let clean = write_temp("lint-clean.lisp", "(define (f _x) 1)\n(f 1)");
+        assert_eq!(
+            run_cli(&["lint", "--json", &clean, &clean]),
+            (0, "[]\n".to_owned(), String::new())
+        );
+        let (status, json, _) = run_cli(&["lint", "--json", &path]);
+        assert_eq!(status, 1);
+        assert!(json.starts_with(&format!(
+            "[{{\"path\":\"{path}\",\"line\":1,\"column\":12,\"start\":11,\"end\":15,\
+             \"severity\":\"warning\",\"message\":\"`list` shadows a builtin\"}},"
+        )));
+        assert_eq!(run_cli(&["lint", "no/such/file.lisp"]).0, 1);
+    }
+
+    #[test]
+    fn test_test() {
+        let dir = std::env::temp_dir().join(format!("tuple-gd-cli-{}-tests", std::process::id()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        let math = dir.join("math_test.lisp");
+        fs::write(
+            &math,
+            "(define (double x) (* x 2))\n\
+             (deftest doubles (assert-equal 4 (double 2)))\n\
+             (deftest lists (assert-equal '(2 4) (map double '(1 3))))",
+        )
+        .unwrap();
+        let broken = dir.join("nested").join("broken_test.lisp");
+        fs::write(&broken, "(deftest fine)\n(car 1)").unwrap();
+        fs::write(dir.join("helper.lisp"), "(car 1)").unwrap();
+        let (math, broken) = (math.display(), broken.display());
+        let (status, out, err) = run_cli(&["test", dir.to_str().unwrap()]);
+        assert_eq!(status, 1);
+        assert_eq!(
+            out,
+            format!(
+                "{math}: doubles ok\n\
+                 {math}: lists FAILED: expected (2 4), got (2 6): item 1: expected 4, got 6\n\
+                 1 passed, 2 failed\n"
+            )
+        );
+        assert_eq!(err, format!("{broken}: error: expected list, found 1\n"));
+        let path = math.to_string();
+        assert_eq!(
+            run_cli(&["test", &path, &path]).1.lines().last(),
+            Some("2 passed, 2 failed")
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn coverage_test() {
+        let dir =
+            std::env::temp_dir().join(format!("tuple-gd-cli-{}-coverage", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("sign.lisp"),
+            "(define (sign n)\n  (if (< n 0) 'negative 'positive))\n(define (unused) (car 1))",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("sign_test.lisp"),
+            "(load \"sign.lisp\")\n(deftest positive (assert-equal 'positive (sign 1)))",
+        )
+        .unwrap();
+        let script = dir.join("main.lisp");
+        fs::write(&script, "(load \"sign.lisp\")\n(sign -1)\n(car 1)").unwrap();
+        let lcov = dir.join("lcov.info");
+        let lcov = lcov.to_str().unwrap();
+        let sign = fs::canonicalize(dir.join("sign.lisp")).unwrap();
+        let record = |expected: &str| {
+            let written = fs::read_to_string(lcov).unwrap();
+            let start = written.find(&format!("SF:{}\n", sign.display())).unwrap();
+            let record = &written[start..];
+            assert_eq!(&record[..record.find("end_of_record").unwrap()], expected);
+        };
+        let (status, out, _) = run_cli(&["test", "--coverage", lcov, dir.to_str().unwrap()]);
+        assert_eq!(
+            (status, out.lines().last()),
+            (0, Some("1 passed, 0 failed"))
+        );
+        record(&format!(
+            "SF:{}\nBRDA:2,0,0,0\nBRDA:2,0,1,1\nBRF:2\nBRH:1\n\
+             DA:1,1\nDA:2,2\nDA:3,1\nLF:3\nLH:3\n",
+            sign.display()
+        ));
+        // The script fails, and what ran before it did is still written.
+        let (status, _, _) = run_cli(&["run", "--coverage", lcov, script.to_str().unwrap()]);
+        assert_eq!(status, 1);
+        record(&format!(
+            "SF:{}\nBRDA:2,0,0,1\nBRDA:2,0,1,0\nBRF:2\nBRH:1\n\
+             DA:1,1\nDA:2,2\nDA:3,1\nLF:3\nLH:3\n",
+            sign.display()
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn profile_test() {
+        let script = write_temp(
+            "profile.lisp",
+            "(define (fact n) (if (= n 0) 1 (* n (fact (- n 1)))))\n(fact 2)",
+        );
+        let folded = format!("{script}.folded");
+        let (status, out, err) = run_cli(&["run", "--profile", "--folded", &folded, &script]);
+        assert_eq!((status, out), (0, String::new()));
+        let names: Vec<&str> = err
+            .lines()
+            .map(|row| row.rsplit(' ').next().unwrap())
+            .collect();
+        assert_eq!(names.len(), 5, "{err}");
+        assert_eq!(names[0], "name");
+        let stacks = fs::read_to_string(&folded).unwrap();
+        let stacks: Vec<&str> = stacks
+            .lines()
+            .map(|line| line.rsplit_once(' ').unwrap().0)
+            .collect();
+        assert_eq!(
+            stacks,
+            [
+                "fact",
+                "fact;*",
+                "fact;-",
+                "fact;=",
+                "fact;fact",
+                "fact;fact;*",
+                "fact;fact;-",
+                "fact;fact;=",
+                "fact;fact;fact",
+                "fact;fact;fact;="
+            ]
+        );
+        // Only the folded stacks.
+        let (status, _, err) = run_cli(&["run", "--folded", &folded, &script]);
+        assert_eq!((status, err), (0, String::new()));
+    }
+
+    #[test]
+    fn watched_files_test() {
+        let dir = std::env::temp_dir().join(format!("tuple-gd-cli-{}-watch", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("lib.lisp"), "(define answer 42)").unwrap();
+        let script = dir.join("main.lisp");
+        fs::write(&script, "(load \"lib.lisp\")\n(car answer)").unwrap();
+        let lib = fs::canonicalize(dir.join("lib.lisp")).unwrap();
+        // What a failed run loaded is watched too.
+        let command = Command::Run {
+            path: script.display().to_string(),
+            args: Vec::new(),
+            coverage: None,
+            profile: false,
+            folded: None,
+            watch: false,
+        };
+        let mut files = Vec::new();
+        let status = execute(command, io::sink(), io::sink(), &mut files).unwrap();
+        assert_eq!(status, 1);
+        assert_eq!(files[0], script);
+        assert!(files.contains(&lib), "{files:?}");
+        let before = modified(&files);
+        assert!(before.iter().all(Option::is_some));
+        fs::remove_file(&lib).unwrap();
+        assert_ne!(modified(&files), before);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn doc_test() {
+        let dir = std::env::temp_dir().join(format!("tuple-gd-cli-{}-doc", std::process::id()));
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("text")).unwrap();
+        fs::write(
+            src.join("math.lisp"),
+            ";;; Twice x.\n(define (double x) (* x 2))",
+        )
+        .unwrap();
+        fs::write(
+            src.join("text").join("words.scm"),
+            "(define (shout s) \"s in capitals.\" (upcase s))",
+        )
+        .unwrap();
+        fs::write(src.join("notes.txt"), "(define (skipped) 1)").unwrap();
+        let out = dir.join("docs");
+        let (src, out) = (src.to_str().unwrap(), out.to_str().unwrap());
+        assert_eq!(
+            run_cli(&["doc", src, "-o", out]),
+            (0, String::new(), String::new())
+        );
+        let read = |page: &str| fs::read_to_string(dir.join("docs").join(page)).unwrap();
+        assert_eq!(
+            read("index.md"),
+            "# Documentation\n\n- [math.lisp](math.md)\n- [text/words.scm](text/words.md)\n"
+        );
+        assert_eq!(
+            read("math.md"),
+            "# math.lisp\n\n## `(double x)`\n\nTwice x.\n"
+        );
+        assert_eq!(
+            read("text/words.md"),
+            "# text/words.scm\n\n## `(shout s)`\n\ns in capitals.\n"
+        );
+        assert_eq!(run_cli(&["doc", "no/such/dir", "-o", out]).0, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn expand_test() {
+        let path = write_temp(
+            "expand.lisp",
+            "(defmacro swap (a b) (list 'let (list (list 't a)) (list 'set! a b) (list 'set! b 't)))\n\
+             (define (f x y) (swap x y) '(swap x y))",
+        );
+        assert_eq!(
+            run_cli(&["expand", &path]).1.lines().last(),
+            Some("(define (f x y) (let ((t x)) (set! x y) (set! y t)) (quote (swap x y)))")
+        );
+        let (status, json, _) = run_cli(&["expand", "--json", &path]);
+        assert_eq!(status, 0);
+        assert!(json.ends_with(
+            "[{\"symbol\":\"quote\"},[{\"symbol\":\"swap\"},{\"symbol\":\"x\"},{\"symbol\":\"y\"}]]]]\n"
+        ));
+        let bad = write_temp("expand-bad.lisp", "(defmacro m () (car 1))\n(m)");
+        let (status, _, err) = run_cli(&["expand", &bad]);
+        assert_eq!(status, 1);
+        assert!(err.contains("expected list, found 1"), "{err}");
+    }
+
+    #[test]
+    fn disasm_test() {
+        let path = write_temp("disasm.lisp", "(define x 1)\n(try x (catch e e))");
+        assert_eq!(
+            run_cli(&["disasm", &path]),
+            (
+                0,
+                "1:1\n   0  Const(0)  ; 1\n   1  DefineGlobal(\"x\")\n   2  Nil\n   3  Return\n\
+                 2:1 not compiled: (try x (catch e e))\n"
+                    .to_owned(),
+                String::new()
+            )
+        );
+        assert_eq!(run_cli(&["disasm", "no/such/file.lisp"]).0, 1);
+    }
+
+    #[test]
+    fn dump_test() {
+        let path = write_temp("dump.lisp", "(f \"a\"\n  [1 . 2])");
+        assert_eq!(
+            run_cli(&["dump", "--tokens", &path]).1,
+            "1:1 `(`\n1:2 `f`\n1:4 `\"a\"`\n2:3 `[`\n2:4 `1`\n2:6 `.`\n2:8 `2`\n2:9 `]`\n2:10 `)`\n"
+        );
+        assert_eq!(
+            run_cli(&["dump", "--tokens", "--json", &path])
+                .1
+                .lines()
+                .next()
+                .unwrap(),
+            concat!(
+                r#"[{"kind":"lparen","text":"(","start":0,"end":1},"#,
+                r#"{"kind":"symbol","text":"f","start":1,"end":2},"#,
+                r#"{"kind":"string","text":"\"a\"","start":3,"end":6},"#,
+                r#"{"kind":"lbracket","text":"[","start":9,"end":10},"#,
+                r#"{"kind":"integer","text":"1","start":10,"end":11},"#,
+                r#"{"kind":"dot","text":".","start":12,"end":13},"#,
+                r#"{"kind":"integer","text":"2","start":14,"end":15},"#,
+                r#"{"kind":"rbracket","text":"]","start":15,"end":16},"#,
+                r#"{"kind":"rparen","text":")","start":16,"end":17}]"#,
+            )
+        );
+        let source = write_temp("ast.lisp", "(define (f x) x)\n'a");
+        assert_eq!(
+            run_cli(&["dump", "--ast", &source]).1,
+            "1:1 list\n  1:2 define\n  1:9 list\n    1:10 f\n    1:12 x\n  1:15 x\n\
+             2:1 list\n  2:1 quote\n  2:2 a\n"
+        );
+        assert_eq!(
+            run_cli(&["dump", "--ast", "--json", &source]).1,
+            "[[{\"symbol\":\"define\"},[{\"symbol\":\"f\"},{\"symbol\":\"x\"}],{\"symbol\":\"x\"}],\
+             [{\"symbol\":\"quote\"},{\"symbol\":\"a\"}]]\n"
+        );
+        let bad = write_temp("bad-dump.lisp", "(a \"b");
+        let (status, _, err) = run_cli(&["dump", "--tokens", &bad]);
+        assert_eq!(status, 1);
+        assert_eq!(
+            err,
+            format!("{bad}:1:4: unterminated string at byte 3\n1 | (a \"b\n  |    ^^\n")
+        );
+    }
+}
@@ -0,0 +1,837 @@
+use alloc::{collections::BTreeSet, vec, vec::Vec};
+use core::fmt;
+
+use crate::{
+    parser::{Expr, Lambda},
+    symbol::{self, SymbolId},
+    sync::Shared,
+    value::Value,
+};
+
+// One VM instruction. Operands are indices into the running function's
+// constants, slots, captures or nested functions, or code offsets for
+// jumps. Unless noted, an instruction pushes its result on the stack.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Op {
+    Const(u32),
+    Nil,
+    // Slots hold a function's parameters and the variables its lets and
+    // internal defines introduce. A variable that is ever assigned lives
+    // in a box, so closures that capture it share the one binding.
+    Local(u32),
+    LocalBox(u32),
+    SetLocal(u32),
+    MakeBox(u32),
+    SetBox(u32),
+    Capture(u32),
+    CaptureBox(u32),
+    SetCaptureBox(u32),
+    Global(SymbolId),
+    SetGlobal(SymbolId),
+    DefineGlobal(SymbolId),
+    Pop,
+    // Pushes another copy of the value on top of the stack.
+    Dup,
+    Jump(u32),
+    JumpIfFalse(u32),
+    Closure(u32),
+    Call(u32),
+    TailCall(u32),
+    Return,
+    Vector(u32),
+    Map(u32),
+    // Makes a generator out of a nested function, as Closure makes a
+    // closure.
+    Generator(u32),
+    // Hands the value on top of the stack to whoever resumed the
+    // generator, and leaves () in its place for when it is resumed again.
+    Yield,
+}
+
+// Where a closure gets each of its captured values from when it is made:
+// a slot of the function creating it, or one of that function's own
+// captures.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CaptureSource {
+    Local(u32),
+    Capture(u32),
+}
+
+// A compiled lambda, or a whole top-level form compiled as a function of
+// no arguments.
+#[derive(PartialEq, Debug, Default)]
+pub struct Function {
+    pub params: usize,
+    pub rest: bool,
+    pub slots: usize,
+    pub code: Vec<Op>,
+    pub constants: Vec<Value>,
+    pub functions: Vec<Shared<Function>>,
+    pub captures: Vec<CaptureSource>,
+}
+
+// A listing of the code, one instruction to a line numbered the way jumps
+// count, with the constants the instructions load and then each nested
+// function, indented.
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+impl Function {
+    fn write(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        for (i, op) in self.code.iter().enumerate() {
+            write!(f, "{:indent$}{i:4}  {op:?}", "")?;
+            match op {
+                Op::Const(n) => write!(f, "  ; {}", self.constants[*n as usize])?,
+                Op::Closure(n) | Op::Generator(n) => write!(f, "  ; function {n}")?,
+                _ => {}
+            }
+            writeln!(f)?;
+        }
+        for (n, function) in self.functions.iter().enumerate() {
+            let rest = if function.rest { " and a rest" } else { "" };
+            let (params, slots) = (function.params, function.slots);
+            writeln!(
+                f,
+                "{:indent$}function {n}: {params} params{rest}, {slots} slots",
+                ""
+            )?;
+            function.write(f, indent + 2)?;
+        }
+        Ok(())
+    }
+}
+
+// The compiler handles the core forms. Anything else, such as quasiquote,
+// match, try, defmacro or keyword parameters, is reported back so the
+// caller can hand the form to the tree-walker instead. Malformed forms are
+// reported the same way, so they fail with the tree-walker's errors.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Unsupported(pub Expr);
+
+type Result<T> = core::result::Result<T, Unsupported>;
+
+// Compiles a top-level form, which should already be macro-expanded.
+pub fn compile(expr: &Expr) -> Result<Shared<Function>> {
+    let mut compiler = Compiler {
+        functions: vec![FnState::default()],
+    };
+    compiler.expr(expr, true)?;
+    compiler.emit(Op::Return);
+    Ok(Shared::new(compiler.functions.pop().unwrap().function))
+}
+
+#[derive(Clone, Copy)]
+struct Local {
+    name: SymbolId,
+    slot: u32,
+    boxed: bool,
+}
+
+#[derive(Clone, Copy)]
+struct Captured {
+    name: SymbolId,
+    boxed: bool,
+}
+
+#[derive(Default)]
+struct FnState {
+    function: Function,
+    locals: Vec<Local>,
+    captured: Vec<Captured>,
+    // Names assigned anywhere in the function's body, by set! or an
+    // internal define. Their variables are boxed.
+    assigned: BTreeSet<SymbolId>,
+    // How many lets deep the compiler is. A define with no lets or
+    // enclosing lambda around it is a global one.
+    depth: usize,
+    // Where the innermost scope's locals start. A define only ever binds
+    // a variable of the scope it's in.
+    scope: usize,
+    // Whether this is the body of a generator or a lambda inside one, the
+    // only places yield can appear.
+    generator: bool,
+}
+
+enum Var {
+    Local(Local),
+    Capture(u32, bool),
+    Global,
+}
+
+struct Compiler {
+    functions: Vec<FnState>,
+}
+
+impl Compiler {
+    fn current(&mut self) -> &mut FnState {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let code = &mut self.current().function.code;
+        code.push(op);
+        code.len() - 1
+    }
+
+    fn patch(&mut self, at: usize) {
+        let target = self.current().function.code.len() as u32;
+        match &mut self.current().function.code[at] {
+            Op::Jump(offset) | Op::JumpIfFalse(offset) => *offset = target,
+            _ => unreachable!(),
+        }
+    }
+
+    fn constant(&mut self, value: Value) {
+        let constants = &mut self.current().function.constants;
+        let index = match constants.iter().position(|c| *c == value) {
+            Some(index) => index,
+            None => {
+                constants.push(value);
+                constants.len() - 1
+            }
+        };
+        self.emit(Op::Const(index as u32));
+    }
+
+    fn declare(&mut self, name: SymbolId) -> Local {
+        let state = self.current();
+        let local = Local {
+            name,
+            slot: state.function.slots as u32,
+            boxed: state.assigned.contains(&name),
+        };
+        state.function.slots += 1;
+        state.locals.push(local);
+        local
+    }
+
+    fn resolve(&mut self, name: SymbolId) -> Var {
+        self.resolve_in(self.functions.len() - 1, name)
+    }
+
+    fn resolve_in(&mut self, level: usize, name: SymbolId) -> Var {
+        let state = &self.functions[level];
+        if let Some(local) = state.locals.iter().rev().find(|l| l.name == name) {
+            return Var::Local(*local);
+        }
+        if let Some(i) = state.captured.iter().position(|c| c.name == name) {
+            return Var::Capture(i as u32, state.captured[i].boxed);
+        }
+        if level == 0 {
+            return Var::Global;
+        }
+        let (source, boxed) = match self.resolve_in(level - 1, name) {
+            Var::Local(local) => (CaptureSource::Local(local.slot), local.boxed),
+            Var::Capture(i, boxed) => (CaptureSource::Capture(i), boxed),
+            Var::Global => return Var::Global,
+        };
+        let state = &mut self.functions[level];
+        state.function.captures.push(source);
+        state.captured.push(Captured { name, boxed });
+        Var::Capture(state.captured.len() as u32 - 1, boxed)
+    }
+
+    fn expr(&mut self, expr: &Expr, tail: bool) -> Result<()> {
+        match expr {
+            Expr::Nil => {
+                self.emit(Op::Nil);
+            }
+            Expr::Symbol(name) => {
+                let op = match self.resolve(*name) {
+                    Var::Local(local) if local.boxed => Op::LocalBox(local.slot),
+                    Var::Local(local) => Op::Local(local.slot),
+                    Var::Capture(i, true) => Op::CaptureBox(i),
+                    Var::Capture(i, false) => Op::Capture(i),
+                    Var::Global => Op::Global(*name),
+                };
+                self.emit(op);
+            }
+            Expr::List(items) => match items.split_first() {
+                None => {
+                    self.emit(Op::Nil);
+                }
+                Some((head, args)) => self.form(expr, head, args, tail)?,
+            },
+            Expr::Lambda(lambda) => self.lambda(expr, lambda)?,
+            Expr::Pair(..) => return Err(Unsupported(expr.clone())),
+            Expr::Vector(items) => {
+                for item in items {
+                    self.expr(item, false)?;
+                }
+                self.emit(Op::Vector(items.len() as u32));
+            }
+            Expr::Map(entries) => {
+                for (key, value) in entries {
+                    self.expr(key, false)?;
+                    self.expr(value, false)?;
+                }
+                self.emit(Op::Map(entries.len() as u32));
+            }
+            literal => self.constant(Value::from(literal)),
+        }
+        Ok(())
+    }
+
+    fn form(&mut self, form: &Expr, head: &Expr, args: &[Expr], tail: bool) -> Result<()> {
+        let unsupported = || Unsupported(form.clone());
+        let Expr::Symbol(name) = head else {
+            return self.call(head, args, tail);
+        };
+        match (*name, args) {
+            (symbol::QUOTE, [datum]) => self.constant(Value::from(datum)),
+            (symbol::IF, [test, then, rest @ ..]) if rest.len() <= 1 => {
+                self.expr(test, false)?;
+                let to_else = self.emit(Op::JumpIfFalse(0));
+                self.expr(then, tail)?;
+                let to_end = self.emit(Op::Jump(0));
+                self.patch(to_else);
+                match rest {
+                    [otherwise] => self.expr(otherwise, tail)?,
+                    _ => {
+                        self.emit(Op::Nil);
+                    }
+                }
+                self.patch(to_end);
+            }
+            (symbol::COND, clauses) => self.cond(form, clauses, tail)?,
+            (symbol::AND | symbol::OR, [init @ .., last]) => {
+                let mut to_end = Vec::new();
+                for arg in init {
+                    self.expr(arg, false)?;
+                    self.emit(Op::Dup);
+                    if *name == symbol::AND {
+                        to_end.push(self.emit(Op::JumpIfFalse(0)));
+                    } else {
+                        let to_next = self.emit(Op::JumpIfFalse(0));
+                        to_end.push(self.emit(Op::Jump(0)));
+                        self.patch(to_next);
+                    }
+                    self.emit(Op::Pop);
+                }
+                self.expr(last, tail)?;
+                for at in to_end {
+                    self.patch(at);
+                }
+            }
+            (symbol::AND | symbol::OR, []) => self.constant(Value::Bool(*name == symbol::AND)),
+            (symbol::WHEN | symbol::UNLESS, [test, body @ ..]) => {
+                self.expr(test, false)?;
+                let to_other = self.emit(Op::JumpIfFalse(0));
+                let (first, second): (&[Expr], &[Expr]) = match *name == symbol::WHEN {
+                    true => (body, &[]),
+                    false => (&[], body),
+                };
+                self.body(first, tail)?;
+                let to_end = self.emit(Op::Jump(0));
+                self.patch(to_other);
+                self.body(second, tail)?;
+                self.patch(to_end);
+            }
+            (symbol::BEGIN, body) => self.body(body, tail)?,
+            (symbol::DEFINE, [Expr::Symbol(name), value]) => {
+                self.define(form, *name, |c| c.expr(value, false))?
+            }
+            (symbol::DEFINE, [signature, body @ ..]) => {
+                let (name, params) = match signature {
+                    Expr::List(items) if !items.is_empty() => {
+                        (&items[0], Expr::List(items[1..].to_vec()))
+                    }
+                    Expr::Pair(name, params) => (&**name, (**params).clone()),
+                    _ => return Err(unsupported()),
+                };
+                let Expr::Symbol(name) = name else {
+                    return Err(unsupported());
+                };
+                let lambda = lambda_parts(&params, body).ok_or_else(unsupported)?;
+                self.define(form, *name, |c| c.lambda(form, &lambda))?;
+            }
+            (symbol::SET, [Expr::Symbol(name), value]) => {
+                self.expr(value, false)?;
+                let op = match self.resolve(*name) {
+                    Var::Local(local) if local.boxed => Op::SetBox(local.slot),
+                    Var::Capture(i, true) => Op::SetCaptureBox(i),
+                    Var::Global => Op::SetGlobal(*name),
+                    // Every name set! assigns is boxed by this point.
+                    _ => return Err(unsupported()),
+                };
+                self.emit(op);
+                self.emit(Op::Nil);
+            }
+            (symbol::LAMBDA, [params, body @ ..]) => {
+                let lambda = lambda_parts(params, body).ok_or_else(unsupported)?;
+                self.lambda(form, &lambda)?;
+            }
+            (symbol::LET | symbol::LET_STAR, [Expr::List(bindings), body @ ..]) => {
+                self.let_form(form, *name == symbol::LET_STAR, bindings, body, tail)?
+            }
+            (symbol::GENERATOR, body) => {
+                let lambda = Lambda {
+                    body: body.to_vec(),
+                    ..Lambda::default()
+                };
+                self.function(form, &lambda, true)?;
+                let index = self.current().function.functions.len() as u32 - 1;
+                self.emit(Op::Generator(index));
+            }
+            (symbol::YIELD, [value]) if self.current().generator => {
+                self.expr(value, false)?;
+                self.emit(Op::Yield);
+            }
+            (
+                symbol::QUOTE
+                | symbol::QUASIQUOTE
+                | symbol::IF
+                | symbol::WHEN
+                | symbol::UNLESS
+                | symbol::DEFINE
+                | symbol::SET
+                | symbol::LAMBDA
+                | symbol::LET
+                | symbol::LET_STAR
+                | symbol::LET_VALUES
+                | symbol::DEFMACRO
+                | symbol::MATCH
+                | symbol::TRY
+                | symbol::YIELD,
+                _,
+            ) => return Err(unsupported()),
+            _ => self.call(head, args, tail)?,
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, head: &Expr, args: &[Expr], tail: bool) -> Result<()> {
+        self.expr(head, false)?;
+        for arg in args {
+            self.expr(arg, false)?;
+        }
+        let argc = args.len() as u32;
+        self.emit(if tail {
+            Op::TailCall(argc)
+        } else {
+            Op::Call(argc)
+        });
+        Ok(())
+    }
+
+    // Every expression but the last is evaluated for its effects.
+    // Each clause's test jumps past its body when false; a clause without
+    // a body keeps its test's value as the result.
+    fn cond(&mut self, form: &Expr, clauses: &[Expr], tail: bool) -> Result<()> {
+        let mut to_end = Vec::new();
+        for (i, clause) in clauses.iter().enumerate() {
+            let Expr::List(parts) = clause else {
+                return Err(Unsupported(form.clone()));
+            };
+            match parts.as_slice() {
+                [Expr::Symbol(symbol::ELSE), body @ ..] if i == clauses.len() - 1 => {
+                    self.body(body, tail)?;
+                    break;
+                }
+                [Expr::Symbol(symbol::ELSE), ..] | [] => return Err(Unsupported(form.clone())),
+                [test] => {
+                    self.expr(test, false)?;
+                    self.emit(Op::Dup);
+                    let to_next = self.emit(Op::JumpIfFalse(0));
+                    to_end.push(self.emit(Op::Jump(0)));
+                    self.patch(to_next);
+                    self.emit(Op::Pop);
+                }
+                [test, body @ ..] => {
+                    self.expr(test, false)?;
+                    let to_next = self.emit(Op::JumpIfFalse(0));
+                    self.body(body, tail)?;
+                    to_end.push(self.emit(Op::Jump(0)));
+                    self.patch(to_next);
+                }
+            }
+            if i == clauses.len() - 1 {
+                self.emit(Op::Nil);
+            }
+        }
+        if clauses.is_empty() {
+            self.emit(Op::Nil);
+        }
+        for at in to_end {
+            self.patch(at);
+        }
+        Ok(())
+    }
+
+    fn body(&mut self, body: &[Expr], tail: bool) -> Result<()> {
+        let Some((last, init)) = body.split_last() else {
+            self.emit(Op::Nil);
+            return Ok(());
+        };
+        for expr in init {
+            self.expr(expr, false)?;
+            self.emit(Op::Pop);
+        }
+        self.expr(last, tail)
+    }
+
+    // A body that introduces variables: a lambda's or a let's. Its
+    // internal defines are declared up front so that they can refer to
+    // each other.
+    fn scoped_body(&mut self, body: &[Expr], tail: bool) -> Result<()> {
+        let mut names = Vec::new();
+        internal_defines(body, &mut names);
+        for name in names {
+            let local = self.declare(name);
+            self.emit(Op::MakeBox(local.slot));
+        }
+        self.body(body, tail)
+    }
+
+    fn define(
+        &mut self,
+        form: &Expr,
+        name: SymbolId,
+        value: impl FnOnce(&mut Self) -> Result<()>,
+    ) -> Result<()> {
+        let global = self.functions.len() == 1 && self.current().depth == 0;
+        let op = if global {
+            Op::DefineGlobal(name)
+        } else {
+            let state = self.current();
+            match state.locals[state.scope..]
+                .iter()
+                .rev()
+                .find(|l| l.name == name)
+            {
+                Some(local) if local.boxed => Op::SetBox(local.slot),
+                // A define that isn't directly in a body.
+                _ => return Err(Unsupported(form.clone())),
+            }
+        };
+        value(self)?;
+        self.emit(op);
+        self.emit(Op::Nil);
+        Ok(())
+    }
+
+    fn lambda(&mut self, form: &Expr, lambda: &Lambda) -> Result<()> {
+        self.function(form, lambda, false)?;
+        let index = self.current().function.functions.len() as u32 - 1;
+        self.emit(Op::Closure(index));
+        Ok(())
+    }
+
+    // Compiles lambda into a nested function of the current one.
+    fn function(&mut self, form: &Expr, lambda: &Lambda, generator: bool) -> Result<()> {
+        if !lambda.keys.is_empty() {
+            return Err(Unsupported(form.clone()));
+        }
+        let mut state = FnState {
+            generator: generator || self.current().generator,
+            ..FnState::default()
+        };
+        state.function.params = lambda.params.len();
+        state.function.rest = lambda.rest.is_some();
+        assigned_names(&lambda.body, &mut state.assigned);
+        self.functions.push(state);
+        let params = lambda.params.iter().chain(&lambda.rest);
+        for &param in params {
+            let local = self.declare(param);
+            if local.boxed {
+                self.emit(Op::MakeBox(local.slot));
+            }
+        }
+        let result = self.scoped_body(&lambda.body, true);
+        self.emit(Op::Return);
+        let function = self.functions.pop().unwrap().function;
+        result?;
+        self.current()
+            .function
+            .functions
+            .push(Shared::new(function));
+        Ok(())
+    }
+
+    // let evaluates every value before binding any of the names; let*
+    // binds each name before evaluating the next value.
+    fn let_form(
+        &mut self,
+        form: &Expr,
+        sequential: bool,
+        bindings: &[Expr],
+        body: &[Expr],
+        tail: bool,
+    ) -> Result<()> {
+        let mut pending = Vec::new();
+        let outer = self.current().locals.len();
+        if self.functions.len() == 1 && self.current().depth == 0 {
+            // Variables introduced at the top level aren't seen by the
+            // function's assigned names, so work them out here.
+            assigned_names(body, &mut self.current().assigned);
+            for binding in bindings {
+                if let Expr::List(parts) = binding {
+                    assigned_names(&parts[1..], &mut self.current().assigned);
+                }
+            }
+        }
+        for binding in bindings {
+            let Expr::List(parts) = binding else {
+                return Err(Unsupported(form.clone()));
+            };
+            let [Expr::Symbol(name), value] = parts.as_slice() else {
+                return Err(Unsupported(form.clone()));
+            };
+            self.expr(value, false)?;
+            let local = self.declare(*name);
+            self.emit(Op::SetLocal(local.slot));
+            if local.boxed {
+                self.emit(Op::MakeBox(local.slot));
+            }
+            if !sequential {
+                // Hidden until every value has been evaluated.
+                let state = self.current();
+                pending.push(state.locals.pop().unwrap());
+            }
+        }
+        let state = self.current();
+        state.locals.extend(pending);
+        state.depth += 1;
+        let scope = core::mem::replace(&mut state.scope, outer);
+        let result = self.scoped_body(body, tail);
+        let state = self.current();
+        state.depth -= 1;
+        state.scope = scope;
+        state.locals.truncate(outer);
+        result
+    }
+}
+
+fn lambda_parts(params: &Expr, body: &[Expr]) -> Option<Lambda> {
+    let mut lambda = Lambda {
+        body: body.to_vec(),
+        ..Lambda::default()
+    };
+    let mut params = params;
+    loop {
+        match params {
+            Expr::Pair(car, cdr) => {
+                lambda.params.push(param_name(car)?);
+                params = cdr;
+            }
+            Expr::List(items) => {
+                for item in items {
+                    lambda.params.push(param_name(item)?);
+                }
+                return Some(lambda);
+            }
+            Expr::Nil => return Some(lambda),
+            Expr::Symbol(rest) => {
+                lambda.rest = Some(*rest);
+                return Some(lambda);
+            }
+            _ => return None,
+        }
+    }
+}
+
+// Keyword parameters are left to the tree-walker.
+fn param_name(param: &Expr) -> Option<SymbolId> {
+    match param {
+        Expr::Symbol(symbol::AND_KEY) => None,
+        Expr::Symbol(name) => Some(*name),
+        _ => None,
+    }
+}
+
+// The names defined directly in a body, including inside a begin.
+fn internal_defines(body: &[Expr], names: &mut Vec<SymbolId>) {
+    for expr in body {
+        let Expr::List(items) = expr else {
+            continue;
+        };
+        match items.as_slice() {
+            [Expr::Symbol(symbol::DEFINE), Expr::Symbol(name), ..] => names.push(*name),
+            [Expr::Symbol(symbol::DEFINE), Expr::List(signature), ..] => {
+                if let Some(Expr::Symbol(name)) = signature.first() {
+                    names.push(*name);
+                }
+            }
+            [Expr::Symbol(symbol::DEFINE), Expr::Pair(name, _), ..] => {
+                if let Expr::Symbol(name) = **name {
+                    names.push(name);
+                }
+            }
+            [Expr::Symbol(symbol::BEGIN), rest @ ..] => internal_defines(rest, names),
+            _ => {}
+        }
+    }
+}
+
+// Every name that set! or define assigns anywhere in exprs, nested lambdas
+// included. Quoted data is searched too, which at worst boxes a variable
+// that didn't need it.
+fn assigned_names(exprs: &[Expr], names: &mut BTreeSet<SymbolId>) {
+    for expr in exprs {
+        match expr {
+            Expr::List(items) => {
+                if let [Expr::Symbol(symbol::SET), Expr::Symbol(name), ..] = items.as_slice() {
+                    names.insert(*name);
+                }
+                let mut defined = Vec::new();
+                internal_defines(core::slice::from_ref(expr), &mut defined);
+                names.extend(defined);
+                assigned_names(items, names);
+            }
+            Expr::Pair(car, cdr) => {
+                assigned_names(core::slice::from_ref(&**car), names);
+                assigned_names(core::slice::from_ref(&**cdr), names);
+            }
+            Expr::Lambda(lambda) => assigned_names(&lambda.body, names),
+            Expr::Vector(items) => assigned_names(items, names),
+            Expr::Map(entries) => {
+                for (key, value) in entries {
+                    assigned_names(core::slice::from_ref(key), names);
+                    assigned_names(core::slice::from_ref(value), names);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::read;
+    use alloc::string::ToString;
+
+    fn compiled(source: &str) -> Shared<Function> {
+        compile(&read(source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn display_test() {
+        let function = compiled("(define (f x . more) (if x 'yes (g)))");
+        assert_eq!(
+            function.to_string(),
+            "   0  Closure(0)  ; function 0
+   1  DefineGlobal(\"f\")
+   2  Nil
+   3  Return
+function 0: 1 params and a rest, 2 slots
+     0  Local(0)
+     1  JumpIfFalse(4)
+     2  Const(0)  ; yes
+     3  Jump(6)
+     4  Global(\"g\")
+     5  TailCall(0)
+     6  Return
+"
+        );
+    }
+
+    #[test]
+    fn compile_test() {
+        let function = compiled("(if x 1 2)");
+        assert_eq!(
+            function.code,
+            [
+                Op::Global("x".into()),
+                Op::JumpIfFalse(4),
+                Op::Const(0),
+                Op::Jump(5),
+                Op::Const(1),
+                Op::Return,
+            ]
+        );
+        assert_eq!(function.constants, [Value::Integer(1), Value::Integer(2)]);
+
+        let function = compiled("(lambda (a b) (f b a))");
+        let lambda = &function.functions[0];
+        assert_eq!((lambda.params, lambda.rest, lambda.slots), (2, false, 2));
+        assert_eq!(
+            lambda.code,
+            [
+                Op::Global("f".into()),
+                Op::Local(1),
+                Op::Local(0),
+                Op::TailCall(2),
+                Op::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn and_or_test() {
+        // Every test but the last is kept on the stack while it is looked
+        // at, in case it is the result.
+        assert_eq!(
+            compiled("(and a b)").code,
+            [
+                Op::Global("a".into()),
+                Op::Dup,
+                Op::JumpIfFalse(5),
+                Op::Pop,
+                Op::Global("b".into()),
+                Op::Return,
+            ]
+        );
+        assert_eq!(
+            compiled("(or a b)").code,
+            [
+                Op::Global("a".into()),
+                Op::Dup,
+                Op::JumpIfFalse(4),
+                Op::Jump(6),
+                Op::Pop,
+                Op::Global("b".into()),
+                Op::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn capture_test() {
+        // n is assigned, so it lives in a box the inner lambda shares.
+        let function = compiled("(lambda (n) (lambda () (set! n (+ n 1)) n))");
+        let outer = &function.functions[0];
+        assert_eq!(outer.code[0], Op::MakeBox(0));
+        let inner = &outer.functions[0];
+        assert_eq!(inner.captures, [CaptureSource::Local(0)]);
+        assert!(inner.code.contains(&Op::SetCaptureBox(0)));
+        assert!(inner.code.contains(&Op::CaptureBox(0)));
+
+        let function = compiled("(lambda (x) (lambda () (lambda () x)))");
+        let middle = &function.functions[0].functions[0];
+        assert_eq!(middle.captures, [CaptureSource::Local(0)]);
+        assert_eq!(middle.functions[0].captures, [CaptureSource::Capture(0)]);
+    }
+
+    #[test]
+    fn generator_test() {
+        let function = compiled("(generator (yield 1) 2)");
+        assert_eq!(function.code, [Op::Generator(0), Op::Return]);
+        assert_eq!(
+            function.functions[0].code,
+            [Op::Const(0), Op::Yield, Op::Pop, Op::Const(1), Op::Return]
+        );
+    }
+
+    #[test]
+    fn unsupported_test() {
+        for source in [
+            "`(a ,b)",
+            "(match x (_ 1))",
+            "(try 1 (catch e 2))",
+            "(lambda (&key a) a)",
+            "(lambda (x) (if x (define y 1)) y)",
+            "(if)",
+            "(yield 1)",
+            "(generator (yield))",
+            "(when)",
+            "(cond (else 1) (x 2))",
+            "(cond x)",
+        ] {
+            assert!(compile(&read(source).unwrap()).is_err(), "{source}");
+        }
+    }
+}
@@ -0,0 +1,674 @@
+use alloc::{borrow::ToOwned, string::String, vec, vec::Vec};
+use core::{error::Error, fmt};
+
+use crate::{eval::EvalError, parser::Expr, value::Value};
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ConversionError {
+    pub expected: &'static str,
+    pub found: Expr,
+}
+
+impl ConversionError {
+    fn new(expected: &'static str, found: Expr) -> Self {
+        ConversionError { expected, found }
+    }
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected {}, found {}", self.expected, self.found)
+    }
+}
+
+impl Error for ConversionError {}
+
+impl From<bool> for Expr {
+    fn from(b: bool) -> Self {
+        Expr::Bool(b)
+    }
+}
+
+impl From<i64> for Expr {
+    fn from(i: i64) -> Self {
+        Expr::Integer(i)
+    }
+}
+
+impl From<f64> for Expr {
+    fn from(f: f64) -> Self {
+        Expr::Float(f)
+    }
+}
+
+impl From<char> for Expr {
+    fn from(c: char) -> Self {
+        Expr::Char(c)
+    }
+}
+
+impl From<String> for Expr {
+    fn from(s: String) -> Self {
+        Expr::String(s)
+    }
+}
+
+impl From<&str> for Expr {
+    fn from(s: &str) -> Self {
+        Expr::String(s.to_owned())
+    }
+}
+
+impl From<()> for Expr {
+    fn from(_: ()) -> Self {
+        Expr::Nil
+    }
+}
+
+impl<T: Into<Expr>> From<Vec<T>> for Expr {
+    fn from(v: Vec<T>) -> Self {
+        Expr::List(v.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Expr>> From<Option<T>> for Expr {
+    fn from(o: Option<T>) -> Self {
+        o.map_or(Expr::Nil, Into::into)
+    }
+}
+
+impl<A: Into<Expr>, B: Into<Expr>> From<(A, B)> for Expr {
+    fn from((a, b): (A, B)) -> Self {
+        Expr::List(vec![a.into(), b.into()])
+    }
+}
+
+impl<A: Into<Expr>, B: Into<Expr>, C: Into<Expr>> From<(A, B, C)> for Expr {
+    fn from((a, b, c): (A, B, C)) -> Self {
+        Expr::List(vec![a.into(), b.into(), c.into()])
+    }
+}
+
+impl TryFrom<Expr> for bool {
+    type Error = ConversionError;
+
+    fn try_from(expr: Expr) -> Result<Self, Self::Error> {
+        match expr {
+            Expr::Bool(b) => Ok(b),
+            other => Err(ConversionError::new("boolean", other)),
+        }
+    }
+}
+
+impl TryFrom<Expr> for i64 {
+    type Error = ConversionError;
+
+    fn try_from(expr: Expr) -> Result<Self, Self::Error> {
+        match expr {
+            Expr::Integer(i) => Ok(i),
+            other => Err(ConversionError::new("integer", other)),
+        }
+    }
+}
+
+// Integers are accepted too, since every i64 has a nearby f64.
+impl TryFrom<Expr> for f64 {
+    type Error = ConversionError;
+
+    fn try_from(expr: Expr) -> Result<Self, Self::Error> {
+        match expr {
+            Expr::Float(f) => Ok(f),
+            Expr::Integer(i) => Ok(i as f64),
+            other => Err(ConversionError::new("number", other)),
+        }
+    }
+}
+
+impl TryFrom<Expr> for char {
+    type Error = ConversionError;
+
+    fn try_from(expr: Expr) -> Result<Self, Self::Error> {
+        match expr {
+            Expr::Char(c) => Ok(c),
+            other => Err(ConversionError::new("character", other)),
+        }
+    }
+}
+
+impl TryFrom<Expr> for String {
+    type Error = ConversionError;
+
+    fn try_from(expr: Expr) -> Result<Self, Self::Error> {
+        match expr {
+            Expr::String(s) => Ok(s),
+            other => Err(ConversionError::new("string", other)),
+        }
+    }
+}
+
+impl TryFrom<Expr> for () {
+    type Error = ConversionError;
+
+    fn try_from(expr: Expr) -> Result<Self, Self::Error> {
+        match expr {
+            Expr::Nil => Ok(()),
+            other => Err(ConversionError::new("nil", other)),
+        }
+    }
+}
+
+impl<T> TryFrom<Expr> for Vec<T>
+where
+    T: TryFrom<Expr, Error = ConversionError>,
+{
+    type Error = ConversionError;
+
+    fn try_from(expr: Expr) -> Result<Self, Self::Error> {
+        match expr {
+            Expr::List(items) => items.into_iter().map(T::try_from).collect(),
+            other => Err(ConversionError::new("list", other)),
+        }
+    }
+}
+
+impl<T> TryFrom<Expr> for Option<T>
+where
+    T: TryFrom<Expr, Error = ConversionError>,
+{
+    type Error = ConversionError;
+
+    fn try_from(expr: Expr) -> Result<Self, Self::Error> {
+        match expr {
+            Expr::Nil => Ok(None),
+            other => T::try_from(other).map(Some),
+        }
+    }
+}
+
+fn list_of(expected: &'static str, len: usize, expr: Expr) -> Result<Vec<Expr>, ConversionError> {
+    match expr {
+        Expr::List(items) if items.len() == len => Ok(items),
+        other => Err(ConversionError::new(expected, other)),
+    }
+}
+
+impl<A, B> TryFrom<Expr> for (A, B)
+where
+    A: TryFrom<Expr, Error = ConversionError>,
+    B: TryFrom<Expr, Error = ConversionError>,
+{
+    type Error = ConversionError;
+
+    fn try_from(expr: Expr) -> Result<Self, Self::Error> {
+        let mut items = list_of("list of length 2", 2, expr)?.into_iter();
+        let a = A::try_from(items.next().unwrap())?;
+        let b = B::try_from(items.next().unwrap())?;
+        Ok((a, b))
+    }
+}
+
+impl<A, B, C> TryFrom<Expr> for (A, B, C)
+where
+    A: TryFrom<Expr, Error = ConversionError>,
+    B: TryFrom<Expr, Error = ConversionError>,
+    C: TryFrom<Expr, Error = ConversionError>,
+{
+    type Error = ConversionError;
+
+    fn try_from(expr: Expr) -> Result<Self, Self::Error> {
+        let mut items = list_of("list of length 3", 3, expr)?.into_iter();
+        let a = A::try_from(items.next().unwrap())?;
+        let b = B::try_from(items.next().unwrap())?;
+        let c = C::try_from(items.next().unwrap())?;
+        Ok((a, b, c))
+    }
+}
+
+// The same conversions for runtime values, so host functions can take
+// their arguments and give back their results as Rust types. A failed
+// conversion is the TypeMismatch a builtin would report, which a host
+// function can pass on with ?. Implementing the two traits for a type of
+// one's own makes it convert inside lists, options and tuples as well.
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> Result<Self, EvalError>;
+}
+
+fn mismatch(expected: &'static str, found: Value) -> EvalError {
+    EvalError::TypeMismatch { expected, found }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Self, EvalError> {
+        Ok(value)
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> Result<Self, EvalError> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            other => Err(mismatch("boolean", other)),
+        }
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> Value {
+        Value::Integer(self)
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: Value) -> Result<Self, EvalError> {
+        match value {
+            Value::Integer(i) => Ok(i),
+            other => Err(mismatch("integer", other)),
+        }
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Float(self)
+    }
+}
+
+// As with Expr, integers are accepted too.
+impl FromValue for f64 {
+    fn from_value(value: Value) -> Result<Self, EvalError> {
+        match value {
+            Value::Float(f) => Ok(f),
+            Value::Integer(i) => Ok(i as f64),
+            other => Err(mismatch("number", other)),
+        }
+    }
+}
+
+impl IntoValue for char {
+    fn into_value(self) -> Value {
+        Value::Char(self)
+    }
+}
+
+impl FromValue for char {
+    fn from_value(value: Value) -> Result<Self, EvalError> {
+        match value {
+            Value::Char(c) => Ok(c),
+            other => Err(mismatch("character", other)),
+        }
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::String(self)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::String(self.to_owned())
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<Self, EvalError> {
+        match value {
+            Value::String(s) => Ok(s),
+            other => Err(mismatch("string", other)),
+        }
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Nil
+    }
+}
+
+// The empty list is nil as well.
+impl FromValue for () {
+    fn from_value(value: Value) -> Result<Self, EvalError> {
+        match value {
+            Value::Nil => Ok(()),
+            Value::List(items) if items.is_empty() => Ok(()),
+            other => Err(mismatch("nil", other)),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        Value::List(self.into_iter().map(IntoValue::into_value).collect())
+    }
+}
+
+// A vector converts as well as a list.
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> Result<Self, EvalError> {
+        match value {
+            Value::Nil => Ok(Vec::new()),
+            Value::List(items) | Value::Vector(items) => {
+                items.into_iter().map(T::from_value).collect()
+            }
+            other => Err(mismatch("list", other)),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        self.map_or(Value::Nil, IntoValue::into_value)
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> Result<Self, EvalError> {
+        match value {
+            Value::Nil => Ok(None),
+            other => T::from_value(other).map(Some),
+        }
+    }
+}
+
+// Tuples are lists of their length.
+macro_rules! tuple_conversions {
+    ($len:expr, $expected:expr, $($name:ident),*) => {
+        impl<$($name: IntoValue),*> IntoValue for ($($name,)*) {
+            #[allow(non_snake_case)]
+            fn into_value(self) -> Value {
+                let ($($name,)*) = self;
+                Value::List(vec![$($name.into_value()),*])
+            }
+        }
+
+        impl<$($name: FromValue),*> FromValue for ($($name,)*) {
+            fn from_value(value: Value) -> Result<Self, EvalError> {
+                let mut items = match value {
+                    Value::List(items) if items.len() == $len => items.into_iter(),
+                    other => return Err(mismatch($expected, other)),
+                };
+                Ok(($($name::from_value(items.next().unwrap())?,)*))
+            }
+        }
+
+        impl<$($name: FromValue),*> FromArgs for ($($name,)*) {
+            fn from_args(args: &[Value]) -> Result<Self, EvalError> {
+                let mut args = match args {
+                    args if args.len() == $len => args.iter().cloned(),
+                    _ => {
+                        return Err(EvalError::ArityMismatch {
+                            expected: $len,
+                            found: args.len(),
+                        })
+                    }
+                };
+                Ok(($($name::from_value(args.next().unwrap())?,)*))
+            }
+        }
+    };
+}
+
+// What #[derive(TasksValue)] expands to with the derive feature: a struct
+// is a map from keywords named after its fields to the fields' values.
+pub fn struct_into_value<const N: usize>(fields: [(&str, Value); N]) -> Value {
+    Value::Map(
+        fields
+            .into_iter()
+            .map(|(name, value)| (Value::Keyword(name.into()), value))
+            .collect(),
+    )
+}
+
+// The field called name of a struct converted from value, which has to be
+// a map. A field the map leaves out converts from (), so an Option field
+// can be left out.
+pub fn struct_field<T: FromValue>(
+    value: &Value,
+    expected: &'static str,
+    name: &str,
+) -> Result<T, EvalError> {
+    let Value::Map(entries) = value else {
+        return Err(mismatch(expected, value.clone()));
+    };
+    let key = Value::Keyword(name.into());
+    let field = entries.iter().find(|(k, _)| *k == key);
+    T::from_value(field.map_or(Value::Nil, |(_, v)| v.clone()))
+}
+
+// A host function's whole argument list, as a tuple with one element per
+// argument.
+pub trait FromArgs: Sized {
+    fn from_args(args: &[Value]) -> Result<Self, EvalError>;
+}
+
+impl FromArgs for () {
+    fn from_args(args: &[Value]) -> Result<Self, EvalError> {
+        match args {
+            [] => Ok(()),
+            _ => Err(EvalError::ArityMismatch {
+                expected: 0,
+                found: args.len(),
+            }),
+        }
+    }
+}
+
+tuple_conversions!(1, "list of length 1", A);
+tuple_conversions!(2, "list of length 2", A, B);
+tuple_conversions!(3, "list of length 3", A, B, C);
+tuple_conversions!(4, "list of length 4", A, B, C, D);
+
+// From and TryFrom for the types above, for callers that would rather use
+// the standard traits.
+macro_rules! std_conversions {
+    ($($ty:ty),*) => {
+        $(
+            impl From<$ty> for Value {
+                fn from(value: $ty) -> Self {
+                    value.into_value()
+                }
+            }
+
+            impl TryFrom<Value> for $ty {
+                type Error = EvalError;
+
+                fn try_from(value: Value) -> Result<Self, Self::Error> {
+                    <$ty>::from_value(value)
+                }
+            }
+        )*
+    };
+}
+
+std_conversions!(bool, i64, f64, char, String);
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        s.into_value()
+    }
+}
+
+impl From<()> for Value {
+    fn from(_: ()) -> Self {
+        Value::Nil
+    }
+}
+
+impl<T: IntoValue> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Self {
+        items.into_value()
+    }
+}
+
+impl<T: FromValue> TryFrom<Value> for Vec<T> {
+    type Error = EvalError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        Vec::from_value(value)
+    }
+}
+
+impl<T: IntoValue> From<Option<T>> for Value {
+    fn from(option: Option<T>) -> Self {
+        option.into_value()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn into_expr_test() {
+        assert_eq!(Expr::from(42), Expr::Integer(42));
+        assert_eq!(Expr::from("hi"), Expr::String("hi".to_owned()));
+        assert_eq!(Expr::from(None::<i64>), Expr::Nil);
+        assert_eq!(
+            Expr::from(vec![(1, "one"), (2, "two")]),
+            Expr::List(vec![
+                Expr::List(vec![Expr::Integer(1), Expr::String("one".to_owned())]),
+                Expr::List(vec![Expr::Integer(2), Expr::String("two".to_owned())]),
+            ])
+        );
+    }
+
+    #[test]
+    fn try_from_expr_test() {
+        assert_eq!(i64::try_from(Expr::Integer(42)), Ok(42));
+        assert_eq!(bool::try_from(Expr::from(true)), Ok(true));
+        assert_eq!(f64::try_from(Expr::Integer(2)), Ok(2.));
+        assert_eq!(f64::try_from(Expr::from(1.5)), Ok(1.5));
+        assert_eq!(
+            Vec::<Option<i64>>::try_from(Expr::List(vec![Expr::Integer(1), Expr::Nil])),
+            Ok(vec![Some(1), None])
+        );
+        assert_eq!(
+            <(i64, String)>::try_from(Expr::from((1, "one"))),
+            Ok((1, "one".to_owned()))
+        );
+        assert_eq!(
+            i64::try_from(Expr::Symbol("x".into())),
+            Err(ConversionError::new("integer", Expr::Symbol("x".into())))
+        );
+        assert!(<(i64, i64)>::try_from(Expr::from(vec![1])).is_err());
+    }
+
+    // A host type that converts through a map, as a derive would write it.
+    #[derive(PartialEq, Debug)]
+    struct Point {
+        x: i64,
+        y: i64,
+    }
+
+    impl IntoValue for Point {
+        fn into_value(self) -> Value {
+            Value::Map(vec![
+                (Value::Keyword("x".into()), self.x.into()),
+                (Value::Keyword("y".into()), self.y.into()),
+            ])
+        }
+    }
+
+    impl FromValue for Point {
+        fn from_value(value: Value) -> Result<Self, EvalError> {
+            let Value::Map(entries) = &value else {
+                return Err(mismatch("point", value));
+            };
+            let field = |name: &str| {
+                entries
+                    .iter()
+                    .find(|(key, _)| *key == Value::Keyword(name.into()))
+                    .map(|(_, v)| i64::try_from(v.clone()))
+                    .unwrap_or_else(|| Err(mismatch("point", value.clone())))
+            };
+            Ok(Point {
+                x: field("x")?,
+                y: field("y")?,
+            })
+        }
+    }
+
+    #[test]
+    fn into_value_test() {
+        assert_eq!(Value::from(42), Value::Integer(42));
+        assert_eq!(Value::from("hi"), Value::String("hi".to_owned()));
+        assert_eq!(Value::from(None::<i64>), Value::Nil);
+        assert_eq!(
+            (1, "one", vec![true]).into_value(),
+            Value::List(vec![
+                Value::Integer(1),
+                Value::String("one".to_owned()),
+                Value::List(vec![Value::Bool(true)]),
+            ])
+        );
+        assert_eq!(
+            Value::from(vec![Point { x: 1, y: 2 }]).to_string(),
+            "({:x 1 :y 2})"
+        );
+    }
+
+    #[test]
+    fn from_value_test() {
+        assert_eq!(i64::try_from(Value::Integer(42)), Ok(42));
+        assert_eq!(f64::try_from(Value::Integer(2)), Ok(2.));
+        assert_eq!(String::try_from(Value::from("s")), Ok("s".to_owned()));
+        assert_eq!(Vec::<i64>::try_from(Value::Nil), Ok(vec![]));
+        assert_eq!(
+            Vec::<Option<char>>::try_from(Value::Vector(vec![Value::Char('a'), Value::Nil])),
+            Ok(vec![Some('a'), None])
+        );
+        assert_eq!(
+            <(i64, String)>::from_value((1, "one").into_value()),
+            Ok((1, "one".to_owned()))
+        );
+        assert_eq!(<()>::from_value(Value::List(vec![])), Ok(()));
+        let value = Value::from(vec![Point { x: 1, y: 2 }, Point { x: -1, y: 0 }]);
+        assert_eq!(
+            Vec::<Point>::try_from(value),
+            Ok(vec![Point { x: 1, y: 2 }, Point { x: -1, y: 0 }])
+        );
+        assert_eq!(
+            bool::try_from(Value::Integer(0)),
+            Err(EvalError::TypeMismatch {
+                expected: "boolean",
+                found: Value::Integer(0)
+            })
+        );
+        assert!(<(i64, i64)>::from_value(Value::from(vec![1])).is_err());
+        assert!(Point::from_value(Value::Map(vec![])).is_err());
+    }
+
+    #[test]
+    fn from_args_test() {
+        let args = [Value::Integer(1), Value::from("a")];
+        assert_eq!(<(i64, String)>::from_args(&args), Ok((1, "a".to_owned())));
+        assert_eq!(<(Value,)>::from_args(&args[..1]), Ok((Value::Integer(1),)));
+        assert_eq!(
+            <(i64,)>::from_args(&args),
+            Err(EvalError::ArityMismatch {
+                expected: 1,
+                found: 2
+            })
+        );
+        assert!(<(String, String)>::from_args(&args).is_err());
+        assert!(<()>::from_args(&args).is_err());
+    }
+}
@@ -0,0 +1,344 @@
+// Which lines of a file ran, and which way its branches went, from the
+// events an interpreter's hook is told, written out as an lcov tracefile
+// for genhtml or an editor to show:
+//
+//   SF:fact.lisp
+//   BRDA:2,0,0,3
+//   BRDA:2,0,1,1
+//   DA:2,4
+//   ...
+//   end_of_record
+//
+// A line is one that can run if a call, define, defmacro, if, when or
+// unless starts on it, outside quoted data and macro calls, and it ran as
+// many times as those were reported. Each if, when and unless has two
+// branches, the one for a true test and the one for a false one. The files
+// loaded while the hook is installed are counted, in the order they were
+// first run, along with the first code run other than from a file if it's
+// been given a name. Only the tree-walker reports events.
+use std::fmt::Write as _;
+
+use crate::{
+    hooks::{self, Event, Origin},
+    interpreter::Interpreter,
+    io,
+    lexer::Span,
+    parser::{read_all_spanned, Expr, SpannedExpr},
+    symbol::SymbolId,
+    sync::{Lock, Shared, Weak},
+};
+
+#[derive(Clone, Default)]
+pub struct Coverage(Shared<Lock<Counts>>);
+
+#[derive(Default)]
+struct Counts {
+    // What to call the code run other than from a file, and its text.
+    source: Option<(String, String)>,
+    // The source of the first such code.
+    run: Option<Weak<[SpannedExpr]>>,
+    files: Vec<File>,
+}
+
+struct File {
+    path: String,
+    text: String,
+    // How many times forms starting at each line ran.
+    lines: Vec<(usize, usize)>,
+    // The start of each branching form that ran, and how many times its
+    // test came out true and false.
+    branches: Vec<(usize, usize, usize)>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage::default()
+    }
+
+    // Has the first code run other than from a file, such as by eval_str,
+    // counted as the file at path with text in it.
+    pub fn set_source(&self, path: &str, text: &str) {
+        self.0.borrow_mut().source = Some((path.to_string(), text.to_string()));
+    }
+
+    pub fn install(&self, interpreter: &mut Interpreter) {
+        let coverage = self.clone();
+        interpreter.set_hook(move |event| coverage.observe(event));
+    }
+
+    // Counts event, for a hook that does more than count.
+    pub fn observe(&self, event: Event) {
+        if let Event::Return { .. } = event {
+            return;
+        }
+        let Some(origin) = hooks::origin() else {
+            return;
+        };
+        let mut counts = self.0.borrow_mut();
+        let Some(file) = counts.file(&origin) else {
+            return;
+        };
+        let span = origin.span;
+        let (line, _) = span.line_col(&file.text);
+        match file.lines.iter_mut().find(|(at, _)| *at == line) {
+            Some((_, count)) => *count += 1,
+            None => file.lines.push((line, 1)),
+        }
+        if let Event::Branch { taken, .. } = event {
+            let branch = match file.branches.iter().position(|b| b.0 == span.start) {
+                Some(i) => &mut file.branches[i],
+                None => {
+                    file.branches.push((span.start, 0, 0));
+                    file.branches.last_mut().unwrap()
+                }
+            };
+            match taken {
+                true => branch.1 += 1,
+                false => branch.2 += 1,
+            }
+        }
+    }
+
+    // A tracefile record for each file, with every line that can run and
+    // every branch, including those that didn't.
+    pub fn lcov(&self) -> String {
+        self.0.borrow().files.iter().map(File::lcov).collect()
+    }
+}
+
+impl Counts {
+    // The counts for the file a form was read from, or None if it isn't
+    // counted or can't be read.
+    fn file(&mut self, origin: &Origin) -> Option<&mut File> {
+        let (path, text) = match &origin.file {
+            Some(path) => (path.to_string(), None),
+            None => {
+                let run = self.run.get_or_insert_with(|| origin.source.clone());
+                if !Weak::ptr_eq(run, &origin.source) {
+                    return None;
+                }
+                let (path, text) = self.source.as_ref()?;
+                (path.clone(), Some(text.clone()))
+            }
+        };
+        if let Some(i) = self.files.iter().position(|file| file.path == path) {
+            return Some(&mut self.files[i]);
+        }
+        let text = match text {
+            Some(text) => text,
+            None => io::read_file(&path).ok()?,
+        };
+        self.files.push(File {
+            path,
+            text,
+            lines: Vec::new(),
+            branches: Vec::new(),
+        });
+        self.files.last_mut()
+    }
+}
+
+impl File {
+    fn lcov(&self) -> String {
+        let mut code = Code::default();
+        if let Ok(forms) = read_all_spanned(&self.text) {
+            code.macros = forms.iter().filter_map(defmacro_name).collect();
+            for form in &forms {
+                code.expr(form);
+            }
+        }
+        let line = |span: Span| span.line_col(&self.text).0;
+        let mut out = format!("TN:\nSF:{}\n", self.path);
+        let mut hit = 0;
+        for (block, span) in code.branches.iter().enumerate() {
+            let line = line(*span);
+            match self.branches.iter().find(|b| b.0 == span.start) {
+                Some(&(_, taken, not_taken)) => {
+                    writeln!(out, "BRDA:{line},{block},0,{taken}").unwrap();
+                    writeln!(out, "BRDA:{line},{block},1,{not_taken}").unwrap();
+                    hit += usize::from(taken > 0) + usize::from(not_taken > 0);
+                }
+                None => {
+                    writeln!(out, "BRDA:{line},{block},0,-").unwrap();
+                    writeln!(out, "BRDA:{line},{block},1,-").unwrap();
+                }
+            }
+        }
+        let branches = code.branches.len() * 2;
+        writeln!(out, "BRF:{branches}\nBRH:{hit}").unwrap();
+        let mut lines: Vec<usize> = code.lines.iter().map(|span| line(*span)).collect();
+        lines.extend(self.lines.iter().map(|(line, _)| *line));
+        lines.sort_unstable();
+        lines.dedup();
+        let mut hit = 0;
+        for &line in &lines {
+            let count = self.lines.iter().find(|(at, _)| *at == line);
+            let count = count.map_or(0, |(_, count)| *count);
+            writeln!(out, "DA:{line},{count}").unwrap();
+            hit += usize::from(count > 0);
+        }
+        writeln!(out, "LF:{}\nLH:{hit}\nend_of_record", lines.len()).unwrap();
+        out
+    }
+}
+
+// The forms in a file that report when they run.
+#[derive(Default)]
+struct Code {
+    macros: Vec<SymbolId>,
+    lines: Vec<Span>,
+    branches: Vec<Span>,
+}
+
+impl Code {
+    fn expr(&mut self, expr: &SpannedExpr) {
+        match expr {
+            SpannedExpr::List(list) => self.list(list.span, &list.value),
+            SpannedExpr::Vector(vector) => self.body(&vector.value),
+            SpannedExpr::Map(map) => {
+                for (key, value) in &map.value {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            SpannedExpr::Atom(_) | SpannedExpr::DottedList(_) => {}
+        }
+    }
+
+    fn body(&mut self, body: &[SpannedExpr]) {
+        for expr in body {
+            self.expr(expr);
+        }
+    }
+
+    fn list(&mut self, span: Span, items: &[SpannedExpr]) {
+        let Some(first) = items.first() else {
+            return;
+        };
+        let head = symbol(first);
+        if head.is_some_and(|head| self.macros.contains(&head)) {
+            return;
+        }
+        let rest = &items[1..];
+        match head.map(SymbolId::as_str) {
+            Some("quote" | "quasiquote" | "import" | "export") => {}
+            Some("defmacro") => self.lines.push(span),
+            Some("define") => {
+                self.lines.push(span);
+                self.body(rest.get(1..).unwrap_or_default());
+            }
+            Some("if" | "when" | "unless") => {
+                self.lines.push(span);
+                self.branches.push(span);
+                self.body(rest);
+            }
+            Some("lambda" | "generator" | "module") => self.body(rest.get(1..).unwrap_or_default()),
+            Some("let" | "let*" | "let-values") => {
+                let named = rest.first().is_some_and(|name| symbol(name).is_some());
+                let rest = &rest[usize::from(named)..];
+                if let Some(SpannedExpr::List(bindings)) = rest.first() {
+                    for binding in &bindings.value {
+                        if let SpannedExpr::List(binding) = binding {
+                            self.body(binding.value.get(1..).unwrap_or_default());
+                        }
+                    }
+                }
+                self.body(rest.get(1..).unwrap_or_default());
+            }
+            // Clauses aren't calls, but what's in them is code, apart from
+            // match's patterns and the name a catch binds.
+            Some("cond") => self.clauses(rest, 0),
+            Some("match") => {
+                self.body(&rest[..rest.len().min(1)]);
+                self.clauses(rest.get(1..).unwrap_or_default(), 1);
+            }
+            Some("try") => {
+                for expr in rest {
+                    match expr {
+                        SpannedExpr::List(clause)
+                            if clause.value.first().and_then(symbol)
+                                == Some(SymbolId::from("catch")) =>
+                        {
+                            self.body(clause.value.get(2..).unwrap_or_default())
+                        }
+                        _ => self.expr(expr),
+                    }
+                }
+            }
+            Some(name) if SPECIAL.contains(&name) => self.body(rest),
+            _ => {
+                self.lines.push(span);
+                self.body(items);
+            }
+        }
+    }
+
+    fn clauses(&mut self, clauses: &[SpannedExpr], skip: usize) {
+        for clause in clauses {
+            if let SpannedExpr::List(clause) = clause {
+                self.body(clause.value.get(skip..).unwrap_or_default());
+            }
+        }
+    }
+}
+
+// The special forms whose arguments are all code.
+const SPECIAL: &[&str] = &["and", "begin", "or", "set!"];
+
+fn symbol(expr: &SpannedExpr) -> Option<SymbolId> {
+    match expr {
+        SpannedExpr::Atom(atom) => match atom.value {
+            Expr::Symbol(name) => Some(name),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn defmacro_name(form: &SpannedExpr) -> Option<SymbolId> {
+    let SpannedExpr::List(list) = form else {
+        return None;
+    };
+    match list.value.as_slice() {
+        [head, name, ..] if symbol(head) == Some(SymbolId::from("defmacro")) => symbol(name),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lcov(source: &str) -> String {
+        let coverage = Coverage::new();
+        coverage.set_source("test.lisp", source);
+        let mut interpreter = Interpreter::new();
+        coverage.install(&mut interpreter);
+        interpreter.eval_str(source).unwrap();
+        coverage.lcov()
+    }
+
+    #[test]
+    fn lcov_test() {
+        let source = "(define (sign n)
+  (if (< n 0)
+      'negative
+      (if (= n 0) 'zero
+          (list 'positive n))))
+(defmacro twice (x) (list 'begin x x))
+(twice
+  (sign 1))
+(define (unused)
+  (car '(1 2)))
+(let ((x (sign 2))) x)";
+        assert_eq!(
+            lcov(source),
+            "TN:\nSF:test.lisp\n\
+             BRDA:2,0,0,0\nBRDA:2,0,1,3\n\
+             BRDA:4,1,0,0\nBRDA:4,1,1,3\n\
+             BRF:4\nBRH:2\n\
+             DA:1,1\nDA:2,6\nDA:4,6\nDA:5,3\nDA:6,1\nDA:9,1\nDA:10,0\nDA:11,1\n\
+             LF:8\nLH:7\nend_of_record\n"
+        );
+    }
+}
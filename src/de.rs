@@ -0,0 +1,374 @@
+// Deserializing a program's result into a host's Rust types with serde,
+// so that a script can be a configuration file that computes its values:
+//
+//   (define base 8000)
+//   {:name "api" :port (+ base 80) :hosts (list "a" "b")}
+//
+// becomes a struct with name, port and hosts fields. Maps give structs and
+// maps, with keywords, symbols and strings as keys by their names; lists
+// and vectors give sequences and tuples; () gives None, unit and empty
+// sequences; and a symbol, keyword or string names a unit variant, while a
+// map with one entry, {:variant value}, gives any other. Keys are matched
+// as written, so a struct whose fields are written kebab-case in scripts
+// wants #[serde(rename_all = "kebab-case")].
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::{self, Vec},
+};
+use core::{error::Error, fmt};
+
+use serde::de::{
+    self,
+    value::{MapDeserializer, SeqDeserializer},
+    DeserializeOwned, DeserializeSeed, IntoDeserializer, Unexpected, Visitor,
+};
+
+use crate::{
+    interpreter::{Interpreter, InterpreterError},
+    parser::Expr,
+    value::Value,
+};
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum DeserializeError {
+    // The source didn't read or run.
+    Interpreter(InterpreterError),
+    // The value isn't data, or isn't in the shape the type wants.
+    Message(String),
+}
+
+impl fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeserializeError::Interpreter(e) => write!(f, "{e}"),
+            DeserializeError::Message(message) => f.write_str(message),
+        }
+    }
+}
+
+impl Error for DeserializeError {}
+
+impl de::Error for DeserializeError {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        DeserializeError::Message(message.to_string())
+    }
+}
+
+// Runs source in a new interpreter and deserializes the value of its last
+// form. A host that wants its own functions or limits in effect runs the
+// source itself and hands the result to from_value.
+pub fn from_str<T: DeserializeOwned>(source: &str) -> Result<T, DeserializeError> {
+    let value = Interpreter::new()
+        .eval_str(source)
+        .map_err(DeserializeError::Interpreter)?;
+    from_value(value)
+}
+
+pub fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, DeserializeError> {
+    let expr = Expr::try_from(value).map_err(|e| DeserializeError::Message(e.to_string()))?;
+    T::deserialize(ExprDeserializer(expr))
+}
+
+struct ExprDeserializer(Expr);
+
+impl<'de> IntoDeserializer<'de, DeserializeError> for ExprDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+type Items = SeqDeserializer<vec::IntoIter<ExprDeserializer>, DeserializeError>;
+
+fn items(items: Vec<Expr>) -> Items {
+    let items: Vec<_> = items.into_iter().map(ExprDeserializer).collect();
+    SeqDeserializer::new(items.into_iter())
+}
+
+type Entries<'de> =
+    MapDeserializer<'de, vec::IntoIter<(ExprDeserializer, ExprDeserializer)>, DeserializeError>;
+
+fn entries<'de>(entries: Vec<(Expr, Expr)>) -> Entries<'de> {
+    let entries: Vec<_> = entries
+        .into_iter()
+        .map(|(key, value)| (ExprDeserializer(key), ExprDeserializer(value)))
+        .collect();
+    MapDeserializer::new(entries.into_iter())
+}
+
+impl<'de> de::Deserializer<'de> for ExprDeserializer {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Expr::Nil => visitor.visit_unit(),
+            Expr::Bool(b) => visitor.visit_bool(b),
+            Expr::Integer(i) => visitor.visit_i64(i),
+            #[cfg(feature = "bigint")]
+            Expr::BigInteger(_) => Err(de::Error::invalid_type(
+                Unexpected::Other("integer outside the i64 range"),
+                &visitor,
+            )),
+            Expr::Rational(r) => visitor.visit_f64(r.to_f64()),
+            Expr::Float(x) => visitor.visit_f64(x),
+            Expr::Char(c) => visitor.visit_char(c),
+            Expr::String(s) => visitor.visit_string(s),
+            Expr::Symbol(name) | Expr::Keyword(name) => visitor.visit_str(name.as_str()),
+            Expr::List(list) | Expr::Vector(list) => {
+                let mut seq = items(list);
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Expr::Map(map) => {
+                let mut map = entries(map);
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+            expr @ (Expr::Pair(..) | Expr::Lambda(_)) => {
+                Err(DeserializeError::Message(format!("{expr} isn't data")))
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Expr::Nil => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    // () is the empty list and the empty map as well as nothing.
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Expr::Nil => visitor.visit_seq(items(vec![])),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Expr::Nil => visitor.visit_map(entries(vec![])),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Expr::Symbol(_) | Expr::Keyword(_) | Expr::String(_) => visitor.visit_enum(Enum {
+                variant: self.0,
+                value: None,
+            }),
+            Expr::Map(map) if map.len() == 1 => {
+                let (variant, value) = map.into_iter().next().unwrap();
+                visitor.visit_enum(Enum {
+                    variant,
+                    value: Some(value),
+                })
+            }
+            expr => Err(DeserializeError::Message(format!(
+                "expected a variant name or a map with one entry, found {expr}"
+            ))),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct identifier ignored_any
+    }
+}
+
+struct Enum {
+    variant: Expr,
+    value: Option<Expr>,
+}
+
+impl<'de> de::EnumAccess<'de> for Enum {
+    type Error = DeserializeError;
+    type Variant = Variant;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Variant), DeserializeError> {
+        let variant = seed.deserialize(ExprDeserializer(self.variant))?;
+        Ok((variant, Variant(self.value)))
+    }
+}
+
+// What the variant holds: None for one given by name alone.
+struct Variant(Option<Expr>);
+
+impl Variant {
+    fn value(self) -> Result<ExprDeserializer, DeserializeError> {
+        self.0.map(ExprDeserializer).ok_or_else(|| {
+            de::Error::invalid_type(Unexpected::UnitVariant, &"a variant with a value")
+        })
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Variant {
+    type Error = DeserializeError;
+
+    fn unit_variant(self) -> Result<(), DeserializeError> {
+        match self.0 {
+            None | Some(Expr::Nil) => Ok(()),
+            Some(expr) => Err(DeserializeError::Message(format!(
+                "expected no value for a unit variant, found {expr}"
+            ))),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, DeserializeError> {
+        seed.deserialize(self.value()?)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, DeserializeError> {
+        de::Deserializer::deserialize_seq(self.value()?, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeserializeError> {
+        de::Deserializer::deserialize_map(self.value()?, visitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::collections::BTreeMap;
+    use serde::Deserialize;
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    #[serde(rename_all = "kebab-case")]
+    struct Server {
+        name: String,
+        port: u16,
+        max_retries: Option<u8>,
+        hosts: Vec<String>,
+        ratio: f64,
+        mode: Mode,
+        limits: BTreeMap<String, (i64, i64)>,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    #[serde(rename_all = "kebab-case")]
+    enum Mode {
+        Fast,
+        Careful { checks: u32 },
+        Named(String),
+    }
+
+    #[test]
+    fn from_str_test() {
+        let source = r#"
+            (define base 8000)
+            (define (host n) (string-append "h" (number->string n)))
+            {:name "api"
+             :port (+ base 80)
+             :hosts (map host '(1 2))
+             :ratio 1/2
+             :mode 'fast
+             :limits {"cpu" [1 4] "memory" (list 2 8)}}"#;
+        assert_eq!(
+            from_str::<Server>(source),
+            Ok(Server {
+                name: "api".to_string(),
+                port: 8080,
+                max_retries: None,
+                hosts: vec!["h1".to_string(), "h2".to_string()],
+                ratio: 0.5,
+                mode: Mode::Fast,
+                limits: BTreeMap::from([
+                    ("cpu".to_string(), (1, 4)),
+                    ("memory".to_string(), (2, 8)),
+                ]),
+            })
+        );
+        assert_eq!(
+            from_str::<Mode>("{:careful {:checks 3}}"),
+            Ok(Mode::Careful { checks: 3 })
+        );
+        assert_eq!(
+            from_str::<Mode>("{'named \"x\"}"),
+            Ok(Mode::Named("x".to_string()))
+        );
+        assert_eq!(from_str::<Vec<i64>>("()"), Ok(vec![]));
+        assert_eq!(from_str::<Option<i64>>("(if #f 1)"), Ok(None));
+    }
+
+    #[test]
+    fn error_test() {
+        let error = |source| from_str::<Server>(source).unwrap_err().to_string();
+        assert_eq!(error("{:name \"api\"}"), "missing field `port`");
+        assert_eq!(
+            error("{:name 1}"),
+            "invalid type: integer `1`, expected a string"
+        );
+        assert_eq!(
+            from_str::<u8>("300").unwrap_err().to_string(),
+            "invalid value: integer `300`, expected u8"
+        );
+        assert_eq!(
+            from_str::<(i64, i64)>("'(1 2 3)").unwrap_err().to_string(),
+            "invalid length 3, expected 2 elements in sequence"
+        );
+        assert!(matches!(
+            from_str::<i64>("(car 1)"),
+            Err(DeserializeError::Interpreter(_))
+        ));
+        assert!(from_value::<i64>(Interpreter::new().get_global("car").unwrap()).is_err());
+    }
+}
@@ -0,0 +1,613 @@
+// An interactive debugger, built on an interpreter's hook: it keeps the
+// calls the tree-walker reports as a stack, and pauses a program at a
+// breakpoint, a call to (break) or the next step to read commands from its
+// input until one says to go on:
+//
+//   step, s        to the next call
+//   next, n        to the next call that isn't inside this one
+//   out, o         to this call's return
+//   continue, c    to the next breakpoint
+//   backtrace, bt  the calls under way, innermost first
+//   locals, l      the parameters of this call and their values, or at a
+//                  (break) the local variables where it was called
+//   print, p EXPR  the value of EXPR evaluated there
+//   break, b NAME  and delete, d NAME, set and clear a breakpoint on calls
+//                  to NAME
+//
+// It writes what it shows to its output. Once its input runs out the
+// program runs on to the end. The VM reports nothing to a hook, so only
+// the tree-walker can be debugged, and spans are located in the source
+// set with set_source, which should be the file being run.
+//
+// A Backtrace keeps the same stack without pausing, to show the calls that
+// were under way when a program failed with an error nobody caught.
+use std::io::{BufRead, Write};
+
+use crate::{
+    eval::{self, Env, EvalError},
+    hooks::{self, Event},
+    interpreter::Interpreter,
+    lexer::Span,
+    parser::{self, SpannedExpr},
+    symbol::SymbolId,
+    sync::{Lock, MaybeSync, Shared, Weak},
+    value::Value,
+};
+
+const HELP: &str = "step (s), next (n), out (o), continue (c), backtrace (bt), locals (l), \
+print (p) EXPR, break (b) NAME, delete (d) NAME";
+
+pub struct Debugger<R, W> {
+    input: R,
+    output: W,
+    source: Option<(String, String)>,
+    // The forms of the first call's source, which is the one set.
+    run: Option<Weak<[SpannedExpr]>>,
+    breakpoints: Vec<SymbolId>,
+    frames: Vec<Frame>,
+    mode: Mode,
+    globals: Env,
+    // Where a (break) was called from, while paused there.
+    scope: Option<Env>,
+}
+
+#[derive(Clone)]
+struct Frame {
+    name: Option<SymbolId>,
+    callee: Value,
+    args: Vec<Value>,
+    span: Option<Span>,
+    // The source the span points into.
+    source: Weak<[SpannedExpr]>,
+}
+
+impl Frame {
+    fn new(name: Option<SymbolId>, callee: &Value, args: &[Value], span: Option<Span>) -> Self {
+        Frame {
+            name,
+            callee: callee.clone(),
+            args: args.to_vec(),
+            span,
+            source: hooks::source(),
+        }
+    }
+}
+
+// Where to pause next, other than at a breakpoint. The depths are how many
+// calls are under way.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Mode {
+    Step,
+    // At a call with no more than this many calls under way once it's
+    // entered.
+    Next(usize),
+    // When the call at this depth returns.
+    Out(usize),
+    Continue,
+    // The input has run out.
+    Detached,
+}
+
+impl<R: BufRead + MaybeSync + 'static, W: Write + MaybeSync + 'static> Debugger<R, W> {
+    // A debugger that pauses at the first call.
+    pub fn new(input: R, output: W) -> Self {
+        Debugger {
+            input,
+            output,
+            source: None,
+            run: None,
+            breakpoints: Vec::new(),
+            frames: Vec::new(),
+            mode: Mode::Step,
+            globals: Env::new(),
+            scope: None,
+        }
+    }
+
+    // Has spans shown as path:line:column, counted in text.
+    pub fn set_source(&mut self, path: &str, text: &str) {
+        self.source = Some((path.to_string(), text.to_string()));
+    }
+
+    pub fn set_breakpoint(&mut self, name: impl Into<SymbolId>) {
+        let name = name.into();
+        if !self.breakpoints.contains(&name) {
+            self.breakpoints.push(name);
+        }
+    }
+
+    // Defines (break) in interpreter and has the debugger take its hook.
+    pub fn install(mut self, interpreter: &mut Interpreter) {
+        interpreter.register_fn("break", |_| Ok(Value::Nil));
+        self.globals = interpreter.env().clone();
+        interpreter.set_hook(move |event| self.event(event));
+    }
+
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::EnterCall {
+                name,
+                callee,
+                args,
+                span,
+                env,
+            } => {
+                self.run.get_or_insert_with(hooks::source);
+                // (break) pauses in the call it's made from, with the
+                // environment it's made in.
+                if name == Some(SymbolId::from("break")) {
+                    if self.mode != Mode::Detached {
+                        self.scope = env.cloned();
+                        let at = self.location(span, &hooks::source());
+                        self.pause(&format!("break{at}"));
+                        self.scope = None;
+                    }
+                    return;
+                }
+                self.frames.push(Frame::new(name, callee, args, span));
+                let depth = self.frames.len();
+                let breakpoint = name.is_some_and(|name| self.breakpoints.contains(&name));
+                let stop = match self.mode {
+                    Mode::Step => true,
+                    Mode::Next(at) => depth <= at,
+                    Mode::Out(_) | Mode::Continue => breakpoint,
+                    Mode::Detached => false,
+                };
+                if stop {
+                    let frame = self.frames.last().unwrap();
+                    let at = self.location(frame.span, &frame.source);
+                    let message = format!("-> {}{at}", call(frame));
+                    self.pause(&message);
+                }
+            }
+            Event::Return { name, result } => {
+                if name == Some(SymbolId::from("break")) {
+                    return;
+                }
+                if self.mode == Mode::Out(self.frames.len()) {
+                    let frame = self.frames.last().unwrap();
+                    let message = match result {
+                        Ok(value) => format!("<- {} = {value}", call(frame)),
+                        Err(error) => format!("<- {} !! {error}", call(frame)),
+                    };
+                    self.pause(&message);
+                }
+                self.frames.pop();
+            }
+            Event::Branch { .. } | Event::Define { .. } => {}
+        }
+    }
+
+    fn location(&self, span: Option<Span>, source: &Weak<[SpannedExpr]>) -> String {
+        match located(&self.source, &self.run, span, source) {
+            Some((path, line, column, _)) => format!(" at {path}:{line}:{column}"),
+            None => String::new(),
+        }
+    }
+
+    // Shows message and reads commands until one goes on.
+    fn pause(&mut self, message: &str) {
+        self.say(message);
+        loop {
+            let _ = write!(self.output, "debug> ");
+            let _ = self.output.flush();
+            let mut line = String::new();
+            if !matches!(self.input.read_line(&mut line), Ok(1..)) {
+                self.mode = Mode::Detached;
+                self.say("");
+                return;
+            }
+            let (command, rest) = line
+                .trim()
+                .split_once(' ')
+                .map_or((line.trim(), ""), |(command, rest)| (command, rest.trim()));
+            let depth = self.frames.len();
+            self.mode = match command {
+                "step" | "s" => Mode::Step,
+                "next" | "n" => Mode::Next(depth),
+                "out" | "o" if depth > 0 => Mode::Out(depth),
+                "continue" | "c" => Mode::Continue,
+                "out" | "o" => {
+                    self.say("not in a call");
+                    continue;
+                }
+                "backtrace" | "bt" => {
+                    let trace: Vec<_> = self
+                        .frames
+                        .iter()
+                        .rev()
+                        .enumerate()
+                        .map(|(i, frame)| {
+                            let at = self.location(frame.span, &frame.source);
+                            format!("#{i} {}{at}", call(frame))
+                        })
+                        .collect();
+                    trace.iter().for_each(|line| self.say(line));
+                    continue;
+                }
+                "locals" | "l" => {
+                    let locals = self.locals();
+                    let lines: Vec<_> = locals
+                        .iter()
+                        .map(|(name, value)| format!("{name} = {value}"))
+                        .collect();
+                    lines.iter().for_each(|line| self.say(line));
+                    continue;
+                }
+                "print" | "p" => {
+                    let shown = match self.eval(rest) {
+                        Ok(value) => value,
+                        Err(error) => format!("error: {error}"),
+                    };
+                    self.say(&shown);
+                    continue;
+                }
+                "break" | "b" if !rest.is_empty() => {
+                    self.set_breakpoint(rest);
+                    continue;
+                }
+                "delete" | "d" if !rest.is_empty() => {
+                    let name = SymbolId::from(rest);
+                    self.breakpoints.retain(|&breakpoint| breakpoint != name);
+                    continue;
+                }
+                "" => continue,
+                _ => {
+                    self.say(HELP);
+                    continue;
+                }
+            };
+            return;
+        }
+    }
+
+    fn say(&mut self, line: &str) {
+        let _ = writeln!(self.output, "{line}");
+    }
+
+    // At a (break) the names bound where it was called other than the
+    // globals; otherwise the innermost call's parameters bound to its
+    // arguments as its body will have them, or for a native function its
+    // arguments in order.
+    fn locals(&self) -> Vec<(String, Value)> {
+        if let Some(scope) = &self.scope {
+            let globals = self.globals.names();
+            return scope
+                .names()
+                .into_iter()
+                .filter(|name| !globals.contains(name))
+                .filter_map(|name| Some((name.to_string(), scope.get(name)?)))
+                .collect();
+        }
+        let Some(frame) = self.frames.last() else {
+            return Vec::new();
+        };
+        parameters(&frame.callee, &frame.args).unwrap_or_else(|| {
+            frame
+                .args
+                .iter()
+                .enumerate()
+                .map(|(i, arg)| (format!("#{i}"), arg.clone()))
+                .collect()
+        })
+    }
+
+    // Runs source in a scope of its own inside the environment of a
+    // (break), the innermost call's or the global one outside any call.
+    // The hook is busy, so what this runs isn't reported to it.
+    fn eval(&self, source: &str) -> Result<String, String> {
+        let expr = parser::read(source).map_err(|e| e.to_string())?;
+        if let Some(scope) = &self.scope {
+            return eval::eval(&expr, &mut scope.extend())
+                .map(|value| value.to_string())
+                .map_err(|e| e.to_string());
+        }
+        let mut env = match self.frames.last().map(|frame| &frame.callee) {
+            Some(Value::Closure(closure)) => closure.env.extend(),
+            _ => self.globals.extend(),
+        };
+        for (name, value) in self.locals() {
+            env.define(name.as_str(), value);
+        }
+        eval::eval(&expr, &mut env)
+            .map(|value| value.to_string())
+            .map_err(|e| e.to_string())
+    }
+}
+
+// The calls under way when the error a program failed with was raised. It
+// has an interpreter's hook, so can't be installed along with a Debugger,
+// though a hook of its own can pass events on to observe.
+#[derive(Clone, Default)]
+pub struct Backtrace(Shared<Lock<Unwinding>>);
+
+#[derive(Default)]
+struct Unwinding {
+    source: Option<(String, String)>,
+    run: Option<Weak<[SpannedExpr]>>,
+    frames: Vec<Frame>,
+    // The error being passed up from call to call, and the frames there were
+    // when it was raised.
+    error: Option<EvalError>,
+    failed: Vec<Frame>,
+}
+
+impl Backtrace {
+    pub fn new() -> Self {
+        Backtrace::default()
+    }
+
+    // Has calls shown as written at path:line:column in text, rather than
+    // with the values they were given.
+    pub fn set_source(&self, path: &str, text: &str) {
+        self.0.borrow_mut().source = Some((path.to_string(), text.to_string()));
+    }
+
+    pub fn install(&self, interpreter: &mut Interpreter) {
+        let backtrace = self.clone();
+        interpreter.set_hook(move |event| backtrace.observe(event));
+    }
+
+    // Keeps track of event, for a hook that does more than this.
+    pub fn observe(&self, event: Event) {
+        self.0.borrow_mut().event(event);
+    }
+
+    // A line for each call under way when error was raised, innermost
+    // first, or none if the error isn't the last one that went uncaught
+    // out of a call.
+    pub fn lines(&self, error: &EvalError) -> Vec<String> {
+        let unwinding = self.0.borrow();
+        if unwinding.error.as_ref() != Some(error) {
+            return Vec::new();
+        }
+        let failed = &unwinding.failed;
+        failed
+            .iter()
+            .rev()
+            .map(|frame| {
+                let at = located(&unwinding.source, &unwinding.run, frame.span, &frame.source);
+                match at {
+                    Some((path, line, column, text)) => {
+                        format!("at {} {path}:{line}:{column}", written(text))
+                    }
+                    None => format!("at {}", call(frame)),
+                }
+            })
+            .collect()
+    }
+
+    // Where the innermost of those calls that can be located is, in the
+    // text set.
+    pub fn span(&self, error: &EvalError) -> Option<Span> {
+        let unwinding = self.0.borrow();
+        if unwinding.error.as_ref() != Some(error) {
+            return None;
+        }
+        let located = |frame: &&Frame| {
+            let at = located(&unwinding.source, &unwinding.run, frame.span, &frame.source);
+            at.is_some()
+        };
+        unwinding.failed.iter().rev().find(located)?.span
+    }
+}
+
+impl Unwinding {
+    // An error that reaches a call that hasn't seen it has been raised
+    // since the last one, which was caught on the way.
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::EnterCall {
+                name,
+                callee,
+                args,
+                span,
+                ..
+            } => {
+                self.run.get_or_insert_with(hooks::source);
+                self.frames.push(Frame::new(name, callee, args, span));
+            }
+            Event::Return { result, .. } => {
+                if let Err(error) = result {
+                    if self.error.as_ref() != Some(error) {
+                        self.error = Some(error.clone());
+                        self.failed = self.frames.clone();
+                    }
+                }
+                self.frames.pop();
+            }
+            Event::Branch { .. } | Event::Define { .. } => {}
+        }
+    }
+}
+
+// Where span is in the text set, with the text there, if source is the
+// one it's the text of: that of the first call, made by the file being run.
+// A file that one loads, and an eval_str after it, have spans of their own.
+fn located<'a>(
+    file: &'a Option<(String, String)>,
+    run: &Option<Weak<[SpannedExpr]>>,
+    span: Option<Span>,
+    source: &Weak<[SpannedExpr]>,
+) -> Option<(&'a str, usize, usize, &'a str)> {
+    let (path, text) = file.as_ref()?;
+    let span = span?;
+    if !run.as_ref().is_some_and(|run| Weak::ptr_eq(run, source)) {
+        return None;
+    }
+    let (line, column) = span.line_col(text);
+    Some((path, line, column, text.get(span.start..span.end)?))
+}
+
+// The parameters of callee bound to args as its body will have them, or
+// None if it isn't a closure.
+pub(crate) fn parameters(callee: &Value, args: &[Value]) -> Option<Vec<(String, Value)>> {
+    let Value::Closure(closure) = callee else {
+        return None;
+    };
+    let lambda = &closure.lambda;
+    let mut bound: Vec<_> = lambda
+        .params
+        .iter()
+        .zip(args)
+        .map(|(param, arg)| (param.to_string(), arg.clone()))
+        .collect();
+    if let Some(rest) = lambda.rest {
+        let rest_args = args.get(lambda.params.len()..).unwrap_or_default();
+        bound.push((rest.to_string(), Value::List(rest_args.to_vec())));
+    }
+    Some(bound)
+}
+
+// A call's text on one line, cut short if it's long.
+fn written(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(40) {
+        Some((end, _)) => format!("{} ...)", &text[..end]),
+        None => text,
+    }
+}
+
+// A call as written with the values it was given.
+fn call(frame: &Frame) -> String {
+    let mut call = match frame.name {
+        Some(name) => format!("({name}"),
+        None => format!("({}", frame.callee),
+    };
+    for arg in &frame.args {
+        call.push_str(&format!(" {arg}"));
+    }
+    call.push(')');
+    call
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::InterpreterError;
+    use std::io;
+
+    // Output the test can still read once the debugger has it.
+    #[derive(Clone, Default)]
+    struct Transcript(Shared<Lock<Vec<u8>>>);
+
+    impl Write for Transcript {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Runs source under a debugger given commands, and gives back what it
+    // showed, a line each, and the program's value.
+    fn debug(source: &str, commands: &str, breakpoints: &[&str]) -> (Vec<String>, String) {
+        let transcript = Transcript::default();
+        let mut debugger = Debugger::new(io::Cursor::new(commands.to_string()), transcript.clone());
+        debugger.set_source("test.lisp", source);
+        for &name in breakpoints {
+            debugger.set_breakpoint(name);
+        }
+        let mut interpreter = Interpreter::new();
+        debugger.install(&mut interpreter);
+        let value = interpreter.eval_str(source).unwrap().to_string();
+        let text = String::from_utf8(transcript.0.borrow().clone()).unwrap();
+        let lines = text
+            .lines()
+            .map(|line| line.trim_start_matches("debug> ").to_string())
+            .filter(|line| !line.is_empty())
+            .collect();
+        (lines, value)
+    }
+
+    const FACT: &str = "(define (fact n)\n  (if (= n 0) 1 (* n (fact (- n 1)))))\n(fact 2)";
+
+    #[test]
+    fn step_test() {
+        let (shown, value) = debug(FACT, "s\ns\nn\nbt\nl\np (* n 10)\nc\n", &[]);
+        assert_eq!(value, "2");
+        assert_eq!(
+            shown,
+            [
+                "-> (fact 2) at test.lisp:3:1",
+                "-> (= 2 0) at test.lisp:2:7",
+                "-> (- 2 1) at test.lisp:2:28",
+                "-> (fact 1) at test.lisp:2:22",
+                "#0 (fact 1) at test.lisp:2:22",
+                "#1 (fact 2) at test.lisp:3:1",
+                "n = 1",
+                "10",
+            ]
+        );
+    }
+
+    #[test]
+    fn breakpoint_test() {
+        // A breakpoint set at the prompt stops the calls after it, and out
+        // runs to the return of the call paused at.
+        let commands = "b fact\nc\nc\nd -\nd fact\no\nc\n";
+        let (shown, _) = debug(FACT, commands, &["-"]);
+        assert_eq!(
+            shown,
+            [
+                "-> (fact 2) at test.lisp:3:1",
+                "-> (- 2 1) at test.lisp:2:28",
+                "-> (fact 1) at test.lisp:2:22",
+                "<- (fact 1) = 1",
+            ]
+        );
+        let source = "(define (f x) (let ((y (* x 2))) (break) (+ x y)))\n(f 3)";
+        let (shown, value) = debug(source, "c\nl\np (list x y)\nbt\nbogus\n", &[]);
+        assert_eq!(value, "9");
+        assert_eq!(
+            shown,
+            [
+                "-> (f 3) at test.lisp:2:1",
+                "break at test.lisp:1:34",
+                "x = 3",
+                "y = 6",
+                "(3 6)",
+                "#0 (f 3) at test.lisp:2:1",
+                HELP,
+            ]
+        );
+    }
+
+    #[test]
+    fn backtrace_test() {
+        // An error caught on the way isn't the one shown.
+        let source = "(define (f n)\n  (if (= n 0) (car n) (f (- n 1))))\n\
+            (define (g) (try (car 1) (catch e e)) (+ 1 (f 1)))\n(g)";
+        let backtrace = Backtrace::new();
+        backtrace.set_source("test.lisp", source);
+        let mut interpreter = Interpreter::new();
+        backtrace.install(&mut interpreter);
+        let error = match interpreter.eval_str(source) {
+            Err(InterpreterError::Eval(error)) => error,
+            result => panic!("{result:?}"),
+        };
+        assert_eq!(
+            backtrace.lines(&error),
+            [
+                "at (car n) test.lisp:2:15",
+                "at (f (- n 1)) test.lisp:2:23",
+                "at (f 1) test.lisp:3:44",
+                "at (g) test.lisp:4:1",
+            ]
+        );
+        assert!(backtrace.lines(&EvalError::Exit(1)).is_empty());
+
+        // g was read from another source, so its calls have no spans.
+        let error = interpreter.eval_str("(g)").unwrap_err();
+        let InterpreterError::Eval(error) = error else {
+            panic!("{error:?}");
+        };
+        assert_eq!(
+            backtrace.lines(&error),
+            ["at (car 0)", "at (f 0)", "at (f 1)", "at (g)"]
+        );
+    }
+}
@@ -0,0 +1,68 @@
+// Markdown pages for a source file's definitions, as `tuple-gd doc` writes
+// them: a heading with the file's name, then one for each definition in
+// the outline, with how it's called and its doc under it, and a module's
+// definitions a level further down.
+//
+//   # lib.lisp
+//
+//   ## `(fact n)`
+//
+//   The factorial of n.
+use alloc::{format, string::String};
+use core::fmt::Write as _;
+
+use crate::analysis::{Document, DocumentSymbol, SymbolKind};
+
+// What a source file's name ends in, when a directory is searched for them.
+pub const SUFFIXES: &[&str] = &[".lisp", ".scm", ".tasks"];
+
+pub fn markdown(title: &str, document: &Document) -> String {
+    let mut out = format!("# {title}\n");
+    for symbol in document.symbols() {
+        section(document, &symbol, 2, &mut out);
+    }
+    out
+}
+
+fn section(document: &Document, symbol: &DocumentSymbol, level: usize, out: &mut String) {
+    let call = symbol.detail.as_deref().unwrap_or(symbol.name.as_str());
+    let heading = match symbol.kind {
+        SymbolKind::Function | SymbolKind::Variable => format!("`{call}`"),
+        SymbolKind::Macro => format!("macro `{call}`"),
+        SymbolKind::Module => format!("module `{}`, exporting `{call}`", symbol.name),
+    };
+    write!(out, "\n{} {heading}\n", "#".repeat(level.min(6))).unwrap();
+    let doc = document.doc(symbol);
+    if !doc.is_empty() {
+        write!(out, "\n{doc}\n").unwrap();
+    }
+    for child in &symbol.children {
+        section(document, child, level + 1, out);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn markdown_test() {
+        let source = ";;; The factorial of n.
+(define (fact n)
+  (if (= n 0) 1 (* n (fact (- n 1)))))
+(define limit 10)
+(defmacro swap! (a b) \"Swaps a and b.\" (list 'set! a b))
+(module shapes (area)
+  ; Of a square.
+  (define (area side) (* side side)))";
+        assert_eq!(
+            markdown("lib.lisp", &Document::new(source)),
+            "# lib.lisp\n\
+             \n## `(fact n)`\n\nThe factorial of n.\n\
+             \n## `limit`\n\
+             \n## macro `(swap! a b)`\n\nSwaps a and b.\n\
+             \n## module `shapes`, exporting `(area)`\n\
+             \n### `(area side)`\n\nOf a square.\n"
+        );
+    }
+}
@@ -0,0 +1,1665 @@
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{error::Error, fmt};
+
+use crate::{
+    compile::{self, Unsupported},
+    gc, hooks,
+    limits::{self, Limit},
+    macros,
+    parser::{Expr, Lambda},
+    symbol::{self, SymbolId},
+    sync::{Lock, Shared, Weak},
+    value::{Closure, Value},
+    vm,
+};
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum EvalError {
+    UnboundSymbol(String),
+    NotCallable(Value),
+    ArityMismatch {
+        expected: usize,
+        found: usize,
+    },
+    TypeMismatch {
+        expected: &'static str,
+        found: Value,
+    },
+    MalformedForm {
+        form: &'static str,
+        found: Expr,
+    },
+    UnexpectedArgument(Value),
+    NoMatch(Value),
+    UnknownModule(String),
+    DivisionByZero,
+    IntegerOverflow,
+    // An exact result that is a rational with a part too large for an i64.
+    RationalOverflow,
+    Load {
+        path: String,
+        reason: String,
+    },
+    // A port couldn't be opened, read or written.
+    Io(String),
+    // A value raised by code and never caught.
+    Raised(Value),
+    NotInGenerator,
+    // An async native was called where evaluation can't be suspended.
+    CannotAwait(String),
+    // Code ran into one of the interpreter's limits. Unlike other errors,
+    // try doesn't catch it.
+    LimitExceeded(Limit),
+    // The host cancelled the run. try doesn't catch this either.
+    Cancelled,
+    // (exit status) was called to end the program, which try doesn't get
+    // in the way of.
+    Exit(i32),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::UnboundSymbol(name) => write!(f, "unbound symbol `{name}`"),
+            EvalError::NotCallable(expr) => write!(f, "{expr} is not callable"),
+            EvalError::ArityMismatch { expected, found } => {
+                write!(f, "expected {expected} arguments, found {found}")
+            }
+            EvalError::TypeMismatch { expected, found } => {
+                write!(f, "expected {expected}, found {found}")
+            }
+            EvalError::MalformedForm { form, found } => write!(f, "malformed {form}: {found}"),
+            EvalError::UnexpectedArgument(arg) => write!(f, "unexpected argument {arg}"),
+            EvalError::NoMatch(value) => write!(f, "no pattern matches {value}"),
+            EvalError::UnknownModule(name) => write!(f, "unknown module `{name}`"),
+            EvalError::DivisionByZero => write!(f, "division by zero"),
+            EvalError::IntegerOverflow => write!(f, "integer overflow"),
+            EvalError::RationalOverflow => write!(f, "rational part larger than an i64"),
+            EvalError::Load { path, reason } => write!(f, "cannot load `{path}`: {reason}"),
+            EvalError::Io(message) => f.write_str(message),
+            EvalError::Raised(Value::Error(error)) => {
+                f.write_str(&error.message)?;
+                for irritant in &error.irritants {
+                    write!(f, " {irritant}")?;
+                }
+                Ok(())
+            }
+            EvalError::Raised(value) => write!(f, "uncaught {value}"),
+            EvalError::NotInGenerator => write!(f, "yield outside of a running generator"),
+            EvalError::CannotAwait(name) => write!(f, "async `{name}` called outside eval_async"),
+            EvalError::LimitExceeded(limit) => write!(f, "limit exceeded: {limit}"),
+            EvalError::Cancelled => write!(f, "cancelled"),
+            EvalError::Exit(status) => write!(f, "exit with status {status}"),
+        }
+    }
+}
+
+impl Error for EvalError {}
+
+impl EvalError {
+    // The value a handler sees for this error. Raised values are passed on
+    // as they are; errors from the interpreter itself become error objects
+    // with their message and no irritants.
+    pub fn into_value(self) -> Value {
+        match self {
+            EvalError::Raised(value) => value,
+            other => Value::error(other.to_string(), vec![]),
+        }
+    }
+}
+
+// Frames are shared so a child scope can outlive the call that created it,
+// and mutable so that set! on a variable is seen by every closure that
+// captured the frame it lives in. Every frame is tracked by the gc module,
+// which frees the ones only kept alive by cycles.
+#[derive(Clone, Debug)]
+pub struct Env(Shared<Lock<Frame>>);
+
+pub(crate) struct WeakEnv(Weak<Lock<Frame>>);
+
+#[derive(Debug, Default)]
+struct Frame {
+    vars: BTreeMap<SymbolId, Value>,
+    parent: Option<Env>,
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Env::with_frame(Frame::default())
+    }
+}
+
+impl Env {
+    pub fn new() -> Self {
+        Env::default()
+    }
+
+    pub fn extend(&self) -> Self {
+        Env::with_frame(Frame {
+            vars: BTreeMap::new(),
+            parent: Some(self.clone()),
+        })
+    }
+
+    fn with_frame(frame: Frame) -> Self {
+        let env = Env(Shared::new(Lock::new(frame)));
+        gc::track_env(&env);
+        env
+    }
+
+    pub fn get(&self, name: impl Into<SymbolId>) -> Option<Value> {
+        let name = name.into();
+        gc::using(|| self.lookup(name))
+    }
+
+    fn lookup(&self, name: SymbolId) -> Option<Value> {
+        let frame = self.0.borrow();
+        match frame.vars.get(&name) {
+            Some(value) => Some(value.clone()),
+            None => frame.parent.as_ref()?.lookup(name),
+        }
+    }
+
+    // The names bound here and in the frames around it, each once.
+    pub fn names(&self) -> Vec<SymbolId> {
+        let mut names = BTreeSet::new();
+        let mut env = Some(self.clone());
+        while let Some(current) = env {
+            let frame = current.0.borrow();
+            names.extend(frame.vars.keys().copied());
+            env = frame.parent.clone();
+        }
+        names.into_iter().collect()
+    }
+
+    pub fn define(&self, name: impl Into<SymbolId>, value: Value) {
+        let name = name.into();
+        gc::using(|| self.0.borrow_mut().vars.insert(name, value));
+    }
+
+    // Rebinds name in the innermost frame that already binds it. Returns
+    // false, changing nothing, if no frame does.
+    pub fn set(&self, name: impl Into<SymbolId>, value: Value) -> bool {
+        let name = name.into();
+        gc::using(|| self.assign(name, value))
+    }
+
+    fn assign(&self, name: SymbolId, value: Value) -> bool {
+        let mut frame = self.0.borrow_mut();
+        match frame.vars.get_mut(&name) {
+            Some(slot) => {
+                *slot = value;
+                true
+            }
+            None => match &frame.parent {
+                Some(parent) => parent.assign(name, value),
+                None => false,
+            },
+        }
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakEnv {
+        WeakEnv(Shared::downgrade(&self.0))
+    }
+
+    pub(crate) fn addr(&self) -> usize {
+        Shared::as_ptr(&self.0) as usize
+    }
+
+    pub(crate) fn strong_count(&self) -> usize {
+        Shared::strong_count(&self.0)
+    }
+
+    // Calls f with each variable's value and returns the parent, or None
+    // if the frame is borrowed.
+    pub(crate) fn visit(&self, mut f: impl FnMut(&Value)) -> Option<Option<Env>> {
+        let frame = self.0.try_borrow().ok()?;
+        frame.vars.values().for_each(&mut f);
+        Some(frame.parent.clone())
+    }
+
+    // Drops the frame's variables and parent, for a frame that is garbage.
+    pub(crate) fn clear(&self) {
+        let frame = self
+            .0
+            .try_borrow_mut()
+            .map(|mut frame| core::mem::take(&mut *frame));
+        drop(frame);
+    }
+}
+
+impl WeakEnv {
+    pub(crate) fn upgrade(&self) -> Option<Env> {
+        self.0.upgrade().map(Env)
+    }
+
+    pub(crate) fn is_live(&self) -> bool {
+        self.0.strong_count() > 0
+    }
+}
+
+// Only #f and nil are false; everything else, including 0, "" and (),
+// counts as true in conditionals.
+pub fn is_truthy(value: &Value) -> bool {
+    !matches!(value, Value::Bool(false) | Value::Nil)
+}
+
+// What is left of an expression once everything outside its tail position
+// has been evaluated. Handing the tail back to the loop in eval instead of
+// evaluating it recursively is what keeps tail calls off the Rust stack.
+enum Tail<'a> {
+    Value(Value),
+    Expr(&'a Expr),
+    // Code made while running, such as a macro call's expansion.
+    Owned(Expr),
+    Call(Shared<Closure>, Vec<Value>),
+}
+
+enum Step {
+    Done(Value),
+    Call(Shared<Closure>, Vec<Value>),
+}
+
+pub fn eval(expr: &Expr, env: &mut Env) -> Result<Value, EvalError> {
+    let _depth = limits::enter()?;
+    let mut env = env.clone();
+    let step = eval_step(expr, &mut env)?;
+    run(step)
+}
+
+// Calls a procedure from Rust, e.g. from a builtin that takes a callback.
+pub fn apply(callee: &Value, args: Vec<Value>) -> Result<Value, EvalError> {
+    let _depth = limits::enter()?;
+    let recorded = callee.is_procedure() && hooks::active();
+    if recorded {
+        hooks::enter_call(None, callee, &args, None);
+    }
+    let result = match callee {
+        Value::Closure(closure) => return run(Step::Call(closure.clone(), args)),
+        Value::Native(native) => (native.func)(&args),
+        Value::AsyncNative(native) => Err(EvalError::CannotAwait(native.name.to_string())),
+        Value::Compiled(closure) => vm::call(closure, args),
+        _ => return Err(EvalError::NotCallable(callee.clone())),
+    };
+    if recorded {
+        hooks::return_call(result.as_ref());
+    }
+    result
+}
+
+// Calls to closures return here rather than where they were made, so this
+// is where hooks hear of them returning: every call run entered, tail calls
+// included, returns what the last one did.
+fn run(step: Step) -> Result<Value, EvalError> {
+    let mut calls = 0;
+    // Each closure's body runs with its own spans, and the caller's are put
+    // back after.
+    let result = match hooks::active() {
+        true => hooks::with_code(None, || run_calls(step, &mut calls)),
+        false => run_calls(step, &mut calls),
+    };
+    for _ in 0..calls {
+        hooks::return_call(result.as_ref());
+    }
+    result
+}
+
+fn run_calls(mut step: Step, calls: &mut usize) -> Result<Value, EvalError> {
+    loop {
+        match step {
+            Step::Done(value) => return Ok(value),
+            Step::Call(closure, args) => {
+                // The call was reported on entering it if hooks are active.
+                if hooks::active() {
+                    *calls += 1;
+                    hooks::enter_code(&closure.spans);
+                }
+                let mut scope = bind_params(&closure.lambda, args, &closure.env)?;
+                step = match eval_body(&closure.lambda.body, &mut scope)? {
+                    Tail::Value(value) => Step::Done(value),
+                    Tail::Expr(expr) => eval_step(expr, &mut scope)?,
+                    Tail::Owned(expr) => eval_step(&expr, &mut scope)?,
+                    Tail::Call(closure, args) => Step::Call(closure, args),
+                };
+            }
+        }
+    }
+}
+
+// Evaluates expr until it either yields a value or reaches a call to a
+// closure in tail position. Special forms may replace env with a new scope.
+fn eval_step(mut expr: &Expr, env: &mut Env) -> Result<Step, EvalError> {
+    loop {
+        limits::step()?;
+        let tail = match expr {
+            Expr::Symbol(name) => {
+                return env
+                    .get(*name)
+                    .map(Step::Done)
+                    .ok_or_else(|| EvalError::UnboundSymbol(name.to_string()))
+            }
+            Expr::List(items) => match items.split_first() {
+                None => return Ok(Step::Done(Value::Nil)),
+                Some((head, args)) => apply_form(expr, head, args, env)?,
+            },
+            Expr::Lambda(lambda) => return Ok(Step::Done(Value::closure((**lambda).clone(), env))),
+            Expr::Pair(..) => {
+                return Err(EvalError::MalformedForm {
+                    form: "call",
+                    found: expr.clone(),
+                })
+            }
+            // Unlike a list, a vector or map literal evaluates its elements
+            // rather than being a call.
+            Expr::Vector(items) => return Ok(Step::Done(Value::Vector(eval_args(items, env)?))),
+            Expr::Map(entries) => {
+                let entries = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        Ok((one_value(eval(key, env)?)?, one_value(eval(value, env)?)?))
+                    })
+                    .collect::<Result<_, EvalError>>()?;
+                return Ok(Step::Done(Value::Map(entries)));
+            }
+            _ => return Ok(Step::Done(Value::from(expr))),
+        };
+        match tail {
+            Tail::Value(value) => return Ok(Step::Done(value)),
+            Tail::Expr(next) => expr = next,
+            // Made code has no owner outside this call, so it runs one
+            // level down, which only lasts until it reaches a step: a tail
+            // call in it still returns to the loop in run.
+            Tail::Owned(made) => {
+                let _depth = limits::enter()?;
+                return eval_step(&made, env);
+            }
+            Tail::Call(closure, args) => return Ok(Step::Call(closure, args)),
+        }
+    }
+}
+
+fn apply_form<'a>(
+    form: &Expr,
+    head: &Expr,
+    args: &'a [Expr],
+    env: &mut Env,
+) -> Result<Tail<'a>, EvalError> {
+    if let Expr::Symbol(name) = head {
+        if let Some(result) = special_form(form, *name, args, env) {
+            return result;
+        }
+    }
+    let callee = eval(head, env)?;
+    if let Value::Macro(closure) = &callee {
+        return macros::expand_call(closure, args).map(Tail::Owned);
+    }
+    let args = eval_args(args, env)?;
+    let recorded = callee.is_procedure() && hooks::active();
+    if recorded {
+        hooks::enter_call(Some(form), &callee, &args, Some(env));
+    }
+    let result = match callee {
+        Value::Closure(closure) => return Ok(Tail::Call(closure, args)),
+        Value::Native(native) => (native.func)(&args),
+        Value::AsyncNative(native) => Err(EvalError::CannotAwait(native.name.to_string())),
+        Value::Compiled(closure) => vm::call(&closure, args),
+        _ => return Err(EvalError::NotCallable(callee)),
+    };
+    if recorded {
+        hooks::return_call(result.as_ref());
+    }
+    result.map(Tail::Value)
+}
+
+fn special_form<'a>(
+    form: &Expr,
+    name: SymbolId,
+    args: &'a [Expr],
+    env: &mut Env,
+) -> Option<Result<Tail<'a>, EvalError>> {
+    let result = match name {
+        symbol::QUOTE => single(args).map(|arg| Tail::Value(Value::from(arg))),
+        symbol::QUASIQUOTE => single(args)
+            .and_then(|template| quasiquote(template, 1, env))
+            .map(Tail::Value),
+        symbol::DEFINE => eval_define(form, args, env).map(Tail::Value),
+        symbol::LAMBDA => parse_lambda("lambda", args)
+            .map(|lambda| Tail::Value(Value::Closure(closure(lambda, args, env)))),
+        symbol::DEFMACRO => eval_defmacro(args, env).map(Tail::Value),
+        symbol::IF => eval_if(form, args, env),
+        symbol::COND => eval_cond(args, env),
+        symbol::AND => eval_and_or(args, env, true),
+        symbol::OR => eval_and_or(args, env, false),
+        symbol::WHEN => eval_when(form, args, env, true),
+        symbol::UNLESS => eval_when(form, args, env, false),
+        symbol::LET => eval_let("let", args, env),
+        symbol::LET_STAR => eval_let("let*", args, env),
+        symbol::LET_VALUES => eval_let_values(args, env),
+        symbol::BEGIN => eval_body(args, env),
+        symbol::MATCH => eval_match(args, env),
+        symbol::SET => eval_set(args, env).map(Tail::Value),
+        symbol::TRY => eval_try(args, env).map(Tail::Value),
+        symbol::GENERATOR => eval_generator(form, env).map(Tail::Value),
+        _ => return None,
+    };
+    Some(result)
+}
+
+fn malformed(form: &'static str, args: &[Expr]) -> EvalError {
+    let mut found = vec![Expr::Symbol(form.into())];
+    found.extend_from_slice(args);
+    EvalError::MalformedForm {
+        form,
+        found: Expr::List(found),
+    }
+}
+
+// A parameter list is (a b), (a b . rest), or a lone symbol that takes
+// every argument as a list. Parameters after &key are keys, each written
+// as name or (name default). A list with keys can't also have a rest.
+fn parse_params(form: &'static str, mut params: &Expr, args: &[Expr]) -> Result<Lambda, EvalError> {
+    let mut items = Vec::new();
+    let mut lambda = Lambda::default();
+    loop {
+        match params {
+            Expr::Pair(car, cdr) => {
+                items.push(&**car);
+                params = cdr;
+            }
+            Expr::List(list) => {
+                items.extend(list);
+                break;
+            }
+            Expr::Nil => break,
+            Expr::Symbol(rest) => {
+                lambda.rest = Some(*rest);
+                break;
+            }
+            _ => return Err(malformed(form, args)),
+        }
+    }
+    let mut keys = false;
+    for item in items {
+        match item {
+            Expr::Symbol(symbol::AND_KEY) if !keys => keys = true,
+            Expr::Symbol(name) if keys => lambda.keys.push((*name, None)),
+            Expr::Symbol(name) => lambda.params.push(*name),
+            Expr::List(key) if keys => match key.as_slice() {
+                [Expr::Symbol(name), default] => lambda.keys.push((*name, Some(default.clone()))),
+                _ => return Err(malformed(form, args)),
+            },
+            _ => return Err(malformed(form, args)),
+        }
+    }
+    if keys && lambda.rest.is_some() {
+        return Err(malformed(form, args));
+    }
+    Ok(lambda)
+}
+
+// (lambda (params... [&key keys...] [. rest]) body...)
+fn parse_lambda(form: &'static str, args: &[Expr]) -> Result<Lambda, EvalError> {
+    let [params, body @ ..] = args else {
+        return Err(malformed(form, args));
+    };
+    let lambda = parse_params(form, params, args)?;
+    Ok(Lambda {
+        body: body.to_vec(),
+        ..lambda
+    })
+}
+
+// A closure over env made from lambda, which was parsed from the
+// parameters and body in args. When a hook wants spans it keeps those of
+// its body and key defaults, which are copies of the ones in args.
+fn closure(lambda: Lambda, args: &[Expr], env: &Env) -> Shared<Closure> {
+    let spans = match args {
+        [params, body @ ..] if hooks::spanning() => {
+            let params = match params {
+                Expr::List(items) => items.as_slice(),
+                _ => &[],
+            };
+            let defaults = params.iter().filter_map(|param| match param {
+                Expr::List(key) => key.get(1),
+                _ => None,
+            });
+            let copies = lambda
+                .keys
+                .iter()
+                .filter_map(|(_, default)| default.as_ref());
+            hooks::copy_spans(body.iter().zip(&lambda.body).chain(defaults.zip(copies)))
+        }
+        _ => None,
+    };
+    Shared::new(Closure {
+        lambda,
+        env: env.clone(),
+        spans,
+    })
+}
+
+// (define name value) or (define (name params...) body...)
+fn eval_define(form: &Expr, args: &[Expr], env: &mut Env) -> Result<Value, EvalError> {
+    match args {
+        [Expr::Symbol(name), value] => {
+            let value = one_value(eval(value, env)?)?;
+            if hooks::active() {
+                hooks::define(form, *name, &value);
+            }
+            env.define(*name, value);
+            Ok(Value::Nil)
+        }
+        [signature, body @ ..] => {
+            let (name, params) = match signature {
+                Expr::List(items) if !items.is_empty() => {
+                    (&items[0], Expr::List(items[1..].to_vec()))
+                }
+                Expr::Pair(name, params) => (&**name, (**params).clone()),
+                _ => return Err(malformed("define", args)),
+            };
+            let Expr::Symbol(name) = name else {
+                return Err(malformed("define", args));
+            };
+            let lambda = Lambda {
+                body: body.to_vec(),
+                ..parse_params("define", &params, args)?
+            };
+            let value = Value::Closure(closure(lambda, args, env));
+            if hooks::active() {
+                hooks::define(form, *name, &value);
+            }
+            env.define(*name, value);
+            Ok(Value::Nil)
+        }
+        _ => Err(malformed("define", args)),
+    }
+}
+
+// (set! name value) changes an existing variable. Unlike define, it never
+// creates one.
+fn eval_set(args: &[Expr], env: &mut Env) -> Result<Value, EvalError> {
+    let [Expr::Symbol(name), value] = args else {
+        return Err(malformed("set!", args));
+    };
+    let value = one_value(eval(value, env)?)?;
+    if env.set(*name, value) {
+        Ok(Value::Nil)
+    } else {
+        Err(EvalError::UnboundSymbol(name.to_string()))
+    }
+}
+
+// (try body... (catch name handler...)) evaluates body, and if it raises
+// or fails, evaluates handler with the error bound to name instead.
+fn eval_try(args: &[Expr], env: &mut Env) -> Result<Value, EvalError> {
+    let Some((Expr::List(clause), body)) = args.split_last() else {
+        return Err(malformed("try", args));
+    };
+    let [Expr::Symbol(symbol::CATCH), Expr::Symbol(name), handler @ ..] = clause.as_slice() else {
+        return Err(malformed("try", args));
+    };
+    match eval_body(body, env).and_then(|tail| finish(tail, env)) {
+        Ok(value) => Ok(value),
+        Err(error @ (EvalError::LimitExceeded(_) | EvalError::Cancelled | EvalError::Exit(_))) => {
+            Err(error)
+        }
+        Err(error) => {
+            let mut scope = env.extend();
+            scope.define(*name, error.into_value());
+            let tail = eval_body(handler, &mut scope)?;
+            finish(tail, &mut scope)
+        }
+    }
+}
+
+// (generator body...) is run by the VM even under the tree-walker, since
+// suspending at a yield needs its frames, which live on the heap. The
+// body sees the variables in scope through env, as it would globals.
+fn eval_generator(form: &Expr, env: &Env) -> Result<Value, EvalError> {
+    let function =
+        compile::compile(form).map_err(|Unsupported(found)| EvalError::MalformedForm {
+            form: "generator",
+            found,
+        })?;
+    vm::run(function, env)
+}
+
+fn finish(tail: Tail, env: &mut Env) -> Result<Value, EvalError> {
+    match tail {
+        Tail::Value(value) => Ok(value),
+        Tail::Expr(expr) => eval(expr, env),
+        Tail::Owned(expr) => eval(&expr, env),
+        Tail::Call(closure, args) => {
+            let _depth = limits::enter()?;
+            run(Step::Call(closure, args))
+        }
+    }
+}
+
+// (defmacro name (params... [. rest]) body...)
+fn eval_defmacro(args: &[Expr], env: &mut Env) -> Result<Value, EvalError> {
+    let [Expr::Symbol(name), rest @ ..] = args else {
+        return Err(malformed("defmacro", args));
+    };
+    let lambda = parse_lambda("defmacro", rest).map_err(|_| malformed("defmacro", args))?;
+    let closure = closure(lambda, rest, env);
+    env.define(*name, Value::Macro(closure));
+    Ok(Value::Nil)
+}
+
+// (if test then [else]); a missing else branch yields nil.
+fn eval_if<'a>(form: &Expr, args: &'a [Expr], env: &mut Env) -> Result<Tail<'a>, EvalError> {
+    let (test, then, otherwise) = match args {
+        [test, then] => (test, then, None),
+        [test, then, otherwise] => (test, then, Some(otherwise)),
+        _ => return Err(malformed("if", args)),
+    };
+    let taken = is_truthy(&eval(test, env)?);
+    if hooks::active() {
+        hooks::branch(form, taken);
+    }
+    if taken {
+        Ok(Tail::Expr(then))
+    } else {
+        Ok(otherwise.map_or(Tail::Value(Value::Nil), Tail::Expr))
+    }
+}
+
+// (cond (test body...)... [(else body...)]) runs the body of the first
+// clause whose test is true. A clause with no body yields its test's
+// value, and when no clause is taken the result is nil.
+fn eval_cond<'a>(args: &'a [Expr], env: &mut Env) -> Result<Tail<'a>, EvalError> {
+    for (i, clause) in args.iter().enumerate() {
+        let Expr::List(parts) = clause else {
+            return Err(malformed("cond", args));
+        };
+        match parts.as_slice() {
+            [Expr::Symbol(symbol::ELSE), body @ ..] if i == args.len() - 1 => {
+                return eval_body(body, env)
+            }
+            [Expr::Symbol(symbol::ELSE), ..] | [] => return Err(malformed("cond", args)),
+            [test, body @ ..] => {
+                let value = eval(test, env)?;
+                if !is_truthy(&value) {
+                    continue;
+                }
+                if body.is_empty() {
+                    return Ok(Tail::Value(value));
+                }
+                return eval_body(body, env);
+            }
+        }
+    }
+    Ok(Tail::Value(Value::Nil))
+}
+
+// and yields the first false value or else the last one, and or the
+// first true value or else the last one, without evaluating the rest.
+// (and) is #t and (or) is #f.
+fn eval_and_or<'a>(args: &'a [Expr], env: &mut Env, and: bool) -> Result<Tail<'a>, EvalError> {
+    let Some((last, init)) = args.split_last() else {
+        return Ok(Tail::Value(Value::Bool(and)));
+    };
+    for arg in init {
+        let value = eval(arg, env)?;
+        if is_truthy(&value) != and {
+            return Ok(Tail::Value(value));
+        }
+    }
+    Ok(Tail::Expr(last))
+}
+
+// (when test body...) runs the body if test is true and (unless test
+// body...) if it is false; otherwise the result is nil.
+fn eval_when<'a>(
+    form: &Expr,
+    args: &'a [Expr],
+    env: &mut Env,
+    when: bool,
+) -> Result<Tail<'a>, EvalError> {
+    let Some((test, body)) = args.split_first() else {
+        return Err(malformed(if when { "when" } else { "unless" }, args));
+    };
+    let taken = is_truthy(&eval(test, env)?) == when;
+    if hooks::active() {
+        hooks::branch(form, taken);
+    }
+    if taken {
+        eval_body(body, env)
+    } else {
+        Ok(Tail::Value(Value::Nil))
+    }
+}
+
+// let evaluates every binding in the outer scope; let* evaluates each one
+// in the new scope so later bindings see earlier ones.
+fn eval_let<'a>(
+    form: &'static str,
+    args: &'a [Expr],
+    env: &mut Env,
+) -> Result<Tail<'a>, EvalError> {
+    let [Expr::List(bindings), body @ ..] = args else {
+        return Err(malformed(form, args));
+    };
+    let mut scope = env.extend();
+    for binding in bindings {
+        let [Expr::Symbol(name), value] = binding_parts(binding) else {
+            return Err(malformed(form, args));
+        };
+        let value = match form {
+            "let*" => eval(value, &mut scope)?,
+            _ => eval(value, env)?,
+        };
+        scope.define(*name, one_value(value)?);
+    }
+    *env = scope;
+    eval_body(body, env)
+}
+
+// (let-values (((a b) expr) ...) body...) binds each parameter list, as
+// lambda's, to the values its expr returns. As in let, every expr is
+// evaluated before any of the names are bound.
+fn eval_let_values<'a>(args: &'a [Expr], env: &mut Env) -> Result<Tail<'a>, EvalError> {
+    let [Expr::List(bindings), body @ ..] = args else {
+        return Err(malformed("let-values", args));
+    };
+    let scope = env.extend();
+    for binding in bindings {
+        let [params, value] = binding_parts(binding) else {
+            return Err(malformed("let-values", args));
+        };
+        let params = parse_params("let-values", params, args)?;
+        define_params(&params, eval(value, env)?.into_values(), &scope)?;
+    }
+    *env = scope;
+    eval_body(body, env)
+}
+
+// (match expr (pattern body...) ...) evaluates the body of the first
+// clause whose pattern matches the value of expr, with the pattern's
+// variables bound around it.
+fn eval_match<'a>(args: &'a [Expr], env: &mut Env) -> Result<Tail<'a>, EvalError> {
+    let [subject, clauses @ ..] = args else {
+        return Err(malformed("match", args));
+    };
+    let value = eval(subject, env)?;
+    for clause in clauses {
+        let [pattern, body @ ..] = binding_parts(clause) else {
+            return Err(malformed("match", args));
+        };
+        let scope = env.extend();
+        if match_pattern(pattern, &value, &scope) {
+            *env = scope;
+            return eval_body(body, env);
+        }
+    }
+    Err(EvalError::NoMatch(value))
+}
+
+// A symbol matches anything and binds it, except _, which binds nothing.
+// 'datum and other literals match values equal to them. List, dotted and
+// vector patterns match element by element, the tail of a dotted pattern
+// taking the rest of the list, and a map pattern {key pattern ...} matches
+// a map that has each key with a value matching its pattern.
+fn match_pattern(pattern: &Expr, value: &Value, scope: &Env) -> bool {
+    match pattern {
+        Expr::Symbol(symbol::WILDCARD) => true,
+        Expr::Symbol(name) => {
+            scope.define(*name, value.clone());
+            true
+        }
+        Expr::List(items) => match tagged(pattern, symbol::QUOTE) {
+            Some(datum) => same_datum(&Value::from(datum), value),
+            None => take_elements(value, items.len()).is_some_and(|(values, rest)| {
+                is_empty_list(&rest)
+                    && items
+                        .iter()
+                        .zip(values)
+                        .all(|(item, value)| match_pattern(item, value, scope))
+            }),
+        },
+        Expr::Pair(..) => {
+            let mut items = Vec::new();
+            let mut tail = pattern;
+            while let Expr::Pair(car, cdr) = tail {
+                items.push(&**car);
+                tail = cdr;
+            }
+            take_elements(value, items.len()).is_some_and(|(values, rest)| {
+                items
+                    .iter()
+                    .zip(values)
+                    .all(|(item, value)| match_pattern(item, value, scope))
+                    && match_pattern(tail, &rest, scope)
+            })
+        }
+        Expr::Vector(items) => match value {
+            Value::Vector(values) => {
+                items.len() == values.len()
+                    && items
+                        .iter()
+                        .zip(values)
+                        .all(|(item, value)| match_pattern(item, value, scope))
+            }
+            _ => false,
+        },
+        Expr::Map(entries) => match value {
+            Value::Map(values) => entries.iter().all(|(key, pattern)| {
+                let key = Value::from(key);
+                values
+                    .iter()
+                    .find(|(k, _)| *k == key)
+                    .is_some_and(|(_, value)| match_pattern(pattern, value, scope))
+            }),
+            _ => false,
+        },
+        literal => same_datum(&Value::from(literal), value),
+    }
+}
+
+// The first n elements of a proper or improper list, and whatever follows
+// them. None if the list is shorter than that.
+fn take_elements(mut value: &Value, n: usize) -> Option<(Vec<&Value>, Value)> {
+    let mut items = Vec::with_capacity(n);
+    while items.len() < n {
+        match value {
+            Value::Pair(car, cdr) => {
+                items.push(&**car);
+                value = cdr;
+            }
+            Value::List(list) if list.len() >= n - items.len() => {
+                let (taken, rest) = list.split_at(n - items.len());
+                items.extend(taken);
+                return Some((items, Value::List(rest.to_vec())));
+            }
+            _ => return None,
+        }
+    }
+    Some((items, value.clone()))
+}
+
+pub(crate) fn is_empty_list(value: &Value) -> bool {
+    match value {
+        Value::Nil => true,
+        Value::List(items) => items.is_empty(),
+        _ => false,
+    }
+}
+
+// Nil and the empty list are the same datum even though they compare
+// unequal as Values.
+fn same_datum(a: &Value, b: &Value) -> bool {
+    a == b || (is_empty_list(a) && is_empty_list(b))
+}
+
+fn binding_parts(binding: &Expr) -> &[Expr] {
+    match binding {
+        Expr::List(parts) => parts,
+        _ => &[],
+    }
+}
+
+// Everything but the last expression is evaluated here; the last one is
+// returned as the tail.
+fn eval_body<'a>(body: &'a [Expr], env: &mut Env) -> Result<Tail<'a>, EvalError> {
+    let Some((last, init)) = body.split_last() else {
+        return Ok(Tail::Value(Value::Nil));
+    };
+    for expr in init {
+        eval(expr, env)?;
+    }
+    Ok(Tail::Expr(last))
+}
+
+fn single(args: &[Expr]) -> Result<&Expr, EvalError> {
+    match args {
+        [arg] => Ok(arg),
+        _ => Err(EvalError::ArityMismatch {
+            expected: 1,
+            found: args.len(),
+        }),
+    }
+}
+
+fn tagged(expr: &Expr, tag: SymbolId) -> Option<&Expr> {
+    match expr {
+        Expr::List(items) => match items.as_slice() {
+            [Expr::Symbol(s), arg] if *s == tag => Some(arg),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Depth counts nested quasiquotes so only the outermost level's unquotes
+// are evaluated.
+fn quasiquote(template: &Expr, depth: usize, env: &mut Env) -> Result<Value, EvalError> {
+    let items = match template {
+        Expr::List(items) => items,
+        Expr::Pair(car, cdr) => {
+            return Ok(Value::cons(
+                quasiquote(car, depth, env)?,
+                quasiquote(cdr, depth, env)?,
+            ))
+        }
+        Expr::Vector(items) => {
+            return match quasiquote(&Expr::List(items.clone()), depth, env)? {
+                Value::List(items) => Ok(Value::Vector(items)),
+                Value::Nil => Ok(Value::Vector(vec![])),
+                other => Err(EvalError::TypeMismatch {
+                    expected: "list",
+                    found: other,
+                }),
+            }
+        }
+        Expr::Map(entries) => {
+            return entries
+                .iter()
+                .map(|(key, value)| {
+                    Ok((quasiquote(key, depth, env)?, quasiquote(value, depth, env)?))
+                })
+                .collect::<Result<_, _>>()
+                .map(Value::Map)
+        }
+        _ => return Ok(Value::from(template)),
+    };
+    let wrap = |tag: SymbolId, value: Value| Value::List(vec![Value::Symbol(tag), value]);
+    if let Some(inner) = tagged(template, symbol::UNQUOTE) {
+        return match depth {
+            1 => eval(inner, env),
+            _ => Ok(wrap(symbol::UNQUOTE, quasiquote(inner, depth - 1, env)?)),
+        };
+    }
+    if let Some(inner) = tagged(template, symbol::QUASIQUOTE) {
+        return Ok(wrap(symbol::QUASIQUOTE, quasiquote(inner, depth + 1, env)?));
+    }
+    let mut result = Vec::with_capacity(items.len());
+    for (i, item) in items.iter().enumerate() {
+        // (a . ,b) reads as (a unquote b), so an unquote just before the
+        // last item is an unquoted tail.
+        if i > 0 && i + 2 == items.len() && *item == Expr::Symbol(symbol::UNQUOTE) {
+            let tail = quasiquote(&Expr::List(items[i..].to_vec()), depth, env)?;
+            return Ok(result
+                .into_iter()
+                .rev()
+                .fold(tail, |cdr, car| Value::cons(car, cdr)));
+        }
+        match tagged(item, symbol::UNQUOTE_SPLICING) {
+            Some(inner) if depth == 1 => match eval(inner, env)? {
+                Value::List(spliced) => result.extend(spliced),
+                Value::Nil => {}
+                other => {
+                    return Err(EvalError::TypeMismatch {
+                        expected: "list",
+                        found: other,
+                    })
+                }
+            },
+            _ => result.push(quasiquote(item, depth, env)?),
+        }
+    }
+    Ok(Value::List(result))
+}
+
+fn eval_args(args: &[Expr], env: &mut Env) -> Result<Vec<Value>, EvalError> {
+    args.iter().map(|arg| one_value(eval(arg, env)?)).collect()
+}
+
+// Several values are only for call-with-values and let-values to take
+// apart, so a place that holds one value, such as an argument or a
+// variable, refuses them rather than let them end up in data.
+pub(crate) fn one_value(value: Value) -> Result<Value, EvalError> {
+    match value {
+        Value::Values(_) => Err(EvalError::TypeMismatch {
+            expected: "one value",
+            found: value,
+        }),
+        value => Ok(value),
+    }
+}
+
+pub(crate) fn one_values(values: &[Value]) -> Result<(), EvalError> {
+    let several = values
+        .iter()
+        .find(|value| matches!(value, Value::Values(_)));
+    several.map_or(Ok(()), |value| one_value(value.clone()).map(drop))
+}
+
+fn bind_params(lambda: &Lambda, args: Vec<Value>, env: &Env) -> Result<Env, EvalError> {
+    let scope = env.extend();
+    define_params(lambda, args, &scope)?;
+    Ok(scope)
+}
+
+fn define_params(lambda: &Lambda, mut args: Vec<Value>, scope: &Env) -> Result<(), EvalError> {
+    let expected = lambda.params.len();
+    let takes_more = lambda.rest.is_some() || !lambda.keys.is_empty();
+    if args.len() < expected || (!takes_more && args.len() > expected) {
+        return Err(EvalError::ArityMismatch {
+            expected,
+            found: args.len(),
+        });
+    }
+    let extra = args.split_off(expected);
+    for (param, arg) in lambda.params.iter().zip(args) {
+        scope.define(*param, arg);
+    }
+    if let Some(rest) = lambda.rest {
+        scope.define(rest, Value::List(extra));
+    } else if !lambda.keys.is_empty() {
+        bind_keys(&lambda.keys, extra, scope)?;
+    }
+    Ok(())
+}
+
+// Keyword arguments follow the positional ones as :name value pairs, in
+// any order. A key that isn't passed gets its default, evaluated in the new
+// scope so it can refer to the parameters before it, or nil if it has none.
+fn bind_keys(
+    keys: &[(SymbolId, Option<Expr>)],
+    args: Vec<Value>,
+    scope: &Env,
+) -> Result<(), EvalError> {
+    let mut passed = BTreeMap::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match (&arg, args.next()) {
+            (Value::Keyword(name), Some(value)) if keys.iter().any(|(key, _)| key == name) => {
+                passed.insert(*name, value);
+            }
+            _ => return Err(EvalError::UnexpectedArgument(arg)),
+        }
+    }
+    for (key, default) in keys {
+        let value = match (passed.remove(key), default) {
+            (Some(value), _) => value,
+            (None, Some(default)) => eval(default, &mut scope.clone())?,
+            (None, None) => Value::Nil,
+        };
+        scope.define(*key, value);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::read;
+    use alloc::{borrow::ToOwned, boxed::Box, format};
+
+    fn items(expr: Expr) -> Vec<Expr> {
+        match expr {
+            Expr::List(items) => items,
+            _ => panic!("expected a list"),
+        }
+    }
+
+    fn call(op: &str, args: Vec<Expr>) -> Expr {
+        let mut items = vec![Expr::Symbol(op.into())];
+        items.extend(args);
+        Expr::List(items)
+    }
+
+    #[test]
+    fn env_test() {
+        let global = Env::new();
+        global.define("x", Value::Integer(1));
+        let local = global.extend();
+        local.define("x", Value::Integer(2));
+        local.define("y", Value::Integer(3));
+        assert_eq!(local.get("x"), Some(Value::Integer(2)));
+        assert_eq!(local.get("y"), Some(Value::Integer(3)));
+        assert_eq!(global.get("x"), Some(Value::Integer(1)));
+        assert_eq!(global.get("y"), None);
+        assert!(local.set("x", Value::Integer(4)));
+        assert!(global.set("x", Value::Integer(5)));
+        assert_eq!(local.get("x"), Some(Value::Integer(4)));
+        assert_eq!(global.get("x"), Some(Value::Integer(5)));
+        assert!(!local.set("z", Value::Nil));
+        assert_eq!(local.get("z"), None);
+    }
+
+    #[test]
+    fn eval_set_test() {
+        let mut env = crate::builtins::global_env();
+        run(
+            "(define (counter)
+               (let ((n 0))
+                 (lambda () (set! n (+ n 1)) n)))
+             (define c (counter))
+             (c)",
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(run("(c)", &mut env), Ok(Value::Integer(2)));
+        assert_eq!(run("((counter))", &mut env), Ok(Value::Integer(1)));
+        assert_eq!(
+            run("(set! nope 1)", &mut env),
+            Err(EvalError::UnboundSymbol("nope".to_owned()))
+        );
+        assert_eq!(env.get("nope"), None);
+        assert!(run("(set! (c) 1)", &mut env).is_err());
+    }
+
+    #[test]
+    fn is_truthy_test() {
+        assert!(!is_truthy(&Value::Bool(false)));
+        assert!(!is_truthy(&Value::Nil));
+        assert!(is_truthy(&Value::Bool(true)));
+        assert!(is_truthy(&Value::Integer(0)));
+        assert!(is_truthy(&Value::String(String::new())));
+    }
+
+    #[test]
+    fn eval_atom_test() {
+        let mut env = Env::new();
+        env.define("answer", Value::Integer(42));
+        assert_eq!(eval(&Expr::Integer(7), &mut env), Ok(Value::Integer(7)));
+        assert_eq!(eval(&Expr::Bool(true), &mut env), Ok(Value::Bool(true)));
+        assert_eq!(
+            eval(&Expr::Symbol("answer".into()), &mut env),
+            Ok(Value::Integer(42))
+        );
+        assert_eq!(
+            eval(&Expr::Symbol("missing".into()), &mut env),
+            Err(EvalError::UnboundSymbol("missing".into()))
+        );
+        assert_eq!(eval(&Expr::List(vec![]), &mut env), Ok(Value::Nil));
+    }
+
+    #[test]
+    fn eval_quote_test() {
+        let mut env = Env::new();
+        env.define("x", Value::Integer(1));
+        env.define(
+            "xs",
+            Value::List(vec![Value::Integer(2), Value::Integer(3)]),
+        );
+        assert_eq!(
+            eval(&read("(quote (a x))").unwrap(), &mut env),
+            Ok(value("(a x)"))
+        );
+        assert_eq!(
+            eval(&call("quote", vec![]), &mut env),
+            Err(EvalError::ArityMismatch {
+                expected: 1,
+                found: 0
+            })
+        );
+        let template = call(
+            "quasiquote",
+            vec![Expr::List(vec![
+                Expr::Symbol("a".into()),
+                call("unquote", vec![Expr::Symbol("x".into())]),
+                call("unquote-splicing", vec![Expr::Symbol("xs".into())]),
+                call(
+                    "quasiquote",
+                    vec![call("unquote", vec![Expr::Symbol("x".into())])],
+                ),
+            ])],
+        );
+        assert_eq!(
+            eval(&template, &mut env),
+            Ok(Value::List(vec![
+                Value::Symbol("a".into()),
+                Value::Integer(1),
+                Value::Integer(2),
+                Value::Integer(3),
+                Value::from(call(
+                    "quasiquote",
+                    vec![call("unquote", vec![Expr::Symbol("x".into())])],
+                )),
+            ]))
+        );
+    }
+
+    fn value(source: &str) -> Value {
+        Value::from(read(source).unwrap())
+    }
+
+    fn run(source: &str, env: &mut Env) -> Result<Value, EvalError> {
+        let mut result = Value::Nil;
+        for expr in crate::parser::read_all(source).unwrap() {
+            result = eval(&expr, env)?;
+        }
+        Ok(result)
+    }
+
+    #[test]
+    fn eval_define_test() {
+        let mut env = Env::new();
+        assert_eq!(run("(define x 42) x", &mut env), Ok(Value::Integer(42)));
+        assert_eq!(
+            run("(define (pair a b) `(,a ,b)) (pair 1 x)", &mut env),
+            Ok(value("(1 42)"))
+        );
+        assert!(matches!(
+            run("(define 1 2)", &mut env),
+            Err(EvalError::MalformedForm { form: "define", .. })
+        ));
+    }
+
+    #[test]
+    fn eval_if_test() {
+        let mut env = Env::new();
+        assert_eq!(run("(if #t 1 2)", &mut env), Ok(Value::Integer(1)));
+        assert_eq!(run("(if #f 1 2)", &mut env), Ok(Value::Integer(2)));
+        assert_eq!(run("(if 0 1 2)", &mut env), Ok(Value::Integer(1)));
+        assert_eq!(run("(if #f 1)", &mut env), Ok(Value::Nil));
+        assert_eq!(run("(if #f undefined 2)", &mut env), Ok(Value::Integer(2)));
+        assert!(run("(if)", &mut env).is_err());
+    }
+
+    #[test]
+    fn eval_cond_test() {
+        let mut env = crate::builtins::global_env();
+        let cond = "(define (sign n) (cond ((< n 0) 'negative) ((= n 0) 'zero) (else 'positive)))";
+        run(cond, &mut env).unwrap();
+        assert_eq!(run("(sign -5)", &mut env), Ok(value("negative")));
+        assert_eq!(run("(sign 0)", &mut env), Ok(value("zero")));
+        assert_eq!(run("(sign 2)", &mut env), Ok(value("positive")));
+        assert_eq!(
+            run("(cond (#f 1) ((+ 1 1)))", &mut env),
+            Ok(Value::Integer(2))
+        );
+        assert_eq!(run("(cond (#f undefined))", &mut env), Ok(Value::Nil));
+        assert_eq!(run("(cond)", &mut env), Ok(Value::Nil));
+        assert!(run("(cond (else 1) (#t 2))", &mut env).is_err());
+        assert!(run("(cond 1)", &mut env).is_err());
+    }
+
+    #[test]
+    fn eval_and_or_test() {
+        let mut env = Env::new();
+        assert_eq!(run("(and 1 2 3)", &mut env), Ok(Value::Integer(3)));
+        assert_eq!(
+            run("(and 1 #f undefined)", &mut env),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(run("(and 1 ())", &mut env), Ok(Value::Nil));
+        assert_eq!(run("(and)", &mut env), Ok(Value::Bool(true)));
+        assert_eq!(
+            run("(or #f () 3 undefined)", &mut env),
+            Ok(Value::Integer(3))
+        );
+        assert_eq!(run("(or #f ())", &mut env), Ok(Value::Nil));
+        assert_eq!(run("(or)", &mut env), Ok(Value::Bool(false)));
+    }
+
+    #[test]
+    fn eval_when_unless_test() {
+        let mut env = Env::new();
+        assert_eq!(
+            run("(when 1 (define w 1) (quote yes))", &mut env),
+            Ok(value("yes"))
+        );
+        assert_eq!(run("w", &mut env), Ok(Value::Integer(1)));
+        assert_eq!(run("(when #f undefined)", &mut env), Ok(Value::Nil));
+        assert_eq!(run("(unless #f 1 2)", &mut env), Ok(Value::Integer(2)));
+        assert_eq!(run("(unless 0 undefined)", &mut env), Ok(Value::Nil));
+        assert!(run("(when)", &mut env).is_err());
+    }
+
+    #[test]
+    fn eval_let_test() {
+        let mut env = Env::new();
+        assert_eq!(
+            run("(define x 1) (let ((x 2) (y x)) `(,x ,y))", &mut env),
+            Ok(value("(2 1)"))
+        );
+        let let_star = call("let*", items(read("(((x 2) (y x)) `(,x ,y))").unwrap()));
+        assert_eq!(eval(&let_star, &mut env), Ok(value("(2 2)")));
+        assert_eq!(run("x", &mut env), Ok(Value::Integer(1)));
+        assert!(run("(let (x) x)", &mut env).is_err());
+    }
+
+    #[test]
+    fn eval_let_values_test() {
+        let mut env = crate::builtins::global_env();
+        assert_eq!(
+            run(
+                "(define x 1)
+                 (let-values (((a b) (values 1 2)) ((c . rest) (values 3 4 5)) (all (values))
+                              ((x) x) ((y) (values x)))
+                   `(,a ,b ,c ,rest ,all ,x ,y))",
+                &mut env
+            ),
+            Ok(value("(1 2 3 (4 5) () 1 1)"))
+        );
+        assert_eq!(
+            run("(let-values (((a b) (values 1))) a)", &mut env),
+            Err(EvalError::ArityMismatch {
+                expected: 2,
+                found: 1
+            })
+        );
+        assert!(run("(let-values ((a)) a)", &mut env).is_err());
+        assert!(run("(let-values (((1) 1)) 1)", &mut env).is_err());
+    }
+
+    #[test]
+    fn eval_begin_test() {
+        let mut env = Env::new();
+        assert_eq!(
+            run("(begin (define a 1) (define b 2) `(,a ,b))", &mut env),
+            Ok(value("(1 2)"))
+        );
+        assert_eq!(run("(begin)", &mut env), Ok(Value::Nil));
+    }
+
+    #[test]
+    fn eval_closure_test() {
+        let mut env = Env::new();
+        run(
+            "(define (constantly v) (lambda (ignored) v))
+             (define five (constantly 5))
+             (define v 0)",
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(run("(five 1)", &mut env), Ok(Value::Integer(5)));
+        assert_eq!(
+            run("(let ((y 7)) ((lambda (x) `(,x ,y)) 1))", &mut env),
+            Ok(value("(1 7)"))
+        );
+        assert_eq!(
+            run("((lambda () 1) 2)", &mut env),
+            Err(EvalError::ArityMismatch {
+                expected: 0,
+                found: 1
+            })
+        );
+    }
+
+    #[test]
+    fn eval_try_test() {
+        let mut env = crate::builtins::global_env();
+        assert_eq!(
+            run(
+                "(try (error \"no\" 1) (catch e (error-object-irritants e)))",
+                &mut env
+            ),
+            Ok(value("(1)"))
+        );
+        assert_eq!(
+            run("(try (define x 1) (+ x 1) (catch e 'unused))", &mut env),
+            Ok(Value::Integer(2))
+        );
+        assert_eq!(
+            run("(try (raise 'up) (catch e (list 'caught e)))", &mut env),
+            Ok(value("(caught up)"))
+        );
+        assert_eq!(
+            run("(try (car 1) (catch e (error-object-message e)))", &mut env),
+            Ok(Value::String("expected list, found 1".to_owned()))
+        );
+        assert_eq!(
+            run(
+                "(try (try (raise 1) (catch e (raise (+ e 1)))) (catch e e))",
+                &mut env
+            ),
+            Ok(Value::Integer(2))
+        );
+        assert_eq!(
+            run("(try (raise 1) (catch e (raise 'again)))", &mut env),
+            Err(EvalError::Raised(Value::Symbol("again".into())))
+        );
+        assert!(run("(try 1)", &mut env).is_err());
+        assert!(run("(try 1 (catch))", &mut env).is_err());
+    }
+
+    #[test]
+    fn higher_order_test() {
+        let mut env = crate::builtins::global_env();
+        run(
+            "(define (adder n) (lambda (x) (+ x n)))
+             (define (compose f g) (lambda (x) (f (g x))))
+             (define add2 (adder 2))
+             (define add10 (adder 10))
+             (define n 100)",
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(
+            run("(list (add2 1) (add10 1))", &mut env),
+            Ok(value("(3 11)"))
+        );
+        assert_eq!(
+            run("((compose add2 add10) 0)", &mut env),
+            Ok(Value::Integer(12))
+        );
+        assert_eq!(
+            run("(((lambda (a) (lambda (b) (list a b))) 1) 2)", &mut env),
+            Ok(value("(1 2)"))
+        );
+    }
+
+    #[test]
+    fn rest_params_test() {
+        let mut env = Env::new();
+        run(
+            "(define (tail a . rest) rest)
+             (define (all . args) args)
+             (define collect (lambda items items))",
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(run("(tail 1 2 3)", &mut env), Ok(value("(2 3)")));
+        assert_eq!(run("(tail 1)", &mut env), Ok(value("()")));
+        assert_eq!(run("(all 1 2)", &mut env), Ok(value("(1 2)")));
+        assert_eq!(run("(collect)", &mut env), Ok(value("()")));
+        assert_eq!(
+            run("(tail)", &mut env),
+            Err(EvalError::ArityMismatch {
+                expected: 1,
+                found: 0
+            })
+        );
+        assert_eq!(run("`(1 . ,(tail 0 2))", &mut env), Ok(value("(1 2)")));
+        assert!(run("(all . 1)", &mut env).is_err());
+    }
+
+    #[test]
+    fn keyword_params_test() {
+        let mut env = crate::builtins::global_env();
+        run(
+            "(define (window title &key (width 80) (height (/ width 2)) border)
+               (list title width height border))",
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(run(":width", &mut env), Ok(Value::Keyword("width".into())));
+        assert_eq!(
+            run("(window 'main)", &mut env).map(|v| v.to_string()),
+            Ok("(main 80 40 ())".to_owned())
+        );
+        assert_eq!(
+            run("(window 'main :border #t :width 100)", &mut env),
+            Ok(value("(main 100 50 #t)"))
+        );
+        assert_eq!(
+            run("(window 'main :depth 3)", &mut env),
+            Err(EvalError::UnexpectedArgument(Value::Keyword(
+                "depth".into()
+            )))
+        );
+        assert_eq!(
+            run("(window 'main 3)", &mut env),
+            Err(EvalError::UnexpectedArgument(Value::Integer(3)))
+        );
+        assert!(run("(window 'main :width)", &mut env).is_err());
+        assert_eq!(
+            run("(lambda (a &key b) a)", &mut env).map(|f| f.is_procedure()),
+            Ok(true)
+        );
+        assert!(run("(lambda (&key b . rest) b)", &mut env).is_err());
+        assert!(run("(lambda (&key (b)) b)", &mut env).is_err());
+    }
+
+    #[test]
+    fn eval_vector_map_test() {
+        let mut env = crate::builtins::global_env();
+        run("(define x 2)", &mut env).unwrap();
+        assert_eq!(
+            run("[1 x (list x)]", &mut env).map(|v| v.to_string()),
+            Ok("[1 2 (2)]".to_owned())
+        );
+        assert_eq!(
+            run("{:x x 'y [x]}", &mut env).map(|v| v.to_string()),
+            Ok("{:x 2 y [2]}".to_owned())
+        );
+        assert_eq!(
+            run("'[x {x x}]", &mut env).map(|v| v.to_string()),
+            Ok("[x {x x}]".to_owned())
+        );
+        assert_eq!(
+            run("`[x ,x ,@(list 3 4) {k ,x}]", &mut env).map(|v| v.to_string()),
+            Ok("[x 2 3 4 {k 2}]".to_owned())
+        );
+        assert_eq!(
+            run("[nope]", &mut env),
+            Err(EvalError::UnboundSymbol("nope".to_owned()))
+        );
+    }
+
+    #[test]
+    fn eval_match_test() {
+        let mut env = crate::builtins::global_env();
+        run(
+            "(define (describe x)
+               (match x
+                 (0 'zero)
+                 ('() 'empty)
+                 (\"hi\" 'greeting)
+                 (('add a b) (+ a b))
+                 (('neg _) 'negation)
+                 ((first . rest) (list 'list first rest))
+                 ([a b] (list 'pair b a))
+                 ({:name n} n)
+                 (other (list 'other other))))",
+            &mut env,
+        )
+        .unwrap();
+        let describe = |arg: &str, env: &mut Env| {
+            run(&format!("(describe {arg})"), env).map(|v| v.to_string())
+        };
+        assert_eq!(describe("0", &mut env), Ok("zero".to_owned()));
+        assert_eq!(describe("'()", &mut env), Ok("empty".to_owned()));
+        assert_eq!(describe("\"hi\"", &mut env), Ok("greeting".to_owned()));
+        assert_eq!(describe("'(add 1 2)", &mut env), Ok("3".to_owned()));
+        assert_eq!(describe("'(neg 5)", &mut env), Ok("negation".to_owned()));
+        assert_eq!(describe("'(neg)", &mut env), Ok("(list neg ())".to_owned()));
+        assert_eq!(
+            describe("'(1 2 . 3)", &mut env),
+            Ok("(list 1 (2 . 3))".to_owned())
+        );
+        assert_eq!(describe("[1 2]", &mut env), Ok("(pair 2 1)".to_owned()));
+        assert_eq!(
+            describe("{:age 3 :name 'ann}", &mut env),
+            Ok("ann".to_owned())
+        );
+        assert_eq!(
+            describe("{:age 3}", &mut env),
+            Ok("(other {:age 3})".to_owned())
+        );
+        assert_eq!(describe("1.5", &mut env), Ok("(other 1.5)".to_owned()));
+        assert_eq!(
+            run("(match 1 (2 'two))", &mut env),
+            Err(EvalError::NoMatch(Value::Integer(1)))
+        );
+        assert_eq!(
+            run("(match 1 (_ a))", &mut env),
+            Err(EvalError::UnboundSymbol("a".to_owned()))
+        );
+        assert!(run("(match 1 2)", &mut env).is_err());
+    }
+
+    #[test]
+    fn eval_application_test() {
+        let mut env = crate::builtins::global_env();
+        let double = Expr::Lambda(Box::new(Lambda {
+            params: vec!["n".into()],
+            rest: None,
+            keys: vec![],
+            body: vec![call("*", vec![Expr::Symbol("n".into()), Expr::Integer(2)])],
+        }));
+        let double = eval(&double, &mut env).unwrap();
+        env.define("double", double);
+        assert_eq!(
+            eval(&call("double", vec![Expr::Integer(21)]), &mut env),
+            Ok(Value::Integer(42))
+        );
+        assert_eq!(
+            eval(&call("double", vec![]), &mut env),
+            Err(EvalError::ArityMismatch {
+                expected: 1,
+                found: 0
+            })
+        );
+        assert_eq!(
+            eval(&Expr::List(vec![Expr::Integer(1)]), &mut env),
+            Err(EvalError::NotCallable(Value::Integer(1)))
+        );
+    }
+
+    #[test]
+    fn tail_call_test() {
+        let mut env = crate::builtins::global_env();
+        assert_eq!(
+            run(
+                "(define (count n) (if (= n 0) 'done (count (- n 1))))
+                 (count 1000000)",
+                &mut env
+            ),
+            Ok(Value::Symbol("done".into()))
+        );
+        assert_eq!(
+            run(
+                "(define (even? n) (if (= n 0) #t (odd? (- n 1))))
+                 (define (odd? n) (if (= n 0) #f (even? (- n 1))))
+                 (even? 10001)",
+                &mut env
+            ),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(
+            run(
+                "(define (down n) (let ((m (- n 1))) (begin (if (= m 0) m (down m)))))
+                 (down 10000)",
+                &mut env
+            ),
+            Ok(Value::Integer(0))
+        );
+        // A macro call expanded as it runs keeps its expansion's tail
+        // calls.
+        assert_eq!(
+            run(
+                "(define (lp n) (my-if (= n 0) 'done (lp (- n 1))))
+                 (defmacro my-if (test then else) `(if ,test ,then ,else))
+                 (lp 1000000)",
+                &mut env
+            ),
+            Ok(Value::Symbol("done".into()))
+        );
+    }
+
+    #[test]
+    fn apply_test() {
+        let mut env = crate::builtins::global_env();
+        let pair = run("(lambda (a b) `(,a ,b))", &mut env).unwrap();
+        assert_eq!(
+            apply(&pair, vec![Value::Integer(1), Value::Integer(2)]),
+            Ok(value("(1 2)"))
+        );
+        assert_eq!(
+            apply(&env.get("-").unwrap(), vec![Value::Integer(3)]),
+            Ok(Value::Integer(-3))
+        );
+        assert!(apply(&Value::Nil, vec![]).is_err());
+    }
+}
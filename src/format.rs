@@ -0,0 +1,456 @@
+use alloc::{
+    borrow::ToOwned,
+    format,
+    string::String,
+    vec,
+    vec::Vec,
+};
+
+use crate::{
+    lexer::{skip_block_comment, Span, Spanned, Token},
+    parser::{lex_source, parse_expr, read_all_spanned, ReadError, SpannedExpr, Tokens},
+};
+
+// Output lines are kept to this many columns where the code allows it.
+const WIDTH: usize = 80;
+
+// Re-emits source with canonical layout: a form that fits on the rest of
+// its line is written on one line, and anything longer is broken with
+// bodies indented two columns and arguments lined up under the first.
+// Atoms are copied as they were written, and so are comments, which stay
+// next to the code they were next to. Blank lines between forms are kept,
+// collapsed to one.
+pub fn format_source(input: &str) -> Result<String, ReadError> {
+    let forms = read_all_spanned(input)?;
+    let tokens = lex_source(input)?;
+    let formatter = Formatter {
+        source: input,
+        comments: comments(input, &tokens),
+    };
+    Ok(formatter.program(&forms))
+}
+
+struct Comment {
+    span: Span,
+    // A ; comment runs to the end of its line, so whatever follows it has
+    // to start on the next.
+    line: bool,
+    // Written on the same line as the code before it.
+    trailing: bool,
+}
+
+// Comments are whatever lies between tokens other than whitespace, plus
+// #; and the datum it comments out, which the parser drops.
+fn comments(source: &str, tokens: &[Spanned<Token>]) -> Vec<Comment> {
+    let mut comments = Vec::new();
+    let mut end = 0;
+    let mut i = 0;
+    loop {
+        let gap_end = tokens.get(i).map_or(source.len(), |token| token.span.start);
+        scan_gap(source, end, gap_end, &mut comments);
+        let Some(token) = tokens.get(i) else {
+            return comments;
+        };
+        if token.value == Token::DatumComment {
+            let next = match parse_expr(Tokens::new(&tokens[i + 1..])) {
+                Ok((rest, _)) => tokens.len() - rest.tokens.len(),
+                Err(_) => tokens.len(),
+            };
+            let span = Span::new(token.span.start, tokens[next - 1].span.end);
+            comments.push(Comment {
+                span,
+                line: false,
+                trailing: is_trailing(source, span.start),
+            });
+            end = span.end;
+            i = next;
+        } else {
+            end = token.span.end;
+            i += 1;
+        }
+    }
+}
+
+fn scan_gap(source: &str, start: usize, end: usize, comments: &mut Vec<Comment>) {
+    let mut pos = start;
+    while pos < end {
+        let rest = &source[pos..end];
+        let trimmed = rest.trim_start();
+        pos += rest.len() - trimmed.len();
+        let len = if trimmed.starts_with(';') {
+            trimmed.find('\n').unwrap_or(trimmed.len())
+        } else if trimmed.starts_with("#|") {
+            // The lexer has already checked the comment is closed.
+            trimmed.len() - skip_block_comment(trimmed).map_or("", |rest| rest).len()
+        } else {
+            break;
+        };
+        let span = Span::new(pos, pos + len);
+        comments.push(Comment {
+            span,
+            line: trimmed.starts_with(';'),
+            trailing: is_trailing(source, pos),
+        });
+        pos = span.end;
+    }
+}
+
+fn is_trailing(source: &str, start: usize) -> bool {
+    let before = source[..start].trim_end_matches([' ', '\t']);
+    !before.is_empty() && !before.ends_with('\n')
+}
+
+#[derive(Clone, Copy)]
+enum Piece<'a> {
+    Expr(&'a SpannedExpr),
+    // The tail of a dotted list, written after a dot.
+    Tail(&'a SpannedExpr),
+    Comment(&'a Comment),
+}
+
+impl Piece<'_> {
+    fn span(&self) -> Span {
+        match self {
+            Piece::Expr(expr) | Piece::Tail(expr) => expr.span(),
+            Piece::Comment(comment) => comment.span,
+        }
+    }
+}
+
+// How the elements of a list are laid out when it doesn't fit on a line.
+#[derive(Clone, Copy)]
+enum Style {
+    // The head and this many more elements on the first line, the rest
+    // indented two columns: (define (f x)\n  body).
+    Body(usize),
+    // The head and first argument on the first line, the rest lined up
+    // under the first argument.
+    Call,
+    // One element per line, lined up under the first.
+    Data,
+    // Like Data, two elements per line, for maps.
+    Pairs,
+}
+
+fn body_style(head: &str) -> Option<Style> {
+    let distinguished = match head {
+        "begin" | "try" => 0,
+        "define" | "lambda" | "let" | "let*" | "let-values" | "when" | "unless" | "match"
+        | "catch" => 1,
+        "defmacro" => 2,
+        _ => return None,
+    };
+    Some(Style::Body(distinguished))
+}
+
+fn quote_prefix(text: &str) -> bool {
+    matches!(text, "'" | "`" | "," | ",@")
+}
+
+fn width(text: &str) -> usize {
+    text.chars().count()
+}
+
+// The column the next thing written after text starts at, text itself
+// having started at column.
+fn end_column(text: &str, column: usize) -> usize {
+    match text.rfind('\n') {
+        Some(i) => width(&text[i + 1..]),
+        None => column + width(text),
+    }
+}
+
+fn blank_line_between(source: &str, a: Span, b: Span) -> bool {
+    source[a.end..b.start].matches('\n').count() >= 2
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    comments: Vec<Comment>,
+}
+
+impl<'a> Formatter<'a> {
+    fn text(&self, span: Span) -> &'a str {
+        &self.source[span.start..span.end]
+    }
+
+    fn has_comments(&self, span: Span) -> bool {
+        self.comments
+            .iter()
+            .any(|comment| comment.span.start >= span.start && comment.span.end <= span.end)
+    }
+
+    // The pieces in order, with the comments that lie between them, but
+    // not those inside them, which they write themselves.
+    fn pieces(&'a self, items: Vec<Piece<'a>>, outer: Span) -> Vec<Piece<'a>> {
+        let inside = |span: Span| {
+            items
+                .iter()
+                .any(|item| item.span().start <= span.start && span.end <= item.span().end)
+        };
+        let mut pieces: Vec<_> = self
+            .comments
+            .iter()
+            .filter(|c| c.span.start >= outer.start && c.span.end <= outer.end && !inside(c.span))
+            .map(Piece::Comment)
+            .chain(items.iter().copied())
+            .collect();
+        pieces.sort_by_key(|piece| piece.span().start);
+        pieces
+    }
+
+    fn program(&self, forms: &[SpannedExpr]) -> String {
+        let items = forms.iter().map(Piece::Expr).collect();
+        let pieces = self.pieces(items, Span::new(0, self.source.len()));
+        let mut out = String::new();
+        for (i, piece) in pieces.iter().enumerate() {
+            if i > 0 {
+                match piece {
+                    Piece::Comment(comment) if comment.trailing => out.push(' '),
+                    _ if blank_line_between(self.source, pieces[i - 1].span(), piece.span()) => {
+                        out.push_str("\n\n")
+                    }
+                    _ => out.push('\n'),
+                }
+            }
+            let column = end_column(&out, 0);
+            out.push_str(&self.render_piece(*piece, column));
+        }
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out
+    }
+
+    fn render_piece(&self, piece: Piece, column: usize) -> String {
+        match piece {
+            Piece::Expr(expr) => self.render(expr, column),
+            Piece::Tail(expr) => format!(". {}", self.render(expr, column + 2)),
+            Piece::Comment(comment) => self.text(comment.span).to_owned(),
+        }
+    }
+
+    // The form on one line, or None if it has comments in it.
+    fn flat(&self, expr: &SpannedExpr) -> Option<String> {
+        if self.has_comments(expr.span()) {
+            return None;
+        }
+        let join = |items: &mut dyn Iterator<Item = &SpannedExpr>| {
+            items
+                .map(|item| self.flat(item))
+                .collect::<Option<Vec<_>>>()
+                .map(|items| items.join(" "))
+        };
+        Some(match expr {
+            SpannedExpr::Atom(atom) => self.text(atom.span).to_owned(),
+            SpannedExpr::List(list) => match self.quoted(list) {
+                Some((prefix, datum)) => format!("{prefix}{}", self.flat(datum)?),
+                None => format!("({})", join(&mut list.value.iter())?),
+            },
+            SpannedExpr::DottedList(list) => {
+                let (items, tail) = &list.value;
+                format!("({} . {})", join(&mut items.iter())?, self.flat(tail)?)
+            }
+            SpannedExpr::Vector(vector) => format!("[{}]", join(&mut vector.value.iter())?),
+            SpannedExpr::Map(map) => {
+                let mut items = map.value.iter().flat_map(|(key, value)| [key, value]);
+                format!("{{{}}}", join(&mut items)?)
+            }
+        })
+    }
+
+    // 'x and the like, which read as (quote x) but are written as they were.
+    fn quoted<'e>(
+        &self,
+        list: &'e Spanned<Vec<SpannedExpr>>,
+    ) -> Option<(&'a str, &'e SpannedExpr)> {
+        match list.value.as_slice() {
+            [SpannedExpr::Atom(head), datum] if quote_prefix(self.text(head.span)) => {
+                Some((self.text(head.span), datum))
+            }
+            _ => None,
+        }
+    }
+
+    fn render(&self, expr: &SpannedExpr, column: usize) -> String {
+        if let Some(flat) = self.flat(expr) {
+            if column + width(&flat) <= WIDTH || matches!(expr, SpannedExpr::Atom(_)) {
+                return flat;
+            }
+        }
+        match expr {
+            SpannedExpr::Atom(atom) => self.text(atom.span).to_owned(),
+            SpannedExpr::List(list) => {
+                if let Some((prefix, datum)) = self.quoted(list) {
+                    return format!("{prefix}{}", self.render(datum, column + width(prefix)));
+                }
+                let style = match list.value.first() {
+                    Some(SpannedExpr::Atom(head)) => {
+                        body_style(self.text(head.span)).unwrap_or(Style::Call)
+                    }
+                    _ => Style::Data,
+                };
+                let items = list.value.iter().map(Piece::Expr).collect();
+                self.list(("(", ")"), self.pieces(items, list.span), column, style)
+            }
+            SpannedExpr::DottedList(list) => {
+                let (items, tail) = &list.value;
+                let items = items
+                    .iter()
+                    .map(Piece::Expr)
+                    .chain([Piece::Tail(tail)])
+                    .collect();
+                let style = match list.value.0.first() {
+                    Some(SpannedExpr::Atom(_)) => Style::Call,
+                    _ => Style::Data,
+                };
+                self.list(("(", ")"), self.pieces(items, list.span), column, style)
+            }
+            SpannedExpr::Vector(vector) => {
+                let items = vector.value.iter().map(Piece::Expr).collect();
+                self.list(("[", "]"), self.pieces(items, vector.span), column, Style::Data)
+            }
+            SpannedExpr::Map(map) => {
+                let items = map
+                    .value
+                    .iter()
+                    .flat_map(|(key, value)| [Piece::Expr(key), Piece::Expr(value)])
+                    .collect();
+                self.list(("{", "}"), self.pieces(items, map.span), column, Style::Pairs)
+            }
+        }
+    }
+
+    fn list(
+        &self,
+        (open, close): (&str, &str),
+        pieces: Vec<Piece>,
+        column: usize,
+        style: Style,
+    ) -> String {
+        let inner = column + open.len();
+        let (first_line, per_line) = match style {
+            Style::Body(distinguished) => (distinguished + 1, 1),
+            Style::Call => (2, 1),
+            Style::Data => (1, 1),
+            Style::Pairs => (2, 2),
+        };
+        // Each line holds whole pieces; a comment ends the line it's on
+        // unless it trails code, in which case it joins that code's line.
+        let mut lines: Vec<Vec<Piece>> = Vec::new();
+        let mut broken = true;
+        for piece in pieces {
+            let limit = if lines.len() <= 1 && !broken || lines.is_empty() {
+                first_line
+            } else {
+                per_line
+            };
+            match piece {
+                Piece::Comment(comment) if comment.trailing && !lines.is_empty() => {
+                    lines.last_mut().unwrap().push(piece);
+                    broken = true;
+                }
+                Piece::Comment(_) => {
+                    lines.push(vec![piece]);
+                    broken = true;
+                }
+                _ => match lines.last_mut() {
+                    Some(line) if !broken && line.len() < limit => line.push(piece),
+                    _ => {
+                        lines.push(vec![piece]);
+                        broken = false;
+                    }
+                },
+            }
+        }
+        let rest = match style {
+            Style::Body(_) => column + 2,
+            Style::Call => match lines.first() {
+                Some(line) if line.len() > 1 => match line[0] {
+                    Piece::Expr(head) => inner + width(&self.render(head, inner)) + 1,
+                    _ => inner,
+                },
+                _ => inner,
+            },
+            Style::Data | Style::Pairs => inner,
+        };
+        let mut out = String::from(open);
+        let mut previous: Option<Span> = None;
+        for (i, line) in lines.iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+                let start = line[0].span();
+                if previous.is_some_and(|p| blank_line_between(self.source, p, start)) {
+                    out.push('\n');
+                }
+                out.push_str(&" ".repeat(rest));
+            }
+            let mut at = if i == 0 { inner } else { rest };
+            for (j, piece) in line.iter().enumerate() {
+                if j > 0 {
+                    out.push(' ');
+                    at += 1;
+                }
+                let text = self.render_piece(*piece, at);
+                at = end_column(&text, at);
+                out.push_str(&text);
+                previous = Some(piece.span());
+            }
+        }
+        let ends_in_line_comment = lines
+            .last()
+            .and_then(|line| line.last())
+            .is_some_and(|piece| matches!(piece, Piece::Comment(comment) if comment.line));
+        if ends_in_line_comment {
+            out.push('\n');
+            out.push_str(&" ".repeat(rest));
+        }
+        out.push_str(close);
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_formats(input: &str, expected: &str) {
+        let formatted = format_source(input).unwrap();
+        assert_eq!(formatted, expected, "formatting {input:?}");
+        assert_eq!(
+            format_source(&formatted).unwrap(),
+            formatted,
+            "formatting is idempotent"
+        );
+    }
+
+    #[test]
+    fn format_test() {
+        assert_formats("  (+   1\n 2)  'x `(a ,b ,@c)", "(+ 1 2)\n'x\n`(a ,b ,@c)\n");
+        assert_formats(
+            "(define (f x) (let ((y (* x x)) (z (+ x 1))) (list y z (vector-ref [1 2 3 4 5 6] 0) {:a 1 :b 2})))",
+            "(define (f x)\n  (let ((y (* x x)) (z (+ x 1)))\n    (list y z (vector-ref [1 2 3 4 5 6] 0) {:a 1 :b 2})))\n",
+        );
+        assert_formats(
+            "(if (some-long-condition-name argument-one) (then-branch-function value) (else-branch-function value))",
+            "(if (some-long-condition-name argument-one)\n    (then-branch-function value)\n    (else-branch-function value))\n",
+        );
+        assert_formats("(a . b) #\\space \"s\\n\" 1/2 :k", "(a . b)\n#\\space\n\"s\\n\"\n1/2\n:k\n");
+        assert_formats("", "");
+    }
+
+    #[test]
+    fn comment_test() {
+        assert_formats(
+            "; header\n\n\n(define x 1) ; one\n#| block |# (define (f)\n ; inside\n x #;(dropped y))\n",
+            "; header\n\n(define x 1) ; one\n#| block |#\n(define (f)\n  ; inside\n  x #;(dropped y))\n",
+        );
+        assert_formats("(list a ; why\n)", "(list a ; why\n      )\n");
+    }
+
+    #[test]
+    fn error_test() {
+        assert!(format_source("(a").is_err());
+        assert!(format_source("\"open").is_err());
+    }
+}
@@ -0,0 +1,34 @@
+// What the fuzz targets in fuzz/ check of any input, as a function they
+// and the property tests share.
+use alloc::string::ToString;
+
+use crate::{
+    format::format_source,
+    lexer::lex,
+    parser::{read, read_all, read_all_recover},
+    reader::Reader,
+};
+
+// Panics unless every entry point that reads source returns, with an
+// error if it can't read input, rather than panicking or overflowing the
+// stack, and unless each form read from input prints as text that reads
+// back as the same form. Input that isn't UTF-8 is taken as far as it is.
+pub fn fuzz_check(input: &[u8]) {
+    let input = match core::str::from_utf8(input) {
+        Ok(input) => input,
+        Err(e) => core::str::from_utf8(&input[..e.valid_up_to()]).unwrap(),
+    };
+    let _ = lex(input);
+    let _ = read(input);
+    let _ = read_all_recover(input);
+    let _ = format_source(input);
+    let mut reader = Reader::new();
+    for chunk in input.split_inclusive('\n') {
+        let _ = reader.feed(chunk);
+    }
+    let _ = reader.finish();
+    for form in read_all(input).unwrap_or_default() {
+        let printed = form.to_string();
+        assert_eq!(read(&printed).as_ref(), Ok(&form), "{printed}");
+    }
+}
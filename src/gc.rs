@@ -0,0 +1,515 @@
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use core::cell::RefCell;
+
+use crate::{
+    eval::{Env, WeakEnv},
+    local::local,
+    symbol::SymbolId,
+    sync::Shared,
+    value::{Atom, Closure, ErrorObject, Value, WeakAtom},
+    vm,
+};
+
+// Values are reference counted, which frees everything except cycles, and
+// cycles can only go through something mutable: an environment frame, as
+// when a closure defined in a scope is stored in that scope, or an atom.
+// The heap keeps a weak reference to every frame and atom so a collection
+// can find the cycles among them.
+//
+// A collection works out, for everything reachable from the heap, how many
+// of its references come from other things the heap reaches. Anything with
+// more references than that is held from outside, by a Rust value or a
+// running call, and is live; so is everything it reaches. Frames and atoms
+// that are left are only kept alive by each other, and are emptied, which
+// breaks their cycles and lets reference counting free them.
+#[derive(Default)]
+struct Heap {
+    envs: Vec<WeakEnv>,
+    atoms: Vec<WeakAtom>,
+    // Dead weak references are dropped when there are twice as many as
+    // there were live ones last time, and so is a collection run
+    // automatically when the live ones have doubled since the last.
+    next_prune: usize,
+    next_collection: usize,
+    collections: usize,
+    freed: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct HeapStats {
+    pub envs: usize,
+    pub atoms: usize,
+    pub collections: usize,
+    pub freed: usize,
+}
+
+const MIN_THRESHOLD: usize = 1024;
+
+local! {
+    static HEAP: RefCell<Heap> = RefCell::default();
+}
+
+// With sync, any thread can use a frame or atom, including one that
+// another thread's heap tracks, and a collection only works if they hold
+// still while it counts: a value moved out of a frame it hasn't looked at
+// yet into one it has, from a frame that is then dropped, would look
+// unreachable. Everything that reads or changes a frame or atom holds
+// WORLD for reading, and a collection holds it for writing, so it waits
+// for those under way and the rest wait for it.
+#[cfg(feature = "sync")]
+static WORLD: std::sync::RwLock<()> = std::sync::RwLock::new(());
+
+// Runs f, which reads or changes frames or atoms, while no collection is
+// running on any thread. f mustn't make a frame or atom, since that can
+// start a collection.
+pub(crate) fn using<T>(f: impl FnOnce() -> T) -> T {
+    #[cfg(feature = "sync")]
+    let _world = WORLD
+        .read()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    f()
+}
+
+pub(crate) fn track_env(env: &Env) {
+    HEAP.with(|heap| heap.borrow_mut().envs.push(env.downgrade()));
+    maybe_collect();
+}
+
+pub(crate) fn track_atom(atom: &Atom) {
+    HEAP.with(|heap| heap.borrow_mut().atoms.push(atom.downgrade()));
+    maybe_collect();
+}
+
+fn maybe_collect() {
+    let collect = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        if heap.envs.len() + heap.atoms.len() < heap.next_prune.max(MIN_THRESHOLD) {
+            return false;
+        }
+        heap.prune();
+        let live = heap.envs.len() + heap.atoms.len();
+        heap.next_prune = 2 * live;
+        live >= heap.next_collection.max(MIN_THRESHOLD)
+    });
+    if collect {
+        collect_garbage();
+    }
+}
+
+impl Heap {
+    fn prune(&mut self) {
+        self.envs.retain(WeakEnv::is_live);
+        self.atoms.retain(WeakAtom::is_live);
+    }
+}
+
+// Empties the frames and atoms that nothing outside a cycle refers to, and
+// returns how many there were.
+pub fn collect_garbage() -> usize {
+    #[cfg(feature = "sync")]
+    let _world = WORLD
+        .write()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    let (envs, atoms) = live();
+    let mut graph = Graph::default();
+    for env in envs {
+        graph.add(Node::Env(env));
+    }
+    for atom in atoms {
+        graph.add(Node::Atom(atom));
+    }
+    let freed = graph.sweep();
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.prune();
+        heap.collections += 1;
+        heap.freed += freed;
+        heap.next_collection = 2 * (heap.envs.len() + heap.atoms.len());
+    });
+    freed
+}
+
+fn live() -> (Vec<Env>, Vec<Atom>) {
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.prune();
+        let envs = heap.envs.iter().filter_map(WeakEnv::upgrade).collect();
+        let atoms = heap.atoms.iter().filter_map(WeakAtom::upgrade).collect();
+        (envs, atoms)
+    })
+}
+
+// Roughly what a frame costs before its variables.
+const FRAME_BYTES: usize = 64;
+
+// An estimate of the memory the live frames and atoms hold: themselves and
+// the data in them. A procedure counts only for itself, since the frame it
+// captured is counted on its own.
+pub fn heap_bytes() -> usize {
+    let (envs, atoms) = live();
+    let mut bytes = 0;
+    for env in envs {
+        bytes += FRAME_BYTES;
+        env.visit(|value| bytes += size_of::<(SymbolId, Value)>() + value_bytes(value));
+    }
+    for atom in atoms {
+        bytes += size_of::<Value>();
+        atom.visit(|value| bytes += value_bytes(value));
+    }
+    bytes
+}
+
+// What value owns outside of itself.
+fn value_bytes(value: &Value) -> usize {
+    let values = |items: &[Value]| -> usize {
+        items
+            .iter()
+            .map(|item| size_of::<Value>() + value_bytes(item))
+            .sum()
+    };
+    match value {
+        Value::String(s) => s.capacity(),
+        Value::List(items) | Value::Vector(items) => values(items),
+        Value::Pair(car, cdr) => 2 * size_of::<Value>() + value_bytes(car) + value_bytes(cdr),
+        Value::Map(entries) => entries
+            .iter()
+            .map(|(key, value)| 2 * size_of::<Value>() + value_bytes(key) + value_bytes(value))
+            .sum(),
+        Value::Error(error) => error.message.capacity() + values(&error.irritants),
+        _ => 0,
+    }
+}
+
+pub fn heap_stats() -> HeapStats {
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.prune();
+        HeapStats {
+            envs: heap.envs.len(),
+            atoms: heap.atoms.len(),
+            collections: heap.collections,
+            freed: heap.freed,
+        }
+    })
+}
+
+// The frames and atoms made by code that ran on another thread for this
+// one, as pmap's calls do. No collection runs there automatically, since
+// it would stop every other call while it ran; they're handed back when
+// the code is done, for the caller's collections to look at.
+#[cfg(feature = "sync")]
+pub(crate) struct Tracked(Heap);
+
+#[cfg(feature = "sync")]
+pub(crate) fn tracking<T>(f: impl FnOnce() -> T) -> (T, Tracked) {
+    let worker = Heap {
+        next_collection: usize::MAX,
+        ..Heap::default()
+    };
+    let saved = HEAP.with(|heap| heap.replace(worker));
+    let mut worker = None;
+    let result = crate::local::finally(f, || {
+        worker = Some(HEAP.with(|heap| heap.replace(saved)));
+    });
+    (result, Tracked(worker.unwrap()))
+}
+
+#[cfg(feature = "sync")]
+pub(crate) fn adopt(tracked: Tracked) {
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.envs.extend(tracked.0.envs);
+        heap.atoms.extend(tracked.0.atoms);
+    });
+    maybe_collect();
+}
+
+// The reference-counted things a value can lead to. Closures and error
+// objects can't be changed, so they're never part of a cycle by
+// themselves, but they can hold the frames and atoms that are.
+enum Node {
+    Env(Env),
+    Atom(Atom),
+    Closure(Shared<Closure>),
+    Compiled(Shared<vm::Closure>),
+    Error(Shared<ErrorObject>),
+}
+
+impl Node {
+    fn addr(&self) -> usize {
+        match self {
+            Node::Env(env) => env.addr(),
+            Node::Atom(atom) => atom.addr(),
+            Node::Closure(closure) => Shared::as_ptr(closure) as usize,
+            Node::Compiled(closure) => Shared::as_ptr(closure) as usize,
+            Node::Error(error) => Shared::as_ptr(error) as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Env(env) => env.strong_count(),
+            Node::Atom(atom) => atom.strong_count(),
+            Node::Closure(closure) => Shared::strong_count(closure),
+            Node::Compiled(closure) => Shared::strong_count(closure),
+            Node::Error(error) => Shared::strong_count(error),
+        }
+    }
+
+    // None when a frame or atom is borrowed, as it may be when a collection
+    // runs in the middle of evaluating. Such a node is treated as live.
+    fn children(&self) -> Option<Vec<Node>> {
+        let mut children = Vec::new();
+        match self {
+            Node::Env(env) => {
+                let parent = env.visit(|value| value_children(value, &mut children))?;
+                children.extend(parent.map(Node::Env));
+            }
+            Node::Atom(atom) => atom.visit(|value| value_children(value, &mut children))?,
+            Node::Closure(closure) => children.push(Node::Env(closure.env.clone())),
+            Node::Compiled(closure) => {
+                for value in &closure.captured {
+                    value_children(value, &mut children);
+                }
+                children.push(Node::Env(closure.globals.clone()));
+            }
+            Node::Error(error) => {
+                for value in &error.irritants {
+                    value_children(value, &mut children);
+                }
+            }
+        }
+        Some(children)
+    }
+}
+
+// Natives are opaque, so whatever their Rust closures hold counts as held
+// from outside.
+fn value_children(value: &Value, children: &mut Vec<Node>) {
+    match value {
+        Value::List(items) | Value::Vector(items) => {
+            for item in items {
+                value_children(item, children);
+            }
+        }
+        Value::Pair(car, cdr) => {
+            value_children(car, children);
+            value_children(cdr, children);
+        }
+        Value::Map(entries) => {
+            for (key, value) in entries {
+                value_children(key, children);
+                value_children(value, children);
+            }
+        }
+        Value::Closure(closure) | Value::Macro(closure) => {
+            children.push(Node::Closure(closure.clone()))
+        }
+        Value::Compiled(closure) => children.push(Node::Compiled(closure.clone())),
+        Value::Atom(atom) => children.push(Node::Atom(atom.clone())),
+        Value::Error(error) => children.push(Node::Error(error.clone())),
+        _ => {}
+    }
+}
+
+struct Entry {
+    node: Node,
+    children: Vec<usize>,
+    // References from other nodes in the graph.
+    internal: usize,
+    // Borrowed while the graph was built, so its children aren't known.
+    pinned: bool,
+    live: bool,
+}
+
+#[derive(Default)]
+struct Graph {
+    entries: BTreeMap<usize, Entry>,
+}
+
+impl Graph {
+    // Adds node and everything it reaches. Each entry holds one reference
+    // to its node, which is discounted when comparing counts.
+    fn add(&mut self, node: Node) {
+        let mut pending = match self.insert(node) {
+            Some(addr) => vec![addr],
+            None => return,
+        };
+        while let Some(addr) = pending.pop() {
+            let children = self.entries[&addr].node.children();
+            let entry = self.entries.get_mut(&addr).unwrap();
+            entry.pinned = children.is_none();
+            let children = children.unwrap_or_default();
+            entry.children = children.iter().map(Node::addr).collect();
+            pending.extend(children.into_iter().filter_map(|child| self.insert(child)));
+        }
+    }
+
+    // None if the node is already in the graph.
+    fn insert(&mut self, node: Node) -> Option<usize> {
+        let addr = node.addr();
+        if self.entries.contains_key(&addr) {
+            return None;
+        }
+        self.entries.insert(
+            addr,
+            Entry {
+                node,
+                children: Vec::new(),
+                internal: 0,
+                pinned: false,
+                live: false,
+            },
+        );
+        Some(addr)
+    }
+
+    fn count(&mut self) {
+        let mut internal: BTreeMap<usize, usize> = BTreeMap::new();
+        for entry in self.entries.values() {
+            for child in &entry.children {
+                *internal.entry(*child).or_default() += 1;
+            }
+        }
+        for (addr, entry) in &mut self.entries {
+            entry.internal = internal.get(addr).copied().unwrap_or(0);
+        }
+    }
+
+    fn sweep(mut self) -> usize {
+        self.count();
+        let mut pending: Vec<usize> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.pinned || entry.node.strong_count() - 1 > entry.internal)
+            .map(|(addr, _)| *addr)
+            .collect();
+        while let Some(addr) = pending.pop() {
+            let entry = self.entries.get_mut(&addr).unwrap();
+            if entry.live {
+                continue;
+            }
+            entry.live = true;
+            pending.extend(&entry.children);
+        }
+        let mut freed = 0;
+        for entry in self.entries.into_values() {
+            if entry.live {
+                continue;
+            }
+            match entry.node {
+                Node::Env(env) => env.clear(),
+                Node::Atom(atom) => atom.clear(),
+                _ => continue,
+            }
+            freed += 1;
+        }
+        freed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::{Backend, Interpreter};
+
+    #[test]
+    fn collect_test() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .eval_str(
+                "(define (make)
+                   (define (self) self)
+                   (let ((cell (atom 0)))
+                     (reset! cell (lambda () cell))
+                     self))
+                 (define kept (make))",
+            )
+            .unwrap();
+        let weak = match interpreter.eval_str("(make)").unwrap() {
+            Value::Closure(closure) => Shared::downgrade(&closure),
+            other => panic!("expected a closure, found {other}"),
+        };
+        assert!(weak.upgrade().is_some(), "the closure's frame holds it");
+        assert!(collect_garbage() >= 2);
+        assert!(weak.upgrade().is_none());
+        assert!(interpreter.eval_str("kept").unwrap().is_procedure());
+        assert!(interpreter.eval_str("((kept))").unwrap().is_procedure());
+        let stats = heap_stats();
+        assert!(stats.collections >= 1 && stats.freed >= 2);
+        assert!(stats.envs >= 1);
+    }
+
+    #[test]
+    fn compiled_cycle_test() {
+        // An internal define in compiled code is a box the closure captures.
+        let mut interpreter = Interpreter::with_backend(Backend::Vm);
+        let weak = match interpreter
+            .eval_str("(define (make) (define (again) again) again) (make)")
+            .unwrap()
+        {
+            Value::Compiled(closure) => Shared::downgrade(&closure),
+            other => panic!("expected a compiled closure, found {other}"),
+        };
+        assert!(weak.upgrade().is_some());
+        assert_eq!(interpreter.eval_str("(gc)"), Ok(Value::Integer(1)));
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn automatic_collection_test() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .eval_str(
+                "(define (make) (define (self) self) self)
+                 (define (spin n) (if (= n 0) 'done (begin (make) (spin (- n 1)))))
+                 (spin 20000)",
+            )
+            .unwrap();
+        let stats = heap_stats();
+        assert!(stats.collections > 0);
+        assert!(stats.envs < 10000, "{stats:?}");
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn thread_test() {
+        // The frames and atoms are made here, so this thread's heap tracks
+        // them, and then moved between each other on another thread while
+        // this one collects. Each closure is taken out of an atom that is
+        // dropped right after, and put into one that stays.
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .eval_str(
+                "(define (keep) (let ((secret 'kept)) (lambda () secret)))
+                 (define (chain n next)
+                   (if (= n 0) next (chain (- n 1) (atom (cons (keep) next)))))
+                 (define next (chain 5000 ()))
+                 (define home (atom #f))
+                 (define (drain)
+                   (if (null? next)
+                       'done
+                       (let ((from next))
+                         (set! next (cdr (deref from)))
+                         (reset! home (car (deref from)))
+                         (reset! from #f)
+                         (if (eq? ((deref home)) 'kept) (drain) 'lost))))",
+            )
+            .unwrap();
+        let draining = std::thread::spawn(move || interpreter.eval_str("(drain)"));
+        let mut other = Interpreter::new();
+        other
+            .eval_str(
+                "(define (make) (define (self) self) self)
+                 (define kept (map atom (range 20000)))",
+            )
+            .unwrap();
+        let before = heap_stats().collections;
+        while !draining.is_finished() {
+            other.eval_str("(make)").unwrap();
+            collect_garbage();
+        }
+        assert_eq!(draining.join().unwrap(), Ok(Value::Symbol("done".into())));
+        assert!(heap_stats().collections > before);
+    }
+}
@@ -0,0 +1,651 @@
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::cell::{Cell, RefCell};
+
+use crate::{
+    eval::{Env, EvalError},
+    io,
+    lexer::Span,
+    local::{finally, local},
+    parser::{Expr, SpannedExpr},
+    symbol::SymbolId,
+    sync::{Lock, Shared, Weak},
+    value::Value,
+};
+
+// What the tree-walker reports to a hook as it runs. Spans point into the
+// source being run and are None for code from elsewhere, such as macro
+// expansions and functions defined by an earlier eval_str. Every EnterCall
+// is followed by one Return, for the innermost call yet to return; a chain
+// of tail calls returns all at once, when the last of them does.
+#[derive(Clone, Copy, Debug)]
+pub enum Event<'a> {
+    // env is the environment the call is made in, or None for a call
+    // from Rust, such as a builtin's callback.
+    EnterCall {
+        name: Option<SymbolId>,
+        callee: &'a Value,
+        args: &'a [Value],
+        span: Option<Span>,
+        env: Option<&'a Env>,
+    },
+    Return {
+        name: Option<SymbolId>,
+        result: Result<&'a Value, &'a EvalError>,
+    },
+    Branch {
+        taken: bool,
+        span: Option<Span>,
+    },
+    Define {
+        name: SymbolId,
+        value: &'a Value,
+        span: Option<Span>,
+    },
+}
+
+#[cfg(not(feature = "sync"))]
+pub type Hook = dyn FnMut(Event<'_>);
+#[cfg(feature = "sync")]
+pub type Hook = dyn FnMut(Event<'_>) + Send + Sync;
+
+#[derive(Default)]
+struct State {
+    hook: Option<Shared<Lock<Hook>>>,
+    source: Shared<[SpannedExpr]>,
+    // The file the source was read from, if it was.
+    file: Option<Shared<str>>,
+    // The spans of the code being run, when there's a hook to report them
+    // to and the code was read from source.
+    code: Option<Shared<Spans>>,
+    // Where the form the hook is being told about was read.
+    origin: Option<Origin>,
+    // The calls that have been entered and not yet returned, and how many
+    // of them are to traced procedures.
+    calls: Vec<Call>,
+    depth: usize,
+    traced: Shared<Lock<Traced>>,
+}
+
+// The procedures trace has been called on, which an interpreter keeps
+// with its hook.
+pub(crate) type Traced = Vec<Value>;
+
+struct Call {
+    name: Option<SymbolId>,
+    traced: bool,
+}
+
+// Where the lists in a piece of code were read, keyed by the address of
+// their items, which stays put for as long as the code does. A top-level
+// form's table lives while the form runs and a closure's as long as the
+// closure, so no other list can have an address in one.
+#[derive(Clone)]
+pub(crate) struct Spans {
+    source: Weak<[SpannedExpr]>,
+    file: Option<Shared<str>>,
+    lists: BTreeMap<usize, Span>,
+}
+
+// Where a form was read: its span in the source it was read from, and the
+// file that source was, if any.
+#[derive(Clone)]
+pub(crate) struct Origin {
+    pub(crate) span: Span,
+    pub(crate) source: Weak<[SpannedExpr]>,
+    #[cfg(feature = "std")]
+    pub(crate) file: Option<Shared<str>>,
+}
+
+fn key(expr: &Expr) -> Option<usize> {
+    match expr {
+        Expr::List(items) if !items.is_empty() => Some(items.as_ptr() as usize),
+        _ => None,
+    }
+}
+
+local! {
+    static STATE: RefCell<State> = RefCell::default();
+}
+
+// Whether there is a hook or a traced procedure, so the evaluator can skip
+// the rest when there are neither.
+local! {
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+}
+
+pub(crate) fn active() -> bool {
+    ACTIVE.with(Cell::get)
+}
+
+fn update_active(state: &State) {
+    let active = state.hook.is_some() || !state.traced.borrow().is_empty();
+    ACTIVE.with(|cell| cell.set(active));
+}
+
+// Runs f with hook receiving events and the procedures in traced traced,
+// then puts back whatever was installed before.
+pub(crate) fn with_hook<T>(
+    hook: Option<Shared<Lock<Hook>>>,
+    traced: Shared<Lock<Traced>>,
+    f: impl FnOnce() -> T,
+) -> T {
+    let saved = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let saved = (
+            core::mem::replace(&mut state.hook, hook),
+            core::mem::replace(&mut state.traced, traced),
+        );
+        update_active(&state);
+        saved
+    });
+    finally(f, || {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            (state.hook, state.traced) = saved;
+            update_active(&state);
+        })
+    })
+}
+
+// Runs f with hook told what happens in place of the interpreter's, as
+// (profile thunk) has its profiler told.
+#[cfg(feature = "std")]
+pub(crate) fn instead<T>(hook: Shared<Lock<Hook>>, f: impl FnOnce() -> T) -> T {
+    let traced = STATE.with(|state| state.borrow().traced.clone());
+    with_hook(Some(hook), traced, f)
+}
+
+// Runs f with source, read from file if it was, as the forms being run,
+// whose spans are the ones reported.
+pub(crate) fn with_source<T>(
+    source: Shared<[SpannedExpr]>,
+    file: Option<Shared<str>>,
+    f: impl FnOnce() -> T,
+) -> T {
+    let saved = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let code = state.code.take();
+        let file = core::mem::replace(&mut state.file, file);
+        (core::mem::replace(&mut state.source, source), file, code)
+    });
+    finally(f, || {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            (state.source, state.file, state.code) = saved;
+        })
+    })
+}
+
+// Whether there's a hook to be told spans, so that they're worth keeping.
+pub(crate) fn spanning() -> bool {
+    active() && STATE.with(|state| state.borrow().hook.is_some())
+}
+
+// The spans of expr, which form, one of source's, was turned into.
+pub(crate) fn spans(
+    source: &Shared<[SpannedExpr]>,
+    form: &SpannedExpr,
+    expr: &Expr,
+) -> Shared<Spans> {
+    let mut lists = BTreeMap::new();
+    record(form, expr, &mut lists);
+    Shared::new(Spans {
+        source: Shared::downgrade(source),
+        file: STATE.with(|state| state.borrow().file.clone()),
+        lists,
+    })
+}
+
+fn record(spanned: &SpannedExpr, expr: &Expr, lists: &mut BTreeMap<usize, Span>) {
+    match (spanned, expr) {
+        (SpannedExpr::List(list), Expr::List(items)) => {
+            lists.extend(key(expr).map(|key| (key, list.span)));
+            for (spanned, expr) in list.value.iter().zip(items) {
+                record(spanned, expr, lists);
+            }
+        }
+        (SpannedExpr::Vector(list), Expr::Vector(items)) => {
+            for (spanned, expr) in list.value.iter().zip(items) {
+                record(spanned, expr, lists);
+            }
+        }
+        // The items of a dotted list are the cars of a chain of pairs.
+        (SpannedExpr::DottedList(list), _) => {
+            let (items, tail) = &list.value;
+            let mut rest = expr;
+            for item in items {
+                let Expr::Pair(car, cdr) = rest else {
+                    return;
+                };
+                record(item, car, lists);
+                rest = cdr;
+            }
+            record(tail, rest, lists);
+        }
+        (SpannedExpr::Map(map), Expr::Map(entries)) => {
+            for ((key, value), (key_expr, value_expr)) in map.value.iter().zip(entries) {
+                record(key, key_expr, lists);
+                record(value, value_expr, lists);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Runs f on code with spans, then puts back the spans of the code that was
+// running before.
+pub(crate) fn with_code<T>(spans: Option<Shared<Spans>>, f: impl FnOnce() -> T) -> T {
+    let saved = STATE.with(|state| core::mem::replace(&mut state.borrow_mut().code, spans));
+    finally(f, || STATE.with(|state| state.borrow_mut().code = saved))
+}
+
+// Goes on to run code with spans, such as a closure's body, until the
+// with_code around it puts back what was there before.
+pub(crate) fn enter_code(spans: &Option<Shared<Spans>>) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.hook.is_some() {
+            state.code = spans.clone();
+        }
+    })
+}
+
+// The spans for a copy of code being run, such as the body a closure
+// takes from its lambda form, from the pairs of lists and their copies.
+pub(crate) fn copy_spans<'a>(
+    copies: impl IntoIterator<Item = (&'a Expr, &'a Expr)>,
+) -> Option<Shared<Spans>> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let code = state.code.as_ref()?;
+        let mut lists = BTreeMap::new();
+        for (from, to) in copies {
+            copy(code, from, to, &mut lists);
+        }
+        Some(Shared::new(Spans {
+            source: code.source.clone(),
+            file: code.file.clone(),
+            lists,
+        }))
+    })
+}
+
+fn copy(code: &Spans, from: &Expr, to: &Expr, lists: &mut BTreeMap<usize, Span>) {
+    if let (Some(from_key), Some(to_key)) = (key(from), key(to)) {
+        lists.extend(code.lists.get(&from_key).map(|span| (to_key, *span)));
+    }
+    match (from, to) {
+        (Expr::List(from), Expr::List(to)) | (Expr::Vector(from), Expr::Vector(to)) => {
+            for (from, to) in from.iter().zip(to) {
+                copy(code, from, to, lists);
+            }
+        }
+        (Expr::Pair(from_car, from_cdr), Expr::Pair(to_car, to_cdr)) => {
+            copy(code, from_car, to_car, lists);
+            copy(code, from_cdr, to_cdr, lists);
+        }
+        (Expr::Map(from), Expr::Map(to)) => {
+            for ((from_key, from_value), (to_key, to_value)) in from.iter().zip(to) {
+                copy(code, from_key, to_key, lists);
+                copy(code, from_value, to_value, lists);
+            }
+        }
+        _ => {}
+    }
+}
+
+// For a list rebuilt around new items, as macro expansion rebuilds those
+// it leaves alone: to has from's span.
+pub(crate) fn copy_span(from: &Expr, to: &Expr) {
+    let (Some(from), Some(to)) = (key(from), key(to)) else {
+        return;
+    };
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let Some(code) = &mut state.code else {
+            return;
+        };
+        if let Some(span) = code.lists.get(&from).copied() {
+            Shared::make_mut(code).lists.insert(to, span);
+        }
+    })
+}
+
+// (trace f) prints each call to f and what it returns to the standard
+// error of the interpreter's I/O backend, until (untrace f).
+pub(crate) fn trace(procedure: Value) {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut traced = state.traced.borrow_mut();
+        if !traced.contains(&procedure) {
+            traced.push(procedure);
+        }
+        drop(traced);
+        update_active(&state);
+    });
+}
+
+pub(crate) fn untrace(procedure: &Value) {
+    STATE.with(|state| {
+        let state = state.borrow();
+        state
+            .traced
+            .borrow_mut()
+            .retain(|traced| traced != procedure);
+        update_active(&state);
+    });
+}
+
+// A hook that calls back into the interpreter isn't told about what
+// happens there, since it's still busy with the event that called it.
+fn emit(event: Event, origin: Option<Origin>) {
+    let hook = STATE.with(|state| state.borrow().hook.clone());
+    if let Some(hook) = hook {
+        if let Ok(mut hook) = hook.try_borrow_mut() {
+            let saved =
+                STATE.with(|state| core::mem::replace(&mut state.borrow_mut().origin, origin));
+            finally(
+                || hook(event),
+                || STATE.with(|state| state.borrow_mut().origin = saved),
+            );
+        }
+    }
+}
+
+// The source the spans reported now point into, so a hook can tell the
+// spans of a file it loads from those of the one it runs.
+#[cfg(feature = "std")]
+pub(crate) fn source() -> Weak<[SpannedExpr]> {
+    STATE.with(|state| Shared::downgrade(&state.borrow().source))
+}
+
+// Where the form the hook is being told about was read, even if it isn't
+// the source being run, for a hook that keeps track of more than one.
+// None for a return, or a form that wasn't read from source.
+#[cfg(feature = "std")]
+pub(crate) fn origin() -> Option<Origin> {
+    STATE.with(|state| state.borrow().origin.clone())
+}
+
+fn origin_of(form: &Expr) -> Option<Origin> {
+    let key = key(form)?;
+    STATE.with(|state| {
+        let state = state.borrow();
+        let code = state.code.as_ref()?;
+        Some(Origin {
+            span: *code.lists.get(&key)?,
+            source: code.source.clone(),
+            #[cfg(feature = "std")]
+            file: code.file.clone(),
+        })
+    })
+}
+
+// The span reported for a form read at origin, which is None unless that's
+// in the source being run: a closure defined by an earlier eval_str was
+// read from another source.
+fn span_of(origin: &Option<Origin>) -> Option<Span> {
+    let origin = origin.as_ref()?;
+    STATE.with(|state| {
+        Weak::ptr_eq(&origin.source, &Shared::downgrade(&state.borrow().source))
+            .then_some(origin.span)
+    })
+}
+
+pub(crate) fn enter_call(form: Option<&Expr>, callee: &Value, args: &[Value], env: Option<&Env>) {
+    let name = match form {
+        Some(Expr::List(items)) => match items.first() {
+            Some(Expr::Symbol(name)) => Some(*name),
+            _ => None,
+        },
+        _ => None,
+    };
+    let (depth, traced) = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let traced = state.traced.borrow().contains(callee);
+        let depth = state.depth;
+        state.depth += traced as usize;
+        state.calls.push(Call { name, traced });
+        (depth, traced)
+    });
+    if traced {
+        let mut line = format!("{}({}", "| ".repeat(depth), display_name(name, callee));
+        for arg in args {
+            line.push_str(&format!(" {arg}"));
+        }
+        line.push(')');
+        trace_line(&line);
+    }
+    let origin = form.and_then(origin_of);
+    let span = span_of(&origin);
+    emit(
+        Event::EnterCall {
+            name,
+            callee,
+            args,
+            span,
+            env,
+        },
+        origin,
+    );
+}
+
+// A trace that can't be written is dropped rather than failing the call
+// being traced.
+fn trace_line(line: &str) {
+    let _ = io::current_error().write_str(&format!("{line}\n"));
+}
+
+fn display_name(name: Option<SymbolId>, callee: &Value) -> String {
+    match name {
+        Some(name) => name.to_string(),
+        None => callee.to_string(),
+    }
+}
+
+pub(crate) fn return_call(result: Result<&Value, &EvalError>) {
+    let Some(call) = STATE.with(|state| state.borrow_mut().calls.pop()) else {
+        return;
+    };
+    if call.traced {
+        let depth = STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.depth -= 1;
+            state.depth
+        });
+        match result {
+            Ok(value) => trace_line(&format!("{}=> {value}", "| ".repeat(depth))),
+            Err(error) => trace_line(&format!("{}!! {error}", "| ".repeat(depth))),
+        }
+    }
+    let event = Event::Return {
+        name: call.name,
+        result,
+    };
+    emit(event, None);
+}
+
+pub(crate) fn branch(form: &Expr, taken: bool) {
+    let origin = origin_of(form);
+    let span = span_of(&origin);
+    emit(Event::Branch { taken, span }, origin);
+}
+
+pub(crate) fn define(form: &Expr, name: SymbolId, value: &Value) {
+    let origin = origin_of(form);
+    let span = span_of(&origin);
+    emit(Event::Define { name, value, span }, origin);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{interpreter::Interpreter, io::MemoryIo};
+
+    #[test]
+    fn hook_test() {
+        let source = "(define (double x) (* x 2))\n(if (> (double 2) 3) 'big 'small)";
+        let span = |text: &str| {
+            let start = source.find(text).unwrap();
+            Some(Span::new(start, start + text.len()))
+        };
+        let events = Shared::new(Lock::new(Vec::new()));
+        let mut interpreter = Interpreter::new();
+        let log = events.clone();
+        interpreter.set_hook(move |event| {
+            let line = match event {
+                Event::EnterCall {
+                    name, args, span, ..
+                } => format!("enter {name:?} {args:?} {span:?}"),
+                Event::Return { name, result } => format!("return {name:?} {result:?}"),
+                Event::Branch { taken, span } => format!("branch {taken} {span:?}"),
+                Event::Define { name, value, span } => format!("define {name} {value} {span:?}"),
+            };
+            log.borrow_mut().push(line);
+        });
+        assert_eq!(interpreter.eval_str(source).unwrap().to_string(), "big");
+        let int = |i| Value::Integer(i);
+        assert_eq!(
+            *events.borrow(),
+            [
+                format!(
+                    "define double #<procedure> {:?}",
+                    span("(define (double x) (* x 2))")
+                ),
+                format!(
+                    "enter Some(\"double\") {:?} {:?}",
+                    [int(2)],
+                    span("(double 2)")
+                ),
+                format!(
+                    "enter Some(\"*\") {:?} {:?}",
+                    [int(2), int(2)],
+                    span("(* x 2)")
+                ),
+                format!("return Some(\"*\") Ok({:?})", int(4)),
+                format!("return Some(\"double\") Ok({:?})", int(4)),
+                format!(
+                    "enter Some(\">\") {:?} {:?}",
+                    [int(4), int(3)],
+                    span("(> (double 2) 3)")
+                ),
+                format!("return Some(\">\") Ok({:?})", Value::Bool(true)),
+                format!(
+                    "branch true {:?}",
+                    span("(if (> (double 2) 3) 'big 'small)")
+                ),
+            ]
+        );
+
+        events.borrow_mut().clear();
+        interpreter.clear_hook();
+        interpreter.eval_str("(double 1)").unwrap();
+        assert!(events.borrow().is_empty());
+    }
+
+    #[test]
+    fn duplicate_test() {
+        // Forms written the same way in different places have their own
+        // spans, in closures' bodies too.
+        let source = "(define (f x) x)\n(f 1)\n(f 1)\n(define (g) (f 1) (f 1))\n(g)";
+        let spans = Shared::new(Lock::new(Vec::new()));
+        let mut interpreter = Interpreter::new();
+        let log = spans.clone();
+        interpreter.set_hook(move |event| {
+            if let Event::EnterCall { span, .. } = event {
+                log.borrow_mut().push(span);
+            }
+        });
+        interpreter.eval_str(source).unwrap();
+        let at = |start| Some(Span::new(start, start + 5));
+        assert_eq!(
+            *spans.borrow(),
+            [at(17), at(23), Some(Span::new(54, 57)), at(41), at(47)]
+        );
+
+        // g was read from another source.
+        spans.borrow_mut().clear();
+        interpreter.eval_str("(g)").unwrap();
+        assert_eq!(*spans.borrow(), [Some(Span::new(0, 3)), None, None]);
+    }
+
+    #[test]
+    fn tail_call_test() {
+        // Each call in a chain of tail calls returns the final value.
+        let returns = Shared::new(Lock::new(0));
+        let mut interpreter = Interpreter::new();
+        let count = returns.clone();
+        interpreter.set_hook(move |event| {
+            if let Event::Return { name, result } = event {
+                if name == Some("down".into()) {
+                    assert_eq!(result, Ok(&Value::Integer(0)));
+                    *count.borrow_mut() += 1;
+                }
+            }
+        });
+        interpreter
+            .eval_str("(define (down n) (if (= n 0) n (down (- n 1)))) (down 3)")
+            .unwrap();
+        assert_eq!(*returns.borrow(), 4);
+    }
+
+    #[test]
+    fn trace_test() {
+        let io = MemoryIo::new();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_io(io.clone());
+        interpreter
+            .eval_str("(define (f x) (+ x 1)) (trace f) (trace car)")
+            .unwrap();
+        assert_eq!(
+            interpreter.eval_str("(f (car '(1)))"),
+            Ok(Value::Integer(2))
+        );
+        STATE.with(|state| assert_eq!(state.borrow().depth, 0));
+        assert_eq!(io.output(), "(car (1))\n=> 1\n(f 1)\n=> 2\n");
+        assert!(interpreter.eval_str("(trace 1)").is_err());
+
+        // What an interpreter traces is its own.
+        let other_io = MemoryIo::new();
+        let mut other = Interpreter::new();
+        other.set_io(other_io.clone());
+        other.eval_str("(car '(1))").unwrap();
+        assert_eq!(other_io.output(), "");
+        drop(interpreter);
+        let mut other = Interpreter::new();
+        other.set_io(other_io.clone());
+        other.eval_str("(car '(1))").unwrap();
+        assert_eq!(other_io.output(), "");
+
+        // A call that fails exits with its error.
+        let io = MemoryIo::new();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_io(io.clone());
+        interpreter
+            .eval_str("(define (g x) (car x)) (trace g)")
+            .unwrap();
+        assert!(interpreter.eval_str("(g 1)").is_err());
+        assert_eq!(io.output(), "(g 1)\n!! expected list, found 1\n");
+    }
+
+    #[test]
+    fn untrace_test() {
+        let io = MemoryIo::new();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_io(io.clone());
+        interpreter
+            .eval_str("(define (f x) x) (trace f) (trace car) (f 1) (untrace f) (f 2) (car '(3))")
+            .unwrap();
+        assert_eq!(io.output(), "(f 1)\n=> 1\n(car (3))\n=> 3\n");
+        interpreter
+            .eval_str("(untrace car) (untrace car) (car '(4))")
+            .unwrap();
+        assert_eq!(io.output(), "(f 1)\n=> 1\n(car (3))\n=> 3\n");
+        assert!(interpreter.eval_str("(untrace 1)").is_err());
+    }
+}
@@ -0,0 +1,1977 @@
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{error::Error, fmt, future::Future};
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+use crate::{
+    builtins, compile,
+    convert::{FromArgs, IntoValue},
+    eval::{apply, eval, Env, EvalError},
+    hooks::{self, Event, Hook, Spans, Traced},
+    io::{self, Io, IoBackend, SandboxPolicy},
+    lexer::Span,
+    limits::{self, Budget, CancellationToken, Limits},
+    macros::expand,
+    module::{self, Module, ModuleRegistry, ModuleResolver},
+    parser::{read_all_spanned, Expr, ReadError, SpannedExpr},
+    reader::{with_read_table, ReadTable},
+    symbol::SymbolId,
+    sync::{Lock, MaybeSend, MaybeSync, Ref, Shared},
+    value::{AsyncFn, NativeFn, Value},
+    vm::{self, Progress, Task},
+};
+#[cfg(feature = "std")]
+use crate::{
+    bytecode::{self, CompiledFile, Form},
+    symbol,
+};
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum InterpreterError {
+    Read(ReadError),
+    Eval(EvalError),
+    // Loading files needs std.
+    #[cfg(feature = "std")]
+    Io {
+        path: PathBuf,
+        message: String,
+    },
+    #[cfg(feature = "std")]
+    Cycle(PathBuf),
+    #[cfg(feature = "std")]
+    InFile {
+        path: PathBuf,
+        error: Box<InterpreterError>,
+    },
+}
+
+impl fmt::Display for InterpreterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterpreterError::Read(e) => write!(f, "read error: {e}"),
+            InterpreterError::Eval(e) => write!(f, "error: {e}"),
+            #[cfg(feature = "std")]
+            InterpreterError::Io { path, message } => {
+                write!(f, "cannot read `{}`: {message}", path.display())
+            }
+            #[cfg(feature = "std")]
+            InterpreterError::Cycle(path) => {
+                write!(f, "`{}` is already being loaded", path.display())
+            }
+            #[cfg(feature = "std")]
+            InterpreterError::InFile { path, error } => write!(f, "{}: {error}", path.display()),
+        }
+    }
+}
+
+impl Error for InterpreterError {}
+
+impl InterpreterError {
+    // Where a read error is in its source. For one in a file being run or
+    // loaded, that's the file the innermost InFile names.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            InterpreterError::Read(e) => Some(e.span()),
+            #[cfg(feature = "std")]
+            InterpreterError::InFile { error, .. } => error.span(),
+            _ => None,
+        }
+    }
+}
+
+impl From<ReadError> for InterpreterError {
+    fn from(e: ReadError) -> Self {
+        InterpreterError::Read(e)
+    }
+}
+
+impl From<EvalError> for InterpreterError {
+    fn from(e: EvalError) -> Self {
+        InterpreterError::Eval(e)
+    }
+}
+
+// How forms are run. The tree-walker evaluates the syntax directly and is
+// the reference for what every program does; the VM compiles each form to
+// bytecode first, and leaves to the tree-walker the forms it can't compile.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Backend {
+    #[default]
+    TreeWalker,
+    Vm,
+}
+
+// The language as a library: a global environment with the builtins that
+// host code can add its own functions to, run source in and read results
+// back out of.
+pub struct Interpreter {
+    env: Env,
+    #[cfg(feature = "std")]
+    loading: Shared<Lock<Loading>>,
+    backend: Backend,
+    modules: Shared<Lock<ModuleRegistry>>,
+    io: Shared<Lock<Io>>,
+    limits: Limits,
+    cancel: Option<CancellationToken>,
+    hook: Option<Shared<Lock<Hook>>>,
+    traced: Shared<Lock<Traced>>,
+    read_table: ReadTable,
+}
+
+// With the sync feature an interpreter can move to another thread, and
+// what it evaluates can be shared between threads.
+#[cfg(feature = "sync")]
+const _: () = {
+    const fn send<T: Send>() {}
+    const fn send_sync<T: Send + Sync>() {}
+    send::<Interpreter>();
+    send_sync::<Value>();
+    send_sync::<Env>();
+    #[cfg(feature = "std")]
+    send_sync::<CompiledFile>();
+};
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter::with_backend(Backend::default())
+    }
+
+    // An interpreter whose every eval_str and eval_file stops with
+    // EvalError::LimitExceeded once it goes past limits.
+    pub fn with_limits(limits: Limits) -> Self {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(limits);
+        interpreter
+    }
+
+    pub fn with_backend(backend: Backend) -> Self {
+        let interpreter = Interpreter {
+            env: builtins::global_env(),
+            #[cfg(feature = "std")]
+            loading: Shared::default(),
+            backend,
+            modules: Shared::default(),
+            io: Shared::new(Lock::new(Io::new(io::default_backend()))),
+            limits: Limits::default(),
+            cancel: None,
+            hook: None,
+            traced: Shared::default(),
+            read_table: ReadTable::new(),
+        };
+        #[cfg(feature = "std")]
+        interpreter.define_load();
+        interpreter
+    }
+
+    // load and include, which run a file, where there are files to run.
+    // They live in the environment they load into, so they only hold it,
+    // and the modules whose scopes hang off it, weakly; a native is opaque
+    // to collections, and a strong reference would keep both alive for as
+    // long as each other.
+    #[cfg(feature = "std")]
+    fn define_load(&self) {
+        let (env, loading, backend) = (self.env.downgrade(), self.loading.clone(), self.backend);
+        let modules = Shared::downgrade(&self.modules);
+        let load = NativeFn::new("load", move |args| match args {
+            [Value::String(path)] => {
+                let env = crate::gc::using(|| env.upgrade());
+                let (Some(env), Some(modules)) = (env, modules.upgrade()) else {
+                    return Err(EvalError::Load {
+                        path: path.clone(),
+                        reason: "its interpreter is gone".to_string(),
+                    });
+                };
+                load_file(&env, &loading, backend, &modules, Path::new(path))
+                    .map_err(|e| load_error(path, e))
+            }
+            [other] => Err(EvalError::TypeMismatch {
+                expected: "string",
+                found: other.clone(),
+            }),
+            _ => Err(EvalError::ArityMismatch {
+                expected: 1,
+                found: args.len(),
+            }),
+        });
+        // With only a global environment to define things in, including a
+        // file and loading it come to the same thing.
+        self.env.define("include", Value::Native(load.clone()));
+        self.env.define("load", Value::Native(load));
+
+        let (env, loading) = (self.env.downgrade(), self.loading.clone());
+        let compile_file = NativeFn::new("compile-file", move |args| match args {
+            [Value::String(source), Value::String(out)] => {
+                let Some(env) = crate::gc::using(|| env.upgrade()) else {
+                    return Err(EvalError::Load {
+                        path: source.clone(),
+                        reason: "its interpreter is gone".to_string(),
+                    });
+                };
+                compile_file(&env, &loading, Path::new(source), Path::new(out))
+                    .map(|()| Value::Nil)
+                    .map_err(|e| load_error(source, e))
+            }
+            [Value::String(_), other] | [other, _] => Err(EvalError::TypeMismatch {
+                expected: "string",
+                found: other.clone(),
+            }),
+            _ => Err(EvalError::ArityMismatch {
+                expected: 2,
+                found: args.len(),
+            }),
+        });
+        self.env.define("compile-file", Value::Native(compile_file));
+
+        let (env, loading, backend) = (self.env.downgrade(), self.loading.clone(), self.backend);
+        let modules = Shared::downgrade(&self.modules);
+        let load_compiled = NativeFn::new("load-compiled", move |args| match args {
+            [Value::String(path)] => {
+                let env = crate::gc::using(|| env.upgrade());
+                let (Some(env), Some(modules)) = (env, modules.upgrade()) else {
+                    return Err(EvalError::Load {
+                        path: path.clone(),
+                        reason: "its interpreter is gone".to_string(),
+                    });
+                };
+                load_compiled(&env, &loading, backend, &modules, Path::new(path))
+                    .map_err(|e| load_error(path, e))
+            }
+            [other] => Err(EvalError::TypeMismatch {
+                expected: "string",
+                found: other.clone(),
+            }),
+            _ => Err(EvalError::ArityMismatch {
+                expected: 1,
+                found: args.len(),
+            }),
+        });
+        self.env
+            .define("load-compiled", Value::Native(load_compiled));
+    }
+
+    // Binds name to a Rust function. A later definition of the same name,
+    // from Rust or from source, replaces it.
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        func: impl Fn(&[Value]) -> Result<Value, EvalError> + MaybeSync + 'static,
+    ) {
+        self.env
+            .define(name, Value::Native(NativeFn::new(name, func)));
+    }
+
+    // Like register_fn for a function of Rust types, such as
+    // |(a, b): (i64, i64)| Ok(a + b). An argument of the wrong type or a
+    // wrong number of them fails the call as it would for a builtin.
+    pub fn register_typed_fn<A: FromArgs, R: IntoValue>(
+        &mut self,
+        name: &str,
+        func: impl Fn(A) -> Result<R, EvalError> + MaybeSync + 'static,
+    ) {
+        self.register_fn(name, move |args| {
+            func(A::from_args(args)?).map(IntoValue::into_value)
+        });
+    }
+
+    // Binds name to a Rust function that returns a future, for eval_async
+    // to wait for.
+    pub fn register_async_fn<F>(
+        &mut self,
+        name: &str,
+        func: impl Fn(Vec<Value>) -> F + MaybeSync + 'static,
+    ) where
+        F: Future<Output = Result<Value, EvalError>> + MaybeSend + 'static,
+    {
+        self.env
+            .define(name, Value::AsyncNative(AsyncFn::new(name, func)));
+    }
+
+    // Runs every form in source and returns the value of the last one, or
+    // () if there are none. Definitions made before an error are kept.
+    pub fn eval_str(&mut self, source: &str) -> Result<Value, InterpreterError> {
+        self.running(|| eval_source(&self.env, self.backend, &self.modules, source, None))
+    }
+
+    // Runs a form that has already been read, as a REPL reading input a
+    // line at a time has it.
+    pub fn eval_expr(&mut self, expr: &Expr) -> Result<Value, EvalError> {
+        self.running(|| eval_form(expr, &self.env, self.backend, &self.modules))
+    }
+
+    // Calls a procedure, such as one a script defined that get_global gave
+    // back, the way eval_str would run a call to it, so scripts can be used
+    // as callbacks.
+    pub fn call(&mut self, function: &Value, args: &[Value]) -> Result<Value, EvalError> {
+        self.running(|| apply(function, args.to_vec()))
+    }
+
+    // Like eval_str, except that a call to an async native stops the run
+    // until the native's future is ready and then carries on with its
+    // result, so neither a long script nor a slow host call blocks the
+    // executor in between. Forms run on the VM whatever the backend, and
+    // only the compiled code they call can stop like this: in a form left
+    // to the tree-walker, a tree-walker closure, a generator or a callback
+    // from a builtin such as swap!, an async call fails with CannotAwait.
+    // An error from a future ends the run. Limits count the whole run,
+    // waits included for the wall time. Without the sync feature values
+    // aren't Send and neither is the future, so under tokio it runs on a
+    // LocalSet; with it the future can be spawned like any other.
+    pub async fn eval_async(&mut self, source: &str) -> Result<Value, InterpreterError> {
+        let forms = with_read_table(&self.read_table, || read_all_spanned(source));
+        let forms: Shared<[_]> = forms?.into();
+        let mut budget = Budget::new(self.limits, self.cancel.clone());
+        let mut result = Value::Nil;
+        for form in forms.iter() {
+            let expr = form.to_expr();
+            let hooked = self.hook.is_some();
+            let spans = hooked.then(|| hooks::spans(&forms, form, &expr));
+            let mut task = None;
+            let mut progress = self.piece(&forms, &spans, &mut budget, || {
+                start(&expr, &self.env, self.backend, &self.modules, &mut task)
+            })?;
+            result = loop {
+                match (progress, &mut task) {
+                    (Progress::Await(future), Some(task)) => {
+                        task.resume(future.await?);
+                        progress = self.piece(&forms, &spans, &mut budget, || task.run())?;
+                    }
+                    (Progress::Done(value), _) => break value,
+                    (Progress::Await(_), None) => unreachable!(),
+                }
+            };
+        }
+        Ok(result)
+    }
+
+    // Runs a source file. Paths given to load inside it are relative to
+    // the file's directory.
+    #[cfg(feature = "std")]
+    pub fn eval_file(&mut self, path: impl AsRef<Path>) -> Result<Value, InterpreterError> {
+        self.running(|| {
+            load_file(
+                &self.env,
+                &self.loading,
+                self.backend,
+                &self.modules,
+                path.as_ref(),
+            )
+        })
+    }
+
+    // The files eval_file, load_compiled and the load in the code they ran
+    // have run, by their canonical names, in the order they were first
+    // run.
+    #[cfg(feature = "std")]
+    pub fn files_run(&self) -> Vec<PathBuf> {
+        self.loading.borrow().run.clone()
+    }
+
+    // Reads, expands and compiles a source file, without running it, into
+    // out, from which load_compiled can run it without doing any of that
+    // again. The file's defmacros are run as they are met, so the forms
+    // after them expand as they would if the file were loaded, but nothing
+    // else is; a macro that calls a function the file defines can't be
+    // expanded ahead of time. Forms the VM can't compile are kept expanded,
+    // for the tree-walker.
+    #[cfg(feature = "std")]
+    pub fn compile_file(
+        &mut self,
+        source: impl AsRef<Path>,
+        out: impl AsRef<Path>,
+    ) -> Result<(), InterpreterError> {
+        self.running(|| compile_file(&self.env, &self.loading, source.as_ref(), out.as_ref()))
+    }
+
+    // Compiles a source file as compile_file does, into memory. What it
+    // gives holds nothing of this interpreter's, so a library compiled
+    // once can be run by any number of interpreters with run_compiled,
+    // which share its bytecode; with the sync feature they can be on other
+    // threads.
+    #[cfg(feature = "std")]
+    pub fn compile(&mut self, source: impl AsRef<Path>) -> Result<CompiledFile, InterpreterError> {
+        self.running(|| compile_source(&self.env, &self.loading, source.as_ref()))
+    }
+
+    // Reads a source file and expands its macros as compile_file does,
+    // running its defmacros as they are met, and gives back the forms that
+    // would be compiled; module and import are left as they are.
+    #[cfg(feature = "std")]
+    pub fn expand_file(&mut self, source: impl AsRef<Path>) -> Result<Vec<Expr>, InterpreterError> {
+        self.running(|| {
+            let (text, path) = read_file(&relative(&self.loading, source.as_ref()))?;
+            expand_forms(&self.env, &text).map_err(|error| InterpreterError::InFile {
+                path,
+                error: Box::new(error),
+            })
+        })
+    }
+
+    // Runs compiled forms in this interpreter, as load_compiled runs a
+    // file, but without looking at their source.
+    #[cfg(feature = "std")]
+    pub fn run_compiled(&mut self, compiled: &CompiledFile) -> Result<Value, InterpreterError> {
+        self.running(|| {
+            run_compiled(
+                &self.env,
+                &self.loading,
+                self.backend,
+                &self.modules,
+                compiled,
+            )
+        })
+    }
+
+    // Runs a file compile_file wrote, which is refused if it was written
+    // by another version of the compiler, or if the source it was compiled
+    // from is still there and has changed since. Paths given to load
+    // inside it are relative to the source's directory.
+    #[cfg(feature = "std")]
+    pub fn load_compiled(&mut self, path: impl AsRef<Path>) -> Result<Value, InterpreterError> {
+        self.running(|| {
+            load_compiled(
+                &self.env,
+                &self.loading,
+                self.backend,
+                &self.modules,
+                path.as_ref(),
+            )
+        })
+    }
+
+    // Runs f with this interpreter's hook, I/O backend, limits and read
+    // table in effect.
+    fn running<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.running_on(&mut Budget::new(self.limits, self.cancel.clone()), f)
+    }
+
+    fn running_on<T>(&self, budget: &mut Budget, f: impl FnOnce() -> T) -> T {
+        hooks::with_hook(self.hook.clone(), self.traced.clone(), || {
+            io::with_io(self.io.clone(), || {
+                with_read_table(&self.read_table, || limits::with_budget(budget, f))
+            })
+        })
+    }
+
+    // Runs the part of eval_async between two waits. Nothing is left
+    // installed while the host's future is polled.
+    fn piece<T>(
+        &self,
+        forms: &Shared<[SpannedExpr]>,
+        spans: &Option<Shared<Spans>>,
+        budget: &mut Budget,
+        f: impl FnOnce() -> Result<T, EvalError>,
+    ) -> Result<T, EvalError> {
+        self.running_on(budget, || {
+            hooks::with_source(forms.clone(), None, || hooks::with_code(spans.clone(), f))
+        })
+    }
+
+    // Has hook told about calls, returns, branches and definitions as the
+    // tree-walker runs code from here on. The VM runs compiled code
+    // without reporting it.
+    pub fn set_hook(&mut self, hook: impl FnMut(Event) + MaybeSync + 'static) {
+        self.hook = Some(Shared::new(Lock::new(hook)));
+    }
+
+    pub fn clear_hook(&mut self) {
+        self.hook = None;
+    }
+
+    // Puts hook in place of this interpreter's, giving back the one it
+    // replaces, for something that watches a run without losing the
+    // host's hook.
+    #[cfg(feature = "std")]
+    pub(crate) fn replace_hook(
+        &mut self,
+        hook: Option<Shared<Lock<Hook>>>,
+    ) -> Option<Shared<Lock<Hook>>> {
+        core::mem::replace(&mut self.hook, hook)
+    }
+
+    // Sends the I/O builtins' reads and writes to backend from here on,
+    // starting from its standard input and output. load, include and
+    // eval_file read their files through it too.
+    pub fn set_io(&mut self, backend: impl IoBackend + 'static) {
+        let mut io = self.io.borrow_mut();
+        let policy = core::mem::take(&mut io.policy);
+        *io = Io::new(Box::new(backend));
+        io.policy = policy;
+    }
+
+    // Keeps the programs this interpreter runs from then on to the files
+    // policy allows, whatever the backend. A file it refuses fails to open
+    // or load as if the backend couldn't find it.
+    pub fn set_sandbox(&mut self, policy: SandboxPolicy) {
+        self.io.borrow_mut().policy = policy;
+    }
+
+    pub fn sandbox(&self) -> SandboxPolicy {
+        self.io.borrow().policy.clone()
+    }
+
+    // Adds reader syntax, as ReadTable::set_dispatch does, for the source
+    // this interpreter runs from then on, files it loads included.
+    pub fn set_dispatch(
+        &mut self,
+        lead: char,
+        sub: char,
+        handler: impl Fn(Expr) -> Result<Expr, String> + MaybeSync + 'static,
+    ) {
+        self.read_table.set_dispatch(lead, sub, handler);
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.env.get(name)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.env.define(name, value);
+    }
+
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    // A token that stops whatever this interpreter is running when it is
+    // cancelled. Runs are only checked for it once there is one.
+    pub fn cancellation_token(&mut self) -> CancellationToken {
+        self.cancel.get_or_insert_with(Default::default).clone()
+    }
+
+    // The modules defined so far, from source or from files it loaded.
+    pub fn modules(&self) -> Ref<'_, ModuleRegistry> {
+        self.modules.borrow()
+    }
+
+    // Has import ask resolver for the source of a module that hasn't been
+    // defined yet.
+    pub fn set_module_resolver(&mut self, resolver: impl ModuleResolver + 'static) {
+        let resolver: Shared<dyn ModuleResolver> = Shared::new(resolver);
+        self.modules.borrow_mut().set_resolver(resolver);
+    }
+
+    // A frame over the globals with bindings in it, to run code in that
+    // shouldn't leave anything behind, such as one request's worth.
+    pub fn scope(&mut self, bindings: &[(&str, Value)]) -> Scope<'_> {
+        let env = self.env.extend();
+        for (name, value) in bindings {
+            env.define(*name, value.clone());
+        }
+        Scope {
+            interpreter: self,
+            env,
+        }
+    }
+}
+
+// What code run in a scope defines stays in the scope and goes when it
+// does. set! on a global still changes the global, and load still loads
+// into the globals.
+pub struct Scope<'a> {
+    interpreter: &'a mut Interpreter,
+    env: Env,
+}
+
+impl Scope<'_> {
+    // Like Interpreter::eval_str, in the scope.
+    pub fn eval_str(&mut self, source: &str) -> Result<Value, InterpreterError> {
+        let interpreter = &*self.interpreter;
+        let (backend, modules) = (interpreter.backend, &interpreter.modules);
+        interpreter.running(|| eval_source(&self.env, backend, modules, source, None))
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.env.get(name)
+    }
+}
+
+fn eval_source(
+    env: &Env,
+    backend: Backend,
+    modules: &Lock<ModuleRegistry>,
+    source: &str,
+    file: Option<&str>,
+) -> Result<Value, InterpreterError> {
+    let forms: Shared<[_]> = read_all_spanned(source)?.into();
+    hooks::with_source(forms.clone(), file.map(Shared::from), || {
+        let mut result = Value::Nil;
+        for form in forms.iter() {
+            let expr = form.to_expr();
+            let spans = hooks::spanning().then(|| hooks::spans(&forms, form, &expr));
+            result = hooks::with_code(spans, || eval_form(&expr, env, backend, modules))?;
+        }
+        Ok(result)
+    })
+}
+
+// module and import need the registry, so they are forms of the
+// interpreter rather than of the evaluator, and only mean something at the
+// top level of a source or of a module's body.
+fn eval_form(
+    expr: &Expr,
+    env: &Env,
+    backend: Backend,
+    modules: &Lock<ModuleRegistry>,
+) -> Result<Value, EvalError> {
+    match interpreter_form(expr) {
+        Some(("module", args)) => return eval_module(args, env, backend, modules),
+        Some((_, args)) => return eval_import(args, env, backend, modules),
+        None => {}
+    }
+    let mut env = env.clone();
+    let expr = expand(expr, &env)?;
+    match backend {
+        Backend::Vm => match compile::compile(&expr) {
+            Ok(function) => vm::run(function, &env),
+            Err(_) => eval(&expr, &mut env),
+        },
+        Backend::TreeWalker => eval(&expr, &mut env),
+    }
+}
+
+fn interpreter_form(expr: &Expr) -> Option<(&'static str, &[Expr])> {
+    let Expr::List(items) = expr else {
+        return None;
+    };
+    match items.split_first() {
+        Some((Expr::Symbol(head), args)) if matches!(head.as_str(), "module" | "import") => {
+            Some((head.as_str(), args))
+        }
+        _ => None,
+    }
+}
+
+// Starts a top-level form for eval_async on a task, or, if it doesn't
+// compile, runs all of it the way eval_form would.
+fn start(
+    expr: &Expr,
+    env: &Env,
+    backend: Backend,
+    modules: &Lock<ModuleRegistry>,
+    task: &mut Option<Task>,
+) -> Result<Progress, EvalError> {
+    if interpreter_form(expr).is_some() {
+        return eval_form(expr, env, backend, modules).map(Progress::Done);
+    }
+    let mut env = env.clone();
+    let expr = expand(expr, &env)?;
+    match compile::compile(&expr) {
+        Ok(function) => task.insert(Task::new(function, &env)?).run(),
+        Err(_) => eval(&expr, &mut env).map(Progress::Done),
+    }
+}
+
+// The body runs in a scope of its own, so only the exports are seen from
+// outside: qualified as name/export (or name::export) where the module is
+// defined, and by their own names wherever it is imported. Both are bound
+// to the values the exports have once the body has run.
+fn eval_module(
+    args: &[Expr],
+    env: &Env,
+    backend: Backend,
+    modules: &Lock<ModuleRegistry>,
+) -> Result<Value, EvalError> {
+    let (name, exports, body) = module::parse_module(args)?;
+    let scope = env.extend();
+    for form in body {
+        eval_form(form, &scope, backend, modules)?;
+    }
+    if let Some(missing) = exports.iter().find(|export| scope.get(**export).is_none()) {
+        return Err(EvalError::UnboundSymbol(missing.to_string()));
+    }
+    let module = Module::new(scope, exports);
+    for (qualified, value) in module.qualified(name) {
+        env.define(qualified, value);
+    }
+    modules.borrow_mut().insert(name, module);
+    Ok(Value::Nil)
+}
+
+fn eval_import(
+    args: &[Expr],
+    env: &Env,
+    backend: Backend,
+    modules: &Lock<ModuleRegistry>,
+) -> Result<Value, EvalError> {
+    let [Expr::Symbol(name)] = args else {
+        let mut found = vec![Expr::Symbol(SymbolId::new("import"))];
+        found.extend_from_slice(args);
+        return Err(EvalError::MalformedForm {
+            form: "import",
+            found: Expr::List(found),
+        });
+    };
+    if modules.borrow().get(*name).is_none() {
+        resolve(*name, env, backend, modules)?;
+    }
+    let modules = modules.borrow();
+    let module = modules
+        .get(*name)
+        .ok_or_else(|| EvalError::UnknownModule(name.to_string()))?;
+    for export in module.exports() {
+        if let Some(value) = module.get(*export) {
+            env.define(*export, value);
+        }
+    }
+    Ok(Value::Nil)
+}
+
+// Runs the source the resolver has for a module that hasn't been defined,
+// if there is a resolver.
+fn resolve(
+    name: SymbolId,
+    env: &Env,
+    backend: Backend,
+    modules: &Lock<ModuleRegistry>,
+) -> Result<(), EvalError> {
+    let Some(resolver) = modules.borrow_mut().start_resolving(name) else {
+        return Ok(());
+    };
+    let result = resolver.load(name.as_str()).and_then(|source| {
+        eval_source(env, backend, modules, &source, None).map_err(|e| e.to_string())
+    });
+    modules.borrow_mut().finish_resolving();
+    result.map(drop).map_err(|reason| EvalError::Load {
+        path: name.to_string(),
+        reason,
+    })
+}
+
+// What load and the like fail with when the file does: EvalError::Load,
+// unless it was an error that try can't catch, which goes on as it is.
+#[cfg(feature = "std")]
+fn load_error(path: &str, error: InterpreterError) -> EvalError {
+    let mut inner = &error;
+    while let InterpreterError::InFile { error, .. } = inner {
+        inner = error;
+    }
+    match inner {
+        InterpreterError::Eval(
+            e @ (EvalError::LimitExceeded(_) | EvalError::Cancelled | EvalError::Exit(_)),
+        ) => e.clone(),
+        _ => EvalError::Load {
+            path: path.to_string(),
+            reason: error.to_string(),
+        },
+    }
+}
+
+// The files being run, innermost last, so a relative path is resolved
+// against the file that loads it and a file that ends up loading itself is
+// caught instead of recursing forever; and every file run, in the order
+// they were first run.
+#[cfg(feature = "std")]
+#[derive(Default)]
+struct Loading {
+    running: Vec<PathBuf>,
+    run: Vec<PathBuf>,
+}
+
+#[cfg(feature = "std")]
+fn load_file(
+    env: &Env,
+    loading: &Lock<Loading>,
+    backend: Backend,
+    modules: &Lock<ModuleRegistry>,
+    path: &Path,
+) -> Result<Value, InterpreterError> {
+    let (source, path) = read_file(&relative(loading, path))?;
+    let file = path.to_string_lossy().into_owned();
+    in_file(loading, path, || {
+        eval_source(env, backend, modules, &source, Some(&file))
+    })
+}
+
+#[cfg(feature = "std")]
+fn relative(loading: &Lock<Loading>, path: &Path) -> PathBuf {
+    let running = &loading.borrow().running;
+    match running.last().and_then(|file| file.parent()) {
+        Some(dir) => dir.join(path),
+        None => path.to_owned(),
+    }
+}
+
+// The contents of the file at path and its canonical name.
+#[cfg(feature = "std")]
+fn read_file(path: &Path) -> Result<(String, PathBuf), InterpreterError> {
+    let source = io::canonical_path(&path.to_string_lossy())
+        .and_then(|name| Ok((io::read_file(&name)?, PathBuf::from(name))));
+    source.map_err(|message| InterpreterError::Io {
+        path: path.to_owned(),
+        message,
+    })
+}
+
+// Runs f as the file at path, which it names in its errors.
+#[cfg(feature = "std")]
+fn in_file(
+    loading: &Lock<Loading>,
+    path: PathBuf,
+    f: impl FnOnce() -> Result<Value, InterpreterError>,
+) -> Result<Value, InterpreterError> {
+    let mut files = loading.borrow_mut();
+    if files.running.contains(&path) {
+        return Err(InterpreterError::Cycle(path));
+    }
+    if !files.run.contains(&path) {
+        files.run.push(path.clone());
+    }
+    files.running.push(path.clone());
+    drop(files);
+    let result = f();
+    loading.borrow_mut().running.pop();
+    result.map_err(|error| InterpreterError::InFile {
+        path,
+        error: Box::new(error),
+    })
+}
+
+#[cfg(feature = "std")]
+fn compile_file(
+    env: &Env,
+    loading: &Lock<Loading>,
+    source: &Path,
+    out: &Path,
+) -> Result<(), InterpreterError> {
+    let compiled = compile_source(env, loading, source)?;
+    let out = relative(loading, out);
+    let port = io::open_output_file(&out.to_string_lossy())?;
+    port.write_str(&compiled.to_text())?;
+    port.close();
+    Ok(())
+}
+
+#[cfg(feature = "std")]
+fn compile_source(
+    env: &Env,
+    loading: &Lock<Loading>,
+    source: &Path,
+) -> Result<CompiledFile, InterpreterError> {
+    let (text, path) = read_file(&relative(loading, source))?;
+    let forms = compile_forms(env, &text).map_err(|error| InterpreterError::InFile {
+        path: path.clone(),
+        error: Box::new(error),
+    })?;
+    Ok(CompiledFile {
+        source: path.to_string_lossy().into_owned(),
+        checksum: bytecode::checksum(&text),
+        forms,
+    })
+}
+
+#[cfg(feature = "std")]
+fn compile_forms(env: &Env, source: &str) -> Result<Vec<Form>, InterpreterError> {
+    let forms = expand_forms(env, source)?;
+    Ok(forms
+        .into_iter()
+        .map(|expr| match interpreter_form(&expr) {
+            // module and import are left for load_compiled to run as they
+            // are.
+            Some(_) => Form::Source(expr),
+            None => match compile::compile(&expr) {
+                Ok(function) => Form::Compiled(function),
+                Err(_) => Form::Source(expr),
+            },
+        })
+        .collect())
+}
+
+#[cfg(feature = "std")]
+fn expand_forms(env: &Env, source: &str) -> Result<Vec<Expr>, InterpreterError> {
+    let mut forms = Vec::new();
+    for form in read_all_spanned(source)? {
+        let expr = form.to_expr();
+        if interpreter_form(&expr).is_some() {
+            forms.push(expr);
+            continue;
+        }
+        let expr = expand(&expr, env)?;
+        let head = match &expr {
+            Expr::List(items) => items.first(),
+            _ => None,
+        };
+        if head == Some(&Expr::Symbol(symbol::DEFMACRO)) {
+            eval(&expr, &mut env.clone())?;
+        }
+        forms.push(expr);
+    }
+    Ok(forms)
+}
+
+#[cfg(feature = "std")]
+fn load_compiled(
+    env: &Env,
+    loading: &Lock<Loading>,
+    backend: Backend,
+    modules: &Lock<ModuleRegistry>,
+    path: &Path,
+) -> Result<Value, InterpreterError> {
+    let (text, path) = read_file(&relative(loading, path))?;
+    let refused = |message| InterpreterError::Io {
+        path: path.clone(),
+        message,
+    };
+    let compiled = CompiledFile::from_text(&text).map_err(refused)?;
+    // A compiled file can be used without its source, but not instead of
+    // a newer one.
+    let source = PathBuf::from(&compiled.source);
+    if let Ok((text, _)) = read_file(&source) {
+        if bytecode::checksum(&text) != compiled.checksum {
+            let message = format!("`{}` has changed since it was compiled", source.display());
+            return Err(refused(message));
+        }
+    }
+    run_compiled(env, loading, backend, modules, &compiled)
+}
+
+#[cfg(feature = "std")]
+fn run_compiled(
+    env: &Env,
+    loading: &Lock<Loading>,
+    backend: Backend,
+    modules: &Lock<ModuleRegistry>,
+    compiled: &CompiledFile,
+) -> Result<Value, InterpreterError> {
+    in_file(loading, PathBuf::from(&compiled.source), || {
+        let mut result = Value::Nil;
+        for form in &compiled.forms {
+            result = match form {
+                Form::Compiled(function) => vm::run(function.clone(), env)?,
+                Form::Source(expr) => eval_form(expr, env, backend, modules)?,
+            };
+        }
+        Ok(result)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::limits::Limit;
+    use alloc::{borrow::ToOwned, format};
+    #[cfg(feature = "std")]
+    use std::fs;
+
+    #[cfg(feature = "std")]
+    fn write_files(dir: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{dir}-{}", std::process::id()));
+        for (name, source) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn eval_str_test() {
+        let mut interpreter = Interpreter::new();
+        assert_eq!(
+            interpreter.eval_str("(define (twice x) (list x x)) (twice 7)"),
+            Ok(Value::List(vec![Value::Integer(7), Value::Integer(7)]))
+        );
+        assert_eq!(interpreter.eval_str(""), Ok(Value::Nil));
+        assert!(interpreter.get_global("twice").unwrap().is_procedure());
+        assert_eq!(interpreter.get_global("missing"), None);
+        interpreter.set_global("limit", Value::Integer(3));
+        assert_eq!(
+            interpreter.eval_str("(length (twice limit))"),
+            Ok(Value::Integer(2))
+        );
+        assert!(matches!(
+            interpreter.eval_str("(twice"),
+            Err(InterpreterError::Read(_))
+        ));
+        assert_eq!(
+            interpreter.eval_str("(twice nope)"),
+            Err(InterpreterError::Eval(EvalError::UnboundSymbol(
+                "nope".to_owned()
+            )))
+        );
+    }
+
+    #[test]
+    fn register_fn_test() {
+        let mut interpreter = Interpreter::new();
+        let log = Shared::new(Lock::new(Vec::new()));
+        let sink = log.clone();
+        interpreter.register_fn("record", move |args| {
+            sink.borrow_mut().extend(args.iter().cloned());
+            Ok(Value::Integer(args.len() as i64))
+        });
+        assert_eq!(
+            interpreter.eval_str("(record 1 (length (list 1 2))) (record 3)"),
+            Ok(Value::Integer(1))
+        );
+        assert_eq!(
+            *log.borrow(),
+            [Value::Integer(1), Value::Integer(2), Value::Integer(3)]
+        );
+        assert_eq!(
+            interpreter.eval_str("record").unwrap().to_string(),
+            "#<procedure record>"
+        );
+    }
+
+    #[test]
+    fn call_test() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let mut interpreter = Interpreter::with_backend(backend);
+            interpreter
+                .eval_str("(define seen 0) (define (on-event n) (set! seen (+ seen n)) seen)")
+                .unwrap();
+            let handler = interpreter.get_global("on-event").unwrap();
+            assert_eq!(
+                interpreter.call(&handler, &[Value::Integer(2)]),
+                Ok(Value::Integer(2))
+            );
+            assert_eq!(
+                interpreter.call(&handler, &[Value::Integer(3)]),
+                Ok(Value::Integer(5))
+            );
+            assert_eq!(
+                interpreter.call(&handler, &[]),
+                Err(EvalError::ArityMismatch {
+                    expected: 1,
+                    found: 0
+                })
+            );
+            assert_eq!(
+                interpreter.call(&Value::Integer(1), &[]),
+                Err(EvalError::NotCallable(Value::Integer(1)))
+            );
+            interpreter.eval_str("(define (spin) (spin))").unwrap();
+            interpreter.set_limits(Limits {
+                max_steps: Some(1000),
+                ..Limits::default()
+            });
+            let spin = interpreter.get_global("spin").unwrap();
+            assert!(matches!(
+                interpreter.call(&spin, &[]),
+                Err(EvalError::LimitExceeded(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn register_typed_fn_test() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_typed_fn("scale", |(factor, xs): (f64, Vec<f64>)| {
+            Ok(xs.into_iter().map(|x| x * factor).collect::<Vec<_>>())
+        });
+        interpreter.register_typed_fn("greet", |(name, title): (String, Option<String>)| {
+            Ok(match title {
+                Some(title) => format!("hello, {title} {name}"),
+                None => format!("hello, {name}"),
+            })
+        });
+        interpreter.register_typed_fn("nothing", |()| Ok(()));
+        assert_eq!(
+            interpreter.eval_str("(scale 2 '(1 1.5))"),
+            Ok(Value::from(vec![2.0, 3.0]))
+        );
+        assert_eq!(
+            interpreter
+                .eval_str("(list (greet \"ada\" ()) (greet \"ada\" \"dr\"))")
+                .map(Vec::<String>::try_from),
+            Ok(Ok(vec![
+                "hello, ada".to_owned(),
+                "hello, dr ada".to_owned()
+            ]))
+        );
+        assert_eq!(interpreter.eval_str("(nothing)"), Ok(Value::Nil));
+        assert_eq!(
+            interpreter.eval_str("(scale 'x '())"),
+            Err(InterpreterError::Eval(EvalError::TypeMismatch {
+                expected: "number",
+                found: Value::Symbol("x".into())
+            }))
+        );
+        assert_eq!(
+            interpreter.eval_str("(scale 1)"),
+            Err(InterpreterError::Eval(EvalError::ArityMismatch {
+                expected: 2,
+                found: 1
+            }))
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn eval_file_test() {
+        let dir = write_files(
+            "tuple-gd-load",
+            &[
+                ("main.scm", "(load \"lib/util.scm\") (pair answer)"),
+                (
+                    "lib/util.scm",
+                    "(load \"consts.scm\") (define (pair x) (list x x))",
+                ),
+                ("lib/consts.scm", "(define answer 42)"),
+                ("cycle_a.scm", "(load \"cycle_b.scm\")"),
+                ("cycle_b.scm", "(load \"cycle_a.scm\")"),
+                (
+                    "broken.scm",
+                    "(define ok 1)\n(load \"lib/consts.scm\") (car nope)",
+                ),
+                ("unclosed.scm", "(define ok 2)\n(car"),
+                ("exits.scm", "(exit 2)"),
+            ],
+        );
+        let mut interpreter = Interpreter::new();
+        assert_eq!(
+            interpreter.eval_file(dir.join("main.scm")),
+            Ok(Value::List(vec![Value::Integer(42), Value::Integer(42)]))
+        );
+        assert_eq!(interpreter.get_global("answer"), Some(Value::Integer(42)));
+        let run: Vec<PathBuf> = ["main.scm", "lib/util.scm", "lib/consts.scm"]
+            .iter()
+            .map(|name| fs::canonicalize(dir.join(name)).unwrap())
+            .collect();
+        assert_eq!(interpreter.files_run(), run);
+
+        let error = interpreter
+            .eval_file(dir.join("cycle_a.scm"))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("cycle_b.scm"), "{error}");
+        assert!(
+            error.ends_with("cycle_a.scm` is already being loaded"),
+            "{error}"
+        );
+
+        let error = interpreter.eval_file(dir.join("broken.scm")).unwrap_err();
+        assert!(matches!(
+            &error,
+            InterpreterError::InFile { path, error }
+                if path.ends_with("broken.scm")
+                    && **error == InterpreterError::Eval(EvalError::UnboundSymbol("nope".to_owned()))
+        ));
+        assert_eq!(error.span(), None);
+        let error = interpreter.eval_file(dir.join("unclosed.scm")).unwrap_err();
+        assert_eq!(error.span(), Some(Span { start: 18, end: 18 }));
+        // try doesn't catch an exit, even from a file being loaded.
+        let exits = format!("(try (load {:?}) (catch e e))", dir.join("exits.scm"));
+        assert_eq!(
+            interpreter.eval_str(&exits),
+            Err(InterpreterError::Eval(EvalError::Exit(2)))
+        );
+        assert_eq!(
+            interpreter.eval_str("(with-exception-handler (lambda (e) e) (lambda () (exit #f)))"),
+            Err(InterpreterError::Eval(EvalError::Exit(1)))
+        );
+        assert!(matches!(
+            interpreter.eval_str("(load \"missing.scm\")"),
+            Err(InterpreterError::Eval(EvalError::Load { .. }))
+        ));
+        assert_eq!(interpreter.get_global("ok"), Some(Value::Integer(1)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn drop_test() {
+        let before = crate::gc::heap_stats().envs;
+        for _ in 0..50 {
+            let mut interpreter = Interpreter::new();
+            interpreter
+                .eval_str("(define x 1) (module m (export y) (define y x)) (import m)")
+                .unwrap();
+        }
+        assert_eq!(crate::gc::heap_stats().envs, before);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn load_after_drop_test() {
+        let mut interpreter = Interpreter::new();
+        let load = interpreter.eval_str("load").unwrap();
+        drop(interpreter);
+        let Value::Native(load) = load else {
+            panic!("expected a native, found {load}");
+        };
+        assert!(matches!(
+            (load.func)(&[Value::String("lib.scm".into())]),
+            Err(EvalError::Load { .. })
+        ));
+    }
+
+    // A host function that panics unwinds through eval_str, and a host that
+    // catches it finds none of the interpreter's state still installed.
+    #[cfg(feature = "std")]
+    #[test]
+    fn panic_test() {
+        let mut interpreter = Interpreter::with_limits(Limits {
+            max_steps: Some(100),
+            ..Limits::default()
+        });
+        interpreter.set_hook(|_| {});
+        interpreter.set_io(io::MemoryIo::new());
+        interpreter.set_dispatch('#', 'x', |_| Ok(Expr::Nil));
+        interpreter.register_fn("boom", |_| panic!("boom"));
+        let run = std::panic::AssertUnwindSafe(|| interpreter.eval_str("#x1 (boom)"));
+        assert!(std::panic::catch_unwind(run).is_err());
+        assert!(!hooks::active());
+        assert!(!Shared::ptr_eq(&io::current(), &interpreter.io));
+        assert!(!crate::reader::dispatches('#', 'x'));
+        assert!((0..1000).all(|_| limits::step().is_ok()));
+        assert_eq!(interpreter.eval_str("(+ 1 2)"), Ok(Value::Integer(3)));
+    }
+
+    #[test]
+    fn module_test() {
+        let mut interpreter = Interpreter::new();
+        let source = "
+            (module geometry (export area scale)
+              (define pi 3)
+              (define (area r) (* pi r r))
+              (define (scale r) (* 2 r)))
+            (list (geometry/area 2) (geometry::scale 5))";
+        assert_eq!(interpreter.eval_str(source).unwrap().to_string(), "(12 10)");
+        // Only the exports come out, and only qualified until imported.
+        assert_eq!(interpreter.get_global("pi"), None);
+        assert_eq!(interpreter.get_global("geometry/pi"), None);
+        assert_eq!(interpreter.get_global("area"), None);
+        assert_eq!(
+            interpreter.eval_str("(import geometry) (area (scale 1))"),
+            Ok(Value::Integer(12))
+        );
+        {
+            let modules = interpreter.modules();
+            let geometry = modules.get("geometry").unwrap();
+            assert_eq!(geometry.exports(), ["area", "scale"].map(SymbolId::new));
+            assert!(geometry.get("area").unwrap().is_procedure());
+            assert_eq!(geometry.get("pi"), None);
+            assert_eq!(
+                modules.names().collect::<Vec<_>>(),
+                [SymbolId::new("geometry")]
+            );
+        }
+
+        // A module can import another; imports inside it stay inside it.
+        assert_eq!(
+            interpreter.eval_str(
+                "(module shapes (export unit)
+                   (import geometry)
+                   (define unit (area 1)))
+                 shapes/unit"
+            ),
+            Ok(Value::Integer(3))
+        );
+
+        assert_eq!(
+            interpreter.eval_str("(import nowhere)"),
+            Err(InterpreterError::Eval(EvalError::UnknownModule(
+                "nowhere".to_owned()
+            )))
+        );
+        assert_eq!(
+            interpreter.eval_str("(module broken (export ghost) (define real 1))"),
+            Err(InterpreterError::Eval(EvalError::UnboundSymbol(
+                "ghost".to_owned()
+            )))
+        );
+        for source in [
+            "(module)",
+            "(module m (a b))",
+            "(module m (export 1))",
+            "(import)",
+        ] {
+            assert!(
+                matches!(
+                    interpreter.eval_str(source),
+                    Err(InterpreterError::Eval(EvalError::MalformedForm { .. }))
+                ),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn scope_test() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let mut interpreter = Interpreter::with_backend(backend);
+            interpreter.eval_str("(define base 10)").unwrap();
+            let mut scope = interpreter.scope(&[("x", Value::Integer(1))]);
+            assert_eq!(
+                scope.eval_str("(define y (+ x base)) (+ y 1)"),
+                Ok(Value::Integer(12))
+            );
+            assert_eq!(scope.get("y"), Some(Value::Integer(11)));
+            assert_eq!(interpreter.get_global("x"), None);
+            assert_eq!(interpreter.get_global("y"), None);
+            assert!(interpreter.scope(&[]).eval_str("x").is_err());
+            let mut scope = interpreter.scope(&[("x", Value::Integer(5))]);
+            scope.eval_str("(set! base x)").unwrap();
+            assert_eq!(interpreter.get_global("base"), Some(Value::Integer(5)));
+        }
+    }
+
+    #[test]
+    fn module_resolver_test() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_module_resolver(|name: &str| {
+            let source = match name {
+                "square" => "(module square (export sq) (define (sq x) (* x x)))",
+                "quad" => "(module quad (export q) (import square) (define (q x) (sq (sq x))))",
+                "itself" => "(import itself)",
+                "nothing" => "(define stray 1)",
+                "broken" => "(module broken",
+                _ => return Err("not found".to_owned()),
+            };
+            Ok(source.to_owned())
+        });
+        assert_eq!(
+            interpreter.eval_str("(import quad) (q 3)"),
+            Ok(Value::Integer(81))
+        );
+        assert!(interpreter.modules().get("square").is_some());
+        assert_eq!(
+            interpreter.eval_str("(import nothing)"),
+            Err(InterpreterError::Eval(EvalError::UnknownModule(
+                "nothing".to_owned()
+            )))
+        );
+        let itself = interpreter.eval_str("(import itself)").unwrap_err();
+        assert!(itself.to_string().ends_with("unknown module `itself`"));
+        assert!(interpreter.eval_str("(import broken)").is_err());
+        assert_eq!(
+            interpreter.eval_str("(import nowhere)"),
+            Err(InterpreterError::Eval(EvalError::Load {
+                path: "nowhere".to_owned(),
+                reason: "not found".to_owned(),
+            }))
+        );
+    }
+
+    #[test]
+    fn io_test() {
+        let memory = io::MemoryIo::new();
+        memory.set_input("first\nsecond\n");
+        memory.add_file("in.txt", "a line\n");
+        let mut interpreter = Interpreter::new();
+        interpreter.set_io(memory.clone());
+        assert_eq!(
+            interpreter.eval_str(
+                "(define out (open-output-file \"out.txt\"))
+                 (write \"quoted\" out) (newline out) (display 'sym out)
+                 (close-port out)
+                 (display (read-line))
+                 (newline)
+                 (write (list (read-line (open-input-file \"in.txt\")) 1/2))
+                 (list (read-line) (read-line) (input-port? (current-input-port))
+                       (output-port? (current-output-port)) (output-port? out))"
+            ),
+            Ok(Value::List(vec![
+                Value::String("second".to_owned()),
+                Value::Bool(false),
+                Value::Bool(true),
+                Value::Bool(true),
+                Value::Bool(true),
+            ]))
+        );
+        assert_eq!(memory.output(), "first\n(\"a line\" 1/2)");
+        assert_eq!(memory.file("out.txt").as_deref(), Some("\"quoted\"\nsym"));
+        assert_eq!(
+            interpreter.eval_str("(display 1 out)"),
+            Err(InterpreterError::Eval(EvalError::Io(
+                "port is closed".to_owned()
+            )))
+        );
+        for source in [
+            "(open-input-file \"missing.txt\")",
+            "(read-line (current-output-port))",
+            "(write 1 (current-input-port))",
+            "(display 1 2)",
+            "(newline 1 2)",
+            "(write)",
+        ] {
+            assert!(interpreter.eval_str(source).is_err(), "{source}");
+        }
+        assert_eq!(
+            interpreter.eval_str("(current-output-port)").unwrap().to_string(),
+            "#<output-port>"
+        );
+        assert_eq!(
+            interpreter.eval_str(
+                "(define s (open-output-string))
+                 (write \"a\" s) (display 'b s) (newline s)
+                 (list (get-output-string s)
+                       (read-line (open-input-string \"one\ntwo\")))"
+            ),
+            Ok(Value::List(vec![
+                Value::String("\"a\"b\n".to_owned()),
+                Value::String("one".to_owned()),
+            ]))
+        );
+        assert_eq!(memory.output(), "first\n(\"a line\" 1/2)");
+        assert!(interpreter.eval_str("(get-output-string out)").is_err());
+    }
+
+    #[test]
+    fn disassemble_test() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let memory = io::MemoryIo::new();
+            let mut interpreter = Interpreter::with_backend(backend);
+            interpreter.set_io(memory.clone());
+            interpreter
+                .eval_str("(define (inc x) (+ x 1)) (disassemble inc)")
+                .unwrap();
+            assert_eq!(
+                memory.output(),
+                "   0  Global(\"+\")\n   1  Local(0)\n   2  Const(0)  ; 1\n   3  TailCall(2)\n   4  Return\n"
+            );
+            for source in ["(disassemble car)", "(disassemble (lambda (&key a) a))"] {
+                assert!(interpreter.eval_str(source).is_err(), "{source}");
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn load_io_test() {
+        let dir = write_files("tuple-gd-load-io", &[("real.scm", "(define real 1)")]);
+        let memory = io::MemoryIo::new();
+        memory.add_file("main.scm", "(load \"lib/util.scm\") (include \"cycle\")");
+        memory.add_file("lib/util.scm", "(load \"consts.scm\") (list answer)");
+        memory.add_file("lib/consts.scm", "(define answer 42)");
+        memory.add_file("cycle", "(load \"cycle\")");
+        let mut interpreter = Interpreter::new();
+        interpreter.set_io(memory);
+        assert_eq!(
+            interpreter.eval_str("(load \"lib/util.scm\")"),
+            Ok(Value::List(vec![Value::Integer(42)]))
+        );
+        let error = interpreter.eval_file("main.scm").unwrap_err().to_string();
+        assert!(error.ends_with("is already being loaded"), "{error}");
+        assert!(interpreter.eval_file(dir.join("real.scm")).is_err());
+        assert_eq!(interpreter.get_global("real"), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn compile_file_test() {
+        let source = "(defmacro twice (x) (list 'list x x))
+                      (define (adder n) (lambda (x) (+ n x)))
+                      (define pair (twice 7))
+                      (try (car 1) (catch e 'caught))";
+        let memory = io::MemoryIo::new();
+        memory.add_file("lib.scm", source);
+        let mut interpreter = Interpreter::new();
+        interpreter.set_io(memory.clone());
+        assert_eq!(interpreter.compile_file("lib.scm", "lib.scmc"), Ok(()));
+        let compiled = memory.file("lib.scmc").unwrap();
+        assert!(compiled.contains("(function 0 #f"), "{compiled}");
+        assert!(!compiled.contains("(twice 7)"), "{compiled}");
+
+        // Without the source, as a library shipped compiled.
+        let shipped = io::MemoryIo::new();
+        shipped.add_file("lib.scmc", compiled);
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let mut interpreter = Interpreter::with_backend(backend);
+            interpreter.set_io(shipped.clone());
+            assert_eq!(
+                interpreter.load_compiled("lib.scmc"),
+                Ok(Value::Symbol("caught".into()))
+            );
+            assert_eq!(
+                interpreter
+                    .eval_str("(list pair ((adder 1) 2))")
+                    .unwrap()
+                    .to_string(),
+                "((7 7) 3)"
+            );
+        }
+
+        assert_eq!(interpreter.load_compiled("lib.scmc").map(drop), Ok(()));
+        memory.add_file("lib.scm", "(define pair 1)");
+        let error = interpreter
+            .load_compiled("lib.scmc")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.ends_with("`lib.scm` has changed since it was compiled"),
+            "{error}"
+        );
+        assert_eq!(
+            interpreter.eval_str(
+                "(compile-file \"lib.scm\" \"lib.scmc\") (load-compiled \"lib.scmc\") pair"
+            ),
+            Ok(Value::Integer(1))
+        );
+        memory.add_file("bad.scmc", "(tuple-gd-compiled 0)");
+        let error = interpreter
+            .load_compiled("bad.scmc")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("compiled by version 0 of the compiler"),
+            "{error}"
+        );
+        assert!(interpreter.eval_str("(load-compiled \"lib.scm\")").is_err());
+
+        // Compiled once, the library runs in each interpreter on its own.
+        memory.add_file(
+            "counter.scm",
+            "(define count 0) (define (bump) (set! count (+ count 1)) count)",
+        );
+        let library = interpreter.compile("counter.scm").unwrap();
+        let mut interpreters = [Interpreter::new(), Interpreter::new()];
+        for interpreter in &mut interpreters {
+            assert_eq!(interpreter.run_compiled(&library).map(drop), Ok(()));
+        }
+        let [first, second] = &mut interpreters;
+        assert_eq!(first.eval_str("(bump) (bump)"), Ok(Value::Integer(2)));
+        assert_eq!(second.eval_str("(bump)"), Ok(Value::Integer(1)));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn sandbox_test() {
+        let memory = io::MemoryIo::new();
+        memory.add_file("lib/consts.scm", "(define answer 42)");
+        memory.add_file("secret.txt", "hunter2");
+        let mut interpreter = Interpreter::new();
+        interpreter.set_sandbox(SandboxPolicy {
+            allowed_paths: vec!["lib".to_owned()],
+            ..SandboxPolicy::default()
+        });
+        interpreter.set_io(memory.clone());
+        assert_eq!(interpreter.sandbox().allowed_paths, ["lib"]);
+        assert_eq!(
+            interpreter.eval_str("(load \"lib/consts.scm\") answer"),
+            Ok(Value::Integer(42))
+        );
+        let refused = Err(InterpreterError::Eval(EvalError::Io(
+            "cannot open `lib/../secret.txt`: not allowed by the sandbox".to_owned(),
+        )));
+        assert_eq!(
+            interpreter.eval_str("(open-input-file \"lib/../secret.txt\")"),
+            refused
+        );
+        assert!(interpreter.eval_str("(load \"secret.txt\")").is_err());
+        assert!(interpreter.eval_str("(open-output-file \"o\")").is_err());
+        assert_eq!(
+            interpreter.eval_str("(try (open-input-file \"secret.txt\") (catch e 'refused))"),
+            Ok(Value::Symbol("refused".into()))
+        );
+        assert_eq!(memory.file("o"), None);
+
+        interpreter.set_sandbox(SandboxPolicy::deny_all());
+        assert!(interpreter.eval_str("(load \"lib/consts.scm\")").is_err());
+        assert!(interpreter.eval_file("lib/consts.scm").is_err());
+    }
+
+    #[test]
+    fn cancel_test() {
+        let mut interpreter = Interpreter::new();
+        let token = interpreter.cancellation_token();
+        let cancel = token.clone();
+        interpreter.register_fn("cancel", move |_| {
+            cancel.cancel();
+            Ok(Value::Nil)
+        });
+        let cancelled = Err(InterpreterError::Eval(EvalError::Cancelled));
+        let looping = "(define (f) (f)) (cancel) (try (f) (catch e 'caught))";
+        assert_eq!(interpreter.eval_str(looping), cancelled);
+        assert!(token.is_cancelled());
+        assert_eq!(interpreter.eval_str("(+ 1 2)"), Ok(Value::Integer(3)));
+        assert!(!token.is_cancelled());
+
+        #[cfg(feature = "std")]
+        {
+            let cancel = interpreter.cancellation_token();
+            let canceller = std::thread::spawn(move || {
+                std::thread::sleep(core::time::Duration::from_millis(50));
+                cancel.cancel();
+            });
+            assert_eq!(interpreter.eval_str("(f)"), cancelled);
+            canceller.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn limits_test() {
+        let limited = |limits: Limits, source: &str| {
+            let mut interpreter = Interpreter::with_limits(limits);
+            assert_eq!(interpreter.limits(), limits);
+            match interpreter.eval_str(source) {
+                Err(InterpreterError::Eval(EvalError::LimitExceeded(limit))) => Some(limit),
+                _ => None,
+            }
+        };
+        let steps = Limits {
+            max_steps: Some(10_000),
+            ..Limits::default()
+        };
+        assert_eq!(
+            limited(steps, "(define (f) (f)) (f)"),
+            Some(Limit::Steps(10_000))
+        );
+        assert_eq!(
+            limited(steps, "(define (f) (f)) (try (f) (catch e 'caught))"),
+            Some(Limit::Steps(10_000))
+        );
+        assert_eq!(limited(steps, "(+ 1 2)"), None);
+
+        let depth = Limits {
+            max_depth: Some(50),
+            ..Limits::default()
+        };
+        let deep = "(define (f n) (if (= n 0) 0 (+ 1 (f (- n 1)))))";
+        assert_eq!(
+            limited(depth, &format!("{deep} (f 1000)")),
+            Some(Limit::Depth(50))
+        );
+        assert_eq!(limited(depth, &format!("{deep} (f 5)")), None);
+        assert_eq!(
+            limited(
+                depth,
+                "(with-exception-handler (lambda (e) 0) (lambda () ((lambda (f) (f f)) (lambda (f) (list (f f))))))"
+            ),
+            Some(Limit::Depth(50))
+        );
+
+        let heap = Limits {
+            max_heap_bytes: Some(1 << 16),
+            ..Limits::default()
+        };
+        // Interned names are never freed, so they count too.
+        let names = "(define (f n) (when (> n 0) (g (string-append \"leak-\" (number->string n))) (f (- n 1))))";
+        let interned = format!("(define g string->symbol) {names} (f 20000)");
+        assert_eq!(limited(heap, &interned), Some(Limit::HeapBytes(1 << 16)));
+        let listed = format!("(define g list) {names} (f 20000)");
+        assert_eq!(limited(heap, &listed), None);
+        assert_eq!(
+            limited(
+                heap,
+                "(define big (atom ()))
+                 (define (grow n) (swap! big (lambda (l) (cons \"some text\" l))) (grow n))
+                 (grow 0)"
+            ),
+            Some(Limit::HeapBytes(1 << 16))
+        );
+        // Collections built in one go are checked before they are made.
+        assert_eq!(
+            limited(heap, "(range 10000000000)"),
+            Some(Limit::HeapBytes(1 << 16))
+        );
+        let values = (1 << 16) / size_of::<Value>();
+        assert_eq!(limited(heap, &format!("(range {})", values / 2)), None);
+        let append = format!("(define l (range {})) (append l l l)", values / 2);
+        assert_eq!(limited(heap, &append), Some(Limit::HeapBytes(1 << 16)));
+        assert_eq!(
+            limited(
+                heap,
+                "(define (grow s n) (if (= n 0) s (grow (string-append s s) (- n 1)))) (grow \"x\" 20)"
+            ),
+            Some(Limit::HeapBytes(1 << 16))
+        );
+
+        #[cfg(feature = "std")]
+        {
+            let time = Limits {
+                max_wall_time: Some(std::time::Duration::from_millis(50)),
+                ..Limits::default()
+            };
+            assert!(matches!(
+                limited(time, "(define (f) (f)) (f)"),
+                Some(Limit::WallTime(_))
+            ));
+        }
+
+        // Without limits, recursion too deep for the native stack is
+        // stopped before it overflows.
+        let unlimited = Limits::default();
+        let deepest = limited(unlimited, &format!("{deep} (f 10000)"));
+        assert!(matches!(deepest, Some(Limit::Depth(_))), "{deepest:?}");
+        assert_eq!(limited(unlimited, &format!("{deep} (f 50)")), None);
+
+        // Each run gets the whole budget again, under either backend.
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let mut interpreter = Interpreter::with_backend(backend);
+            interpreter.set_limits(steps);
+            let program = "(define (loop n) (if (= n 0) 'done (loop (- n 1))))";
+            assert_eq!(interpreter.eval_str(program), Ok(Value::Nil));
+            for _ in 0..3 {
+                assert!(interpreter.eval_str("(loop 100)").is_ok());
+            }
+            assert!(matches!(
+                interpreter.eval_str("(loop 100000)"),
+                Err(InterpreterError::Eval(EvalError::LimitExceeded(Limit::Steps(_))))
+            ));
+            assert!(matches!(
+                interpreter.eval_str(deep).and_then(|_| {
+                    interpreter.set_limits(depth);
+                    interpreter.eval_str("(f 1000)")
+                }),
+                Err(InterpreterError::Eval(EvalError::LimitExceeded(Limit::Depth(_))))
+            ));
+        }
+        assert_eq!(
+            EvalError::LimitExceeded(Limit::Steps(3)).to_string(),
+            "limit exceeded: more than 3 steps"
+        );
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn sync_test() {
+        // An interpreter can be run from another thread, and pmap's calls
+        // write to its ports and count against its limits.
+        let memory = io::MemoryIo::new();
+        let mut interpreter = Interpreter::with_limits(Limits {
+            max_steps: Some(10_000),
+            ..Limits::default()
+        });
+        interpreter.set_io(memory.clone());
+        interpreter
+            .eval_str("(define (square x) (display x) (* x x))")
+            .unwrap();
+        let mut interpreter = std::thread::spawn(move || {
+            let squares = interpreter.eval_str("(pmap square '(1 2 3))").unwrap();
+            assert_eq!(squares.to_string(), "(1 4 9)");
+            interpreter
+        })
+        .join()
+        .unwrap();
+        let mut written: Vec<char> = memory.output().chars().collect();
+        written.sort_unstable();
+        assert_eq!(written, ['1', '2', '3']);
+        assert_eq!(
+            interpreter.eval_str("(define (f) (f)) (pmap (lambda (x) (f)) '(1 2))"),
+            Err(InterpreterError::Eval(EvalError::LimitExceeded(
+                Limit::Steps(10_000)
+            )))
+        );
+        // Each call would fit in the limit on its own, but not all of them
+        // together, and what they use is charged to the caller.
+        interpreter
+            .eval_str("(define (spin n) (if (= n 0) n (spin (- n 1))))")
+            .unwrap();
+        let exceeded = Err(InterpreterError::Eval(EvalError::LimitExceeded(
+            Limit::Steps(10_000),
+        )));
+        assert_eq!(
+            interpreter.eval_str("(pmap (lambda (x) (spin 100)) (range 20))"),
+            exceeded
+        );
+        let spins = "(pmap (lambda (x) (spin 100)) (range 6))";
+        assert!(interpreter.eval_str(spins).is_ok());
+        assert_eq!(interpreter.eval_str(&format!("{spins} {spins}")), exceeded);
+    }
+
+    #[test]
+    fn backend_test() {
+        // Every program runs the same under the VM as under the
+        // tree-walker, including the forms the VM hands back to it.
+        let programs = [
+            "(define (fact n) (if (= n 0) 1 (* n (fact (- n 1))))) (fact 20)",
+            "(define (loop n acc) (if (= n 0) acc (loop (- n 1) (+ acc 1)))) (loop 100000 0)",
+            "(define (make-counter) (let ((n 0)) (lambda () (set! n (+ n 1)) n)))
+             (define c (make-counter)) (c) (c) (list (c) ((make-counter)))",
+            "(define (parity n)
+               (define (ev? n) (if (= n 0) #t (od? (- n 1))))
+               (define (od? n) (if (= n 0) #f (ev? (- n 1))))
+               (list (ev? n) (od? n)))
+             (parity 7)",
+            "(define (f x) (let ((g (lambda () x))) (set! x 10) (g))) (f 1)",
+            "(define x 1) (list (let ((x 2) (y x)) y) (let* ((x 2) (y x)) y))",
+            "(let ((a 1)) (define b (+ a 1)) (begin (define c (* b 10))) (list a b c))",
+            "(list ((lambda (a . rest) (list a rest)) 1 2 3) ((lambda args args)))",
+            "(let ((k 'a)) [k {k (+ 1 2)} '(k)])",
+            "(define g 1) (set! g (+ g 1)) (begin (define h 5) (* g h))",
+            "(define (twice f x) (f (f x))) (twice (lambda (x) (* x x)) 3)",
+            "(define a (atom 1)) (swap! a (lambda (x) (+ x 1))) (deref a)",
+            "(defmacro unless (c body) `(if ,c () ,body)) (unless #f 'yes)",
+            "(try (raise 'oops) (catch e (list 'caught e)))",
+            "(match '(1 2) ((a b) (+ a b)))",
+            "(let ((n 2)) (define g (generator (yield n) (set! n 5) (yield n))) (list (next g) (next g) n (next g 'end)))",
+            "(define (squares l) (generator (define (walk l) (if (pair? l) (begin (yield (* (car l) (car l))) (walk (cdr l))))) (walk l)))
+             (generator->list (squares '(1 2 3)))",
+            "(generator (match 1 (_ (yield 1))))",
+            "(define (classify n) (cond ((< n 0) 'neg) ((= n 0)) ((> n 100) 'big 'huge) (else (list n))))
+             (list (classify -1) (classify 0) (classify 101) (classify 5) (cond (#f 1)))",
+            "(list (and) (or) (and 1 2) (and 1 #f 3) (or #f () 2) (or #f ()) (and () undefined))",
+            "(define (count n acc) (cond ((= n 0) acc) (else (count (- n 1) (+ acc 1))))) (count 100000 0)",
+            "(define (walk n) (and #t (or #f (if (= n 0) 'done (walk (- n 1)))))) (walk 100000)",
+            "(list (when 1 2 3) (when #f 1) (unless #f 4) (unless 1 5) (when (= 1 1)))",
+            "(list (map (lambda (x y) (cons x y)) '(1 2 3) (range 2)) (filter (lambda (x) (> x 1)) (range 4))
+             (fold-right (lambda (x acc) (+ x (* 10 acc))) 0 '(1 2 3)) (apply map list '((1 2) (3 4))))",
+            "(define total (atom 0)) (for-each (lambda (x) (swap! total + x)) (range 1 101)) (deref total)",
+            "(define (div-mod a b) (values (quotient a b) (- a (* b (quotient a b)))))
+             (list (call-with-values (lambda () (div-mod 17 5)) list)
+                   (let-values (((q r) (div-mod 9 4)) ((all) 1)) (list q r all)))",
+            "(define (f) (let-values (((a . b) (values 1 2 3))) (lambda () (cons a b)))) ((f))",
+            "(cond (else))",
+            "(cond (else 1) (#t 2))",
+            "(when)",
+            "(yield 1)",
+            "(if #f #f)",
+            "(car 1)",
+            "((lambda (x) x))",
+            "(undefined-thing)",
+            "(set! nope 1)",
+            "(1 2)",
+            "(let ((x)) x)",
+        ];
+        let outcome = |backend, source| match Interpreter::with_backend(backend).eval_str(source) {
+            Ok(value) => Ok(value.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        for source in programs {
+            assert_eq!(
+                outcome(Backend::Vm, source),
+                outcome(Backend::TreeWalker, source),
+                "{source}"
+            );
+        }
+        let mut interpreter = Interpreter::with_backend(Backend::Vm);
+        assert_eq!(interpreter.backend(), Backend::Vm);
+        assert!(matches!(
+            interpreter.eval_str("(lambda (x) x)"),
+            Ok(Value::Compiled(_))
+        ));
+    }
+
+    // Ready the second time it's polled, like a future waiting on I/O.
+    struct Later {
+        result: Option<Result<Value, EvalError>>,
+        polled: bool,
+    }
+
+    impl Future for Later {
+        type Output = Result<Value, EvalError>;
+
+        fn poll(
+            mut self: core::pin::Pin<&mut Self>,
+            cx: &mut core::task::Context<'_>,
+        ) -> core::task::Poll<Self::Output> {
+            if core::mem::replace(&mut self.polled, true) {
+                return core::task::Poll::Ready(self.result.take().unwrap());
+            }
+            cx.waker().wake_by_ref();
+            core::task::Poll::Pending
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = core::pin::pin!(future);
+        let mut cx = core::task::Context::from_waker(core::task::Waker::noop());
+        loop {
+            if let core::task::Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    #[test]
+    fn async_test() {
+        let mut interpreter = Interpreter::new();
+        let polls = Shared::new(Lock::new(0));
+        let counted = polls.clone();
+        interpreter.register_async_fn("fetch", move |args| {
+            *counted.borrow_mut() += 1;
+            let result = match args.as_slice() {
+                [Value::String(message)] => Err(EvalError::Io(message.clone())),
+                [value] => Ok(value.clone()),
+                _ => Ok(Value::Nil),
+            };
+            Later {
+                result: Some(result),
+                polled: false,
+            }
+        });
+        let run = |interpreter: &mut Interpreter, source| block_on(interpreter.eval_async(source));
+        assert_eq!(
+            run(
+                &mut interpreter,
+                "(define (twice x) (* 2 (fetch x)))
+                 (define (loop n acc) (if (= n 0) acc (loop (- n 1) (+ acc (fetch n)))))
+                 (list (twice 1) (fetch 'a) (loop 3 0))"
+            ),
+            Ok(Value::List(vec![
+                Value::Integer(2),
+                Value::Symbol("a".into()),
+                Value::Integer(6),
+            ]))
+        );
+        assert_eq!(*polls.borrow(), 5);
+        assert_eq!(run(&mut interpreter, "(twice 4)"), Ok(Value::Integer(8)));
+        assert_eq!(
+            run(
+                &mut interpreter,
+                "(define x 1) (fetch \"failed\") (set! x 2)"
+            ),
+            Err(InterpreterError::Eval(EvalError::Io("failed".to_owned())))
+        );
+        assert_eq!(interpreter.get_global("x"), Some(Value::Integer(1)));
+        for source in ["(fetch 1)", "(twice 1)"] {
+            assert_eq!(
+                interpreter.eval_str(source),
+                Err(InterpreterError::Eval(EvalError::CannotAwait(
+                    "fetch".to_owned()
+                )))
+            );
+        }
+        // Nothing can stop inside a builtin's callback.
+        assert!(matches!(
+            run(&mut interpreter, "(swap! (atom 1) fetch)"),
+            Err(InterpreterError::Eval(EvalError::CannotAwait(_)))
+        ));
+
+        interpreter.set_limits(Limits {
+            max_steps: Some(1000),
+            ..Limits::default()
+        });
+        assert!(matches!(
+            run(&mut interpreter, "(loop 100 0)"),
+            Err(InterpreterError::Eval(EvalError::LimitExceeded(
+                Limit::Steps(_)
+            )))
+        ));
+    }
+
+    #[test]
+    fn dispatch_test() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_dispatch('#', '(', |datum| match datum {
+            Expr::List(items) => Ok(Expr::Vector(items)),
+            _ => Err("expected a list".to_owned()),
+        });
+        assert_eq!(
+            interpreter.eval_str("(vector? #(1 2 3))"),
+            Ok(Value::Bool(true))
+        );
+        #[cfg(feature = "std")]
+        {
+            let dir = write_files("tuple-gd-dispatch", &[("v.lisp", "(define v #(1 2))")]);
+            interpreter.eval_file(dir.join("v.lisp")).unwrap();
+            assert_eq!(interpreter.eval_str("(vector? v)"), Ok(Value::Bool(true)));
+        }
+        assert!(Interpreter::new().eval_str("#(1 2 3)").is_err());
+    }
+}
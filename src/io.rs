@@ -0,0 +1,593 @@
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    cell::RefCell,
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+use crate::{
+    eval::EvalError,
+    local::{finally, local},
+    sync::{Lock, MaybeSync, Shared},
+};
+
+// Where a port's input comes from.
+pub trait Input: MaybeSync {
+    // The next line without its line ending, or None once there are no
+    // more.
+    fn read_line(&mut self) -> Result<Option<String>, String>;
+}
+
+// Where a port's output goes.
+pub trait Output: MaybeSync {
+    fn write_str(&mut self, text: &str) -> Result<(), String>;
+}
+
+// Everything the language's I/O builtins do to the outside world goes
+// through one of these, so a host can point it somewhere else or refuse
+// it. Paths are passed on as the program wrote them.
+pub trait IoBackend: MaybeSync {
+    fn stdin(&mut self) -> Box<dyn Input>;
+    fn stdout(&mut self) -> Box<dyn Output>;
+    // Where trace writes. Standard output unless the backend has
+    // somewhere else.
+    fn stderr(&mut self) -> Box<dyn Output> {
+        self.stdout()
+    }
+    fn open_input_file(&mut self, path: &str) -> Result<Box<dyn Input>, String>;
+    fn open_output_file(&mut self, path: &str) -> Result<Box<dyn Output>, String>;
+    // The whole of a file that load or include runs. By default its lines
+    // as open_input_file gives them.
+    fn read_file(&mut self, path: &str) -> Result<String, String> {
+        let mut input = self.open_input_file(path)?;
+        let mut text = String::new();
+        while let Some(line) = input.read_line()? {
+            text.push_str(&line);
+            text.push('\n');
+        }
+        Ok(text)
+    }
+    // One name for a file however a program spells its path, so load can
+    // tell when a file ends up loading itself. The path as given unless
+    // the backend knows better.
+    fn canonical_path(&mut self, path: &str) -> Result<String, String> {
+        Ok(path.to_string())
+    }
+}
+
+// The input a port reads from or the output it writes to, until it is
+// closed. Ports are compared by identity, like atoms.
+#[derive(Clone)]
+pub struct Port(Shared<Lock<PortState>>);
+
+enum PortState {
+    Input(Box<dyn Input>),
+    Output(Box<dyn Output>),
+    // Output kept in memory for output_text, as a string port's is.
+    Collected(MemoryOutput),
+    Closed { input: bool },
+}
+
+impl Port {
+    pub fn input(input: Box<dyn Input>) -> Self {
+        Port(Shared::new(Lock::new(PortState::Input(input))))
+    }
+
+    pub fn output(output: Box<dyn Output>) -> Self {
+        Port(Shared::new(Lock::new(PortState::Output(output))))
+    }
+
+    // Reads the lines of text, as open-input-string does.
+    pub fn input_string(text: impl Into<String>) -> Self {
+        Port::input(Box::new(StringInput::new(text)))
+    }
+
+    // Keeps what is written to it, as open-output-string does.
+    pub fn output_string() -> Self {
+        let output = MemoryOutput {
+            files: Shared::default(),
+            path: None,
+            output: Shared::default(),
+        };
+        Port(Shared::new(Lock::new(PortState::Collected(output))))
+    }
+
+    // Everything written so far to a port from output_string that is still
+    // open.
+    pub fn output_text(&self) -> Option<String> {
+        match &*self.0.borrow() {
+            PortState::Collected(collected) => Some(collected.output.borrow().clone()),
+            _ => None,
+        }
+    }
+
+    pub fn is_input(&self) -> bool {
+        matches!(
+            *self.0.borrow(),
+            PortState::Input(_) | PortState::Closed { input: true }
+        )
+    }
+
+    pub fn is_closed(&self) -> bool {
+        matches!(*self.0.borrow(), PortState::Closed { .. })
+    }
+
+    pub fn close(&self) {
+        let input = self.is_input();
+        *self.0.borrow_mut() = PortState::Closed { input };
+    }
+
+    pub fn read_line(&self) -> Result<Option<String>, EvalError> {
+        match &mut *self.0.borrow_mut() {
+            PortState::Input(input) => input.read_line().map_err(EvalError::Io),
+            PortState::Closed { input: true } => Err(closed()),
+            _ => Err(EvalError::Io("not an input port".to_string())),
+        }
+    }
+
+    pub fn write_str(&self, text: &str) -> Result<(), EvalError> {
+        match &mut *self.0.borrow_mut() {
+            PortState::Output(output) => output.write_str(text).map_err(EvalError::Io),
+            PortState::Collected(collected) => collected.write_str(text).map_err(EvalError::Io),
+            PortState::Closed { input: false } => Err(closed()),
+            _ => Err(EvalError::Io("not an output port".to_string())),
+        }
+    }
+}
+
+fn closed() -> EvalError {
+    EvalError::Io("port is closed".to_string())
+}
+
+impl PartialEq for Port {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Hash for Port {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Shared::as_ptr(&self.0).hash(state);
+    }
+}
+
+impl fmt::Debug for Port {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Port")
+            .field("input", &self.is_input())
+            .field("closed", &self.is_closed())
+            .finish()
+    }
+}
+
+// Which files a program may open, load or include, whatever the backend
+// would let it. Standard input and output are always there, and there are
+// no builtins that reach the network, run processes or read the
+// environment, so files are all there is to allow. By default everything
+// is allowed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SandboxPolicy {
+    pub fs: bool,
+    // If there are any, only paths inside one of these are allowed. Paths
+    // are compared as written, with `.` and `..` worked out, so a backend
+    // on a real file system shouldn't have links out of them.
+    pub allowed_paths: Vec<String>,
+}
+
+impl Default for SandboxPolicy {
+    fn default() -> Self {
+        SandboxPolicy {
+            fs: true,
+            allowed_paths: Vec::new(),
+        }
+    }
+}
+
+impl SandboxPolicy {
+    // No files at all.
+    pub fn deny_all() -> Self {
+        SandboxPolicy {
+            fs: false,
+            allowed_paths: Vec::new(),
+        }
+    }
+
+    pub fn allows(&self, path: &str) -> bool {
+        if !self.fs {
+            return false;
+        }
+        if self.allowed_paths.is_empty() {
+            return true;
+        }
+        let Some(path) = path_parts(path) else {
+            return false;
+        };
+        self.allowed_paths.iter().any(|dir| {
+            path_parts(dir).is_some_and(|dir| dir.0 == path.0 && path.1.starts_with(&dir.1))
+        })
+    }
+}
+
+// Whether path is absolute, and its parts with `.` and `..` worked out, or
+// None if a `..` would go above where it starts.
+fn path_parts(path: &str) -> Option<(bool, Vec<&str>)> {
+    let mut parts = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            part => parts.push(part),
+        }
+    }
+    Some((path.starts_with('/'), parts))
+}
+
+// An interpreter's backend, the ports its programs read from and write to
+// by default, and the files it lets them at.
+pub(crate) struct Io {
+    backend: Box<dyn IoBackend>,
+    input: Port,
+    output: Port,
+    error: Port,
+    pub(crate) policy: SandboxPolicy,
+}
+
+impl Io {
+    pub(crate) fn new(mut backend: Box<dyn IoBackend>) -> Self {
+        Io {
+            input: Port::input(backend.stdin()),
+            output: Port::output(backend.stdout()),
+            error: Port::output(backend.stderr()),
+            backend,
+            policy: SandboxPolicy::default(),
+        }
+    }
+}
+
+local! {
+    static CURRENT: RefCell<Option<Shared<Lock<Io>>>> = const { RefCell::new(None) };
+}
+
+// Runs f with the I/O builtins going through io, then puts back whatever
+// was installed before.
+pub(crate) fn with_io<T>(io: Shared<Lock<Io>>, f: impl FnOnce() -> T) -> T {
+    let saved = CURRENT.with(|current| current.borrow_mut().replace(io));
+    finally(f, || CURRENT.with(|current| *current.borrow_mut() = saved))
+}
+
+// Code run outside an interpreter, as by eval on a global_env, gets the
+// default backend.
+pub(crate) fn current() -> Shared<Lock<Io>> {
+    CURRENT.with(|current| {
+        current
+            .borrow_mut()
+            .get_or_insert_with(|| Shared::new(Lock::new(Io::new(default_backend()))))
+            .clone()
+    })
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn default_backend() -> Box<dyn IoBackend> {
+    Box::new(StdIo)
+}
+
+// Without std there is nothing else to talk to.
+#[cfg(not(feature = "std"))]
+pub(crate) fn default_backend() -> Box<dyn IoBackend> {
+    Box::new(MemoryIo::new())
+}
+
+pub(crate) fn current_input() -> Port {
+    current().borrow().input.clone()
+}
+
+pub(crate) fn current_output() -> Port {
+    current().borrow().output.clone()
+}
+
+pub(crate) fn current_error() -> Port {
+    current().borrow().error.clone()
+}
+
+fn allowed(path: &str) -> Result<(), String> {
+    match current().borrow().policy.allows(path) {
+        true => Ok(()),
+        false => Err("not allowed by the sandbox".to_string()),
+    }
+}
+
+pub(crate) fn open_input_file(path: &str) -> Result<Port, EvalError> {
+    let input = allowed(path).and_then(|()| current().borrow_mut().backend.open_input_file(path));
+    input
+        .map(Port::input)
+        .map_err(|reason| EvalError::Io(format!("cannot open `{path}`: {reason}")))
+}
+
+pub(crate) fn open_output_file(path: &str) -> Result<Port, EvalError> {
+    let output = allowed(path).and_then(|()| current().borrow_mut().backend.open_output_file(path));
+    output
+        .map(Port::output)
+        .map_err(|reason| EvalError::Io(format!("cannot open `{path}`: {reason}")))
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn read_file(path: &str) -> Result<String, String> {
+    current().borrow_mut().backend.read_file(path)
+}
+
+// load asks for this first, so it is where the sandbox is consulted.
+#[cfg(feature = "std")]
+pub(crate) fn canonical_path(path: &str) -> Result<String, String> {
+    allowed(path)?;
+    current().borrow_mut().backend.canonical_path(path)
+}
+
+// Lines read out of text held in memory.
+pub struct StringInput {
+    text: String,
+    position: usize,
+}
+
+impl StringInput {
+    pub fn new(text: impl Into<String>) -> Self {
+        StringInput {
+            text: text.into(),
+            position: 0,
+        }
+    }
+}
+
+impl Input for StringInput {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        let rest = &self.text[self.position..];
+        if rest.is_empty() {
+            return Ok(None);
+        }
+        let (line, len) = match rest.find('\n') {
+            Some(end) => (&rest[..end], end + 1),
+            None => (rest, rest.len()),
+        };
+        self.position += len;
+        Ok(Some(line.strip_suffix('\r').unwrap_or(line).to_string()))
+    }
+}
+
+// The process's standard input and output and the file system.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StdIo;
+
+#[cfg(feature = "std")]
+struct Stdin;
+
+#[cfg(feature = "std")]
+impl Input for Stdin {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        let mut line = String::new();
+        match std::io::stdin().read_line(&mut line) {
+            Ok(0) => Ok(None),
+            Ok(_) => {
+                let line = line.strip_suffix('\n').unwrap_or(&line);
+                Ok(Some(line.strip_suffix('\r').unwrap_or(line).to_string()))
+            }
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+struct Writer<W>(W);
+
+// Written through at once, so what a program prints shows up in order
+// with what it reads.
+#[cfg(feature = "std")]
+impl<W: std::io::Write + MaybeSync> Output for Writer<W> {
+    fn write_str(&mut self, text: &str) -> Result<(), String> {
+        self.0
+            .write_all(text.as_bytes())
+            .and_then(|()| self.0.flush())
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(feature = "std")]
+impl IoBackend for StdIo {
+    fn stdin(&mut self) -> Box<dyn Input> {
+        Box::new(Stdin)
+    }
+
+    fn stdout(&mut self) -> Box<dyn Output> {
+        Box::new(Writer(std::io::stdout()))
+    }
+
+    fn stderr(&mut self) -> Box<dyn Output> {
+        Box::new(Writer(std::io::stderr()))
+    }
+
+    fn open_input_file(&mut self, path: &str) -> Result<Box<dyn Input>, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Ok(Box::new(StringInput::new(text)))
+    }
+
+    fn open_output_file(&mut self, path: &str) -> Result<Box<dyn Output>, String> {
+        let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+        Ok(Box::new(Writer(file)))
+    }
+
+    fn read_file(&mut self, path: &str) -> Result<String, String> {
+        std::fs::read_to_string(path).map_err(|e| e.to_string())
+    }
+
+    fn canonical_path(&mut self, path: &str) -> Result<String, String> {
+        let path = std::fs::canonicalize(path).map_err(|e| e.to_string())?;
+        Ok(path.to_string_lossy().into_owned())
+    }
+}
+
+// A sandbox: files are strings in a map, standard input is a string given
+// up front and standard output is collected. Clones share their state, so
+// a host can keep one to look at what a program wrote.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryIo {
+    files: Shared<Lock<BTreeMap<String, String>>>,
+    input: Shared<Lock<String>>,
+    output: Shared<Lock<String>>,
+}
+
+impl MemoryIo {
+    pub fn new() -> Self {
+        MemoryIo::default()
+    }
+
+    // What standard input will read. Set it before the interpreter is
+    // given the backend, which is when standard input is opened.
+    pub fn set_input(&self, text: impl Into<String>) {
+        *self.input.borrow_mut() = text.into();
+    }
+
+    pub fn add_file(&self, path: impl Into<String>, contents: impl Into<String>) {
+        self.files.borrow_mut().insert(path.into(), contents.into());
+    }
+
+    pub fn file(&self, path: &str) -> Option<String> {
+        self.files.borrow().get(path).cloned()
+    }
+
+    pub fn output(&self) -> String {
+        self.output.borrow().clone()
+    }
+}
+
+struct MemoryOutput {
+    files: Shared<Lock<BTreeMap<String, String>>>,
+    path: Option<String>,
+    output: Shared<Lock<String>>,
+}
+
+impl Output for MemoryOutput {
+    fn write_str(&mut self, text: &str) -> Result<(), String> {
+        match &self.path {
+            Some(path) => match self.files.borrow_mut().get_mut(path) {
+                Some(file) => file.push_str(text),
+                None => return Err("file was removed".to_string()),
+            },
+            None => self.output.borrow_mut().push_str(text),
+        }
+        Ok(())
+    }
+}
+
+impl IoBackend for MemoryIo {
+    fn stdin(&mut self) -> Box<dyn Input> {
+        Box::new(StringInput::new(self.input.borrow().clone()))
+    }
+
+    fn stdout(&mut self) -> Box<dyn Output> {
+        Box::new(MemoryOutput {
+            files: self.files.clone(),
+            path: None,
+            output: self.output.clone(),
+        })
+    }
+
+    fn open_input_file(&mut self, path: &str) -> Result<Box<dyn Input>, String> {
+        match self.file(path) {
+            Some(text) => Ok(Box::new(StringInput::new(text))),
+            None => Err("no such file".to_string()),
+        }
+    }
+
+    // Creates the file, or empties it if it is already there.
+    fn open_output_file(&mut self, path: &str) -> Result<Box<dyn Output>, String> {
+        self.add_file(path, "");
+        Ok(Box::new(MemoryOutput {
+            files: self.files.clone(),
+            path: Some(path.to_string()),
+            output: self.output.clone(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::{borrow::ToOwned, vec};
+
+    #[test]
+    fn string_input_test() {
+        let mut input = StringInput::new("one\r\n\ntwo");
+        assert_eq!(input.read_line(), Ok(Some("one".to_owned())));
+        assert_eq!(input.read_line(), Ok(Some(String::new())));
+        assert_eq!(input.read_line(), Ok(Some("two".to_owned())));
+        assert_eq!(input.read_line(), Ok(None));
+    }
+
+    #[test]
+    fn sandbox_policy_test() {
+        let policy = SandboxPolicy {
+            allowed_paths: vec!["scripts".to_owned(), "/data/./shared/".to_owned()],
+            ..SandboxPolicy::default()
+        };
+        for path in ["scripts/a.scm", "./scripts/lib/../b.scm", "/data/shared/c"] {
+            assert!(policy.allows(path), "{path}");
+        }
+        for path in [
+            "a.scm",
+            "scripts/../a.scm",
+            "../scripts/a.scm",
+            "/scripts/a.scm",
+            "/data/x",
+        ] {
+            assert!(!policy.allows(path), "{path}");
+        }
+        assert!(SandboxPolicy::default().allows("../anything"));
+        assert!(!SandboxPolicy::deny_all().allows("scripts/a.scm"));
+    }
+
+    #[test]
+    fn port_test() {
+        let io = MemoryIo::new();
+        let mut backend = io.clone();
+        let output = Port::output(backend.open_output_file("out.txt").unwrap());
+        output.write_str("a").unwrap();
+        output.write_str("b\n").unwrap();
+        assert_eq!(io.file("out.txt").as_deref(), Some("ab\n"));
+        assert!(output.read_line().is_err());
+        output.close();
+        assert!(output.is_closed() && !output.is_input());
+        assert_eq!(
+            output.write_str("c"),
+            Err(EvalError::Io("port is closed".to_owned()))
+        );
+
+        let input = Port::input(backend.open_input_file("out.txt").unwrap());
+        assert!(input.is_input());
+        assert_eq!(input.read_line(), Ok(Some("ab".to_owned())));
+        assert_eq!(input.read_line(), Ok(None));
+        assert!(input.write_str("x").is_err());
+        assert!(backend.open_input_file("missing.txt").is_err());
+        assert_eq!(input, input.clone());
+        assert_ne!(input, Port::input(Box::new(StringInput::new(""))));
+        assert_eq!(input.output_text(), None);
+
+        let collected = Port::output_string();
+        assert_eq!(collected.output_text().as_deref(), Some(""));
+        collected.write_str("one ").unwrap();
+        collected.write_str("two").unwrap();
+        assert!(!collected.is_input());
+        assert_eq!(collected.output_text().as_deref(), Some("one two"));
+        assert!(io.output().is_empty());
+        collected.close();
+        assert_eq!(collected.output_text(), None);
+        let read = Port::input_string("three\nfour");
+        assert_eq!(read.read_line(), Ok(Some("three".to_owned())));
+    }
+}
@@ -0,0 +1,361 @@
+#[cfg(feature = "bigint")]
+use alloc::boxed::Box;
+use alloc::{string::String, vec};
+use core::fmt::{self, Write};
+
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take},
+    character::complete::{char, multispace0},
+    combinator::{all_consuming, map, map_opt, map_res, value},
+    multi::separated_list0,
+    number::complete::recognize_float,
+    sequence::{delimited, preceded, separated_pair},
+    Err, IResult,
+};
+
+use crate::{parser::Expr, rational::Rational};
+
+// Expressions as JSON, for configuration files and APIs that speak JSON
+// rather than s-expressions. Lists are arrays and strings are strings, so
+// symbols are written as {"symbol": name}, keywords as {"keyword": name}
+// and characters as {"char": c} to tell them apart. An improper list is
+// {"pair": [car, cdr]}, a vector {"vector": [items]}, a map
+// {"map": [[key, value], ...]} and a rational {"rational": [n, d]}.
+// Floats that JSON can't express, NaN and the infinities, are written as
+// null.
+pub fn expr_to_json(expr: &Expr) -> String {
+    let mut out = String::new();
+    write_json(&mut out, expr);
+    out
+}
+
+fn write_json(out: &mut String, expr: &Expr) {
+    match expr {
+        Expr::Nil => out.push_str("null"),
+        Expr::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Expr::Integer(i) => write!(out, "{i}").unwrap(),
+        #[cfg(feature = "bigint")]
+        Expr::BigInteger(i) => write!(out, "{i}").unwrap(),
+        Expr::Rational(r) => write!(
+            out,
+            "{{\"rational\":[{},{}]}}",
+            r.numerator(),
+            r.denominator()
+        )
+        .unwrap(),
+        Expr::Float(x) if x.is_finite() => write!(out, "{x:?}").unwrap(),
+        Expr::Float(_) => out.push_str("null"),
+        Expr::Keyword(k) => {
+            out.push_str("{\"keyword\":");
+            write_json_string(out, k.as_str());
+            out.push('}');
+        }
+        Expr::Char(c) => {
+            out.push_str("{\"char\":");
+            write_json_string(out, c.encode_utf8(&mut [0; 4]));
+            out.push('}');
+        }
+        Expr::String(s) => write_json_string(out, s),
+        Expr::Symbol(s) => {
+            out.push_str("{\"symbol\":");
+            write_json_string(out, s.as_str());
+            out.push('}');
+        }
+        Expr::Lambda(lambda) => write_json(out, &lambda.to_expr()),
+        Expr::Pair(car, cdr) => {
+            out.push_str("{\"pair\":[");
+            write_json(out, car);
+            out.push(',');
+            write_json(out, cdr);
+            out.push_str("]}");
+        }
+        Expr::List(items) => write_json_array(out, items),
+        Expr::Vector(items) => {
+            out.push_str("{\"vector\":");
+            write_json_array(out, items);
+            out.push('}');
+        }
+        Expr::Map(entries) => {
+            out.push_str("{\"map\":[");
+            for (i, (key, value)) in entries.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push('[');
+                write_json(out, key);
+                out.push(',');
+                write_json(out, value);
+                out.push(']');
+            }
+            out.push_str("]}");
+        }
+    }
+}
+
+fn write_json_array(out: &mut String, items: &[Expr]) {
+    out.push('[');
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_json(out, item);
+    }
+    out.push(']');
+}
+
+pub(crate) fn write_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct JsonError {
+    pub offset: usize,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid JSON at byte {}", self.offset)
+    }
+}
+
+impl core::error::Error for JsonError {}
+
+// The reverse of expr_to_json. Any other object becomes a list of
+// (key value) pairs with string keys, in the order they were written.
+// Numbers without a fraction or exponent that fit in an i64 are integers,
+// and so are larger ones with the bigint feature.
+pub fn json_to_expr(input: &str) -> Result<Expr, JsonError> {
+    match all_consuming(delimited(multispace0, json_value, multispace0))(input) {
+        Ok((_, expr)) => Ok(expr),
+        Err(Err::Error(e) | Err::Failure(e)) => Err(JsonError {
+            offset: input.len() - e.input.len(),
+        }),
+        Err(Err::Incomplete(_)) => Err(JsonError {
+            offset: input.len(),
+        }),
+    }
+}
+
+fn json_value(input: &str) -> IResult<&str, Expr> {
+    preceded(
+        multispace0,
+        alt((
+            value(Expr::Nil, tag("null")),
+            value(Expr::Bool(true), tag("true")),
+            value(Expr::Bool(false), tag("false")),
+            json_number,
+            map(json_string, Expr::String),
+            json_array,
+            json_object,
+        )),
+    )(input)
+}
+
+fn json_number(input: &str) -> IResult<&str, Expr> {
+    map_res(recognize_float, |s: &str| {
+        if let Ok(i) = s.parse() {
+            return Ok(Expr::Integer(i));
+        }
+        #[cfg(feature = "bigint")]
+        if let Ok(i) = s.parse() {
+            return Ok(Expr::BigInteger(Box::new(i)));
+        }
+        s.parse().map(Expr::Float)
+    })(input)
+}
+
+fn json_string(input: &str) -> IResult<&str, String> {
+    let (mut rest, _) = char('"')(input)?;
+    let mut string = String::new();
+    loop {
+        let mut chars = rest.chars();
+        match chars.next() {
+            Some('"') => return Ok((chars.as_str(), string)),
+            Some('\\') => {
+                let (after, c) = json_escape(chars.as_str())?;
+                string.push(c);
+                rest = after;
+            }
+            Some(c) if !c.is_control() => {
+                string.push(c);
+                rest = chars.as_str();
+            }
+            _ => {
+                return Err(Err::Error(nom::error::Error::new(
+                    rest,
+                    nom::error::ErrorKind::Char,
+                )))
+            }
+        }
+    }
+}
+
+// Input starts just after the backslash. Characters outside the basic
+// plane are written as a pair of \u escapes.
+fn json_escape(input: &str) -> IResult<&str, char> {
+    alt((
+        value('"', char('"')),
+        value('\\', char('\\')),
+        value('/', char('/')),
+        value('\u{8}', char('b')),
+        value('\u{c}', char('f')),
+        value('\n', char('n')),
+        value('\r', char('r')),
+        value('\t', char('t')),
+        map_opt(
+            separated_pair(hex4, char('\\'), hex4),
+            |(high, low)| match (high, low) {
+                (0xd800..=0xdbff, 0xdc00..=0xdfff) => {
+                    char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00))
+                }
+                _ => None,
+            },
+        ),
+        map_opt(hex4, char::from_u32),
+    ))(input)
+}
+
+fn hex4(input: &str) -> IResult<&str, u32> {
+    preceded(
+        char('u'),
+        map_res(take(4usize), |hex| u32::from_str_radix(hex, 16)),
+    )(input)
+}
+
+fn json_array(input: &str) -> IResult<&str, Expr> {
+    map(
+        delimited(
+            char('['),
+            separated_list0(char(','), json_value),
+            preceded(multispace0, char(']')),
+        ),
+        Expr::List,
+    )(input)
+}
+
+fn json_object(input: &str) -> IResult<&str, Expr> {
+    let member = separated_pair(
+        preceded(multispace0, json_string),
+        preceded(multispace0, char(':')),
+        json_value,
+    );
+    map(
+        delimited(
+            char('{'),
+            separated_list0(char(','), member),
+            preceded(multispace0, char('}')),
+        ),
+        |members| match members.as_slice() {
+            [(key, Expr::String(name))] if key == "symbol" => Expr::Symbol(name.as_str().into()),
+            [(key, Expr::String(name))] if key == "keyword" => Expr::Keyword(name.as_str().into()),
+            [(key, Expr::List(pair))] if key == "pair" && pair.len() == 2 => {
+                Expr::cons(pair[0].clone(), pair[1].clone())
+            }
+            [(key, Expr::String(c))] if key == "char" && c.chars().count() == 1 => {
+                Expr::Char(c.chars().next().unwrap())
+            }
+            [(key, Expr::List(items))] if key == "vector" => Expr::Vector(items.clone()),
+            [(key, Expr::List(parts))] if key == "rational" && json_rational(parts).is_some() => {
+                json_rational(parts).unwrap()
+            }
+            [(key, Expr::List(entries))]
+                if key == "map"
+                    && entries
+                        .iter()
+                        .all(|entry| matches!(entry, Expr::List(kv) if kv.len() == 2)) =>
+            {
+                Expr::Map(
+                    entries
+                        .iter()
+                        .map(|entry| match entry {
+                            Expr::List(kv) => (kv[0].clone(), kv[1].clone()),
+                            _ => unreachable!(),
+                        })
+                        .collect(),
+                )
+            }
+            _ => Expr::List(
+                members
+                    .into_iter()
+                    .map(|(key, value)| Expr::List(vec![Expr::String(key), value]))
+                    .collect(),
+            ),
+        },
+    )(input)
+}
+
+fn json_rational(parts: &[Expr]) -> Option<Expr> {
+    let [Expr::Integer(n), Expr::Integer(d)] = parts else {
+        return None;
+    };
+    let r = Rational::new(*n, *d)?;
+    Some(r.to_integer().map_or(Expr::Rational(r), Expr::Integer))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::read;
+
+    #[test]
+    fn expr_to_json_test() {
+        let expr = read("(define (f x) (g x \"a\\\"b\\n\" 1.5 -2 #t))").unwrap();
+        assert_eq!(
+            expr_to_json(&expr),
+            r#"[{"symbol":"define"},[{"symbol":"f"},{"symbol":"x"}],[{"symbol":"g"},{"symbol":"x"},"a\"b\n",1.5,-2,true]]"#
+        );
+        assert_eq!(expr_to_json(&Expr::Float(f64::NAN)), "null");
+        assert_eq!(json_to_expr(&expr_to_json(&expr)), Ok(expr));
+        let expr = read("-1/3").unwrap();
+        assert_eq!(expr_to_json(&expr), r#"{"rational":[-1,3]}"#);
+        assert_eq!(json_to_expr(&expr_to_json(&expr)), Ok(expr));
+        let expr = read("[1 {:a [] 2 x}]").unwrap();
+        assert_eq!(
+            expr_to_json(&expr),
+            r#"{"vector":[1,{"map":[[{"keyword":"a"},{"vector":[]}],[2,{"symbol":"x"}]]}]}"#
+        );
+        assert_eq!(json_to_expr(&expr_to_json(&expr)), Ok(expr));
+    }
+
+    #[test]
+    fn json_to_expr_test() {
+        assert_eq!(
+            json_to_expr(r#" {"name": "demo", "ports": [80, 443.5e0], "debug": null} "#),
+            Ok(Expr::List(vec![
+                Expr::List(vec![
+                    Expr::String("name".into()),
+                    Expr::String("demo".into())
+                ]),
+                Expr::List(vec![
+                    Expr::String("ports".into()),
+                    Expr::List(vec![Expr::Integer(80), Expr::Float(443.5)]),
+                ]),
+                Expr::List(vec![Expr::String("debug".into()), Expr::Nil]),
+            ]))
+        );
+        assert_eq!(
+            json_to_expr(r#""\u00e9\ud83d\ude00\/""#),
+            Ok(Expr::String("é😀/".into()))
+        );
+        assert_eq!(json_to_expr("[1, 2"), Err(JsonError { offset: 0 }));
+        assert_eq!(json_to_expr("[1] x"), Err(JsonError { offset: 4 }));
+        #[cfg(feature = "bigint")]
+        assert_eq!(
+            json_to_expr("123456789012345678901234567890").map(|expr| expr.to_string()),
+            Ok("123456789012345678901234567890".to_owned())
+        );
+    }
+}
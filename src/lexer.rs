@@ -1,11 +1,20 @@
+use alloc::{borrow::Cow, string::String, vec::Vec};
+use core::fmt;
+
+#[cfg(feature = "bigint")]
+use crate::bigint::BigInt;
+use crate::rational::Rational;
+use crate::reader::dispatches;
+
 use nom::{
     branch::alt,
     bytes::complete::{tag, take_while1},
-    character::complete::{self, alphanumeric1, char, i64, multispace0},
-    combinator::{all_consuming, value},
-    error::{Error, ErrorKind},
-    multi::{many0, many1},
-    sequence::{preceded, terminated},
+    character::complete::{char, digit1, multispace0, one_of, satisfy},
+    combinator::{all_consuming, not, opt, peek, recognize, value, verify},
+    error::{ErrorKind, ParseError},
+    multi::many0,
+    number::complete::recognize_float,
+    sequence::{pair, preceded, separated_pair, terminated},
     Err, IResult,
 };
 
@@ -17,53 +26,559 @@ pub fn tag_add(i: &str) -> IResult<&str, &str> {
     tag("add")(i)
 }
 
+// Byte offsets into the source, end exclusive.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+
+    // 1-based line and column of the start of the span.
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+        (line, column)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Spanned<T> {
+    pub value: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(value: T, span: Span) -> Self {
+        Spanned { value, span }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LexErrorKind {
+    UnexpectedCharacter,
+    UnterminatedString,
+    // A |...| symbol with no closing bar.
+    UnterminatedSymbol,
+    UnterminatedComment,
+    InvalidEscape,
+    UnknownCharacter,
+    // An integer literal too large for an i64, without the bigint feature.
+    IntegerOverflow,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct LexError<'a> {
+    pub input: &'a str,
+    pub kind: LexErrorKind,
+}
+
+impl<'a> LexError<'a> {
+    fn new(input: &'a str, kind: LexErrorKind) -> Self {
+        LexError { input, kind }
+    }
+}
+
+impl<'a> ParseError<&'a str> for LexError<'a> {
+    fn from_error_kind(input: &'a str, _: ErrorKind) -> Self {
+        LexError::new(input, LexErrorKind::UnexpectedCharacter)
+    }
+
+    fn append(_: &'a str, _: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+type LexResult<'a, T> = IResult<&'a str, T, LexError<'a>>;
+
 #[derive(Clone, PartialEq, Debug)]
-pub enum Token {
+pub enum Token<'a> {
     LParan,
     RParan,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Dot,
+    Quote,
+    Quasiquote,
+    Unquote,
+    UnquoteSplicing,
+    DatumComment,
+    // A prefix from the read table: its lead character and the one after.
+    Dispatch(char, char),
+    Bool(bool),
     Integer(i64),
-    // Float(f64),
-    Symbol(String),
+    #[cfg(feature = "bigint")]
+    BigInteger(BigInt),
+    Rational(Rational),
+    Float(f64),
+    Char(char),
+    String(Cow<'a, str>),
+    Symbol(Cow<'a, str>),
+    Keyword(Cow<'a, str>),
 }
 
-fn lex_lparan(input: &str) -> IResult<&str, Token> {
+impl Token<'_> {
+    // Detaches the token from the input it was lexed from.
+    pub fn into_owned(self) -> Token<'static> {
+        match self {
+            Token::LParan => Token::LParan,
+            Token::RParan => Token::RParan,
+            Token::LBracket => Token::LBracket,
+            Token::RBracket => Token::RBracket,
+            Token::LBrace => Token::LBrace,
+            Token::RBrace => Token::RBrace,
+            Token::Dot => Token::Dot,
+            Token::Quote => Token::Quote,
+            Token::Quasiquote => Token::Quasiquote,
+            Token::Unquote => Token::Unquote,
+            Token::UnquoteSplicing => Token::UnquoteSplicing,
+            Token::DatumComment => Token::DatumComment,
+            Token::Dispatch(lead, sub) => Token::Dispatch(lead, sub),
+            Token::Bool(b) => Token::Bool(b),
+            Token::Integer(i) => Token::Integer(i),
+            #[cfg(feature = "bigint")]
+            Token::BigInteger(i) => Token::BigInteger(i),
+            Token::Rational(r) => Token::Rational(r),
+            Token::Float(f) => Token::Float(f),
+            Token::Char(c) => Token::Char(c),
+            Token::String(s) => Token::String(Cow::Owned(s.into_owned())),
+            Token::Symbol(s) => Token::Symbol(Cow::Owned(s.into_owned())),
+            Token::Keyword(s) => Token::Keyword(Cow::Owned(s.into_owned())),
+        }
+    }
+}
+
+impl fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::LParan => f.write_str("`(`"),
+            Token::RParan => f.write_str("`)`"),
+            Token::LBracket => f.write_str("`[`"),
+            Token::RBracket => f.write_str("`]`"),
+            Token::LBrace => f.write_str("`{`"),
+            Token::RBrace => f.write_str("`}`"),
+            Token::Dot => f.write_str("`.`"),
+            Token::Quote => f.write_str("`'`"),
+            Token::Quasiquote => f.write_str("`` ` ``"),
+            Token::Unquote => f.write_str("`,`"),
+            Token::UnquoteSplicing => f.write_str("`,@`"),
+            Token::DatumComment => f.write_str("`#;`"),
+            Token::Dispatch(lead, sub) if is_symbol_char(*sub) => write!(f, "`{lead}{sub}`"),
+            Token::Dispatch(lead, _) => write!(f, "`{lead}`"),
+            Token::Bool(true) => f.write_str("`#t`"),
+            Token::Bool(false) => f.write_str("`#f`"),
+            Token::Integer(i) => write!(f, "`{i}`"),
+            #[cfg(feature = "bigint")]
+            Token::BigInteger(i) => write!(f, "`{i}`"),
+            Token::Rational(r) => write!(f, "`{r}`"),
+            Token::Float(x) => write!(f, "`{x:?}`"),
+            Token::Char(c) => write!(f, "`{c:?}`"),
+            Token::String(s) => write!(f, "`{s:?}`"),
+            Token::Symbol(s) => write!(f, "`{s}`"),
+            Token::Keyword(s) => write!(f, "`:{s}`"),
+        }
+    }
+}
+
+// Whitespace, ; line comments and nestable #| |# block comments.
+pub(crate) fn skip_trivia(input: &str) -> LexResult<'_, ()> {
+    let (mut rest, _) = multispace0(input)?;
+    loop {
+        if let Some(comment) = rest.strip_prefix(';') {
+            rest = comment.find('\n').map_or("", |i| &comment[i..]);
+        } else if rest.starts_with("#|") {
+            rest = skip_block_comment(rest)?;
+        } else {
+            return Ok((rest, ()));
+        }
+        (rest, _) = multispace0(rest)?;
+    }
+}
+
+pub(crate) fn skip_block_comment(input: &str) -> Result<&str, Err<LexError<'_>>> {
+    let mut depth = 0;
+    let mut rest = input;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("#|") {
+            depth += 1;
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("|#") {
+            depth -= 1;
+            rest = after;
+            if depth == 0 {
+                return Ok(rest);
+            }
+        } else {
+            let mut chars = rest.chars();
+            chars.next();
+            rest = chars.as_str();
+        }
+    }
+    Err(Err::Failure(LexError::new(
+        input,
+        LexErrorKind::UnterminatedComment,
+    )))
+}
+
+// #; comments out the next datum; the parser does the skipping since only
+// it knows where that datum ends.
+fn lex_datum_comment(input: &str) -> LexResult<'_, Token<'_>> {
+    value(Token::DatumComment, preceded(multispace0, tag("#;")))(input)
+}
+
+fn lex_lparan(input: &str) -> LexResult<'_, Token<'_>> {
     value(Token::LParan {}, preceded(multispace0, char('(')))(input)
 }
 
-fn lex_rparan(input: &str) -> IResult<&str, Token> {
+fn lex_rparan(input: &str) -> LexResult<'_, Token<'_>> {
     value(Token::RParan {}, preceded(multispace0, char(')')))(input)
 }
 
-fn lex_integer(input: &str) -> IResult<&str, Token> {
-    let (rem, int) = preceded(multispace0, i64)(input)?;
-    Ok((rem, Token::Integer(int)))
+// [ ] delimit vectors and { } maps.
+fn lex_bracket(input: &str) -> LexResult<'_, Token<'_>> {
+    preceded(
+        multispace0,
+        alt((
+            value(Token::LBracket, char('[')),
+            value(Token::RBracket, char(']')),
+            value(Token::LBrace, char('{')),
+            value(Token::RBrace, char('}')),
+        )),
+    )(input)
+}
+
+// A dot on its own; .5 is a float and ... a symbol.
+fn lex_dot(input: &str) -> LexResult<'_, Token<'_>> {
+    value(
+        Token::Dot,
+        preceded(
+            multispace0,
+            terminated(char('.'), not(peek(satisfy(is_symbol_char)))),
+        ),
+    )(input)
+}
+
+fn lex_quote(input: &str) -> LexResult<'_, Token<'_>> {
+    preceded(
+        multispace0,
+        alt((
+            value(Token::Quote, char('\'')),
+            value(Token::Quasiquote, char('`')),
+            value(Token::UnquoteSplicing, tag(",@")),
+            value(Token::Unquote, char(',')),
+        )),
+    )(input)
+}
+
+// Only what the read table installed by the reader has a handler for.
+fn lex_dispatch(input: &str) -> LexResult<'_, Token<'_>> {
+    let (rest, _) = multispace0(input)?;
+    let mut chars = rest.chars();
+    match (chars.next(), chars.next()) {
+        (Some(lead), Some(sub)) if dispatches(lead, sub) => {
+            let len = match is_symbol_char(sub) {
+                true => lead.len_utf8() + sub.len_utf8(),
+                false => lead.len_utf8(),
+            };
+            Ok((&rest[len..], Token::Dispatch(lead, sub)))
+        }
+        _ => Err(Err::Error(LexError::new(
+            rest,
+            LexErrorKind::UnexpectedCharacter,
+        ))),
+    }
+}
+
+fn lex_bool(input: &str) -> LexResult<'_, Token<'_>> {
+    let (rem, b) = preceded(
+        multispace0,
+        terminated(
+            alt((
+                value(true, tag("#true")),
+                value(false, tag("#false")),
+                value(true, tag("#t")),
+                value(false, tag("#f")),
+            )),
+            not(peek(satisfy(is_symbol_char))),
+        ),
+    )(input)?;
+    Ok((rem, Token::Bool(b)))
+}
+
+// A number is an optional sign and digits, and has to end where the token
+// does: -42 and +3 are numbers, while - and + on their own, -x and 1st are
+// symbols. So (- 1) is a call and (-1) is a list of one number. With the
+// bigint feature a literal too large for an i64 is a BigInteger; without
+// it, it is an error.
+fn lex_integer(input: &str) -> LexResult<'_, Token<'_>> {
+    let (rem, digits) = preceded(
+        multispace0,
+        terminated(
+            recognize(pair(opt(one_of("+-")), digit1)),
+            not(peek(satisfy(is_symbol_char))),
+        ),
+    )(input)?;
+    match digits.parse() {
+        Ok(int) => Ok((rem, Token::Integer(int))),
+        #[cfg(feature = "bigint")]
+        Err(_) => digits
+            .parse()
+            .map(|int| (rem, Token::BigInteger(int)))
+            .map_err(|_| Err::Error(LexError::from_error_kind(input, ErrorKind::Digit))),
+        #[cfg(not(feature = "bigint"))]
+        Err(_) => Err(Err::Failure(LexError::new(
+            &input[input.len() - rem.len() - digits.len()..],
+            LexErrorKind::IntegerOverflow,
+        ))),
+    }
+}
+
+// n/d with an optional sign on n, reduced to lowest terms. One that
+// reduces to a whole number, like 4/2, is an integer. A denominator of zero
+// or numbers too large for an i64 mean it isn't a rational at all.
+fn lex_rational(input: &str) -> LexResult<'_, Token<'_>> {
+    let (rem, (numerator, denominator)) = preceded(
+        multispace0,
+        terminated(
+            separated_pair(
+                recognize(pair(opt(one_of("+-")), digit1)),
+                char('/'),
+                digit1,
+            ),
+            not(peek(satisfy(is_symbol_char))),
+        ),
+    )(input)?;
+    let rational = numerator
+        .parse()
+        .ok()
+        .zip(denominator.parse().ok())
+        .and_then(|(n, d)| Rational::new(n, d))
+        .ok_or_else(|| Err::Error(LexError::from_error_kind(input, ErrorKind::Digit)))?;
+    match rational.to_integer() {
+        Some(int) => Ok((rem, Token::Integer(int))),
+        None => Ok((rem, Token::Rational(rational))),
+    }
+}
+
+// Only literals with a fraction or exponent are floats, so 42 stays an
+// integer. The infinities and NaN have no digits and are spelled +inf.0,
+// -inf.0 and +nan.0.
+fn lex_float(input: &str) -> LexResult<'_, Token<'_>> {
+    let (rem, matched) = preceded(
+        multispace0,
+        terminated(
+            alt((
+                tag("+inf.0"),
+                tag("-inf.0"),
+                tag("+nan.0"),
+                tag("-nan.0"),
+                verify(float_text, |s: &str| s.contains(['.', 'e', 'E'])),
+            )),
+            not(peek(satisfy(is_symbol_char))),
+        ),
+    )(input)?;
+    let float = match matched {
+        "+inf.0" => f64::INFINITY,
+        "-inf.0" => f64::NEG_INFINITY,
+        "+nan.0" | "-nan.0" => f64::NAN,
+        _ => matched
+            .parse()
+            .map_err(|_| Err::Error(LexError::from_error_kind(input, ErrorKind::Float)))?,
+    };
+    Ok((rem, Token::Float(float)))
+}
+
+// recognize_float fails outright on an exponent marker without digits
+// after it, but 1e and 3em are symbols, as 1st is.
+fn float_text(input: &str) -> LexResult<'_, &str> {
+    recognize_float(input).map_err(|e| match e {
+        Err::Failure(e) => Err::Error(e),
+        e => e,
+    })
+}
+
+// Once an opening quote is seen the token must be a string, so errors
+// are failures rather than letting alt() retry it as a symbol.
+fn lex_string(input: &str) -> LexResult<'_, Token<'_>> {
+    let (rest, string) = quoted(input, '"', LexErrorKind::UnterminatedString)?;
+    Ok((rest, Token::String(string)))
 }
 
-// pub fn parse_float(input: &str) -> IResult<&str, Token> {
-//     let (rem, float) = preceded(multispace0, f64)(input)?;
-//     Ok((rem, Token::Float(float)))
-// }
+// |...| is a symbol named by whatever is between the bars, with the same
+// escapes as a string, so any name string->symbol makes can be written.
+fn lex_bar_symbol(input: &str) -> LexResult<'_, Token<'_>> {
+    let (rest, name) = quoted(input, '|', LexErrorKind::UnterminatedSymbol)?;
+    Ok((rest, Token::Symbol(name)))
+}
 
-fn lex_symbol(input: &str) -> IResult<&str, Token> {
+// The text between quote and the next one that isn't escaped. Text
+// without escapes borrows from the input.
+fn quoted(input: &str, quote: char, kind: LexErrorKind) -> LexResult<'_, Cow<'_, str>> {
+    let (body, _) = preceded(multispace0, char(quote))(input)?;
+    let start = &input[input.len() - body.len() - 1..];
+    let unterminated = || Err::Failure(LexError::new(start, kind));
+    let plain = body.find([quote, '\\']).ok_or_else(unterminated)?;
+    if body[plain..].starts_with(quote) {
+        return Ok((&body[plain + 1..], Cow::Borrowed(&body[..plain])));
+    }
+    let mut string = String::from(&body[..plain]);
+    let mut rest = &body[plain..];
+    loop {
+        let mut chars = rest.chars();
+        match chars.next() {
+            None => return Err(unterminated()),
+            Some(c) if c == quote => return Ok((chars.as_str(), Cow::Owned(string))),
+            Some('\\') => match lex_escape(chars.as_str()) {
+                Some((c, after)) => {
+                    string.push(c);
+                    rest = after;
+                }
+                None if chars.as_str().is_empty() => return Err(unterminated()),
+                None => {
+                    return Err(Err::Failure(LexError::new(
+                        rest,
+                        LexErrorKind::InvalidEscape,
+                    )))
+                }
+            },
+            Some(c) => {
+                string.push(c);
+                rest = chars.as_str();
+            }
+        }
+    }
+}
+
+// Input starts just after the backslash.
+fn lex_escape(input: &str) -> Option<(char, &str)> {
+    let mut chars = input.chars();
+    let c = match chars.next()? {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        '"' => '"',
+        '|' => '|',
+        '\\' => '\\',
+        'u' => {
+            let hex = chars.as_str().strip_prefix('{')?;
+            let end = hex.find('}')?;
+            let c = char::from_u32(u32::from_str_radix(&hex[..end], 16).ok()?)?;
+            return Some((c, &hex[end + 1..]));
+        }
+        _ => return None,
+    };
+    Some((c, chars.as_str()))
+}
+
+// Characters that are hard to read written as themselves.
+pub const CHAR_NAMES: &[(&str, char)] = &[
+    ("space", ' '),
+    ("newline", '\n'),
+    ("tab", '\t'),
+    ("return", '\r'),
+    ("null", '\0'),
+    ("alarm", '\u{7}'),
+    ("backspace", '\u{8}'),
+    ("delete", '\u{7f}'),
+    ("escape", '\u{1b}'),
+];
+
+// #\a is the character a. A symbol character followed by more of them is
+// a name, as in #\space, or a hex code point, as in #\x3bb. Any other
+// character after #\ stands for itself, so #\( and #\  are characters.
+fn lex_char(input: &str) -> LexResult<'_, Token<'_>> {
+    let (body, _) = preceded(multispace0, tag("#\\"))(input)?;
+    let start = &input[input.len() - body.len() - 2..];
+    let unknown = || Err::Failure(LexError::new(start, LexErrorKind::UnknownCharacter));
+    let first = body.chars().next().ok_or_else(unknown)?;
+    let len = match is_symbol_char(first) {
+        true => body.find(|c| !is_symbol_char(c)).unwrap_or(body.len()),
+        false => first.len_utf8(),
+    };
+    let (name, rest) = body.split_at(len);
+    if name.len() == first.len_utf8() {
+        return Ok((rest, Token::Char(first)));
+    }
+    let named = CHAR_NAMES.iter().find(|(n, _)| *n == name).map(|&(_, c)| c);
+    let code = name
+        .strip_prefix('x')
+        .filter(|hex| hex.chars().all(|c| c.is_ascii_hexdigit()))
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .and_then(char::from_u32);
+    let c = named.or(code).ok_or_else(unknown)?;
+    Ok((rest, Token::Char(c)))
+}
+
+// Everything but whitespace and the characters with a meaning of their
+// own: parens, brackets, quotes, `;`, `|` and `#`.
+pub(crate) fn is_symbol_char(c: char) -> bool {
+    c.is_alphanumeric() || "!$%&*/:<=>?^_~+-.@".contains(c)
+}
+
+fn lex_symbol(input: &str) -> LexResult<'_, Token<'_>> {
+    let (input, matched) = preceded(multispace0, take_while1(is_symbol_char))(input)?;
+    Ok((input, Token::Symbol(Cow::Borrowed(matched))))
+}
+
+// :name is a keyword. A colon anywhere else is part of a symbol, and : on
+// its own is a symbol too.
+fn lex_keyword(input: &str) -> LexResult<'_, Token<'_>> {
     let (input, matched) = preceded(
         multispace0,
-        take_while1(|x: char| x.is_alphanumeric() || x == '_'),
+        preceded(char(':'), take_while1(is_symbol_char)),
     )(input)?;
-    Ok((input, Token::Symbol(matched.to_owned())))
+    Ok((input, Token::Keyword(Cow::Borrowed(matched))))
+}
+
+pub(crate) fn lex_token(
+    source: &str,
+) -> impl FnMut(&str) -> LexResult<'_, Spanned<Token<'_>>> + '_ {
+    move |input| {
+        let (input, _) = skip_trivia(input)?;
+        let start = source.len() - input.len();
+        let (rest, token) = alt((
+            lex_lparan,
+            lex_rparan,
+            lex_bracket,
+            lex_quote,
+            lex_datum_comment,
+            lex_string,
+            lex_char,
+            lex_bool,
+            lex_dispatch,
+            lex_float,
+            lex_rational,
+            lex_integer,
+            lex_dot,
+            lex_keyword,
+            lex_symbol,
+            lex_bar_symbol,
+        ))(input)?;
+        let span = Span::new(start, source.len() - rest.len());
+        Ok((rest, Spanned::new(token, span)))
+    }
 }
 
-pub fn lex(input: &str) -> IResult<&str, Vec<Token>> {
-    all_consuming(many0(alt((
-        lex_lparan,
-        lex_rparan,
-        lex_integer,
-        lex_symbol,
-    ))))(input)
+pub fn lex(input: &str) -> LexResult<'_, Vec<Spanned<Token<'_>>>> {
+    all_consuming(terminated(many0(lex_token(input)), skip_trivia))(input)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use alloc::{format, vec};
 
     #[test]
     fn do_nothing_test() {
@@ -85,21 +600,289 @@ mod test {
         assert_eq!(lex_rparan("  ) ").unwrap(), (" ", Token::RParan));
     }
 
+    #[test]
+    fn lex_bracket_test() {
+        let tokens: Vec<_> = lex("[a]{:k 1}")
+            .unwrap()
+            .1
+            .into_iter()
+            .map(|t| t.value)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::LBracket,
+                Token::Symbol("a".into()),
+                Token::RBracket,
+                Token::LBrace,
+                Token::Keyword("k".into()),
+                Token::Integer(1),
+                Token::RBrace,
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_quote_test() {
+        assert_eq!(lex_quote(" 'x").unwrap(), ("x", Token::Quote));
+        assert_eq!(lex_quote("`(a)").unwrap(), ("(a)", Token::Quasiquote));
+        assert_eq!(lex_quote(",x").unwrap(), ("x", Token::Unquote));
+        assert_eq!(lex_quote(",@xs").unwrap(), ("xs", Token::UnquoteSplicing));
+    }
+
+    #[test]
+    fn lex_bool_test() {
+        assert_eq!(lex_bool(" #t)").unwrap(), (")", Token::Bool(true)));
+        assert_eq!(lex_bool("#f ").unwrap(), (" ", Token::Bool(false)));
+        assert_eq!(lex_bool("#true").unwrap(), ("", Token::Bool(true)));
+        assert_eq!(lex_bool("#false").unwrap(), ("", Token::Bool(false)));
+        assert!(lex_bool("#tx").is_err());
+    }
+
     #[test]
     fn lex_integer_test() {
         assert_eq!(lex_integer(" 42 ").unwrap(), (" ", Token::Integer(42)));
+        assert_eq!(lex_integer("-42)").unwrap(), (")", Token::Integer(-42)));
+        assert_eq!(lex_integer("+3").unwrap(), ("", Token::Integer(3)));
+        assert!(lex_integer("-").is_err());
+        assert!(lex_integer("1st").is_err());
+        assert!(lex_integer("3-").is_err());
+        #[cfg(feature = "bigint")]
+        assert_eq!(
+            lex_integer("-99999999999999999999)").unwrap(),
+            (
+                ")",
+                Token::BigInteger("-99999999999999999999".parse().unwrap())
+            )
+        );
+        #[cfg(not(feature = "bigint"))]
+        assert_eq!(
+            lex_integer(" 99999999999999999999"),
+            Err(Err::Failure(LexError::new(
+                "99999999999999999999",
+                LexErrorKind::IntegerOverflow
+            )))
+        );
+    }
+
+    #[test]
+    fn lex_rational_test() {
+        assert_eq!(
+            lex_rational("-2/6)").unwrap(),
+            (")", Token::Rational(Rational::new(-1, 3).unwrap()))
+        );
+        assert_eq!(lex_rational("4/2").unwrap(), ("", Token::Integer(2)));
+        assert!(lex_rational("1/0").is_err());
+        assert!(lex_rational("1/-2").is_err());
+        assert!(lex_rational("1/2x").is_err());
+        assert_eq!(lex("1/x").unwrap().1[0].value, Token::Symbol("1/x".into()));
+    }
+
+    #[test]
+    fn lex_sign_test() {
+        let tokens = |source| {
+            lex(source)
+                .unwrap()
+                .1
+                .into_iter()
+                .map(|t| t.value)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            tokens("(- x) (-1) - + -x +5 -2.5 1st"),
+            vec![
+                Token::LParan,
+                Token::Symbol("-".into()),
+                Token::Symbol("x".into()),
+                Token::RParan,
+                Token::LParan,
+                Token::Integer(-1),
+                Token::RParan,
+                Token::Symbol("-".into()),
+                Token::Symbol("+".into()),
+                Token::Symbol("-x".into()),
+                Token::Integer(5),
+                Token::Float(-2.5),
+                Token::Symbol("1st".into()),
+            ]
+        );
+        assert_eq!(
+            tokens("(- 1 -1)"),
+            vec![
+                Token::LParan,
+                Token::Symbol("-".into()),
+                Token::Integer(1),
+                Token::Integer(-1),
+                Token::RParan,
+            ]
+        );
+        assert_eq!(tokens("'-5"), vec![Token::Quote, Token::Integer(-5)]);
+        assert_eq!(
+            tokens("(a . b) .5 ..."),
+            vec![
+                Token::LParan,
+                Token::Symbol("a".into()),
+                Token::Dot,
+                Token::Symbol("b".into()),
+                Token::RParan,
+                Token::Float(0.5),
+                Token::Symbol("...".into()),
+            ]
+        );
     }
 
-    // #[test]
-    // fn parse_float_test() {
-    //     assert_eq!(parse_float(" 42. ").unwrap(), (" ", Token::Float(42.)));
-    // }
+    #[test]
+    fn lex_float_test() {
+        assert_eq!(lex_float(" 42. ").unwrap(), (" ", Token::Float(42.)));
+        assert_eq!(lex_float("1.5)").unwrap(), (")", Token::Float(1.5)));
+        assert_eq!(lex_float(".5").unwrap(), ("", Token::Float(0.5)));
+        assert_eq!(lex_float("1e10").unwrap(), ("", Token::Float(1e10)));
+        assert_eq!(lex_float("-2.5").unwrap(), ("", Token::Float(-2.5)));
+        assert!(lex_float("42").is_err());
+        assert!(lex_float("1.5x").is_err());
+        for symbol in ["1e", "3em", "1e5x", "1st", "1.5x", "2e+"] {
+            assert!(matches!(lex_float(symbol), Err(Err::Error(_))), "{symbol}");
+            assert_eq!(
+                lex(symbol).unwrap().1[0].value,
+                Token::Symbol(symbol.into()),
+                "{symbol}"
+            );
+        }
+        let tokens: Vec<_> = lex("(list 2e)").unwrap().1;
+        assert_eq!(tokens[2].value, Token::Symbol("2e".into()));
+        assert_eq!(
+            lex_float("+inf.0)").unwrap(),
+            (")", Token::Float(f64::INFINITY))
+        );
+        assert_eq!(
+            lex_float("-inf.0").unwrap(),
+            ("", Token::Float(f64::NEG_INFINITY))
+        );
+        assert!(matches!(lex_float("+nan.0"), Ok(("", Token::Float(x))) if x.is_nan()));
+        assert_eq!(
+            lex("+inf.0x").unwrap().1[0].value,
+            Token::Symbol("+inf.0x".into())
+        );
+    }
+
+    #[test]
+    fn lex_string_test() {
+        assert_eq!(
+            lex_string(r#" "hi there" )"#).unwrap(),
+            (" )", Token::String("hi there".into()))
+        );
+        assert_eq!(
+            lex_string(r#""a\"b\\c\nd\te\u{3bb}""#).unwrap(),
+            ("", Token::String("a\"b\\c\nd\te\u{3bb}".into()))
+        );
+        assert!(matches!(
+            lex_string(r#""plain" rest"#).unwrap().1,
+            Token::String(Cow::Borrowed("plain"))
+        ));
+        assert_eq!(
+            lex_string(r#"(x "abc"#),
+            Err(Err::Error(LexError::new(
+                "(x \"abc",
+                LexErrorKind::UnexpectedCharacter
+            )))
+        );
+        assert_eq!(
+            lex_string(r#" "abc"#),
+            Err(Err::Failure(LexError::new(
+                "\"abc",
+                LexErrorKind::UnterminatedString
+            )))
+        );
+        assert_eq!(
+            lex_string(r#""a\qb""#),
+            Err(Err::Failure(LexError::new(
+                "\\qb\"",
+                LexErrorKind::InvalidEscape
+            )))
+        );
+        assert_eq!(
+            lex(r#"(a "b"#).unwrap_err(),
+            Err::Failure(LexError::new("\"b", LexErrorKind::UnterminatedString))
+        );
+    }
+
+    #[test]
+    fn lex_char_test() {
+        assert_eq!(lex_char(" #\\a)").unwrap(), (")", Token::Char('a')));
+        assert_eq!(lex_char("#\\space").unwrap(), ("", Token::Char(' ')));
+        assert_eq!(lex_char("#\\newline x").unwrap(), (" x", Token::Char('\n')));
+        assert_eq!(lex_char("#\\x3bb").unwrap(), ("", Token::Char('\u{3bb}')));
+        assert_eq!(lex_char("#\\x").unwrap(), ("", Token::Char('x')));
+        assert_eq!(lex_char("#\\λ").unwrap(), ("", Token::Char('λ')));
+        assert_eq!(lex_char("#\\()").unwrap(), (")", Token::Char('(')));
+        assert_eq!(lex_char("#\\ ").unwrap(), ("", Token::Char(' ')));
+        assert_eq!(
+            lex_char("#\\bogus"),
+            Err(Err::Failure(LexError::new(
+                "#\\bogus",
+                LexErrorKind::UnknownCharacter
+            )))
+        );
+        assert!(lex_char("#\\x+1").is_err());
+        assert!(lex_char("#\\").is_err());
+    }
+
+    #[test]
+    fn lex_keyword_test() {
+        assert_eq!(
+            lex_keyword(" :width 10").unwrap(),
+            (" 10", Token::Keyword("width".into()))
+        );
+        assert!(lex_keyword(":").is_err());
+        assert!(lex_keyword("a:b").is_err());
+        assert_eq!(
+            lex(": a:b").unwrap().1[1].value,
+            Token::Symbol("a:b".into())
+        );
+    }
 
     #[test]
     fn lex_symbol_test() {
         assert_eq!(
             lex_symbol(" some_name = 42").unwrap(),
-            (" = 42", Token::Symbol("some_name".to_owned()))
+            (" = 42", Token::Symbol("some_name".into()))
+        );
+        for name in [
+            "+", "*", "/", "<=", "=", "set!", "null?", "->vec", "a.b", "...",
+        ] {
+            assert_eq!(
+                lex_symbol(&format!("{name})")).unwrap(),
+                (")", Token::Symbol(name.into()))
+            );
+        }
+        assert_eq!(
+            lex_symbol("x'y").unwrap(),
+            ("'y", Token::Symbol("x".into()))
+        );
+        assert_eq!(
+            lex_symbol("ab\"c\"").unwrap(),
+            ("\"c\"", Token::Symbol("ab".into()))
+        );
+        assert!(lex_symbol("#t").is_err());
+        assert!(lex_symbol("(a)").is_err());
+        for (source, name) in [
+            ("|a b|", "a b"),
+            ("||", ""),
+            ("|1|", "1"),
+            ("|a;b\\|c\\n|", "a;b|c\n"),
+        ] {
+            assert_eq!(
+                lex_bar_symbol(source).unwrap(),
+                ("", Token::Symbol(name.into())),
+                "{source}"
+            );
+        }
+        assert_eq!(
+            lex_bar_symbol("|a b"),
+            Err(Err::Failure(LexError::new(
+                "|a b",
+                LexErrorKind::UnterminatedSymbol
+            )))
         );
     }
 
@@ -107,12 +890,74 @@ mod test {
     fn lex_test() {
         assert_eq!(
             lex("(some_name 42)").unwrap().1,
+            vec![
+                Spanned::new(Token::LParan, Span::new(0, 1)),
+                Spanned::new(Token::Symbol("some_name".into()), Span::new(1, 10)),
+                Spanned::new(Token::Integer(42), Span::new(11, 13)),
+                Spanned::new(Token::RParan, Span::new(13, 14)),
+            ]
+        );
+        assert_eq!(lex(" (42) \n").unwrap().1.len(), 3);
+        assert_eq!(
+            lex("(plus 1.5 2)")
+                .unwrap()
+                .1
+                .into_iter()
+                .map(|t| t.value)
+                .collect::<Vec<_>>(),
+            vec![
+                Token::LParan,
+                Token::Symbol("plus".into()),
+                Token::Float(1.5),
+                Token::Integer(2),
+                Token::RParan
+            ]
+        );
+        assert!(lex("(42 #)").is_err());
+    }
+
+    #[test]
+    fn lex_comment_test() {
+        let tokens = |input| {
+            lex(input)
+                .unwrap()
+                .1
+                .into_iter()
+                .map(|t| t.value)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            tokens("; leading\n(a ; trailing\n b) ; end"),
+            tokens("(a b)")
+        );
+        assert_eq!(
+            tokens("(a #| one #| two |# still one |# b)"),
+            tokens("(a b)")
+        );
+        assert_eq!(
+            tokens("(a #;b c)"),
             vec![
                 Token::LParan,
-                Token::Symbol("some_name".to_owned()),
-                Token::Integer(42),
+                Token::Symbol("a".into()),
+                Token::DatumComment,
+                Token::Symbol("b".into()),
+                Token::Symbol("c".into()),
                 Token::RParan
             ]
-        )
+        );
+        assert_eq!(
+            lex("(a #| never closed").unwrap_err(),
+            Err::Failure(LexError::new(
+                "#| never closed",
+                LexErrorKind::UnterminatedComment
+            ))
+        );
+    }
+
+    #[test]
+    fn line_col_test() {
+        let source = "(a\n  (b 42))";
+        assert_eq!(Span::new(0, 1).line_col(source), (1, 1));
+        assert_eq!(Span::new(6, 7).line_col(source), (2, 4));
     }
 }
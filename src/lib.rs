@@ -1,2 +1,74 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+// The test harness has std, and the tests use it.
+#[cfg(all(test, not(feature = "std")))]
+extern crate std;
+
+pub mod analysis;
+pub mod arena;
+#[cfg(feature = "bigint")]
+pub mod bigint;
+pub mod builtins;
+pub mod bytecode;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "std")]
+pub mod cli;
+pub mod compile;
+pub mod convert;
+#[cfg(feature = "std")]
+pub mod coverage;
+#[cfg(feature = "serde")]
+pub mod de;
+#[cfg(feature = "std")]
+pub mod debug;
+pub mod docs;
+pub mod eval;
+pub mod format;
+mod fuzz;
+pub mod gc;
+pub mod hooks;
+pub mod interpreter;
+pub mod io;
+pub mod json;
 pub mod lexer;
-pub mod parser;
\ No newline at end of file
+pub mod limits;
+mod local;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod macros;
+pub mod module;
+pub mod optimize;
+pub mod parser;
+pub mod printer;
+#[cfg(feature = "std")]
+pub mod profile;
+pub mod rational;
+pub mod reader;
+#[cfg(feature = "std")]
+pub mod repl;
+pub mod report;
+pub mod symbol;
+pub mod sync;
+#[cfg(feature = "std")]
+pub mod testing;
+pub mod value;
+pub mod visit;
+pub mod vm;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+// What a host embedding the language needs, without the modules behind
+// it.
+#[cfg(feature = "serde")]
+pub use de::{from_str, from_value};
+pub use eval::EvalError;
+pub use fuzz::fuzz_check;
+pub use interpreter::{Interpreter, InterpreterError};
+#[cfg(feature = "derive")]
+pub use tuple_gd_derive::TasksValue;
+pub use value::Value;
+
+#[cfg(not(any(feature = "std", test)))]
+pub use local::assume_single_threaded;
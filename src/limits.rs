@@ -0,0 +1,316 @@
+use alloc::sync::Arc;
+#[cfg(feature = "sync")]
+use core::sync::atomic::AtomicU64;
+use core::{
+    cell::{Cell, RefCell},
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "sync")]
+use crate::sync::Shared;
+use crate::{
+    eval::EvalError,
+    gc,
+    local::{finally, local},
+};
+
+// Bounds on what one eval_str or eval_file may use, for running code that
+// isn't trusted. None means no bound. Steps are forms the tree-walker
+// evaluates and instructions the VM runs; depth is how deeply calls and
+// evaluations nest, which the native stack bounds even without max_depth;
+// heap bytes are an estimate of what the environments and atoms hold,
+// taken every few thousand steps, as is the time, plus the names the run
+// interned, which are never freed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    pub max_depth: Option<usize>,
+    pub max_heap_bytes: Option<usize>,
+    // Measuring time needs std.
+    #[cfg(feature = "std")]
+    pub max_wall_time: Option<Duration>,
+}
+
+// The bound that was reached, with its value.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Limit {
+    Steps(u64),
+    Depth(usize),
+    HeapBytes(usize),
+    #[cfg(feature = "std")]
+    WallTime(Duration),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Steps(max) => write!(f, "more than {max} steps"),
+            Limit::Depth(max) => write!(f, "nested more than {max} deep"),
+            Limit::HeapBytes(max) => write!(f, "more than {max} bytes of heap"),
+            #[cfg(feature = "std")]
+            Limit::WallTime(max) => write!(f, "ran for longer than {max:?}"),
+        }
+    }
+}
+
+// Stops a run from another thread, or from a host function: once cancel
+// is called, the run going on stops with EvalError::Cancelled within a
+// few thousand steps. Each run starts out not cancelled, so cancelling
+// between runs does nothing.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// How many steps go by between checks of the heap, the clock and
+// cancellation, which cost more than counting.
+const CHECK_INTERVAL: u64 = 4096;
+
+#[derive(Clone, Default)]
+struct State {
+    limits: Limits,
+    steps: u64,
+    depth: usize,
+    // The heap as last measured, and the bytes of the names interned so far.
+    heap: usize,
+    interned: usize,
+    #[cfg(feature = "std")]
+    started: Option<Instant>,
+    cancel: Option<CancellationToken>,
+    // The steps taken by all of pmap's calls together, which is what
+    // max_steps bounds while they run; steps still counts this thread's,
+    // for the periodic checks.
+    #[cfg(feature = "sync")]
+    shared_steps: Option<Shared<AtomicU64>>,
+}
+
+impl State {
+    fn step(&mut self) -> Result<(), EvalError> {
+        self.steps += 1;
+        #[cfg(feature = "sync")]
+        let steps = match &self.shared_steps {
+            Some(shared) => shared.fetch_add(1, Ordering::Relaxed) + 1,
+            None => self.steps,
+        };
+        #[cfg(not(feature = "sync"))]
+        let steps = self.steps;
+        if let Some(max) = self.limits.max_steps.filter(|&max| steps > max) {
+            return Err(EvalError::LimitExceeded(Limit::Steps(max)));
+        }
+        if !self.steps.is_multiple_of(CHECK_INTERVAL) {
+            return Ok(());
+        }
+        if matches!(&self.cancel, Some(cancel) if cancel.is_cancelled()) {
+            return Err(EvalError::Cancelled);
+        }
+        if let Some(max) = self.limits.max_heap_bytes {
+            self.heap = gc::heap_bytes().saturating_add(self.interned);
+            if self.heap > max {
+                return Err(EvalError::LimitExceeded(Limit::HeapBytes(max)));
+            }
+        }
+        #[cfg(feature = "std")]
+        if let (Some(max), Some(started)) = (self.limits.max_wall_time, self.started) {
+            if started.elapsed() > max {
+                return Err(EvalError::LimitExceeded(Limit::WallTime(max)));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_depth(&self, depth: usize) -> Result<(), EvalError> {
+        match self.limits.max_depth {
+            Some(max) if depth > max => Err(EvalError::LimitExceeded(Limit::Depth(max))),
+            _ => Ok(()),
+        }
+    }
+}
+
+local! {
+    static STATE: RefCell<State> = RefCell::default();
+}
+
+// Whether there are any limits, so the evaluator can skip counting when
+// there are none.
+local! {
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+}
+
+// How much native stack the tree-walker's nesting may take up on a
+// thread, from where it first nested. A thread other than the main one
+// gets 2 MiB unless it asks for more, and wasm gets 1 MiB.
+#[cfg(not(target_arch = "wasm32"))]
+const STACK_BYTES: usize = 1 << 20;
+#[cfg(target_arch = "wasm32")]
+const STACK_BYTES: usize = 1 << 19;
+
+// How deeply the tree-walker is nested on this thread, limits or not, and
+// the stack address it started nesting at.
+local! {
+    static NESTING: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+// What a run has used of its limits so far. An async run is made of
+// pieces with waits in between, and they all count against one budget.
+#[derive(Clone)]
+pub(crate) struct Budget(State);
+
+impl Budget {
+    pub(crate) fn new(limits: Limits, cancel: Option<CancellationToken>) -> Self {
+        if let Some(cancel) = &cancel {
+            cancel.0.store(false, Ordering::Relaxed);
+        }
+        Budget(State {
+            limits,
+            // The clock is only read when the time is limited, as
+            // wasm32-unknown-unknown has none and panics if asked.
+            #[cfg(feature = "std")]
+            started: limits.max_wall_time.map(|_| Instant::now()),
+            cancel,
+            ..State::default()
+        })
+    }
+}
+
+// Runs f counting against budget, which keeps what f used, then puts
+// back whatever was being counted before.
+pub(crate) fn with_budget<T>(budget: &mut Budget, f: impl FnOnce() -> T) -> T {
+    let active = budget.0.limits != Limits::default() || budget.0.cancel.is_some();
+    let state = core::mem::take(&mut budget.0);
+    let saved = STATE.with(|current| current.replace(state));
+    let was_active = ACTIVE.with(|current| current.replace(active));
+    finally(f, || {
+        budget.0 = STATE.with(|current| current.replace(saved));
+        ACTIVE.with(|current| current.set(was_active));
+    })
+}
+
+// The budget being counted against, for code run on other threads. Each
+// thread counts against a clone of it, and all their steps go to one
+// counter, so together they get what was left. join charges them back.
+#[cfg(feature = "sync")]
+pub(crate) fn fork() -> Budget {
+    let mut state = STATE.with(|state| state.borrow().clone());
+    // Inside another fork it's already shared.
+    let steps = state.steps;
+    state
+        .shared_steps
+        .get_or_insert_with(|| Shared::new(AtomicU64::new(steps)));
+    Budget(state)
+}
+
+// Counts the steps taken against a fork as taken here.
+#[cfg(feature = "sync")]
+pub(crate) fn join(budget: Budget) {
+    if let Some(shared) = budget.0.shared_steps {
+        STATE.with(|state| state.borrow_mut().steps = shared.load(Ordering::Relaxed));
+    }
+}
+
+pub(crate) fn step() -> Result<(), EvalError> {
+    if !ACTIVE.with(Cell::get) {
+        return Ok(());
+    }
+    STATE.with(|state| state.borrow_mut().step())
+}
+
+// For builtins that build a collection in one go, such as range, which
+// would otherwise only be measured once they had built it: whether bytes
+// more would still be within the heap limit, going by the heap as last
+// measured.
+pub(crate) fn allocate(bytes: usize) -> Result<(), EvalError> {
+    if !ACTIVE.with(Cell::get) {
+        return Ok(());
+    }
+    STATE.with(|state| {
+        let state = state.borrow();
+        match state.limits.max_heap_bytes {
+            Some(max) if state.heap.saturating_add(bytes) > max => {
+                Err(EvalError::LimitExceeded(Limit::HeapBytes(max)))
+            }
+            _ => Ok(()),
+        }
+    })
+}
+
+// Counts a name new to the interner, which is kept for good, against the
+// heap. It's checked along with the rest of the heap.
+pub(crate) fn interned(bytes: usize) {
+    if !ACTIVE.with(Cell::get) {
+        return;
+    }
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.interned += bytes;
+        state.heap = state.heap.saturating_add(bytes);
+    });
+}
+
+// Counts one more level of nesting until the guard is dropped. Whatever
+// the limits, nesting that would take the tree-walker past STACK_BYTES of
+// native stack is refused rather than left to overflow it.
+pub(crate) fn enter() -> Result<Depth, EvalError> {
+    let counted = ACTIVE.with(Cell::get);
+    if counted {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.check_depth(state.depth + 1)?;
+            state.depth += 1;
+            Ok::<_, EvalError>(())
+        })?;
+    }
+    let here = stack_address();
+    let (depth, start) = NESTING.with(Cell::get);
+    let start = if depth == 0 { here } else { start };
+    NESTING.with(|nesting| nesting.set((depth + 1, start)));
+    let guard = Depth(counted);
+    if here.abs_diff(start) > STACK_BYTES {
+        return Err(EvalError::LimitExceeded(Limit::Depth(depth)));
+    }
+    Ok(guard)
+}
+
+fn stack_address() -> usize {
+    let marker = 0u8;
+    core::hint::black_box(&marker) as *const u8 as usize
+}
+
+// For the VM, whose calls nest in its own frames rather than on the Rust
+// stack: whether frames more levels would be within the limit.
+pub(crate) fn check_depth(frames: usize) -> Result<(), EvalError> {
+    if !ACTIVE.with(Cell::get) {
+        return Ok(());
+    }
+    STATE.with(|state| {
+        let state = state.borrow();
+        state.check_depth(state.depth + frames)
+    })
+}
+
+pub(crate) struct Depth(bool);
+
+impl Drop for Depth {
+    fn drop(&mut self) {
+        NESTING.with(|nesting| {
+            let (depth, start) = nesting.get();
+            nesting.set((depth - 1, start));
+        });
+        if self.0 {
+            STATE.with(|state| {
+                let mut state = state.borrow_mut();
+                state.depth = state.depth.saturating_sub(1);
+            });
+        }
+    }
+}
@@ -0,0 +1,96 @@
+#[cfg(not(any(feature = "std", test)))]
+use core::{
+    cell::OnceCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+// Declares the state the evaluator keeps per thread, read with
+// NAME.with(|value| ...). Without std there is no thread_local!, so the
+// state lives in a static instead, which is only sound if the crate is
+// used from one thread; a host says it is by calling
+// assume_single_threaded. Tests have std whatever the features, and keep
+// to thread-locals so the harness can run them in parallel.
+macro_rules! local {
+    (static $name:ident: $ty:ty = const { $init:expr };) => {
+        #[cfg(any(feature = "std", test))]
+        std::thread_local! {
+            static $name: $ty = const { $init };
+        }
+        #[cfg(not(any(feature = "std", test)))]
+        static $name: $crate::local::Local<$ty> = $crate::local::Local::new(|| $init);
+    };
+    (static $name:ident: $ty:ty = $init:expr;) => {
+        #[cfg(any(feature = "std", test))]
+        std::thread_local! {
+            static $name: $ty = $init;
+        }
+        #[cfg(not(any(feature = "std", test)))]
+        static $name: $crate::local::Local<$ty> = $crate::local::Local::new(|| $init);
+    };
+}
+
+pub(crate) use local;
+
+// Runs f, then restore, which puts back what f's caller had in a local.
+// restore also runs if f panics, so a host that catches the panic doesn't
+// go on with f's state in place of its own.
+pub(crate) fn finally<T>(f: impl FnOnce() -> T, restore: impl FnOnce()) -> T {
+    struct Guard<F: FnOnce()>(Option<F>);
+
+    impl<F: FnOnce()> Drop for Guard<F> {
+        fn drop(&mut self) {
+            if let Some(restore) = self.0.take() {
+                restore();
+            }
+        }
+    }
+
+    let _guard = Guard(Some(restore));
+    f()
+}
+
+#[cfg(not(any(feature = "std", test)))]
+static SINGLE_THREADED: AtomicBool = AtomicBool::new(false);
+
+// Lets a no_std build use its global state. Until this is called, reading
+// or evaluating anything panics.
+//
+// SAFETY: the caller must make sure that the crate, and every value, Expr
+// and Interpreter it hands out, is only ever used from one thread, with no
+// interrupt handler or other thread reaching it in between. The state is
+// in statics shared by everything and holds reference counts that aren't
+// atomic.
+#[cfg(not(any(feature = "std", test)))]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn assume_single_threaded() {
+    SINGLE_THREADED.store(true, Ordering::Release);
+}
+
+#[cfg(not(any(feature = "std", test)))]
+pub(crate) struct Local<T> {
+    cell: OnceCell<T>,
+    init: fn() -> T,
+}
+
+// SAFETY: a Local is only reached once the host has promised, through
+// assume_single_threaded, that only one thread ever touches it.
+#[cfg(not(any(feature = "std", test)))]
+unsafe impl<T> Sync for Local<T> {}
+
+#[cfg(not(any(feature = "std", test)))]
+impl<T> Local<T> {
+    pub(crate) const fn new(init: fn() -> T) -> Self {
+        Local {
+            cell: OnceCell::new(),
+            init,
+        }
+    }
+
+    pub(crate) fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        assert!(
+            SINGLE_THREADED.load(Ordering::Acquire),
+            "tuple_gd::assume_single_threaded must be called before the crate is used without std"
+        );
+        f(self.cell.get_or_init(self.init))
+    }
+}
@@ -0,0 +1,428 @@
+// A language server for editors, over standard input and output: JSON-RPC
+// messages with Content-Length headers, as the Language Server Protocol
+// has them. Each open file is an analysis::Document kept up to date with
+// the edits the editor sends, and everything is answered from it: lint's
+// diagnostics whenever a file changes, hover, go to definition and the
+// outline of document symbols.
+//
+// The protocol's positions are lines and UTF-16 code units within them,
+// which are turned into the document's byte offsets and back here.
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use serde_json::{json, Value as Json};
+
+use crate::{
+    analysis::{Document, DocumentSymbol, Severity, SymbolKind},
+    interpreter::Interpreter,
+    lexer::Span,
+    symbol::SymbolId,
+};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+struct Server {
+    documents: HashMap<String, Document>,
+    // What a script can use without defining it, for lint.
+    globals: Vec<SymbolId>,
+    shut_down: bool,
+}
+
+// Answers messages from input until the client sends exit, or input ends.
+// Returns the exit status the protocol asks for: 0 if the client asked
+// the server to shut down first and 1 if it didn't.
+pub fn run(mut input: impl BufRead, mut output: impl Write) -> io::Result<i32> {
+    let mut server = Server {
+        documents: HashMap::new(),
+        globals: Interpreter::new().env().names(),
+        shut_down: false,
+    };
+    while let Some(message) = read_message(&mut input)? {
+        if message["method"] == "exit" {
+            break;
+        }
+        for reply in server.handle(&message) {
+            write_message(&mut output, &reply)?;
+        }
+    }
+    Ok(match server.shut_down {
+        true => 0,
+        false => 1,
+    })
+}
+
+// The next message, or None at the end of input. A message that isn't
+// JSON is dropped, as there is no id to answer it with.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    loop {
+        let mut length = None;
+        loop {
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse::<usize>().ok();
+                }
+            }
+        }
+        let Some(length) = length else {
+            continue;
+        };
+        let mut body = vec![0; length];
+        input.read_exact(&mut body)?;
+        if let Ok(message) = serde_json::from_slice(&body) {
+            return Ok(Some(message));
+        }
+    }
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    output.flush()
+}
+
+impl Server {
+    // The replies to message: a response if it's a request, which has an
+    // id, and any notifications it leads to.
+    fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let params = &message["params"];
+        let method = message["method"].as_str().unwrap_or_default();
+        let result = match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    // Edits come as the ranges that changed.
+                    "textDocumentSync": {"openClose": true, "change": 2},
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": {"name": "tuple-gd"},
+            })),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Json::Null)
+            }
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                return match (document["uri"].as_str(), document["text"].as_str()) {
+                    (Some(uri), Some(text)) => {
+                        self.documents.insert(uri.to_string(), Document::new(text));
+                        self.publish(uri)
+                    }
+                    _ => Vec::new(),
+                };
+            }
+            "textDocument/didChange" => {
+                let Some(uri) = params["textDocument"]["uri"].as_str() else {
+                    return Vec::new();
+                };
+                let Some(document) = self.documents.get_mut(uri) else {
+                    return Vec::new();
+                };
+                let changes = params["contentChanges"].as_array();
+                for change in changes.into_iter().flatten() {
+                    let text = change["text"].as_str().unwrap_or_default();
+                    match range(document.text(), &change["range"]) {
+                        Some(range) => {
+                            document.edit(range, text);
+                        }
+                        // A change without a range is the whole text.
+                        None => *document = Document::new(text),
+                    }
+                }
+                return self.publish(uri);
+            }
+            "textDocument/didClose" => {
+                let Some(uri) = params["textDocument"]["uri"].as_str() else {
+                    return Vec::new();
+                };
+                self.documents.remove(uri);
+                return vec![diagnostics(uri, Vec::new())];
+            }
+            "textDocument/hover" => {
+                self.at(params)
+                    .map(|(document, offset)| match document.hover(offset) {
+                        Some(hover) => json!({
+                            "contents": {"kind": "plaintext", "value": hover.contents},
+                            "range": span_range(document.text(), hover.span),
+                        }),
+                        None => Json::Null,
+                    })
+            }
+            "textDocument/definition" => {
+                self.at(params)
+                    .map(|(document, offset)| match document.definition(offset) {
+                        Some(span) => json!({
+                            "uri": params["textDocument"]["uri"],
+                            "range": span_range(document.text(), span),
+                        }),
+                        None => Json::Null,
+                    })
+            }
+            "textDocument/documentSymbol" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                match self.documents.get(uri) {
+                    Some(document) => {
+                        let symbols = document.symbols();
+                        Ok(symbols_json(document.text(), &symbols))
+                    }
+                    None => Err((INVALID_PARAMS, format!("`{uri}` isn't open"))),
+                }
+            }
+            method => Err((METHOD_NOT_FOUND, format!("no method `{method}`"))),
+        };
+        // Notifications have no id and get no response, not even an
+        // error.
+        let id = &message["id"];
+        if id.is_null() {
+            return Vec::new();
+        }
+        vec![match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {"code": code, "message": message},
+            }),
+        }]
+    }
+
+    // The open document params name and the offset of the position in
+    // it.
+    fn at(&self, params: &Json) -> Result<(&Document, usize), (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let document = self
+            .documents
+            .get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("`{uri}` isn't open")))?;
+        let offset = offset(document.text(), &params["position"])
+            .ok_or_else(|| (INVALID_PARAMS, "no such position".to_string()))?;
+        Ok((document, offset))
+    }
+
+    fn publish(&self, uri: &str) -> Vec<Json> {
+        let Some(document) = self.documents.get(uri) else {
+            return Vec::new();
+        };
+        let text = document.text();
+        let found = document
+            .lint(&self.globals)
+            .into_iter()
+            .map(|diagnostic| {
+                json!({
+                    "range": span_range(text, diagnostic.span()),
+                    "severity": match diagnostic.severity() {
+                        Severity::Error => 1,
+                        Severity::Warning => 2,
+                    },
+                    "source": "tuple-gd",
+                    "message": diagnostic.to_string(),
+                })
+            })
+            .collect();
+        vec![diagnostics(uri, found)]
+    }
+}
+
+fn diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": {"uri": uri, "diagnostics": diagnostics},
+    })
+}
+
+fn symbols_json(text: &str, symbols: &[DocumentSymbol]) -> Json {
+    let symbols = symbols.iter().map(|symbol| {
+        // The protocol has no kind for a macro.
+        let kind = match symbol.kind {
+            SymbolKind::Function | SymbolKind::Macro => 12,
+            SymbolKind::Variable => 13,
+            SymbolKind::Module => 2,
+        };
+        json!({
+            "name": symbol.name.as_str(),
+            "detail": symbol.detail,
+            "kind": kind,
+            "range": span_range(text, symbol.span),
+            "selectionRange": span_range(text, symbol.name_span),
+            "children": symbols_json(text, &symbol.children),
+        })
+    });
+    Json::Array(symbols.collect())
+}
+
+// The byte offset of a {"line", "character"} position, or None if the
+// text has no such line. A character past the end of its line is the end
+// of it, as the protocol says.
+fn offset(text: &str, position: &Json) -> Option<usize> {
+    let line = usize::try_from(position["line"].as_u64()?).ok()?;
+    let character = usize::try_from(position["character"].as_u64()?).ok()?;
+    let start = match line {
+        0 => 0,
+        line => text.match_indices('\n').nth(line - 1)?.0 + 1,
+    };
+    let rest = &text[start..];
+    let rest = &rest[..rest.find('\n').unwrap_or(rest.len())];
+    let mut units = 0;
+    for (i, c) in rest.char_indices() {
+        if units >= character {
+            return Some(start + i);
+        }
+        units += c.len_utf16();
+    }
+    Some(start + rest.len())
+}
+
+fn range(text: &str, range: &Json) -> Option<Span> {
+    let start = offset(text, &range["start"])?;
+    let end = offset(text, &range["end"])?;
+    Some(Span::new(start, end.max(start)))
+}
+
+fn position(text: &str, offset: usize) -> Json {
+    let before = &text[..offset.min(text.len())];
+    let (line, start) = match before.rfind('\n') {
+        Some(i) => (before.matches('\n').count(), i + 1),
+        None => (0, 0),
+    };
+    let character: usize = before[start..].chars().map(char::len_utf16).sum();
+    json!({"line": line, "character": character})
+}
+
+fn span_range(text: &str, span: Span) -> Json {
+    json!({"start": position(text, span.start), "end": position(text, span.end)})
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame(message: Json) -> String {
+        let body = message.to_string();
+        format!("Content-Length: {}\r\n\r\n{body}", body.len())
+    }
+
+    // Sends messages to a server and gives back what it sent, and its
+    // exit status.
+    fn session(messages: Vec<Json>) -> (Vec<Json>, i32) {
+        let input: String = messages.into_iter().map(frame).collect();
+        let mut output = Vec::new();
+        let status = run(input.as_bytes(), &mut output).unwrap();
+        let mut output = output.as_slice();
+        let mut replies = Vec::new();
+        while let Some(reply) = read_message(&mut output).unwrap() {
+            replies.push(reply);
+        }
+        (replies, status)
+    }
+
+    fn request(id: i64, method: &str, params: Json) -> Json {
+        json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params})
+    }
+
+    fn notification(method: &str, params: Json) -> Json {
+        json!({"jsonrpc": "2.0", "method": method, "params": params})
+    }
+
+    #[test]
+    fn session_test() {
+        let uri = "file:///a.lisp";
+        let text = "; Doubles x.\n(define (double x) (* x 2))\n(double 1 2)";
+        let at = |line, character| {
+            json!({
+                "textDocument": {"uri": uri},
+                "position": {"line": line, "character": character},
+            })
+        };
+        let (replies, status) = session(vec![
+            request(1, "initialize", json!({})),
+            notification("initialized", json!({})),
+            notification(
+                "textDocument/didOpen",
+                json!({"textDocument": {"uri": uri, "text": text}}),
+            ),
+            request(2, "textDocument/hover", at(2, 2)),
+            request(3, "textDocument/definition", at(1, 22)),
+            request(4, "textDocument/documentSymbol", at(0, 0)),
+            notification(
+                "textDocument/didChange",
+                json!({
+                    "textDocument": {"uri": uri},
+                    "contentChanges": [{
+                        "range": {
+                            "start": {"line": 2, "character": 9},
+                            "end": {"line": 2, "character": 11},
+                        },
+                        "text": "",
+                    }],
+                }),
+            ),
+            request(5, "no/such", json!({})),
+            request(6, "shutdown", Json::Null),
+            notification("exit", Json::Null),
+        ]);
+        assert_eq!(status, 0);
+        assert_eq!(replies.len(), 8);
+        assert_eq!(replies[0]["result"]["capabilities"]["hoverProvider"], true);
+
+        let published = &replies[1]["params"]["diagnostics"];
+        assert_eq!(
+            published,
+            &json!([{
+                "range": {
+                    "start": {"line": 2, "character": 0},
+                    "end": {"line": 2, "character": 12},
+                },
+                "severity": 1,
+                "source": "tuple-gd",
+                "message": "`double` expects 1 arguments, found 2",
+            }])
+        );
+        assert_eq!(
+            replies[2]["result"]["contents"]["value"],
+            "(double x)\n\nDoubles x."
+        );
+        assert_eq!(
+            replies[3]["result"]["range"],
+            json!({
+                "start": {"line": 1, "character": 16},
+                "end": {"line": 1, "character": 17},
+            })
+        );
+        assert_eq!(replies[4]["result"][0]["name"], "double");
+        assert_eq!(replies[4]["result"][0]["kind"], 12);
+        // The edit fixed the call.
+        assert_eq!(replies[5]["params"]["diagnostics"], json!([]));
+        assert_eq!(replies[6]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(
+            replies[7],
+            json!({"jsonrpc": "2.0", "id": 6, "result": null})
+        );
+    }
+
+    #[test]
+    fn position_test() {
+        let text = "(a \"é😀\" b)\nc";
+        let b = text.find('b').unwrap();
+        assert_eq!(position(text, b), json!({"line": 0, "character": 9}));
+        assert_eq!(offset(text, &json!({"line": 0, "character": 9})), Some(b));
+        assert_eq!(
+            offset(text, &json!({"line": 1, "character": 5})),
+            Some(text.len())
+        );
+        assert_eq!(offset(text, &json!({"line": 2, "character": 0})), None);
+        let (_, status) = session(vec![notification("exit", Json::Null)]);
+        assert_eq!(status, 1);
+    }
+}
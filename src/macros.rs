@@ -0,0 +1,218 @@
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::{
+    eval::{apply, Env, EvalError},
+    hooks,
+    parser::{Expr, Lambda},
+    sync::Shared,
+    value::{Closure, Value},
+    visit,
+};
+
+// A macro is a closure run on its unevaluated arguments, whose result is
+// read back as the code to evaluate in place of the call.
+pub fn expand_call(closure: &Shared<Closure>, args: &[Expr]) -> Result<Expr, EvalError> {
+    let args = args.iter().map(Value::from).collect();
+    Expr::try_from(apply(&Value::Closure(closure.clone()), args)?)
+}
+
+fn macro_named(expr: &Expr, env: &Env) -> Option<Shared<Closure>> {
+    let Expr::List(items) = expr else {
+        return None;
+    };
+    let Some(Expr::Symbol(name)) = items.first() else {
+        return None;
+    };
+    match env.get(*name)? {
+        Value::Macro(closure) => Some(closure),
+        _ => None,
+    }
+}
+
+// Expands expr once if it is a macro call, and returns it unchanged
+// otherwise.
+pub fn macroexpand_1(expr: &Expr, env: &Env) -> Result<Expr, EvalError> {
+    match (macro_named(expr, env), expr) {
+        (Some(closure), Expr::List(items)) => expand_call(&closure, &items[1..]),
+        _ => Ok(expr.clone()),
+    }
+}
+
+// Expands expr until it is no longer a macro call. Subforms are left alone.
+pub fn macroexpand(expr: &Expr, env: &Env) -> Result<Expr, EvalError> {
+    let mut expr = expr.clone();
+    while macro_named(&expr, env).is_some() {
+        expr = macroexpand_1(&expr, env)?;
+    }
+    Ok(expr)
+}
+
+// Expands every macro call in expr, including nested ones, so that eval
+// sees only special forms and procedure calls. Quoted data is left
+// untouched, as are the names bound by lambda, define, defmacro and let.
+// Expansion isn't hygienic: a local variable that shares a macro's name
+// doesn't stop the macro from being expanded.
+pub fn expand(expr: &Expr, env: &Env) -> Result<Expr, EvalError> {
+    let expanded = expand_form(expr, env)?;
+    if hooks::spanning() {
+        copy_spans(expr, &expanded, env);
+    }
+    Ok(expanded)
+}
+
+fn expand_form(expr: &Expr, env: &Env) -> Result<Expr, EvalError> {
+    let expr = macroexpand(expr, env)?;
+    let items = match expr {
+        Expr::List(items) => items,
+        Expr::Lambda(lambda) => {
+            return Ok(Expr::Lambda(Box::new(Lambda {
+                params: lambda.params,
+                rest: lambda.rest,
+                keys: lambda
+                    .keys
+                    .iter()
+                    .map(|(name, default)| {
+                        Ok((
+                            *name,
+                            default.as_ref().map(|d| expand_form(d, env)).transpose()?,
+                        ))
+                    })
+                    .collect::<Result<_, EvalError>>()?,
+                body: expand_all(&lambda.body, env)?,
+            })))
+        }
+        Expr::Vector(items) => return expand_all(&items, env).map(Expr::Vector),
+        Expr::Map(entries) => {
+            return entries
+                .iter()
+                .map(|(key, value)| Ok((expand_form(key, env)?, expand_form(value, env)?)))
+                .collect::<Result<_, _>>()
+                .map(Expr::Map)
+        }
+        other => return Ok(other),
+    };
+    visit::try_fold_code(items, |item| expand_form(&item, env)).map(Expr::List)
+}
+
+// Gives each list that expansion rebuilt around its expanded items the
+// span of the list it was rebuilt from, for a hook. Macro calls, and what
+// they expanded to, have none.
+fn copy_spans(expr: &Expr, expanded: &Expr, env: &Env) {
+    if macro_named(expr, env).is_some() {
+        return;
+    }
+    let (items, expanded_items) = match (expr, expanded) {
+        (Expr::List(items), Expr::List(expanded_items)) => {
+            hooks::copy_span(expr, expanded);
+            (items, expanded_items)
+        }
+        (Expr::Vector(items), Expr::Vector(expanded_items)) => (items, expanded_items),
+        (Expr::Lambda(lambda), Expr::Lambda(expanded)) => (&lambda.body, &expanded.body),
+        (Expr::Map(entries), Expr::Map(expanded_entries)) => {
+            for ((key, value), (expanded_key, expanded_value)) in
+                entries.iter().zip(expanded_entries)
+            {
+                copy_spans(key, expanded_key, env);
+                copy_spans(value, expanded_value, env);
+            }
+            return;
+        }
+        _ => return,
+    };
+    for (item, expanded) in items.iter().zip(expanded_items) {
+        copy_spans(item, expanded, env);
+    }
+}
+
+fn expand_all(exprs: &[Expr], env: &Env) -> Result<Vec<Expr>, EvalError> {
+    exprs.iter().map(|expr| expand_form(expr, env)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{builtins::global_env, eval::eval, parser::read_all};
+
+    fn run(source: &str, env: &mut Env) -> Result<Value, EvalError> {
+        let mut result = Value::Nil;
+        for expr in read_all(source).unwrap() {
+            result = eval(&expand(&expr, env)?, env)?;
+        }
+        Ok(result)
+    }
+
+    fn expr(source: &str) -> Expr {
+        crate::parser::read(source).unwrap()
+    }
+
+    const MACROS: &str = "
+        (defmacro my_when (test body) `(if ,test ,body ()))
+        (defmacro my_unless (test body) `(if ,test () ,body))
+        (defmacro my_or (a b) `(let ((t ,a)) (if t t ,b)))
+        (defmacro my_and (a b) `(if ,a ,b #f))
+        (defmacro twice (body) `(begin ,body ,body))
+        (defmacro my_unless2 (test body) `(my_when (my_not ,test) ,body))
+        (define (my_not x) (if x #f #t))";
+
+    #[test]
+    fn macroexpand_test() {
+        let mut env = global_env();
+        run(MACROS, &mut env).unwrap();
+        assert_eq!(
+            macroexpand_1(&expr("(my_unless2 a b)"), &env),
+            Ok(expr("(my_when (my_not a) b)"))
+        );
+        assert_eq!(
+            macroexpand(&expr("(my_unless2 a b)"), &env),
+            Ok(expr("(if (my_not a) b ())"))
+        );
+        assert_eq!(macroexpand(&expr("(f a)"), &env), Ok(expr("(f a)")));
+        assert_eq!(
+            expand(&expr("(f (my_when a b) '(my_when a b))"), &env),
+            Ok(expr("(f (if a b ()) '(my_when a b))"))
+        );
+        assert_eq!(
+            expand(&expr("(lambda (my_when) (twice x))"), &env),
+            Ok(expr("(lambda (my_when) (begin x x))"))
+        );
+        assert_eq!(
+            expand(&expr("(let ((x (twice y))) (my_and x y))"), &env),
+            Ok(expr("(let ((x (begin y y))) (if x y #f))"))
+        );
+        assert_eq!(
+            expand(&expr("(match (twice x) ((my_when a) (twice a)))"), &env),
+            Ok(expr("(match (begin x x) ((my_when a) (begin a a)))"))
+        );
+        assert_eq!(
+            expand(
+                &expr("(cond (twice x) ((my_when a b)) (else (twice y)))"),
+                &env
+            ),
+            Ok(expr("(cond (twice x) ((if a b ())) (else (begin y y)))"))
+        );
+    }
+
+    #[test]
+    fn defmacro_test() {
+        let mut env = global_env();
+        run(MACROS, &mut env).unwrap();
+        assert_eq!(run("(my_when #t 1)", &mut env), Ok(Value::Integer(1)));
+        assert_eq!(run("(my_when #f undefined)", &mut env), Ok(Value::Nil));
+        assert_eq!(run("(my_unless #f 2)", &mut env), Ok(Value::Integer(2)));
+        assert_eq!(run("(my_or #f 3)", &mut env), Ok(Value::Integer(3)));
+        assert_eq!(run("(my_and 1 #f)", &mut env), Ok(Value::Bool(false)));
+        assert_eq!(run("(my_unless2 #f 4)", &mut env), Ok(Value::Integer(4)));
+        // Calls that only show up at run time are expanded by eval.
+        assert_eq!(
+            eval(&expr("(my_when #t 5)"), &mut env),
+            Ok(Value::Integer(5))
+        );
+        assert!(matches!(
+            run("(defmacro 1 2)", &mut env),
+            Err(EvalError::MalformedForm {
+                form: "defmacro",
+                ..
+            })
+        ));
+    }
+}
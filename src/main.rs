@@ -19,31 +19,19 @@
 // Evaluate
 // Print
 
-// use std::{error::Error, io::stdin};
-
-// type BoxErr = Box<dyn Error>;
-
-// Read
-// fn read_stdin() -> Result<Input, BoxErr> {
-//     let mut buffer = String::new();
-//     stdin().read_line(&mut buffer);
-//     parse(buffer)
-// }
-
-// enum Output {}
-
-// struct State {}
-
-// struct Interpreter {
-//     state: State,
-// }
-
-// impl Interpreter {
-//     fn eval(&mut self, input: &Input) -> Output {}
-// }
-
-// fn effect(output: &Output) {}
-
-fn main() {
-    println!("Hello, world!");
+use std::{env, io, process};
+
+// `tuple-gd run file.lisp` runs a program, `tuple-gd -e EXPR` prints the
+// value of an expression and `tuple-gd -` that of a program on standard
+// input, `tuple-gd check file.lisp` finds its errors, `tuple-gd expand
+// file.lisp` prints it with its macros expanded, `tuple-gd dump
+// --tokens|--ast [--json] file.lisp` prints what the lexer or parser made
+// of it, `tuple-gd fmt file.lisp` writes it formatted and `tuple-gd disasm
+// file.lisp` prints its bytecode; `tuple-gd test` runs the tests under the
+// current directory and `tuple-gd doc src -o docs` documents the files
+// under src, and with no arguments the REPL starts.
+fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let status = tuple_gd::cli::run(&args, io::stdout(), io::stderr())?;
+    process::exit(status)
 }
@@ -0,0 +1,149 @@
+use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+use core::fmt;
+
+use crate::{
+    eval::{Env, EvalError},
+    parser::Expr,
+    symbol::SymbolId,
+    sync::{MaybeSync, Shared},
+    value::Value,
+};
+
+// A module's own environment, a child of the one it was defined in, and
+// the names it lets other code see.
+#[derive(Clone, Debug)]
+pub struct Module {
+    env: Env,
+    exports: Vec<SymbolId>,
+}
+
+impl Module {
+    pub(crate) fn new(env: Env, exports: Vec<SymbolId>) -> Self {
+        Module { env, exports }
+    }
+
+    pub fn exports(&self) -> &[SymbolId] {
+        &self.exports
+    }
+
+    // The current value of an exported name. Names the module defines but
+    // doesn't export aren't visible.
+    pub fn get(&self, name: impl Into<SymbolId>) -> Option<Value> {
+        let name = name.into();
+        self.exports
+            .contains(&name)
+            .then(|| self.env.get(name))
+            .flatten()
+    }
+
+    // Every export as it would be written from outside: name/export and
+    // name::export, with its value.
+    pub(crate) fn qualified(&self, name: SymbolId) -> Vec<(SymbolId, Value)> {
+        let mut bindings = Vec::new();
+        for export in &self.exports {
+            if let Some(value) = self.env.get(*export) {
+                for separator in ["/", "::"] {
+                    let qualified = format!("{name}{separator}{export}");
+                    bindings.push((SymbolId::new(&qualified), value.clone()));
+                }
+            }
+        }
+        bindings
+    }
+}
+
+// Where import looks for a module that hasn't been defined: the source of
+// the module, from wherever the host keeps it, such as assets in the
+// binary or a database. The source is run where the import is, and has to
+// define the module.
+pub trait ModuleResolver: MaybeSync {
+    fn load(&self, name: &str) -> Result<String, String>;
+}
+
+impl<F: Fn(&str) -> Result<String, String> + MaybeSync> ModuleResolver for F {
+    fn load(&self, name: &str) -> Result<String, String> {
+        self(name)
+    }
+}
+
+// The modules an interpreter has defined, by name. Names are global:
+// defining a module again replaces it for the imports that come after.
+#[derive(Clone, Default)]
+pub struct ModuleRegistry {
+    modules: BTreeMap<SymbolId, Module>,
+    resolver: Option<Shared<dyn ModuleResolver>>,
+    // The modules whose sources are being run, so one that imports itself
+    // before defining itself is unknown rather than loaded forever.
+    resolving: Vec<SymbolId>,
+}
+
+impl fmt::Debug for ModuleRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ModuleRegistry")
+            .field("modules", &self.modules)
+            .field("resolver", &self.resolver.is_some())
+            .finish()
+    }
+}
+
+impl ModuleRegistry {
+    pub fn get(&self, name: impl Into<SymbolId>) -> Option<&Module> {
+        self.modules.get(&name.into())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = SymbolId> + '_ {
+        self.modules.keys().copied()
+    }
+
+    pub(crate) fn insert(&mut self, name: SymbolId, module: Module) {
+        self.modules.insert(name, module);
+    }
+
+    pub(crate) fn set_resolver(&mut self, resolver: Shared<dyn ModuleResolver>) {
+        self.resolver = Some(resolver);
+    }
+
+    // The resolver to find name with, if there is one and name isn't
+    // already being found. finish_resolving has to follow.
+    pub(crate) fn start_resolving(&mut self, name: SymbolId) -> Option<Shared<dyn ModuleResolver>> {
+        if self.resolving.contains(&name) {
+            return None;
+        }
+        let resolver = self.resolver.clone()?;
+        self.resolving.push(name);
+        Some(resolver)
+    }
+
+    pub(crate) fn finish_resolving(&mut self) {
+        self.resolving.pop();
+    }
+}
+
+// The parts of (module name (export a ...) body ...): its name, its
+// exports and its body.
+pub(crate) fn parse_module(args: &[Expr]) -> Result<(SymbolId, Vec<SymbolId>, &[Expr]), EvalError> {
+    let malformed = || EvalError::MalformedForm {
+        form: "module",
+        found: Expr::List(
+            core::iter::once(Expr::Symbol("module".into()))
+                .chain(args.iter().cloned())
+                .collect(),
+        ),
+    };
+    let [Expr::Symbol(name), Expr::List(export), body @ ..] = args else {
+        return Err(malformed());
+    };
+    match export.split_first() {
+        Some((Expr::Symbol(head), names)) if head.as_str() == "export" => {
+            let exports = names
+                .iter()
+                .map(|name| match name {
+                    Expr::Symbol(name) => Ok(*name),
+                    _ => Err(malformed()),
+                })
+                .collect::<Result<_, _>>()?;
+            Ok((*name, exports, body))
+        }
+        _ => Err(malformed()),
+    }
+}
@@ -0,0 +1,132 @@
+use alloc::vec::Vec;
+
+use crate::{
+    builtins,
+    parser::Expr,
+    symbol::{self, SymbolId},
+    value::Value,
+    visit::Folder,
+};
+
+// Builtins whose result depends only on their arguments, so a call with
+// constant arguments can be made once, ahead of time.
+const PURE: &[&str] = &[
+    "+", "-", "*", "/", "quotient", "exact->inexact", "exact?", "=", "<", ">", "<=", ">=",
+];
+
+// Rewrites expr into a cheaper form with the same meaning: calls of pure
+// arithmetic on constants are replaced by their result, an if whose test
+// is a constant by the branch it takes, and a begin of one form by that
+// form. A call that would fail is left for the evaluator to report. Like
+// any static pass it assumes the builtins and special forms keep their
+// usual meaning, i.e. that the program doesn't rebind +, if and the rest.
+pub fn optimize(expr: Expr) -> Expr {
+    Optimizer.fold_expr(expr)
+}
+
+struct Optimizer;
+
+impl Folder for Optimizer {
+    // Only code is rewritten: quoted data, the names a form binds and
+    // match patterns are left as they were written.
+    fn fold_list(&mut self, items: Vec<Expr>) -> Expr {
+        let head = match items.first() {
+            Some(Expr::Symbol(name)) => *name,
+            _ => return Expr::List(self.fold_code(items)),
+        };
+        simplify(head, self.fold_code(items))
+    }
+}
+
+// Atoms that evaluate to themselves.
+fn is_constant(expr: &Expr) -> bool {
+    match expr {
+        Expr::Nil
+        | Expr::Bool(_)
+        | Expr::Integer(_)
+        | Expr::Rational(_)
+        | Expr::Float(_)
+        | Expr::Char(_)
+        | Expr::String(_)
+        | Expr::Keyword(_) => true,
+        #[cfg(feature = "bigint")]
+        Expr::BigInteger(_) => true,
+        _ => false,
+    }
+}
+
+// items is the form with its subforms already optimized.
+fn simplify(head: SymbolId, mut items: Vec<Expr>) -> Expr {
+    match (head, items.len()) {
+        (symbol::IF, 3 | 4) if is_constant(&items[1]) => {
+            let taken = !matches!(items[1], Expr::Nil | Expr::Bool(false));
+            match (taken, items.len()) {
+                (true, _) => items.swap_remove(2),
+                (false, 4) => items.swap_remove(3),
+                // () is how nil is written in code.
+                (false, _) => Expr::List(Vec::new()),
+            }
+        }
+        (symbol::BEGIN, 2) => items.swap_remove(1),
+        _ if PURE.contains(&head.as_str()) && items[1..].iter().all(is_constant) => {
+            let args: Vec<Value> = items[1..].iter().map(Value::from).collect();
+            builtins::find(head.as_str())
+                .and_then(|func| func(&args).ok())
+                .and_then(|value| Expr::try_from(value).ok())
+                .unwrap_or(Expr::List(items))
+        }
+        _ => Expr::List(items),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{builtins::global_env, eval::eval, parser::read};
+
+    fn assert_optimizes(input: &str, expected: &str) {
+        let expr = read(input).unwrap();
+        let optimized = optimize(expr.clone());
+        assert_eq!(optimized, read(expected).unwrap(), "optimizing {input}");
+        assert_eq!(
+            eval(&optimized, &mut global_env()),
+            eval(&expr, &mut global_env()),
+            "optimizing {input} keeps its value"
+        );
+    }
+
+    #[test]
+    fn fold_test() {
+        assert_optimizes("(+ 1 (* 2 3))", "7");
+        assert_optimizes("(/ 1 2)", "1/2");
+        assert_optimizes("(< 1 2.5)", "#t");
+        assert_optimizes("(list (- 10 4) (exact->inexact 1))", "(list 6 1.0)");
+        // Errors are left for run time.
+        assert_optimizes("(+ 1 (/ 1 0))", "(+ 1 (/ 1 0))");
+        assert_optimizes("(+ 1 \"a\")", "(+ 1 \"a\")");
+    }
+
+    #[test]
+    fn branch_test() {
+        assert_optimizes("(if (< 1 2) 'yes 'no)", "'yes");
+        assert_optimizes("(if #f 1 (begin (* 2 2)))", "4");
+        assert_optimizes("(if #f 1)", "()");
+        assert_optimizes("(if 0 \"truthy\" \"falsy\")", "\"truthy\"");
+        assert_optimizes("(begin (+ 1 1) 2)", "(begin 2 2)");
+    }
+
+    #[test]
+    fn binding_test() {
+        assert_optimizes(
+            "(let ((x (+ 1 2)) (begin 5)) (* x begin))",
+            "(let ((x 3) (begin 5)) (* x begin))",
+        );
+        assert_optimizes("((lambda (begin x) (+ 1 1)) 1 2)", "((lambda (begin x) 2) 1 2)");
+        assert_optimizes("'(+ 1 2)", "'(+ 1 2)");
+        assert_optimizes(
+            "(match (+ 1 1) ((begin x) x) (2 (if #t 'two)))",
+            "(match 2 ((begin x) x) (2 'two))",
+        );
+        assert_optimizes("(cond (+ 1) ((= 1 1) (* 2 3)))", "(cond (+ 1) (#t 6))");
+    }
+}
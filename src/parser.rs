@@ -1,46 +1,214 @@
-use std::{
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{
+    error::Error,
+    fmt,
     iter::Enumerate,
     ops::{Range, RangeFrom, RangeFull, RangeTo},
 };
 
+use nom::error::{ErrorKind, ParseError};
 use nom::{
     branch::alt,
     bytes::complete::take,
-    combinator::{map, value},
-    multi::many0,
-    sequence::delimited,
+    combinator::map,
+    sequence::{pair, terminated},
 };
-use nom::{combinator::verify, sequence::preceded};
-use nom::{AsBytes, IResult, InputIter, InputLength, InputTake, Slice};
+use nom::{IResult, InputIter, InputLength, InputTake, Slice};
 
-use crate::lexer::{self, lex, Token};
+#[cfg(feature = "bigint")]
+use crate::bigint::BigInt;
+use crate::lexer::{is_symbol_char, lex, LexError, LexErrorKind, Span, Spanned, Token};
+use crate::rational::Rational;
+use crate::reader;
+use crate::symbol::{self, SymbolId};
 
+// Equality is structural: Integer(1) and Float(1.0) are different
+// expressions, and a NaN float is not equal to itself. Numeric comparison
+// across integers and floats is the evaluator's job, not PartialEq's.
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expr {
     Nil,
-    // Bool(bool),
+    Bool(bool),
     Integer(i64),
-    // Float(f64),
+    // Only for integers outside the i64 range, so each number has a single
+    // representation.
+    #[cfg(feature = "bigint")]
+    BigInteger(Box<BigInt>),
+    Rational(Rational),
+    Float(f64),
+    Char(char),
     String(String),
-    Symbol(String),
-    Lambda(Vec<String>, Vec<Expr>),
+    Symbol(SymbolId),
+    Keyword(SymbolId),
+    Lambda(Box<Lambda>),
     List(Vec<Expr>),
+    Pair(Box<Expr>, Box<Expr>),
+    Vector(Vec<Expr>),
+    Map(Vec<(Expr, Expr)>),
+}
+
+// Lambda is boxed so it doesn't set the size of every Expr. A rest
+// parameter collects the arguments past the named ones into a list. Keys
+// are the parameters after &key, passed by name as :name value, each with
+// the expression for its default.
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Lambda {
+    pub params: Vec<SymbolId>,
+    pub rest: Option<SymbolId>,
+    pub keys: Vec<(SymbolId, Option<Expr>)>,
+    pub body: Vec<Expr>,
+}
+
+impl Expr {
+    // Proper lists are always List, so a Pair only ever holds an improper
+    // list: its cdr is never a list. Consing onto a list makes a longer
+    // list, and consing onto anything else makes a pair.
+    pub fn cons(car: Expr, cdr: Expr) -> Expr {
+        match cdr {
+            Expr::Nil => Expr::List(vec![car]),
+            Expr::List(mut items) => {
+                items.insert(0, car);
+                Expr::List(items)
+            }
+            cdr => Expr::Pair(Box::new(car), Box::new(cdr)),
+        }
+    }
+}
+
+impl Lambda {
+    // The (lambda (params... . rest) body...) list the lambda was read from.
+    pub fn to_expr(&self) -> Expr {
+        let mut params: Vec<Expr> = self
+            .params
+            .iter()
+            .map(|&param| Expr::Symbol(param))
+            .collect();
+        if !self.keys.is_empty() {
+            params.push(Expr::Symbol(symbol::AND_KEY));
+        }
+        params.extend(self.keys.iter().map(|(name, default)| match default {
+            Some(default) => Expr::List(vec![Expr::Symbol(*name), default.clone()]),
+            None => Expr::Symbol(*name),
+        }));
+        let params = params.into_iter().rev();
+        let rest = self.rest.map_or(Expr::Nil, Expr::Symbol);
+        let mut items = vec![
+            Expr::Symbol(symbol::LAMBDA),
+            params.fold(rest, |cdr, car| Expr::cons(car, cdr)),
+        ];
+        items.extend(self.body.iter().cloned());
+        Expr::List(items)
+    }
+}
+
+const _: () = assert!(core::mem::size_of::<Expr>() <= 32);
+
+// Parallel tree to Expr that remembers where each node came from.
+#[derive(Clone, PartialEq, Debug)]
+pub enum SpannedExpr {
+    Atom(Spanned<Expr>),
+    List(Spanned<Vec<SpannedExpr>>),
+    DottedList(Spanned<(Vec<SpannedExpr>, Box<SpannedExpr>)>),
+    Vector(Spanned<Vec<SpannedExpr>>),
+    Map(Spanned<Vec<(SpannedExpr, SpannedExpr)>>),
+}
+
+impl SpannedExpr {
+    pub fn span(&self) -> Span {
+        match self {
+            SpannedExpr::Atom(atom) => atom.span,
+            SpannedExpr::List(list) => list.span,
+            SpannedExpr::DottedList(list) => list.span,
+            SpannedExpr::Vector(vector) => vector.span,
+            SpannedExpr::Map(map) => map.span,
+        }
+    }
+
+    pub fn to_expr(&self) -> Expr {
+        match self {
+            SpannedExpr::Atom(atom) => atom.value.clone(),
+            SpannedExpr::List(list) => Expr::List(list.value.iter().map(Self::to_expr).collect()),
+            SpannedExpr::DottedList(list) => {
+                let (items, tail) = &list.value;
+                items
+                    .iter()
+                    .rev()
+                    .fold(tail.to_expr(), |cdr, car| Expr::cons(car.to_expr(), cdr))
+            }
+            SpannedExpr::Vector(vector) => {
+                Expr::Vector(vector.value.iter().map(Self::to_expr).collect())
+            }
+            SpannedExpr::Map(map) => Expr::Map(
+                map.value
+                    .iter()
+                    .map(|(k, v)| (k.to_expr(), v.to_expr()))
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn into_expr(self) -> Expr {
+        match self {
+            SpannedExpr::Atom(atom) => atom.value,
+            SpannedExpr::List(list) => {
+                Expr::List(list.value.into_iter().map(Self::into_expr).collect())
+            }
+            SpannedExpr::DottedList(list) => {
+                let (items, tail) = list.value;
+                items.into_iter().rev().fold(tail.into_expr(), |cdr, car| {
+                    Expr::cons(car.into_expr(), cdr)
+                })
+            }
+            SpannedExpr::Vector(vector) => {
+                Expr::Vector(vector.value.into_iter().map(Self::into_expr).collect())
+            }
+            SpannedExpr::Map(map) => Expr::Map(
+                map.value
+                    .into_iter()
+                    .map(|(k, v)| (k.into_expr(), v.into_expr()))
+                    .collect(),
+            ),
+        }
+    }
 }
 
+// The parser's input: a view of the lexed tokens that nom advances
+// through. It can be used as the input of combinators written outside the
+// crate too, over tokens from lexer::lex. Indices past the end are clamped
+// to it, so no count or range makes taking or slicing panic; nom checks
+// counts with slice_index before it takes anyway.
 #[derive(Clone, PartialEq, Debug)]
 pub struct Tokens<'a> {
-    tokens: &'a [Token],
-    start: usize,
-    end: usize,
+    pub(crate) tokens: &'a [Spanned<Token<'a>>],
 }
 
 impl<'a> Tokens<'a> {
-    fn new(tokens: &'a [Token]) -> Self {
-        Tokens {
-            tokens,
-            start: 0,
-            end: tokens.len(),
-        }
+    pub fn new(tokens: &'a [Spanned<Token<'a>>]) -> Self {
+        Tokens { tokens }
+    }
+
+    // The tokens left to parse.
+    pub fn tokens(&self) -> &'a [Spanned<Token<'a>>] {
+        self.tokens
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    fn clamp(&self, index: usize) -> usize {
+        index.min(self.tokens.len())
     }
 }
 
@@ -52,31 +220,17 @@ impl<'a> InputLength for Tokens<'a> {
 
 impl<'a> InputTake for Tokens<'a> {
     fn take(&self, count: usize) -> Self {
-        Tokens {
-            tokens: &self.tokens[0..count],
-            start: 0,
-            end: count,
-        }
+        Tokens::new(&self.tokens[..self.clamp(count)])
     }
 
+    // nom's order: what is left first, then what was taken.
     fn take_split(&self, count: usize) -> (Self, Self) {
-        let (first, second) = self.tokens.split_at(count);
-        (
-            Tokens {
-                tokens: second,
-                start: 0,
-                end: second.len(),
-            },
-            Tokens {
-                tokens: first,
-                start: 0,
-                end: first.len(),
-            },
-        )
+        let (taken, rest) = self.tokens.split_at(self.clamp(count));
+        (Tokens::new(rest), Tokens::new(taken))
     }
 }
 
-impl InputLength for Token {
+impl InputLength for Spanned<Token<'_>> {
     fn input_len(&self) -> usize {
         1
     }
@@ -84,11 +238,8 @@ impl InputLength for Token {
 
 impl<'a> Slice<Range<usize>> for Tokens<'a> {
     fn slice(&self, range: Range<usize>) -> Self {
-        Tokens {
-            tokens: self.tokens.slice(range.clone()),
-            start: self.start + range.start,
-            end: self.start + range.end,
-        }
+        let end = self.clamp(range.end);
+        Tokens::new(&self.tokens[range.start.min(end)..end])
     }
 }
 
@@ -100,24 +251,20 @@ impl<'a> Slice<RangeTo<usize>> for Tokens<'a> {
 
 impl<'a> Slice<RangeFrom<usize>> for Tokens<'a> {
     fn slice(&self, range: RangeFrom<usize>) -> Self {
-        self.slice(range.start..self.end - self.end)
+        self.slice(range.start..self.tokens.len())
     }
 }
 
 impl<'a> Slice<RangeFull> for Tokens<'a> {
-    fn slice(&self, range: RangeFull) -> Self {
-        Tokens {
-            tokens: self.tokens,
-            start: self.start,
-            end: self.end,
-        }
+    fn slice(&self, _: RangeFull) -> Self {
+        self.clone()
     }
 }
 
 impl<'a> InputIter for Tokens<'a> {
-    type Item = &'a Token;
-    type Iter = Enumerate<::std::slice::Iter<'a, Token>>;
-    type IterElem = ::std::slice::Iter<'a, Token>;
+    type Item = &'a Spanned<Token<'a>>;
+    type Iter = Enumerate<core::slice::Iter<'a, Spanned<Token<'a>>>>;
+    type IterElem = core::slice::Iter<'a, Spanned<Token<'a>>>;
 
     fn iter_elements(&self) -> Self::IterElem {
         self.tokens.iter()
@@ -138,59 +285,728 @@ impl<'a> InputIter for Tokens<'a> {
         if self.tokens.len() >= count {
             Ok(count)
         } else {
-            Err(nom::Needed::Unknown)
+            Err(nom::Needed::new(count - self.tokens.len()))
+        }
+    }
+}
+
+// Spans are byte offsets into the source; an error at end of input has
+// an empty span at the end.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ReadError {
+    Lex {
+        span: Span,
+        kind: LexErrorKind,
+    },
+    Parse {
+        span: Span,
+        expected: Expected,
+        found: Option<Token<'static>>,
+    },
+    TrailingInput {
+        span: Span,
+        found: Token<'static>,
+    },
+    // The token that took nesting past MAX_DEPTH.
+    TooDeep {
+        span: Span,
+    },
+    // A read table handler turned down the datum after its prefix.
+    Dispatch {
+        span: Span,
+        message: String,
+    },
+}
+
+impl ReadError {
+    pub fn span(&self) -> Span {
+        match self {
+            ReadError::Lex { span, .. }
+            | ReadError::Parse { span, .. }
+            | ReadError::TrailingInput { span, .. }
+            | ReadError::TooDeep { span }
+            | ReadError::Dispatch { span, .. } => *span,
+        }
+    }
+
+    // True when the input ended in the middle of a form, so more input
+    // could still make it readable.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self,
+            ReadError::Parse { found: None, .. }
+                | ReadError::Lex {
+                    kind: LexErrorKind::UnterminatedString
+                        | LexErrorKind::UnterminatedSymbol
+                        | LexErrorKind::UnterminatedComment,
+                    ..
+                }
+        )
+    }
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Lex { span, kind } => {
+                let what = match kind {
+                    LexErrorKind::UnexpectedCharacter => "unexpected character",
+                    LexErrorKind::UnterminatedString => "unterminated string",
+                    LexErrorKind::UnterminatedSymbol => "unterminated |symbol|",
+                    LexErrorKind::UnterminatedComment => "unterminated block comment",
+                    LexErrorKind::InvalidEscape => "invalid escape sequence",
+                    LexErrorKind::UnknownCharacter => "unknown character name",
+                    LexErrorKind::IntegerOverflow => "integer too large",
+                };
+                write!(f, "{what} at byte {}", span.start)
+            }
+            ReadError::Parse {
+                span,
+                expected,
+                found: Some(token),
+            } => write!(
+                f,
+                "expected {expected}, found {token} at byte {}",
+                span.start
+            ),
+            ReadError::Parse {
+                expected,
+                found: None,
+                ..
+            } => write!(f, "expected {expected}, found end of input"),
+            ReadError::TrailingInput { span, found } => {
+                write!(f, "trailing {found} at byte {}", span.start)
+            }
+            ReadError::TooDeep { span } => write!(
+                f,
+                "forms nested more than {MAX_DEPTH} deep at byte {}",
+                span.start
+            ),
+            ReadError::Dispatch { span, message } => write!(f, "{message} at byte {}", span.start),
+        }
+    }
+}
+
+impl Error for ReadError {}
+
+// What the parser was looking for when it failed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Expected {
+    Expr,
+    LParan,
+    RParan,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Dot,
+    Quote,
+    DatumComment,
+    Bool,
+    Integer,
+    Rational,
+    Float,
+    Char,
+    String,
+    Symbol,
+    Keyword,
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Expected::Expr => "expression",
+            Expected::LParan => "`(`",
+            Expected::RParan => "`)`",
+            Expected::LBracket => "`[`",
+            Expected::RBracket => "`]`",
+            Expected::LBrace => "`{`",
+            Expected::RBrace => "`}`",
+            Expected::Dot => "`.`",
+            Expected::Quote => "quote",
+            Expected::DatumComment => "`#;`",
+            Expected::Bool => "boolean",
+            Expected::Integer => "integer",
+            Expected::Rational => "rational",
+            Expected::Float => "float",
+            Expected::Char => "character",
+            Expected::String => "string",
+            Expected::Symbol => "symbol",
+            Expected::Keyword => "keyword",
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct TokenError<'a> {
+    pub input: Tokens<'a>,
+    pub expected: Expected,
+    // Why a read table handler turned down what it was given.
+    pub rejected: Option<String>,
+}
+
+impl<'a> TokenError<'a> {
+    fn new(input: Tokens<'a>, expected: Expected) -> Self {
+        TokenError {
+            input,
+            expected,
+            rejected: None,
+        }
+    }
+}
+
+// Of two failed alternatives, the one that got further into the input
+// says more about what went wrong.
+impl<'a> ParseError<Tokens<'a>> for TokenError<'a> {
+    fn from_error_kind(input: Tokens<'a>, _: ErrorKind) -> Self {
+        TokenError::new(input, Expected::Expr)
+    }
+
+    fn append(_: Tokens<'a>, _: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    fn or(self, other: Self) -> Self {
+        if self.input.tokens.len() < other.input.tokens.len() {
+            self
+        } else {
+            other
         }
     }
 }
 
-#[derive(Debug)]
-pub struct CustomParserError(String);
+pub type ParseResult<'a, T> = IResult<Tokens<'a>, T, TokenError<'a>>;
 
 macro_rules! tag_token (
-    ($func_name:ident, $tag: pat) => (
-        fn $func_name(tokens: Tokens) -> IResult<Tokens, Tokens> {
-            verify(take(1usize), |x: &Tokens| match x.tokens[0] {
-                $tag => true,
-                _ => false,
-            })(tokens)
+    ($func_name:ident, $tag: pat, $expected: expr) => (
+        fn $func_name(tokens: Tokens) -> ParseResult<Tokens> {
+            match tokens.tokens.first().map(|x| &x.value) {
+                Some($tag) => Ok(take(1usize)(tokens)?),
+                _ => Err(nom::Err::Error(TokenError::new(tokens, $expected))),
+            }
         }
     )
   );
 
-tag_token!(tag_lparan, Token::LParan);
-tag_token!(tag_rparan, Token::RParan);
-tag_token!(tag_integer, Token::Integer(_));
-tag_token!(tag_symbol, Token::Symbol(_));
+tag_token!(tag_lparan, Token::LParan, Expected::LParan);
+tag_token!(tag_rparan, Token::RParan, Expected::RParan);
+tag_token!(tag_dot, Token::Dot, Expected::Dot);
+tag_token!(tag_lbracket, Token::LBracket, Expected::LBracket);
+tag_token!(tag_rbracket, Token::RBracket, Expected::RBracket);
+tag_token!(tag_lbrace, Token::LBrace, Expected::LBrace);
+tag_token!(tag_rbrace, Token::RBrace, Expected::RBrace);
+tag_token!(
+    tag_quote,
+    Token::Quote | Token::Quasiquote | Token::Unquote | Token::UnquoteSplicing,
+    Expected::Quote
+);
+tag_token!(
+    tag_datum_comment,
+    Token::DatumComment,
+    Expected::DatumComment
+);
+tag_token!(tag_dispatch, Token::Dispatch(..), Expected::Expr);
+tag_token!(tag_bool, Token::Bool(_), Expected::Bool);
+#[cfg(not(feature = "bigint"))]
+tag_token!(tag_integer, Token::Integer(_), Expected::Integer);
+#[cfg(feature = "bigint")]
+tag_token!(
+    tag_integer,
+    Token::Integer(_) | Token::BigInteger(_),
+    Expected::Integer
+);
+tag_token!(tag_rational, Token::Rational(_), Expected::Rational);
+tag_token!(tag_float, Token::Float(_), Expected::Float);
+tag_token!(tag_char, Token::Char(_), Expected::Char);
+tag_token!(tag_string, Token::String(_), Expected::String);
+tag_token!(tag_symbol, Token::Symbol(_), Expected::Symbol);
+tag_token!(tag_keyword, Token::Keyword(_), Expected::Keyword);
 
-pub fn parse_integer(input: Tokens) -> IResult<Tokens, Expr> {
-    map(tag_integer, |x| match &x.tokens[0] {
-        Token::Integer(i) => Expr::Integer(i.clone()),
+pub fn parse_bool(input: Tokens) -> ParseResult<SpannedExpr> {
+    map(tag_bool, |x| match &x.tokens[0].value {
+        Token::Bool(b) => SpannedExpr::Atom(Spanned::new(Expr::Bool(*b), x.tokens[0].span)),
         _ => unreachable!(),
     })(input)
 }
 
-pub fn parse_symbol(input: Tokens) -> IResult<Tokens, Expr> {
-    map(tag_symbol, |x| match &x.tokens[0] {
-        Token::Symbol(s) => Expr::Symbol(s.clone()),
+pub fn parse_integer(input: Tokens) -> ParseResult<SpannedExpr> {
+    map(tag_integer, |x| match &x.tokens[0].value {
+        Token::Integer(i) => SpannedExpr::Atom(Spanned::new(Expr::Integer(*i), x.tokens[0].span)),
+        #[cfg(feature = "bigint")]
+        Token::BigInteger(i) => SpannedExpr::Atom(Spanned::new(
+            Expr::BigInteger(Box::new(i.clone())),
+            x.tokens[0].span,
+        )),
         _ => unreachable!(),
     })(input)
 }
 
-pub fn parse_list(input: Tokens) -> IResult<Tokens, Expr> {
-    map(
-        delimited(
-            tag_lparan,
-            many0(alt((parse_integer, parse_symbol, parse_list))),
-            tag_rparan,
-        ),
-        |x| Expr::List(x),
-    )(input)
+pub fn parse_rational(input: Tokens) -> ParseResult<SpannedExpr> {
+    map(tag_rational, |x| match &x.tokens[0].value {
+        Token::Rational(r) => SpannedExpr::Atom(Spanned::new(Expr::Rational(*r), x.tokens[0].span)),
+        _ => unreachable!(),
+    })(input)
+}
+
+pub fn parse_float(input: Tokens) -> ParseResult<SpannedExpr> {
+    map(tag_float, |x| match &x.tokens[0].value {
+        Token::Float(f) => SpannedExpr::Atom(Spanned::new(Expr::Float(*f), x.tokens[0].span)),
+        _ => unreachable!(),
+    })(input)
+}
+
+pub fn parse_char(input: Tokens) -> ParseResult<SpannedExpr> {
+    map(tag_char, |x| match &x.tokens[0].value {
+        Token::Char(c) => SpannedExpr::Atom(Spanned::new(Expr::Char(*c), x.tokens[0].span)),
+        _ => unreachable!(),
+    })(input)
+}
+
+pub fn parse_string(input: Tokens) -> ParseResult<SpannedExpr> {
+    map(tag_string, |x| match &x.tokens[0].value {
+        Token::String(s) => {
+            SpannedExpr::Atom(Spanned::new(Expr::String(s.to_string()), x.tokens[0].span))
+        }
+        _ => unreachable!(),
+    })(input)
+}
+
+pub fn parse_symbol(input: Tokens) -> ParseResult<SpannedExpr> {
+    map(tag_symbol, |x| match &x.tokens[0].value {
+        Token::Symbol(s) => SpannedExpr::Atom(Spanned::new(
+            Expr::Symbol(SymbolId::new(s)),
+            x.tokens[0].span,
+        )),
+        _ => unreachable!(),
+    })(input)
+}
+
+pub fn parse_keyword(input: Tokens) -> ParseResult<SpannedExpr> {
+    map(tag_keyword, |x| match &x.tokens[0].value {
+        Token::Keyword(s) => SpannedExpr::Atom(Spanned::new(
+            Expr::Keyword(SymbolId::new(s)),
+            x.tokens[0].span,
+        )),
+        _ => unreachable!(),
+    })(input)
+}
+
+// 'x reads as (quote x), and likewise for quasiquote and unquote.
+pub fn parse_quoted(input: Tokens) -> ParseResult<SpannedExpr> {
+    map(pair(tag_quote, parse_expr), |(q, datum)| {
+        let prefix = &q.tokens[0];
+        let name = match prefix.value {
+            Token::Quote => symbol::QUOTE,
+            Token::Quasiquote => symbol::QUASIQUOTE,
+            Token::Unquote => symbol::UNQUOTE,
+            Token::UnquoteSplicing => symbol::UNQUOTE_SPLICING,
+            _ => unreachable!(),
+        };
+        let span = prefix.span.to(datum.span());
+        let symbol = SpannedExpr::Atom(Spanned::new(Expr::Symbol(name), prefix.span));
+        SpannedExpr::List(Spanned::new(vec![symbol, datum], span))
+    })(input)
+}
+
+// A read table prefix and the datum after it read as whatever the
+// prefix's handler makes of the datum. The handler sees the datum as
+// written, so #x10 gives it the integer 10.
+pub fn parse_dispatch(input: Tokens) -> ParseResult<SpannedExpr> {
+    let (rest, d) = tag_dispatch(input)?;
+    let (rest, datum) = parse_expr(rest)?;
+    let prefix = &d.tokens[0];
+    let Token::Dispatch(lead, sub) = prefix.value else {
+        unreachable!()
+    };
+    let span = prefix.span.to(datum.span());
+    match reader::dispatch(lead, sub, datum.into_expr()) {
+        Ok(expr) => Ok((rest, SpannedExpr::Atom(Spanned::new(expr, span)))),
+        Err(message) => Err(nom::Err::Failure(TokenError {
+            rejected: Some(message),
+            ..TokenError::new(d, Expected::Expr)
+        })),
+    }
+}
+
+// A `#;` must be followed by the datum it comments out, so an error in
+// that datum is returned rather than leaving the `#;` for the caller.
+pub(crate) fn skip_datum_comments(mut input: Tokens) -> ParseResult<()> {
+    while let Ok((rest, _)) = tag_datum_comment(input.clone()) {
+        (input, _) = parse_expr(rest)?;
+    }
+    Ok((input, ()))
+}
+
+// When no alternative gets past the first token the error is reported as
+// "expected expression" rather than whichever alternative was tried last.
+pub fn parse_expr(input: Tokens) -> ParseResult<SpannedExpr> {
+    let (input, _) = skip_datum_comments(input)?;
+    alt((
+        parse_bool,
+        parse_integer,
+        parse_rational,
+        parse_float,
+        parse_char,
+        parse_string,
+        parse_symbol,
+        parse_keyword,
+        parse_quoted,
+        parse_dispatch,
+        parse_list,
+        parse_vector,
+        parse_map,
+    ))(input.clone())
+    .map_err(|e| {
+        e.map(|e| match e.input.tokens.len() == input.tokens.len() {
+            true => TokenError::new(input.clone(), Expected::Expr),
+            false => e,
+        })
+    })
+}
+
+// many0 would throw away why the last element failed, so elements are
+// parsed by hand. A missing `)` is reported at whichever failure got
+// further: an unfinished element or the token where `)` was expected.
+// (a b . c) has at least one element before the dot and exactly one after.
+pub fn parse_list(input: Tokens) -> ParseResult<SpannedExpr> {
+    let (mut rest, l) = tag_lparan(input)?;
+    let mut items = Vec::new();
+    loop {
+        match parse_expr(rest.clone()) {
+            Ok((after, item)) => {
+                items.push(item);
+                rest = after;
+            }
+            Err(nom::Err::Error(item_error)) => {
+                let (after, _) = skip_datum_comments(rest)?;
+                let (after, tail) = match tag_dot(after.clone()) {
+                    Ok((after, _)) if !items.is_empty() => {
+                        let (after, tail) = parse_expr(after)?;
+                        let (after, _) = skip_datum_comments(after)?;
+                        (after, Some(tail))
+                    }
+                    _ => (after, None),
+                };
+                let (after, r) = tag_rparan(after).map_err(|e| e.map(|e| item_error.or(e)))?;
+                let span = l.tokens[0].span.to(r.tokens[0].span);
+                let list = match tail {
+                    Some(tail) => {
+                        SpannedExpr::DottedList(Spanned::new((items, Box::new(tail)), span))
+                    }
+                    None => SpannedExpr::List(Spanned::new(items, span)),
+                };
+                return Ok((after, list));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// The elements of a vector or map up to and including the closing token,
+// which is returned for its span. A missing closer is reported the same
+// way as in parse_list.
+fn parse_elements<'a>(
+    mut rest: Tokens<'a>,
+    close: impl Fn(Tokens<'a>) -> ParseResult<'a, Tokens<'a>>,
+) -> ParseResult<'a, (Vec<SpannedExpr>, Tokens<'a>)> {
+    let mut items = Vec::new();
+    loop {
+        match parse_expr(rest.clone()) {
+            Ok((after, item)) => {
+                items.push(item);
+                rest = after;
+            }
+            Err(nom::Err::Error(item_error)) => {
+                let (after, _) = skip_datum_comments(rest)?;
+                let (after, closer) = close(after).map_err(|e| e.map(|e| item_error.or(e)))?;
+                return Ok((after, (items, closer)));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// [a b c]
+pub fn parse_vector(input: Tokens) -> ParseResult<SpannedExpr> {
+    let (rest, l) = tag_lbracket(input)?;
+    let (rest, (items, r)) = parse_elements(rest, tag_rbracket)?;
+    let span = l.tokens[0].span.to(r.tokens[0].span);
+    Ok((rest, SpannedExpr::Vector(Spanned::new(items, span))))
+}
+
+// {key value ...}, with the entries kept in the order they were written.
+pub fn parse_map(input: Tokens) -> ParseResult<SpannedExpr> {
+    let (rest, l) = tag_lbrace(input)?;
+    let (rest, (items, r)) = parse_elements(rest, tag_rbrace)?;
+    if items.len() % 2 == 1 {
+        return Err(nom::Err::Error(TokenError::new(r, Expected::Expr)));
+    }
+    let mut items = items.into_iter();
+    let mut entries = Vec::new();
+    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+        entries.push((key, value));
+    }
+    let span = l.tokens[0].span.to(r.tokens[0].span);
+    Ok((rest, SpannedExpr::Map(Spanned::new(entries, span))))
+}
+
+// As with list elements, many0 would throw away why the form that failed
+// didn't parse, so forms are parsed by hand.
+pub fn parse_program(mut input: Tokens) -> ParseResult<Vec<SpannedExpr>> {
+    let mut exprs = Vec::new();
+    loop {
+        (input, _) = skip_datum_comments(input)?;
+        if input.is_empty() {
+            return Ok((input, exprs));
+        }
+        let (rest, expr) = parse_expr(input)?;
+        exprs.push(expr);
+        input = rest;
+    }
+}
+
+// How deeply forms may nest, counting each open bracket and each prefix
+// such as ' or #; that applies to what follows it. The parser recurses once
+// per level, so this is what keeps any input from overflowing the stack.
+pub const MAX_DEPTH: usize = 128;
+
+pub(crate) fn check_depth(tokens: &[Spanned<Token>]) -> Result<(), ReadError> {
+    // What each level is waiting for: a closing bracket, or for a prefix,
+    // the datum after it, whose end also ends the prefix.
+    let mut open: Vec<bool> = Vec::new();
+    let close_prefixes = |open: &mut Vec<bool>| {
+        while open.last() == Some(&false) {
+            open.pop();
+        }
+    };
+    for token in tokens {
+        match token.value {
+            Token::LParan | Token::LBracket | Token::LBrace => open.push(true),
+            Token::Quote
+            | Token::Quasiquote
+            | Token::Unquote
+            | Token::UnquoteSplicing
+            | Token::DatumComment
+            | Token::Dispatch(..) => open.push(false),
+            Token::RParan | Token::RBracket | Token::RBrace => {
+                // A stray closer is left for the parser to report.
+                if open.last() == Some(&true) {
+                    open.pop();
+                }
+                close_prefixes(&mut open);
+            }
+            Token::Dot => {}
+            _ => close_prefixes(&mut open),
+        }
+        if open.len() > MAX_DEPTH {
+            return Err(ReadError::TooDeep { span: token.span });
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn lex_source(input: &str) -> Result<Vec<Spanned<Token<'_>>>, ReadError> {
+    let (_, tokens) = lex(input).map_err(|e| lex_failure(input, e))?;
+    check_depth(&tokens)?;
+    Ok(tokens)
+}
+
+// The ReadError for the lexer failing partway through input, which has to
+// be the whole source for the span to come out right.
+pub(crate) fn lex_failure(input: &str, e: nom::Err<LexError>) -> ReadError {
+    let (start, kind) = match e {
+        nom::Err::Error(e) | nom::Err::Failure(e) => (input.len() - e.input.len(), e.kind),
+        nom::Err::Incomplete(_) => (input.len(), LexErrorKind::UnexpectedCharacter),
+    };
+    let end = match kind {
+        LexErrorKind::UnterminatedString
+        | LexErrorKind::UnterminatedSymbol
+        | LexErrorKind::UnterminatedComment => input.len(),
+        LexErrorKind::IntegerOverflow => input[start..]
+            .find(|c| !is_symbol_char(c))
+            .map_or(input.len(), |len| start + len),
+        _ => start + input[start..].chars().next().map_or(0, char::len_utf8),
+    };
+    ReadError::Lex {
+        span: Span::new(start, end),
+        kind,
+    }
+}
+
+pub(crate) fn parse_failure(e: nom::Err<TokenError>, eof: Span) -> ReadError {
+    if let nom::Err::Failure(TokenError {
+        input,
+        rejected: Some(message),
+        ..
+    }) = e
+    {
+        return ReadError::Dispatch {
+            span: input.tokens[0].span,
+            message,
+        };
+    }
+    let (found, expected) = match &e {
+        nom::Err::Error(e) | nom::Err::Failure(e) => (e.input.tokens.first(), e.expected),
+        nom::Err::Incomplete(_) => (None, Expected::Expr),
+    };
+    match found {
+        Some(token) => ReadError::Parse {
+            span: token.span,
+            expected,
+            found: Some(token.value.clone().into_owned()),
+        },
+        None => ReadError::Parse {
+            span: eof,
+            expected,
+            found: None,
+        },
+    }
+}
+
+pub fn read_spanned(input: &str) -> Result<SpannedExpr, ReadError> {
+    let tokens = lex_source(input)?;
+    let eof = Span::new(input.len(), input.len());
+    let (rest, expr) = terminated(parse_expr, skip_datum_comments)(Tokens::new(&tokens))
+        .map_err(|e| parse_failure(e, eof))?;
+    match rest.tokens.first() {
+        Some(token) => Err(ReadError::TrailingInput {
+            span: token.span,
+            found: token.value.clone().into_owned(),
+        }),
+        None => Ok(expr),
+    }
+}
+
+pub fn read(input: &str) -> Result<Expr, ReadError> {
+    read_spanned(input).map(SpannedExpr::into_expr)
+}
+
+pub fn read_all_spanned(input: &str) -> Result<Vec<SpannedExpr>, ReadError> {
+    let tokens = lex_source(input)?;
+    let eof = Span::new(input.len(), input.len());
+    match parse_program(Tokens::new(&tokens)) {
+        Ok((_, exprs)) => Ok(exprs),
+        Err(e) => Err(parse_failure(e, eof)),
+    }
+}
+
+pub fn read_all(input: &str) -> Result<Vec<Expr>, ReadError> {
+    read_all_spanned(input).map(|exprs| exprs.into_iter().map(SpannedExpr::into_expr).collect())
+}
+
+// Reads as much of the input as it can and reports every problem on the
+// way instead of stopping at the first: a stray `)` is dropped, lists still
+// open at the end of input are closed there, and a top-level form that
+// fails to parse is skipped.
+pub fn read_all_recover(input: &str) -> (Vec<SpannedExpr>, Vec<ReadError>) {
+    match lex_source(input) {
+        Ok(tokens) => recover(tokens, Span::new(input.len(), input.len())),
+        Err(e) => (Vec::new(), vec![e]),
+    }
+}
+
+// read_all_recover for tokens already lexed, which end at eof.
+pub(crate) fn recover(
+    tokens: Vec<Spanned<Token>>,
+    eof: Span,
+) -> (Vec<SpannedExpr>, Vec<ReadError>) {
+    let mut errors = Vec::new();
+    let mut balanced = Vec::with_capacity(tokens.len());
+    // The closer each open bracket is waiting for, innermost last.
+    let mut open: Vec<Token> = Vec::new();
+    let missing = |errors: &mut Vec<ReadError>, closer: &Token, span| {
+        errors.push(ReadError::Parse {
+            span,
+            expected: expected_closer(closer),
+            found: None,
+        });
+    };
+    for token in tokens {
+        match token.value {
+            Token::LParan => open.push(Token::RParan),
+            Token::LBracket => open.push(Token::RBracket),
+            Token::LBrace => open.push(Token::RBrace),
+            Token::RParan | Token::RBracket | Token::RBrace => {
+                // A closer for a bracket further out closes the ones inside
+                // it too; one nothing is waiting for is dropped.
+                match open.iter().rposition(|closer| *closer == token.value) {
+                    Some(i) => {
+                        for closer in open.drain(i + 1..).rev() {
+                            missing(&mut errors, &closer, token.span);
+                            balanced.push(Spanned::new(closer, token.span));
+                        }
+                        open.pop();
+                    }
+                    None => {
+                        errors.push(ReadError::Parse {
+                            span: token.span,
+                            expected: match open.last() {
+                                Some(closer) => expected_closer(closer),
+                                None => Expected::Expr,
+                            },
+                            found: Some(token.value.into_owned()),
+                        });
+                        continue;
+                    }
+                }
+            }
+            _ => {}
+        }
+        balanced.push(token);
+    }
+    while let Some(closer) = open.pop() {
+        missing(&mut errors, &closer, eof);
+        balanced.push(Spanned::new(closer, eof));
+    }
+
+    let mut exprs = Vec::new();
+    let mut rest = Tokens::new(&balanced);
+    loop {
+        if let Ok((after, _)) = skip_datum_comments(rest.clone()) {
+            rest = after;
+        }
+        if rest.tokens.is_empty() {
+            break;
+        }
+        match parse_expr(rest.clone()) {
+            Ok((after, expr)) => {
+                exprs.push(expr);
+                rest = after;
+            }
+            Err(e) => {
+                errors.push(parse_failure(e, eof));
+                rest = rest.slice(form_len(rest.tokens)..);
+            }
+        }
+    }
+    errors.sort_by_key(|e| e.span().start);
+    (exprs, errors)
+}
+
+fn expected_closer(closer: &Token) -> Expected {
+    match closer {
+        Token::RBracket => Expected::RBracket,
+        Token::RBrace => Expected::RBrace,
+        _ => Expected::RParan,
+    }
 }
 
-pub fn read(input: &str) -> Option<Expr> {
-    let (_, token_vec) = lex(input).unwrap();
-    let (_, expr) = parse_list(Tokens::new(&token_vec)).unwrap();
-    Some(expr)
+// Number of tokens up to and including the closer matching a leading open
+// bracket, or just the first token otherwise. The tokens are known to be
+// balanced.
+fn form_len(tokens: &[Spanned<Token>]) -> usize {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.value {
+            Token::LParan | Token::LBracket | Token::LBrace => depth += 1,
+            Token::RParan | Token::RBracket | Token::RBrace => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return i + 1;
+        }
+    }
+    tokens.len()
 }
 
 // pub fn parse_list(tokens: Tokens) -> IResult<Tokens, Expr> {}
@@ -198,86 +1014,477 @@ pub fn read(input: &str) -> Option<Expr> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use alloc::{borrow::ToOwned, format};
+
+    fn spanned<'a>(tokens: &[Token<'a>]) -> Vec<Spanned<Token<'a>>> {
+        tokens
+            .iter()
+            .enumerate()
+            .map(|(i, token)| Spanned::new(token.clone(), Span::new(i, i + 1)))
+            .collect()
+    }
 
     #[test]
     fn take_test() {
-        let tokens = vec![Token::LParan];
+        let tokens = spanned(&[Token::LParan]);
         assert_eq!(
             take::<usize, Tokens<'_>, nom::error::Error<Tokens<'_>>>(1usize)(Tokens::new(&tokens)),
-            Ok((Tokens::new(&vec![]), Tokens::new(&vec![Token::LParan])))
+            Ok((Tokens::new(&[]), Tokens::new(&tokens)))
+        );
+    }
+
+    #[test]
+    fn tokens_take_test() {
+        let tokens = spanned(&[Token::LParan, Token::Integer(1), Token::RParan]);
+        let input = Tokens::new(&tokens);
+        assert_eq!(input.input_len(), 3);
+        assert_eq!(input.take(0), Tokens::new(&[]));
+        assert_eq!(input.take(2), Tokens::new(&tokens[..2]));
+        assert_eq!(input.take(3), input);
+        assert_eq!(input.take(4), input);
+        assert_eq!(
+            input.take_split(1),
+            (Tokens::new(&tokens[1..]), Tokens::new(&tokens[..1]))
+        );
+        assert_eq!(input.take_split(0), (input.clone(), Tokens::new(&[])));
+        assert_eq!(input.take_split(5), (Tokens::new(&[]), input.clone()));
+        // Taking from a view counts from the start of the view.
+        let rest = input.take_split(1).0;
+        assert_eq!(rest.input_len(), 2);
+        assert_eq!(rest.take(1).tokens(), &tokens[1..2]);
+    }
+
+    #[test]
+    fn tokens_slice_test() {
+        let tokens = spanned(&[Token::LParan, Token::Integer(1), Token::RParan]);
+        let input = Tokens::new(&tokens);
+        assert_eq!(input.slice(1..2).tokens(), &tokens[1..2]);
+        assert_eq!(input.slice(..2).tokens(), &tokens[..2]);
+        assert_eq!(input.slice(1..).tokens(), &tokens[1..]);
+        assert_eq!(input.slice(..), input);
+        assert!(input.slice(3..).is_empty());
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 2..1;
+        assert!(input.slice(reversed).is_empty());
+        assert_eq!(input.slice(1..9).tokens(), &tokens[1..]);
+        assert!(input.slice(7..).is_empty());
+        let rest = input.slice(1..);
+        assert_eq!(rest.slice(1..).tokens(), &tokens[2..]);
+        assert_eq!(rest.slice(..1).tokens(), &tokens[1..2]);
+        assert_eq!(rest.slice(..).len(), 2);
+    }
+
+    #[test]
+    fn tokens_iter_test() {
+        let tokens = spanned(&[Token::LParan, Token::Integer(1), Token::RParan]);
+        let rest = Tokens::new(&tokens).slice(1..);
+        assert!(rest.iter_elements().eq(&tokens[1..]));
+        assert_eq!(
+            rest.iter_indices().map(|(i, token)| (i, &token.value)).collect::<Vec<_>>(),
+            [(0, &Token::Integer(1)), (1, &Token::RParan)]
         );
+        assert_eq!(rest.position(|token| token.value == Token::RParan), Some(1));
+        assert_eq!(rest.position(|token| token.value == Token::LParan), None);
+        assert_eq!(rest.slice_index(2), Ok(2));
+        assert_eq!(rest.slice_index(3), Err(nom::Needed::new(1)));
     }
 
     #[test]
     fn tag_lparan_test() {
+        let tokens = spanned(&[Token::LParan, Token::RParan]);
         assert_eq!(
-            tag_lparan(Tokens::new(&vec![Token::LParan, Token::RParan])).unwrap(),
-            (
-                Tokens::new(&vec![Token::RParan]),
-                Tokens::new(&vec![Token::LParan])
-            )
+            tag_lparan(Tokens::new(&tokens)).unwrap(),
+            (Tokens::new(&tokens[1..]), Tokens::new(&tokens[..1]))
         );
     }
 
     #[test]
     fn tag_rparan_test() {
+        let tokens = spanned(&[Token::RParan, Token::LParan]);
         assert_eq!(
-            tag_rparan(Tokens::new(&vec![Token::RParan, Token::LParan])).unwrap(),
-            (
-                Tokens::new(&vec![Token::LParan]),
-                Tokens::new(&vec![Token::RParan]),
-            )
+            tag_rparan(Tokens::new(&tokens)).unwrap(),
+            (Tokens::new(&tokens[1..]), Tokens::new(&tokens[..1]))
         );
     }
 
     #[test]
     fn tag_integer_test() {
+        let tokens = spanned(&[Token::Integer(42), Token::RParan]);
         assert_eq!(
-            tag_integer(Tokens::new(&vec![Token::Integer(42), Token::RParan])).unwrap(),
-            (
-                Tokens::new(&vec![Token::RParan]),
-                Tokens::new(&vec![Token::Integer(42)]),
-            )
+            tag_integer(Tokens::new(&tokens)).unwrap(),
+            (Tokens::new(&tokens[1..]), Tokens::new(&tokens[..1]))
         );
     }
 
     #[test]
     fn tag_symbol_test() {
+        let tokens = spanned(&[Token::Symbol("()".into()), Token::RParan]);
         assert_eq!(
-            tag_symbol(Tokens::new(&vec![
-                Token::Symbol("()".to_owned()),
-                Token::RParan
-            ]))
-            .unwrap(),
-            (
-                Tokens::new(&vec![Token::RParan]),
-                Tokens::new(&vec![Token::Symbol("()".to_owned())]),
-            )
+            tag_symbol(Tokens::new(&tokens)).unwrap(),
+            (Tokens::new(&tokens[1..]), Tokens::new(&tokens[..1]))
         );
     }
 
     #[test]
     fn read_test() {
         assert_eq!(read("()").unwrap(), Expr::List(vec![]));
+        assert_eq!(read(" 42 ").unwrap(), Expr::Integer(42));
         assert_eq!(read("(42)").unwrap(), Expr::List(vec![Expr::Integer(42)]));
         assert_eq!(
             read("(the_number 42)").unwrap(),
-            Expr::List(vec![
-                Expr::Symbol("the_number".to_owned()),
-                Expr::Integer(42),
-            ])
+            Expr::List(vec![Expr::Symbol("the_number".into()), Expr::Integer(42),])
         );
         assert_eq!(
             read("(plus 40 2)").unwrap(),
             Expr::List(vec![
-                Expr::Symbol("plus".to_owned()),
+                Expr::Symbol("plus".into()),
                 Expr::Integer(40),
                 Expr::Integer(2),
             ])
         );
+        assert_eq!(
+            read("(plus 1.5 2)").unwrap(),
+            Expr::List(vec![
+                Expr::Symbol("plus".into()),
+                Expr::Float(1.5),
+                Expr::Integer(2),
+            ])
+        );
+        assert_eq!(
+            read(r#"(greet "hello \"world\"")"#).unwrap(),
+            Expr::List(vec![
+                Expr::Symbol("greet".into()),
+                Expr::String("hello \"world\"".to_owned()),
+            ])
+        );
+        assert_eq!(
+            read("(#t #false)").unwrap(),
+            Expr::List(vec![Expr::Bool(true), Expr::Bool(false)])
+        );
         assert_eq!(
             read("(( 42) )").unwrap(),
             Expr::List(vec![Expr::List(vec![Expr::Integer(42)])])
         );
     }
+
+    #[test]
+    fn read_quoted_test() {
+        let sym = |s: &str| Expr::Symbol(s.into());
+        assert_eq!(
+            read("('x `(a ,b ,@c))").unwrap(),
+            Expr::List(vec![
+                Expr::List(vec![sym("quote"), sym("x")]),
+                Expr::List(vec![
+                    sym("quasiquote"),
+                    Expr::List(vec![
+                        sym("a"),
+                        Expr::List(vec![sym("unquote"), sym("b")]),
+                        Expr::List(vec![sym("unquote-splicing"), sym("c")]),
+                    ])
+                ]),
+            ])
+        );
+        let expr = read_spanned("('(1 2))").unwrap();
+        let SpannedExpr::List(list) = &expr else {
+            panic!("expected a list")
+        };
+        assert_eq!(list.value[0].span(), Span::new(1, 7));
+        assert!(read("(')").is_err());
+    }
+
+    #[test]
+    fn read_dotted_test() {
+        let sym = |s: &str| Box::new(Expr::Symbol(s.into()));
+        assert_eq!(read("(a . b)"), Ok(Expr::Pair(sym("a"), sym("b"))));
+        assert_eq!(
+            read("(a b . c)"),
+            Ok(Expr::Pair(
+                sym("a"),
+                Box::new(Expr::Pair(sym("b"), sym("c")))
+            ))
+        );
+        assert_eq!(read("(a . (b c))"), read("(a b c)"));
+        assert_eq!(read("(a #;x . #;y b)"), read("(a . b)"));
+        assert_eq!(read("(1 2 . 3)").unwrap().to_string(), "(1 2 . 3)");
+        assert_eq!(
+            read("(. a)").unwrap_err().to_string(),
+            "expected `)`, found `.` at byte 1"
+        );
+        assert_eq!(
+            read("(a . b c)").unwrap_err().to_string(),
+            "expected `)`, found `c` at byte 7"
+        );
+        assert!(read("(a .)").is_err());
+        assert!(read("(a . b").unwrap_err().is_incomplete());
+        let expr = read_spanned("(a . b)").unwrap();
+        assert_eq!(expr.span(), Span::new(0, 7));
+    }
+
+    #[test]
+    fn read_vector_map_test() {
+        let sym = |s: &str| Expr::Symbol(s.into());
+        assert_eq!(
+            read("[a #;b [] (c)]"),
+            Ok(Expr::Vector(vec![
+                sym("a"),
+                Expr::Vector(vec![]),
+                Expr::List(vec![sym("c")]),
+            ]))
+        );
+        assert_eq!(
+            read("{:a 1 b [2]}"),
+            Ok(Expr::Map(vec![
+                (Expr::Keyword("a".into()), Expr::Integer(1)),
+                (sym("b"), Expr::Vector(vec![Expr::Integer(2)])),
+            ]))
+        );
+        assert_eq!(read("{}"), Ok(Expr::Map(vec![])));
+        assert_eq!(
+            read("{:a 1 :b}").unwrap_err().to_string(),
+            "expected expression, found `}` at byte 8"
+        );
+        assert_eq!(
+            read("[a)").unwrap_err().to_string(),
+            "expected `]`, found `)` at byte 2"
+        );
+        assert!(read("[a {b").unwrap_err().is_incomplete());
+        assert_eq!(read_spanned(" [a] ").unwrap().span(), Span::new(1, 4));
+    }
+
+    #[test]
+    fn read_comment_test() {
+        assert_eq!(
+            read("; a list\n(1 #| two |# 3 #;(4 5) 6 #;7) #;8").unwrap(),
+            Expr::List(vec![Expr::Integer(1), Expr::Integer(3), Expr::Integer(6)])
+        );
+        assert_eq!(
+            read("(#;#;1 2 3)").unwrap(),
+            Expr::List(vec![Expr::Integer(3)])
+        );
+    }
+
+    #[test]
+    fn read_all_test() {
+        assert_eq!(
+            read_all("(define x 1)\n; comment\nx 'y (plus x 2)").unwrap(),
+            vec![
+                read("(define x 1)").unwrap(),
+                Expr::Symbol("x".into()),
+                read("'y").unwrap(),
+                read("(plus x 2)").unwrap(),
+            ]
+        );
+        assert_eq!(read_all("").unwrap(), vec![]);
+        assert_eq!(read_all(" #;(ignored) ").unwrap(), vec![]);
+        assert_eq!(
+            read_all("(a) (b"),
+            Err(ReadError::Parse {
+                span: Span::new(6, 6),
+                expected: Expected::RParan,
+                found: None
+            })
+        );
+        assert_eq!(
+            read_all("(a) ) (b)"),
+            Err(ReadError::Parse {
+                span: Span::new(4, 5),
+                expected: Expected::Expr,
+                found: Some(Token::RParan)
+            })
+        );
+        // Inside a commented-out form as anywhere else.
+        assert_eq!(
+            read_all("#;(b ]"),
+            Err(ReadError::Parse {
+                span: Span::new(5, 6),
+                expected: Expected::RParan,
+                found: Some(Token::RBracket)
+            })
+        );
+    }
+
+    #[test]
+    fn read_error_message_test() {
+        assert_eq!(
+            read("(a 'b ')").unwrap_err().to_string(),
+            "expected expression, found `)` at byte 7"
+        );
+        assert_eq!(
+            read_all("(a) )").unwrap_err().to_string(),
+            "expected expression, found `)` at byte 4"
+        );
+        assert_eq!(
+            read("(a").unwrap_err().to_string(),
+            "expected `)`, found end of input"
+        );
+    }
+
+    #[test]
+    fn read_all_recover_test() {
+        let source = ") (a (b)) ) (c 'd ') (e";
+        let (exprs, errors) = read_all_recover(source);
+        let exprs: Vec<Expr> = exprs.into_iter().map(SpannedExpr::into_expr).collect();
+        assert_eq!(exprs, vec![read("(a (b))").unwrap(), read("(e)").unwrap()]);
+        let eof = Span::new(source.len(), source.len());
+        assert_eq!(
+            errors,
+            vec![
+                ReadError::Parse {
+                    span: Span::new(0, 1),
+                    expected: Expected::Expr,
+                    found: Some(Token::RParan)
+                },
+                ReadError::Parse {
+                    span: Span::new(10, 11),
+                    expected: Expected::Expr,
+                    found: Some(Token::RParan)
+                },
+                ReadError::Parse {
+                    span: Span::new(19, 20),
+                    expected: Expected::Expr,
+                    found: Some(Token::RParan)
+                },
+                ReadError::Parse {
+                    span: eof,
+                    expected: Expected::RParan,
+                    found: None
+                },
+            ]
+        );
+        assert_eq!(read_all_recover("(a) b").1, vec![]);
+    }
+
+    #[test]
+    fn read_all_recover_brackets_test() {
+        let source = "[1 (2] {3 ] (5";
+        let (exprs, errors) = read_all_recover(source);
+        let exprs: Vec<Expr> = exprs.into_iter().map(SpannedExpr::into_expr).collect();
+        assert_eq!(exprs, read_all("[1 (2)] {3 (5)}").unwrap());
+        let eof = Span::new(source.len(), source.len());
+        assert_eq!(
+            errors,
+            vec![
+                ReadError::Parse {
+                    span: Span::new(5, 6),
+                    expected: Expected::RParan,
+                    found: None
+                },
+                ReadError::Parse {
+                    span: Span::new(10, 11),
+                    expected: Expected::RBrace,
+                    found: Some(Token::RBracket)
+                },
+                ReadError::Parse {
+                    span: eof,
+                    expected: Expected::RParan,
+                    found: None
+                },
+                ReadError::Parse {
+                    span: eof,
+                    expected: Expected::RBrace,
+                    found: None
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn is_incomplete_test() {
+        assert!(read_all("(a (b)").unwrap_err().is_incomplete());
+        assert!(read_all("(a (b (c").unwrap_err().is_incomplete());
+        assert!(read_all("(a \"b").unwrap_err().is_incomplete());
+        assert!(read_all("#| open").unwrap_err().is_incomplete());
+        assert!(!read_all("(a))").unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn read_spanned_test() {
+        let expr = read_spanned("(a\n  (b 42))").unwrap();
+        assert_eq!(expr.span(), Span::new(0, 12));
+        let SpannedExpr::List(list) = &expr else {
+            panic!("expected a list")
+        };
+        assert_eq!(list.value[1].span(), Span::new(5, 11));
+        assert_eq!(list.value[1].span().line_col("(a\n  (b 42))"), (2, 3));
+        assert_eq!(
+            expr.to_expr(),
+            Expr::List(vec![
+                Expr::Symbol("a".into()),
+                Expr::List(vec![Expr::Symbol("b".into()), Expr::Integer(42)]),
+            ])
+        );
+    }
+
+    #[test]
+    fn depth_test() {
+        let nested = |open: &str, close: &str, n| format!("{}x{}", open.repeat(n), close.repeat(n));
+        assert!(read(&nested("(", ")", MAX_DEPTH)).is_ok());
+        assert!(read(&nested("'[", "]", MAX_DEPTH / 2)).is_ok());
+        assert_eq!(
+            read(&nested("(", ")", MAX_DEPTH + 1)),
+            Err(ReadError::TooDeep {
+                span: Span::new(MAX_DEPTH, MAX_DEPTH + 1)
+            })
+        );
+        assert!(read(&nested("'", "", MAX_DEPTH + 1)).is_err());
+        // Depth is how far forms nest, not how many there are.
+        assert!(read(&format!("({})", "'(a) ".repeat(10 * MAX_DEPTH))).is_ok());
+        assert!(read(&"(".repeat(100_000)).is_err());
+    }
+
+    #[test]
+    fn read_error_test() {
+        assert_eq!(
+            read("(42 #)"),
+            Err(ReadError::Lex {
+                span: Span::new(4, 5),
+                kind: LexErrorKind::UnexpectedCharacter
+            })
+        );
+        #[cfg(not(feature = "bigint"))]
+        assert_eq!(
+            read("(+ 99999999999999999999)").map_err(|e| (e.to_string(), e)),
+            Err((
+                "integer too large at byte 3".to_owned(),
+                ReadError::Lex {
+                    span: Span::new(3, 23),
+                    kind: LexErrorKind::IntegerOverflow
+                }
+            ))
+        );
+        assert_eq!(
+            read("(display \"hi)"),
+            Err(ReadError::Lex {
+                span: Span::new(9, 13),
+                kind: LexErrorKind::UnterminatedString
+            })
+        );
+        assert_eq!(
+            read("(42"),
+            Err(ReadError::Parse {
+                span: Span::new(3, 3),
+                expected: Expected::RParan,
+                found: None
+            })
+        );
+        assert_eq!(
+            read(")"),
+            Err(ReadError::Parse {
+                span: Span::new(0, 1),
+                expected: Expected::Expr,
+                found: Some(Token::RParan)
+            })
+        );
+        assert_eq!(
+            read("(42) 7"),
+            Err(ReadError::TrailingInput {
+                span: Span::new(5, 6),
+                found: Token::Integer(7)
+            })
+        );
+        assert_eq!(
+            read(" (42) \n").unwrap(),
+            Expr::List(vec![Expr::Integer(42)])
+        );
+    }
 }
@@ -0,0 +1,306 @@
+use alloc::string::{String, ToString};
+use core::fmt::{self, Write};
+
+use crate::{
+    lexer::{is_symbol_char, lex, Token, CHAR_NAMES},
+    parser::Expr,
+    value::Value,
+};
+
+// Output is valid input: reading the printed form of an expression gives
+// the expression back. Nil prints as (), which is what () evaluates to.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Nil => f.write_str("()"),
+            Expr::Bool(b) => write_bool(f, *b),
+            Expr::Integer(i) => write!(f, "{i}"),
+            #[cfg(feature = "bigint")]
+            Expr::BigInteger(i) => write!(f, "{i}"),
+            Expr::Rational(r) => write!(f, "{r}"),
+            Expr::Float(x) => write_float(f, *x),
+            Expr::Char(c) => write_char(f, *c),
+            Expr::String(s) => write_string(f, s),
+            Expr::Symbol(s) => write_symbol(f, s.as_str()),
+            Expr::Keyword(k) => write!(f, ":{k}"),
+            Expr::Lambda(lambda) => write!(f, "{}", lambda.to_expr()),
+            Expr::List(items) => write_list(f, items),
+            Expr::Pair(car, cdr) => {
+                write!(f, "({car}")?;
+                let mut tail = &**cdr;
+                while let Expr::Pair(car, cdr) = tail {
+                    write!(f, " {car}")?;
+                    tail = cdr;
+                }
+                write!(f, " . {tail})")
+            }
+            Expr::Vector(items) => write_vector(f, items),
+            Expr::Map(entries) => write_map(f, entries),
+        }
+    }
+}
+
+// Procedures have no written form, so they print as #<...> which the
+// reader rejects rather than misreads.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => f.write_str("()"),
+            Value::Bool(b) => write_bool(f, *b),
+            Value::Integer(i) => write!(f, "{i}"),
+            #[cfg(feature = "bigint")]
+            Value::BigInteger(i) => write!(f, "{i}"),
+            Value::Rational(r) => write!(f, "{r}"),
+            Value::Float(x) => write_float(f, *x),
+            Value::Char(c) => write_char(f, *c),
+            Value::String(s) => write_string(f, s),
+            Value::Symbol(s) => write_symbol(f, s.as_str()),
+            Value::Keyword(k) => write!(f, ":{k}"),
+            Value::List(items) => write_list(f, items),
+            Value::Pair(car, cdr) => {
+                write!(f, "({car}")?;
+                let mut tail = &**cdr;
+                while let Value::Pair(car, cdr) = tail {
+                    write!(f, " {car}")?;
+                    tail = cdr;
+                }
+                write!(f, " . {tail})")
+            }
+            Value::Vector(items) => write_vector(f, items),
+            Value::Map(entries) => write_map(f, entries),
+            Value::Closure(_) | Value::Compiled(_) => f.write_str("#<procedure>"),
+            Value::Native(native) => write!(f, "#<procedure {}>", native.name),
+            Value::AsyncNative(native) => write!(f, "#<procedure {}>", native.name),
+            Value::Macro(_) => f.write_str("#<macro>"),
+            Value::Atom(atom) => write!(f, "#<atom {}>", atom.get()),
+            Value::Port(port) if port.is_input() => f.write_str("#<input-port>"),
+            Value::Port(_) => f.write_str("#<output-port>"),
+            Value::Generator(_) => f.write_str("#<generator>"),
+            // Each value as it would print on its own, as at a prompt.
+            Value::Values(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(' ')?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                Ok(())
+            }
+            Value::Error(error) => {
+                write!(f, "#<error ")?;
+                write_string(f, &error.message)?;
+                for irritant in &error.irritants {
+                    write!(f, " {irritant}")?;
+                }
+                f.write_char('>')
+            }
+        }
+    }
+}
+
+fn write_bool(f: &mut fmt::Formatter<'_>, b: bool) -> fmt::Result {
+    f.write_str(if b { "#t" } else { "#f" })
+}
+
+// Debug keeps the decimal point, so 1.0 doesn't read back as an integer.
+// The infinities and NaN are spelled the way the lexer reads them.
+fn write_float(f: &mut fmt::Formatter<'_>, x: f64) -> fmt::Result {
+    match x {
+        f64::INFINITY => f.write_str("+inf.0"),
+        f64::NEG_INFINITY => f.write_str("-inf.0"),
+        _ if x.is_nan() => f.write_str("+nan.0"),
+        _ => write!(f, "{x:?}"),
+    }
+}
+
+// A name that wouldn't read back as the same symbol, such as "a b", "1" or
+// "", is written between bars.
+fn write_symbol(f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+    if reads_as_symbol(name) {
+        return f.write_str(name);
+    }
+    f.write_char('|')?;
+    write_escaped(f, name, '|')?;
+    f.write_char('|')
+}
+
+// Most names start with a letter and are all symbol characters, which
+// can't be anything else; the rest are left to the lexer to decide.
+fn reads_as_symbol(name: &str) -> bool {
+    let plain = name.starts_with(|c: char| c.is_alphabetic() || "!$%&*/<=>?^_~".contains(c));
+    let plain = plain && name.chars().all(is_symbol_char);
+    plain
+        || matches!(lex(name), Ok((_, tokens))
+            if matches!(&tokens[..], [token] if token.value == Token::Symbol(name.into())))
+}
+
+fn write_char(f: &mut fmt::Formatter<'_>, c: char) -> fmt::Result {
+    match CHAR_NAMES.iter().find(|&&(_, named)| named == c) {
+        Some((name, _)) => write!(f, "#\\{name}"),
+        None if c.is_control() => write!(f, "#\\x{:x}", c as u32),
+        None => write!(f, "#\\{c}"),
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    write_escaped(f, s, '"')?;
+    f.write_char('"')
+}
+
+// s as it goes between quote characters, where quote has to be escaped.
+fn write_escaped(f: &mut fmt::Formatter<'_>, s: &str, quote: char) -> fmt::Result {
+    for c in s.chars() {
+        match c {
+            c if c == quote => write!(f, "\\{c}")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            '\r' => f.write_str("\\r")?,
+            '\0' => f.write_str("\\0")?,
+            c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    Ok(())
+}
+
+fn write_separated<T: fmt::Display>(f: &mut fmt::Formatter<'_>, items: &[T]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            f.write_char(' ')?;
+        }
+        write!(f, "{item}")?;
+    }
+    Ok(())
+}
+
+fn write_list<T: fmt::Display>(f: &mut fmt::Formatter<'_>, items: &[T]) -> fmt::Result {
+    f.write_char('(')?;
+    write_separated(f, items)?;
+    f.write_char(')')
+}
+
+fn write_vector<T: fmt::Display>(f: &mut fmt::Formatter<'_>, items: &[T]) -> fmt::Result {
+    f.write_char('[')?;
+    write_separated(f, items)?;
+    f.write_char(']')
+}
+
+fn write_map<K: fmt::Display, V: fmt::Display>(
+    f: &mut fmt::Formatter<'_>,
+    entries: &[(K, V)],
+) -> fmt::Result {
+    f.write_char('{')?;
+    for (i, (key, value)) in entries.iter().enumerate() {
+        if i > 0 {
+            f.write_char(' ')?;
+        }
+        write!(f, "{key} {value}")?;
+    }
+    f.write_char('}')
+}
+
+// Lists that don't fit in the remaining width are broken after their head,
+// with every other element on its own line, indented two columns.
+pub fn pretty(expr: &Expr, width: usize) -> String {
+    let mut out = String::new();
+    write_pretty(&mut out, expr, 0, width);
+    out
+}
+
+fn write_pretty(out: &mut String, expr: &Expr, indent: usize, width: usize) {
+    let flat = expr.to_string();
+    let items = match expr {
+        Expr::List(items) if indent + flat.len() > width && items.len() > 1 => items,
+        _ => return out.push_str(&flat),
+    };
+    out.push('(');
+    write_pretty(out, &items[0], indent + 1, width);
+    for item in &items[1..] {
+        out.push('\n');
+        out.extend(core::iter::repeat_n(' ', indent + 2));
+        write_pretty(out, item, indent + 2, width);
+    }
+    out.push(')');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{read, Lambda};
+    use alloc::{borrow::ToOwned, boxed::Box, vec};
+
+    #[test]
+    fn display_expr_test() {
+        let sources = [
+            "(define (f x) (g x \"a \\\"b\\\"\\n\" 1.5 -2 #t #f))",
+            "(quote (1.0 1e100 ()))",
+            "\"tab\\t\\u{7}\"",
+            "(#\\a #\\space #\\x7 #\\( #\\λ)",
+            "(:key (a . b) (lambda (x &key (y 1) z) y))",
+            "([1 [] (a)] {:a 1 \"b\" [2]} {})",
+            "(-1/3 2/5)",
+        ];
+        for source in sources {
+            let expr = read(source).unwrap();
+            assert_eq!(read(&expr.to_string()), Ok(expr));
+        }
+        assert_eq!(read("( a  (b   c) )").unwrap().to_string(), "(a (b c))");
+        let lambda = Expr::Lambda(Box::new(Lambda {
+            params: vec!["x".into(), "y".into()],
+            rest: None,
+            keys: vec![],
+            body: vec![Expr::Symbol("x".into())],
+        }));
+        assert_eq!(lambda.to_string(), "(lambda (x y) x)");
+    }
+
+    #[test]
+    fn display_value_test() {
+        let value = Value::List(vec![
+            Value::Integer(1),
+            Value::String("two".to_owned()),
+            Value::Nil,
+        ]);
+        assert_eq!(value.to_string(), "(1 \"two\" ())");
+        assert_eq!(Value::Float(3.).to_string(), "3.0");
+        for (x, printed) in [
+            (f64::INFINITY, "+inf.0"),
+            (f64::NEG_INFINITY, "-inf.0"),
+            (f64::NAN, "+nan.0"),
+        ] {
+            assert_eq!(Value::Float(x).to_string(), printed);
+        }
+        for (name, printed) in [
+            ("a b", "|a b|"),
+            ("a;b", "|a;b|"),
+            ("1", "|1|"),
+            ("", "||"),
+            (".", "|.|"),
+            (":k", "|:k|"),
+            ("+inf.0", "|+inf.0|"),
+            ("a|b\n", "|a\\|b\\n|"),
+            ("-", "-"),
+            ("...", "..."),
+            ("->vec", "->vec"),
+            ("λ", "λ"),
+        ] {
+            let symbol = Value::Symbol(name.into());
+            assert_eq!(symbol.to_string(), printed);
+            assert_eq!(read(printed), Ok(Expr::Symbol(name.into())), "{printed}");
+        }
+    }
+
+    #[test]
+    fn pretty_test() {
+        let expr = read("(define (f x) (if x (g x x) (h 1 2)))").unwrap();
+        assert_eq!(pretty(&expr, 80), expr.to_string());
+        let printed = pretty(&expr, 20);
+        assert_eq!(
+            printed,
+            "(define\n  (f x)\n  (if\n    x\n    (g x x)\n    (h 1 2)))"
+        );
+        assert_eq!(read(&printed), Ok(expr));
+    }
+}
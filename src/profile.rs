@@ -0,0 +1,230 @@
+// Where the time of a run went, by procedure, from the calls and returns
+// an interpreter's hook is told about:
+//
+//    calls   total ms    self ms  name
+//        1     12.401      0.310  main
+//      120     12.091     11.502  fact
+//
+// total is the time from each call to its return, with a recursive call's
+// counted once, as part of the outermost, and self that less the time
+// spent in the calls it made. The rows are sorted by self time, most
+// first. The same times can be had as folded stacks,
+//
+//   main;fact;fact 812
+//
+// the microseconds spent in each chain of calls itself, outermost first,
+// which flamegraph.pl and inferno draw. Only the tree-walker reports calls,
+// and a chain of tail calls returns all at once, so each call in it lasts
+// until the last does.
+use std::{
+    fmt::Write as _,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    hooks::{self, Event, Hook},
+    interpreter::Interpreter,
+    sync::{Lock, Shared},
+};
+
+#[derive(Clone, Default)]
+pub struct Profile(Shared<Lock<Timings>>);
+
+#[derive(Default)]
+struct Timings {
+    // The calls under way, innermost last.
+    calls: Vec<Call>,
+    procedures: Vec<Procedure>,
+    // The time spent in each chain of calls itself, keyed by its folded
+    // names.
+    stacks: Vec<(String, Duration)>,
+}
+
+struct Call {
+    name: String,
+    entered: Instant,
+    // How long the calls this one made took.
+    inner: Duration,
+}
+
+struct Procedure {
+    name: String,
+    calls: usize,
+    total: Duration,
+    own: Duration,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Profile::default()
+    }
+
+    pub fn install(&self, interpreter: &mut Interpreter) {
+        let profile = self.clone();
+        interpreter.set_hook(move |event| profile.observe(event));
+    }
+
+    // Times event, for a hook that does more than time.
+    pub fn observe(&self, event: Event) {
+        let now = Instant::now();
+        let mut timings = self.0.borrow_mut();
+        match event {
+            Event::EnterCall { name, callee, .. } => timings.calls.push(Call {
+                name: name.map_or_else(|| callee.to_string(), |name| name.to_string()),
+                entered: now,
+                inner: Duration::ZERO,
+            }),
+            Event::Return { .. } => timings.returned(now),
+            Event::Branch { .. } | Event::Define { .. } => {}
+        }
+    }
+
+    // Runs f with its calls timed instead of told to the interpreter's
+    // hook.
+    pub(crate) fn during<T>(&self, f: impl FnOnce() -> T) -> T {
+        let profile = self.clone();
+        let hook: Shared<Lock<Hook>> =
+            Shared::new(Lock::new(move |event: Event| profile.observe(event)));
+        hooks::instead(hook, f)
+    }
+
+    // A row for each procedure called, with the most time spent in it
+    // itself first.
+    pub fn table(&self) -> String {
+        let timings = self.0.borrow();
+        let mut procedures: Vec<&Procedure> = timings.procedures.iter().collect();
+        procedures.sort_by(|a, b| b.own.cmp(&a.own).then_with(|| a.name.cmp(&b.name)));
+        let mut out = format!(
+            "{:>7} {:>10} {:>10}  name\n",
+            "calls", "total ms", "self ms"
+        );
+        for procedure in procedures {
+            writeln!(
+                out,
+                "{:>7} {:>10.3} {:>10.3}  {}",
+                procedure.calls,
+                procedure.total.as_secs_f64() * 1000.0,
+                procedure.own.as_secs_f64() * 1000.0,
+                procedure.name
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    // A line for each chain of calls, in order by their names, with the
+    // whole microseconds spent in it itself.
+    pub fn folded(&self) -> String {
+        let timings = self.0.borrow();
+        let mut stacks: Vec<&(String, Duration)> = timings.stacks.iter().collect();
+        stacks.sort_by(|a, b| a.0.cmp(&b.0));
+        stacks
+            .iter()
+            .map(|(stack, time)| format!("{stack} {}\n", time.as_micros()))
+            .collect()
+    }
+}
+
+impl Timings {
+    fn returned(&mut self, now: Instant) {
+        let Some(call) = self.calls.pop() else {
+            return;
+        };
+        let took = now.saturating_duration_since(call.entered);
+        let own = took.saturating_sub(call.inner);
+        if let Some(caller) = self.calls.last_mut() {
+            caller.inner += took;
+        }
+        let recursive = self.calls.iter().any(|outer| outer.name == call.name);
+        let mut stack: Vec<&str> = self.calls.iter().map(|outer| outer.name.as_str()).collect();
+        stack.push(&call.name);
+        let stack = stack.join(";");
+        match self.stacks.iter_mut().find(|(folded, _)| *folded == stack) {
+            Some((_, time)) => *time += own,
+            None => self.stacks.push((stack, own)),
+        }
+        let procedure = match self.procedures.iter().position(|p| p.name == call.name) {
+            Some(i) => &mut self.procedures[i],
+            None => {
+                self.procedures.push(Procedure {
+                    name: call.name,
+                    calls: 0,
+                    total: Duration::ZERO,
+                    own: Duration::ZERO,
+                });
+                self.procedures.last_mut().unwrap()
+            }
+        };
+        procedure.calls += 1;
+        procedure.own += own;
+        if !recursive {
+            procedure.total += took;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{io::MemoryIo, value::Value};
+
+    #[test]
+    fn profile_test() {
+        let profile = Profile::new();
+        let mut interpreter = Interpreter::new();
+        profile.install(&mut interpreter);
+        interpreter
+            .eval_str(
+                "(define (fact n) (if (= n 0) 1 (* n (fact (- n 1)))))
+                 (define (main) (fact 3) (car '(1)))
+                 (main)",
+            )
+            .unwrap();
+        let table = profile.table();
+        let mut rows: Vec<(usize, &str)> = table
+            .lines()
+            .skip(1)
+            .map(|row| {
+                let columns: Vec<&str> = row.split_whitespace().collect();
+                let total: f64 = columns[1].parse().unwrap();
+                let own: f64 = columns[2].parse().unwrap();
+                assert!(own <= total, "{row}");
+                (columns[0].parse().unwrap(), columns[3])
+            })
+            .collect();
+        rows.sort_by_key(|&(_, name)| name);
+        assert_eq!(
+            rows,
+            [
+                (3, "*"),
+                (3, "-"),
+                (4, "="),
+                (1, "car"),
+                (4, "fact"),
+                (1, "main")
+            ]
+        );
+        let folded = profile.folded();
+        let stacks: Vec<&str> = folded
+            .lines()
+            .map(|line| line.rsplit_once(' ').unwrap().0)
+            .collect();
+        assert!(stacks.contains(&"main;fact;fact;fact;fact;="));
+        assert!(stacks.contains(&"main;car"));
+    }
+
+    #[test]
+    fn profile_builtin_test() {
+        let io = MemoryIo::new();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_io(io.clone());
+        assert_eq!(
+            interpreter.eval_str("(profile (lambda () (car '(1))))"),
+            Ok(Value::Integer(1))
+        );
+        let output = io.output();
+        assert!(output.starts_with("  calls   total ms    self ms  name\n"));
+        assert!(output.ends_with("  car\n"), "{output}");
+        assert!(interpreter.eval_str("(profile)").is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use core::{cmp::Ordering, fmt};
+
+// An exact fraction in lowest terms with a positive denominator. Whole
+// numbers are Integers rather than fractions over 1, so a Rational always
+// has a denominator of at least 2 once it reaches an Expr or Value.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rational {
+    numerator: i64,
+    denominator: i64,
+}
+
+fn gcd(mut a: i128, mut b: i128) -> i128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a.abs()
+}
+
+impl Rational {
+    // None when the denominator is zero or the reduced fraction doesn't
+    // fit in two i64s.
+    pub fn new(numerator: i64, denominator: i64) -> Option<Self> {
+        Rational::reduce(numerator as i128, denominator as i128)
+    }
+
+    fn reduce(numerator: i128, denominator: i128) -> Option<Self> {
+        if denominator == 0 {
+            return None;
+        }
+        let divisor = gcd(numerator, denominator) * denominator.signum();
+        Some(Rational {
+            numerator: i64::try_from(numerator / divisor).ok()?,
+            denominator: i64::try_from(denominator / divisor).ok()?,
+        })
+    }
+
+    pub fn numerator(self) -> i64 {
+        self.numerator
+    }
+
+    pub fn denominator(self) -> i64 {
+        self.denominator
+    }
+
+    // The whole number this is equal to, if any.
+    pub fn to_integer(self) -> Option<i64> {
+        (self.denominator == 1).then_some(self.numerator)
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.numerator as f64 / self.denominator as f64
+    }
+
+    pub fn checked_add(self, other: Rational) -> Option<Rational> {
+        let (a, b) = (self.wide(), other.wide());
+        Rational::reduce(a.0 * b.1 + b.0 * a.1, a.1 * b.1)
+    }
+
+    pub fn checked_sub(self, other: Rational) -> Option<Rational> {
+        let (a, b) = (self.wide(), other.wide());
+        Rational::reduce(a.0 * b.1 - b.0 * a.1, a.1 * b.1)
+    }
+
+    pub fn checked_mul(self, other: Rational) -> Option<Rational> {
+        let (a, b) = (self.wide(), other.wide());
+        Rational::reduce(a.0 * b.0, a.1 * b.1)
+    }
+
+    pub fn checked_div(self, other: Rational) -> Option<Rational> {
+        let (a, b) = (self.wide(), other.wide());
+        Rational::reduce(a.0 * b.1, a.1 * b.0)
+    }
+
+    // Products of two i64s always fit in an i128, so the operations above
+    // can't overflow before reducing.
+    fn wide(self) -> (i128, i128) {
+        (self.numerator as i128, self.denominator as i128)
+    }
+}
+
+impl From<i64> for Rational {
+    fn from(i: i64) -> Self {
+        Rational {
+            numerator: i,
+            denominator: 1,
+        }
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (a, b) = (self.wide(), other.wide());
+        (a.0 * b.1).cmp(&(b.0 * a.1))
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::string::ToString;
+
+    fn ratio(n: i64, d: i64) -> Rational {
+        Rational::new(n, d).unwrap()
+    }
+
+    #[test]
+    fn new_test() {
+        assert_eq!(ratio(2, 4), ratio(1, 2));
+        assert_eq!(ratio(3, -6).to_string(), "-1/2");
+        assert_eq!(ratio(-4, -2).to_integer(), Some(2));
+        assert_eq!(Rational::new(1, 0), None);
+        assert_eq!(Rational::new(i64::MIN, -1), None);
+        assert_eq!(ratio(1, 4).to_f64(), 0.25);
+    }
+
+    #[test]
+    fn arithmetic_test() {
+        let third = ratio(1, 3);
+        assert_eq!(third.checked_add(ratio(1, 6)), Some(ratio(1, 2)));
+        assert_eq!(third.checked_sub(ratio(1, 2)), Some(ratio(-1, 6)));
+        assert_eq!(third.checked_mul(ratio(3, 1)), Some(ratio(1, 1)));
+        assert_eq!(third.checked_div(ratio(-2, 3)), Some(ratio(-1, 2)));
+        assert_eq!(third.checked_div(ratio(0, 1)), None);
+        assert_eq!(
+            Rational::from(i64::MAX).checked_add(Rational::from(1)),
+            None
+        );
+        assert!(ratio(1, 3) < ratio(1, 2) && ratio(-1, 2) < ratio(-1, 3));
+    }
+}
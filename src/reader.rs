@@ -0,0 +1,431 @@
+use alloc::{string::String, vec::Vec};
+use core::{cell::RefCell, fmt};
+
+use crate::{
+    lexer::{is_symbol_char, LexErrorKind, Span, Spanned, Token},
+    local::{finally, local},
+    parser::{lex_source, parse_expr, parse_failure, skip_datum_comments, Expr, ReadError, Tokens},
+    sync::{MaybeSync, Shared},
+};
+
+// Turns the datum read after a dispatch prefix into the expression read in
+// its place, or says what is wrong with it.
+#[cfg(not(feature = "sync"))]
+pub type DispatchFn = Shared<dyn Fn(Expr) -> Result<Expr, String>>;
+#[cfg(feature = "sync")]
+pub type DispatchFn = Shared<dyn Fn(Expr) -> Result<Expr, String> + Send + Sync>;
+
+// Syntax added to the reader from Rust. A lead character such as `#` and
+// the character after it pick a handler for the datum that follows, so
+// #x1F can hand 1F to a function that reads it as hexadecimal. When the
+// second character could start a symbol it is part of the prefix;
+// otherwise, as in #( or #", it is left to start the datum. Syntax the
+// lexer already has, such as #t or #;, keeps its meaning.
+#[derive(Clone, Default)]
+pub struct ReadTable {
+    dispatch: Vec<(char, char, DispatchFn)>,
+}
+
+impl ReadTable {
+    pub fn new() -> Self {
+        ReadTable::default()
+    }
+
+    // Replaces a handler already set for the same two characters.
+    pub fn set_dispatch(
+        &mut self,
+        lead: char,
+        sub: char,
+        handler: impl Fn(Expr) -> Result<Expr, String> + MaybeSync + 'static,
+    ) {
+        let handler: DispatchFn = Shared::new(handler);
+        let entry = self.dispatch.iter_mut().find(|e| (e.0, e.1) == (lead, sub));
+        match entry {
+            Some(entry) => entry.2 = handler,
+            None => self.dispatch.push((lead, sub, handler)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dispatch.is_empty()
+    }
+
+    fn get(&self, lead: char, sub: char) -> Option<&DispatchFn> {
+        self.dispatch
+            .iter()
+            .find(|entry| (entry.0, entry.1) == (lead, sub))
+            .map(|entry| &entry.2)
+    }
+}
+
+impl fmt::Debug for ReadTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prefixes = self.dispatch.iter().map(|(lead, sub, _)| (lead, sub));
+        f.debug_struct("ReadTable")
+            .field("dispatch", &prefixes.collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+local! {
+    static CURRENT: RefCell<ReadTable> = const {
+        RefCell::new(ReadTable { dispatch: Vec::new() })
+    };
+}
+
+// Reads in f go through table, then whatever was installed before is put
+// back.
+pub(crate) fn with_read_table<T>(table: &ReadTable, f: impl FnOnce() -> T) -> T {
+    let saved = CURRENT.with(|current| current.replace(table.clone()));
+    finally(f, || CURRENT.with(|current| *current.borrow_mut() = saved))
+}
+
+// Whether a token starting with lead then sub is a dispatch prefix.
+pub(crate) fn dispatches(lead: char, sub: char) -> bool {
+    CURRENT.with(|current| {
+        let table = current.borrow();
+        !table.is_empty() && table.get(lead, sub).is_some()
+    })
+}
+
+// The handler is cloned out of the table, which isn't borrowed while it
+// runs, so it can read source itself.
+pub(crate) fn dispatch(lead: char, sub: char, datum: Expr) -> Result<Expr, String> {
+    let handler = CURRENT.with(|current| current.borrow().get(lead, sub).cloned());
+    match handler {
+        Some(handler) => handler(datum),
+        None => Err(alloc::format!("no reader syntax for `{lead}{sub}`")),
+    }
+}
+
+// Reads forms from input that arrives in pieces, such as lines typed at a
+// prompt or chunks off a socket. Each complete form is returned as soon as
+// it has been fed; an unfinished one stays buffered until more input
+// completes it.
+#[derive(Clone, Debug, Default)]
+pub struct Reader {
+    buffer: String,
+    pending: bool,
+    table: ReadTable,
+}
+
+impl Reader {
+    pub fn new() -> Self {
+        Reader::default()
+    }
+
+    // Returns the forms completed by this chunk. On a read error the
+    // buffered input is discarded, so the reader can go on with fresh input.
+    pub fn feed(&mut self, chunk: &str) -> Result<Vec<Expr>, ReadError> {
+        self.buffer.push_str(chunk);
+        self.read_buffered(false)
+    }
+
+    // Reads whatever is still buffered once there is no more input to come.
+    // An unfinished form is an error at this point.
+    pub fn finish(&mut self) -> Result<Vec<Expr>, ReadError> {
+        self.read_buffered(true)
+    }
+
+    // What has been fed and not yet read, which with the chunk fed next is
+    // the text that a read error from that chunk has its span in.
+    pub fn buffered(&self) -> &str {
+        &self.buffer
+    }
+
+    // True when the buffer holds the start of a form that isn't complete.
+    pub fn needs_more_input(&self) -> bool {
+        self.pending
+    }
+
+    // Adds reader syntax, as ReadTable::set_dispatch does. It applies to
+    // whatever is fed from then on, including what is already buffered.
+    pub fn set_dispatch(
+        &mut self,
+        lead: char,
+        sub: char,
+        handler: impl Fn(Expr) -> Result<Expr, String> + MaybeSync + 'static,
+    ) {
+        self.table.set_dispatch(lead, sub, handler);
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.pending = false;
+    }
+
+    fn read_buffered(&mut self, at_end: bool) -> Result<Vec<Expr>, ReadError> {
+        match with_read_table(&self.table, || read_prefix(&self.buffer, at_end)) {
+            Ok((exprs, consumed, pending)) => {
+                self.buffer.drain(..consumed);
+                self.pending = pending;
+                Ok(exprs)
+            }
+            Err(e) => {
+                self.clear();
+                Err(e)
+            }
+        }
+    }
+}
+
+// Reads the complete forms at the start of input. Returns them with the
+// number of bytes they took up and whether an unfinished form follows.
+// Anything after the last complete form, comments included, is left in
+// place since the next chunk may change how it reads.
+fn read_prefix(input: &str, at_end: bool) -> Result<(Vec<Expr>, usize, bool), ReadError> {
+    let (tokens, mut pending) = match lex_source(input) {
+        Ok(tokens) => (tokens, false),
+        Err(e) if !at_end && lex_may_continue(&e, input) => {
+            (lex_source(&input[..token_start(&e, input)])?, true)
+        }
+        Err(e) => return Err(e),
+    };
+    let eof = Span::new(input.len(), input.len());
+    let mut exprs = Vec::new();
+    let mut consumed = 0;
+    let mut rest = Tokens::new(&tokens);
+    loop {
+        if let Ok((after, _)) = skip_datum_comments(rest.clone()) {
+            rest = after;
+        }
+        if rest.tokens.is_empty() {
+            break;
+        }
+        match parse_expr(rest.clone()) {
+            Ok((after, _)) if !at_end && after.tokens.is_empty() && ends_open(&tokens, input) => {
+                pending = true;
+                break;
+            }
+            Ok((after, expr)) => {
+                consumed = expr.span().end;
+                exprs.push(expr.into_expr());
+                rest = after;
+            }
+            Err(e) => match parse_failure(e, eof) {
+                e if e.is_incomplete() && !at_end => {
+                    pending = true;
+                    break;
+                }
+                e => return Err(e),
+            },
+        }
+    }
+    if at_end {
+        consumed = input.len();
+    }
+    Ok((exprs, consumed, pending))
+}
+
+// An unterminated string, |symbol| or block comment can still be closed
+// by a later chunk, and so can a string cut off in the middle of an escape
+// or a character name that runs to the end. A `#` at the very end may be the
+// start of `#t`, `#;` or `#|`.
+fn lex_may_continue(e: &ReadError, input: &str) -> bool {
+    let ReadError::Lex { span, kind } = e else {
+        return false;
+    };
+    match kind {
+        LexErrorKind::UnterminatedString
+        | LexErrorKind::UnterminatedSymbol
+        | LexErrorKind::UnterminatedComment => true,
+        LexErrorKind::InvalidEscape => !input[span.start..].contains('"'),
+        LexErrorKind::UnexpectedCharacter => &input[span.start..] == "#",
+        LexErrorKind::UnknownCharacter => input[span.start + 2..].chars().all(is_symbol_char),
+        // More characters could make it a symbol.
+        LexErrorKind::IntegerOverflow => span.end == input.len(),
+    }
+}
+
+// Where the token that failed to lex begins. Only the forms before it are
+// read for now.
+fn token_start(e: &ReadError, input: &str) -> usize {
+    match e {
+        ReadError::Lex {
+            kind: LexErrorKind::InvalidEscape,
+            span,
+        } => input[..span.start].rfind('"').unwrap_or(0),
+        e => e.span().start,
+    }
+}
+
+// A symbol, number or boolean that runs up to the end of the input may
+// carry on in the next chunk, as `ab` does when followed by `cd`.
+fn ends_open(tokens: &[Spanned<Token>], input: &str) -> bool {
+    tokens.last().is_some_and(|token| {
+        token.span.end == input.len()
+            && !matches!(
+                token.value,
+                Token::LParan
+                    | Token::RParan
+                    | Token::LBracket
+                    | Token::RBracket
+                    | Token::LBrace
+                    | Token::RBrace
+                    | Token::String(_)
+            )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        parser::{read, read_all},
+        symbol::SymbolId,
+    };
+    use alloc::{borrow::ToOwned, format, string::ToString, vec};
+
+    // #x1F reads as 31. The digits are read as a symbol, or as an integer
+    // when there are no letters among them.
+    fn hex(datum: Expr) -> Result<Expr, String> {
+        let digits = match datum {
+            Expr::Integer(i) => i.to_string(),
+            Expr::Symbol(s) => s.as_str().to_string(),
+            _ => return Err("expected hex digits".to_string()),
+        };
+        i64::from_str_radix(&digits, 16)
+            .map(Expr::Integer)
+            .map_err(|_| "invalid hex literal".to_string())
+    }
+
+    fn reader_with_syntax() -> Reader {
+        let mut reader = Reader::new();
+        reader.set_dispatch('#', 'x', hex);
+        reader.set_dispatch('#', '(', |datum| match datum {
+            Expr::List(items) => Ok(Expr::Vector(items)),
+            _ => Err("expected a list".to_string()),
+        });
+        reader.set_dispatch('#', '"', |datum| {
+            let regex = Expr::Symbol(SymbolId::new("regex"));
+            Ok(Expr::List(vec![regex, datum]))
+        });
+        reader
+    }
+
+    #[test]
+    fn feed_test() {
+        let mut reader = Reader::new();
+        assert_eq!(reader.feed("(define x"), Ok(vec![]));
+        assert!(reader.needs_more_input());
+        assert_eq!(
+            reader.feed(" 1) (list"),
+            Ok(vec![read("(define x 1)").unwrap()])
+        );
+        assert!(reader.needs_more_input());
+        assert_eq!(
+            reader.feed(" x \"a)"),
+            Ok(vec![]),
+            "the paren inside the string doesn't close the list"
+        );
+        assert_eq!(
+            reader.feed("b\")\n"),
+            Ok(vec![read("(list x \"a)b\")").unwrap()])
+        );
+        assert!(!reader.needs_more_input());
+    }
+
+    #[test]
+    fn split_token_test() {
+        let mut reader = Reader::new();
+        assert_eq!(reader.feed("ab"), Ok(vec![]));
+        assert!(reader.needs_more_input());
+        assert_eq!(reader.feed("cd 12"), Ok(vec![read("abcd").unwrap()]));
+        assert_eq!(reader.feed("3 ; com"), Ok(vec![read("123").unwrap()]));
+        assert_eq!(reader.feed("ment\n'x"), Ok(vec![]));
+        assert_eq!(reader.feed(" #| a"), Ok(vec![read("'x").unwrap()]));
+        assert!(reader.needs_more_input());
+        assert_eq!(reader.feed(" |# y\n"), Ok(vec![read("y").unwrap()]));
+        assert_eq!(reader.finish(), Ok(vec![]));
+    }
+
+    #[test]
+    fn finish_test() {
+        let mut reader = Reader::new();
+        assert_eq!(reader.feed("1 2"), Ok(vec![Expr::Integer(1)]));
+        assert_eq!(reader.finish(), Ok(vec![Expr::Integer(2)]));
+        assert_eq!(reader.feed("(a"), Ok(vec![]));
+        assert!(reader.finish().unwrap_err().is_incomplete());
+        assert!(!reader.needs_more_input());
+    }
+
+    #[test]
+    fn error_test() {
+        let mut reader = Reader::new();
+        assert!(reader.feed("(a))").is_err());
+        assert!(!reader.needs_more_input());
+        assert_eq!(reader.feed("(b)\n"), Ok(vec![read("(b)").unwrap()]));
+    }
+
+    #[test]
+    fn chunked_test() {
+        let source = "(define (f x) `(,x \"s\\u{3bb}\")) ; done\n#;(skip) (f 'y) #t 42\n";
+        for size in 1..8 {
+            let mut reader = Reader::new();
+            let mut exprs = Vec::new();
+            for chunk in source.as_bytes().chunks(size) {
+                exprs.extend(reader.feed(core::str::from_utf8(chunk).unwrap()).unwrap());
+            }
+            exprs.extend(reader.finish().unwrap());
+            assert_eq!(exprs, read_all(source).unwrap(), "chunks of {size}");
+        }
+    }
+
+    #[test]
+    fn dispatch_test() {
+        let mut reader = reader_with_syntax();
+        assert_eq!(
+            reader.feed("(+ #x1F #x10) #(1 #x2 3) #\"a+b\" #t\n"),
+            Ok(vec![
+                read("(+ 31 16)").unwrap(),
+                read("[1 2 3]").unwrap(),
+                read("(regex \"a+b\")").unwrap(),
+                Expr::Bool(true),
+            ])
+        );
+        // The prefix's datum can arrive in a later chunk.
+        assert_eq!(reader.feed("#x"), Ok(vec![]));
+        assert_eq!(reader.feed("f"), Ok(vec![]));
+        assert!(reader.needs_more_input());
+        assert_eq!(reader.feed("f #("), Ok(vec![Expr::Integer(255)]));
+        assert_eq!(
+            reader.feed("a)"),
+            Ok(vec![Expr::Vector(vec![read("a").unwrap()])])
+        );
+
+        assert_eq!(
+            reader.feed("(list #xzz)"),
+            Err(ReadError::Dispatch {
+                span: Span::new(6, 8),
+                message: "invalid hex literal".to_owned(),
+            })
+        );
+        assert_eq!(
+            reader.feed("#y1").unwrap_err().to_string(),
+            "unexpected character at byte 0"
+        );
+        // The syntax belongs to the reader it was added to.
+        assert!(read("#x1F").is_err());
+        assert!(Reader::new().feed("#(1) ").is_err());
+        assert!(format!("{reader:?}").contains("('#', 'x')"));
+    }
+
+    #[test]
+    fn read_table_test() {
+        let mut table = ReadTable::new();
+        table.set_dispatch('#', 'x', |_| Err("replaced".to_string()));
+        table.set_dispatch('#', 'x', hex);
+        assert_eq!(
+            with_read_table(&table, || read_all("(a . #xA) '#x1 #;#x2")),
+            read_all("(a . 10) '1")
+        );
+        assert_eq!(
+            with_read_table(&table, || read_all("(#x\"s\")")),
+            Err(ReadError::Dispatch {
+                span: Span::new(1, 3),
+                message: "expected hex digits".to_owned(),
+            })
+        );
+        assert!(read_all("#x1").is_err());
+    }
+}
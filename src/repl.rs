@@ -0,0 +1,575 @@
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+use crate::{
+    debug,
+    eval::EvalError,
+    hooks::{Event, Hook},
+    interpreter::{Interpreter, InterpreterError},
+    lexer::{is_symbol_char, Span},
+    reader::Reader,
+    report::{did_you_mean, snippet},
+    symbol::SPECIAL_FORMS,
+    sync::{Lock, Shared},
+};
+
+// Lines go to a Reader until they make up complete forms, so a form can be
+// spread over several lines, and the forms run on an Interpreter, so load,
+// module and import work as they do in a file. The lines of a pending form
+// are kept so they can be recorded as one history entry.
+pub struct Repl {
+    interpreter: Interpreter,
+    reader: Reader,
+    buffer: String,
+    history: Vec<String>,
+    // The forms that ran without an error, written out again.
+    session: Vec<String>,
+    // The status a form asked to exit with.
+    exit: Option<i32>,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Repl::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Repl::with_interpreter(Interpreter::new())
+    }
+
+    // A REPL on an interpreter the host has set up, with its own functions,
+    // limits or backend.
+    pub fn with_interpreter(interpreter: Interpreter) -> Self {
+        Repl {
+            interpreter,
+            reader: Reader::new(),
+            buffer: String::new(),
+            history: Vec::new(),
+            session: Vec::new(),
+            exit: None,
+        }
+    }
+
+    pub fn prompt(&self) -> &'static str {
+        if self.buffer.is_empty() {
+            "> "
+        } else {
+            ".. "
+        }
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    pub fn session(&self) -> &[String] {
+        &self.session
+    }
+
+    // Writes the forms that ran without an error to path, one to a line, so
+    // what was worked out at the prompt can be run again as a script.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let text: String = self
+            .session
+            .iter()
+            .map(|form| format!("{form}\n"))
+            .collect();
+        fs::write(path, text)
+    }
+
+    // Runs the forms in a file as if they had been entered, giving their
+    // output as feed would. A form the file leaves unfinished is dropped.
+    pub fn replay(&mut self, path: impl AsRef<Path>) -> io::Result<Vec<String>> {
+        let source = fs::read_to_string(path)?;
+        self.cancel();
+        let output = self.feed(&source);
+        if self.cancel() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the file ends inside a form",
+            ));
+        }
+        Ok(output.unwrap_or_default())
+    }
+
+    // What the word at the end of line could be, for a line editor to
+    // offer: the bound names and special forms that start with it or,
+    // inside a string as in (load "lib/u, the files whose paths start with
+    // what the string has so far. Each candidate is the whole word, and
+    // they come in order.
+    pub fn complete(&self, line: &str) -> Vec<String> {
+        let text = format!("{}{line}", self.buffer);
+        if in_string(&text) {
+            return complete_path(&text[text.rfind('"').unwrap() + 1..]);
+        }
+        let start = line
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| !is_symbol_char(c))
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let word = &line[start..];
+        let names = self.interpreter.env().names();
+        let mut candidates: Vec<String> = names
+            .iter()
+            .map(|name| name.as_str())
+            .chain(SPECIAL_FORMS.iter().copied())
+            .filter(|name| name.starts_with(word))
+            .map(str::to_owned)
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+
+    // Set once a form has called (exit n), after which the session is over.
+    pub fn exit_status(&self) -> Option<i32> {
+        self.exit
+    }
+
+    pub fn is_pending(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+
+    // Drops a partially entered form. Returns whether there was one.
+    pub fn cancel(&mut self) -> bool {
+        let pending = self.is_pending();
+        self.reader.clear();
+        self.buffer.clear();
+        pending
+    }
+
+    // Returns None while the input so far is an unfinished form, otherwise
+    // one entry of output per completed form: its value or the error it
+    // raised, which may go on for more lines to show where it is and what
+    // was meant. Forms before an unfinished one on the same line still run.
+    pub fn feed(&mut self, line: &str) -> Option<Vec<String>> {
+        if !self.is_pending() && line.trim().is_empty() {
+            return Some(Vec::new());
+        }
+        let chunk = format!("{line}\n");
+        self.buffer.push_str(&chunk);
+        // The blank lines the reader still has after earlier entries
+        // aren't shown.
+        let pending = self.reader.buffered();
+        let blank = pending.len() - pending.trim_start().len();
+        let text = format!("{}{chunk}", &pending[blank..]);
+        let source = self.reader.feed(&chunk);
+        if self.reader.needs_more_input() && matches!(source, Ok(ref exprs) if exprs.is_empty()) {
+            return None;
+        }
+        if !self.reader.needs_more_input() {
+            let entry = std::mem::take(&mut self.buffer);
+            self.history.push(entry.trim_end().to_owned());
+        }
+        let exprs = match source {
+            Ok(exprs) => exprs,
+            Err(e) => {
+                let span = e.span();
+                let span = Span::new(
+                    span.start.saturating_sub(blank),
+                    span.end.saturating_sub(blank),
+                );
+                let shown = snippet(&text, span);
+                return Some(vec![format!("read error: {e}\n{}", shown.trim_end())]);
+            }
+        };
+        let mut output = Vec::with_capacity(exprs.len());
+        for expr in exprs {
+            match self.interpreter.eval_expr(&expr) {
+                Ok(value) => {
+                    self.session.push(expr.to_string());
+                    output.push(value.to_string());
+                }
+                Err(EvalError::Exit(status)) => {
+                    self.exit = Some(status);
+                    break;
+                }
+                Err(e) => {
+                    output.push(self.error(&e));
+                    break;
+                }
+            }
+        }
+        Some(output)
+    }
+
+    // Runs source as feed would, with an entry for each step of its
+    // evaluation the tree-walker reports: a call with the values it was
+    // given, marked where it's written in source, and the parameters they
+    // bind; its value once it returns; which way a test went; and what a
+    // define bound. The last entry is source's value or error.
+    pub fn step(&mut self, source: &str) -> Vec<String> {
+        let steps = Shared::new(Lock::new(Steps {
+            text: source.to_owned(),
+            ..Steps::default()
+        }));
+        let recorder = steps.clone();
+        let hook: Shared<Lock<Hook>> = Shared::new(Lock::new(move |event: Event| {
+            recorder.borrow_mut().observe(event)
+        }));
+        let saved = self.interpreter.replace_hook(Some(hook));
+        let result = self.interpreter.eval_str(source);
+        self.interpreter.replace_hook(saved);
+        let mut output = std::mem::take(&mut steps.borrow_mut().entries);
+        output.push(match result {
+            Ok(value) => {
+                self.session.push(source.trim().to_owned());
+                value.to_string()
+            }
+            Err(InterpreterError::Eval(EvalError::Exit(status))) => {
+                self.exit = Some(status);
+                return output;
+            }
+            Err(InterpreterError::Eval(e)) => self.error(&e),
+            Err(e) => e.to_string(),
+        });
+        output
+    }
+
+    // An error a form raised, with a hint at the name an unbound symbol
+    // was likely meant to be.
+    fn error(&self, e: &EvalError) -> String {
+        let mut shown = format!("error: {e}");
+        if let EvalError::UnboundSymbol(name) = e {
+            let bound = self.interpreter.env().names();
+            if let Some(hint) = did_you_mean(name, &bound) {
+                shown.push_str(&format!("\nhelp: {hint}"));
+            }
+        }
+        shown
+    }
+}
+
+// What step shows of a run so far.
+#[derive(Default)]
+struct Steps {
+    text: String,
+    entries: Vec<String>,
+    // How many calls there have been.
+    count: usize,
+    // The calls under way, as step shows them, innermost last.
+    calls: Vec<String>,
+}
+
+impl Steps {
+    fn observe(&mut self, event: Event) {
+        let entry = match event {
+            Event::EnterCall {
+                name,
+                callee,
+                args,
+                span,
+                ..
+            } => {
+                let mut call = match name {
+                    Some(name) => format!("({name}"),
+                    None => format!("({callee}"),
+                };
+                for arg in args {
+                    call.push_str(&format!(" {arg}"));
+                }
+                call.push(')');
+                self.count += 1;
+                let mut entry = format!("{}. {call}", self.count);
+                if let Some(span) = span {
+                    for line in snippet(&self.text, span).lines() {
+                        entry.push_str(&format!("\n   {line}"));
+                    }
+                }
+                for (name, value) in debug::parameters(callee, args).unwrap_or_default() {
+                    entry.push_str(&format!("\n   + {name} = {value}"));
+                }
+                self.calls.push(call);
+                entry
+            }
+            Event::Return { result, .. } => {
+                let call = self.calls.pop().unwrap_or_default();
+                match result {
+                    Ok(value) => format!("   {call} => {value}"),
+                    Err(e) => format!("   {call} raised: {e}"),
+                }
+            }
+            Event::Branch { taken, .. } => format!("   the test was {taken}"),
+            Event::Define { name, value, .. } => format!("   + {name} = {value}"),
+        };
+        self.entries.push(entry);
+    }
+}
+
+// Whether text ends inside a string literal, not counting quotes that are
+// escaped or in comments.
+fn in_string(text: &str) -> bool {
+    let mut chars = text.chars();
+    let mut open = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => open = !open,
+            '\\' if open => {
+                chars.next();
+            }
+            ';' if !open => {
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            _ => {}
+        }
+    }
+    open
+}
+
+// The paths that start with partial, with a slash after directories.
+fn complete_path(partial: &str) -> Vec<String> {
+    let (dir, prefix) = match partial.rfind('/') {
+        Some(i) => partial.split_at(i + 1),
+        None => ("", partial),
+    };
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<String> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            name.starts_with(prefix)
+                .then(|| format!("{dir}{name}{slash}"))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+// End of input cancels a pending form, or quits when there is none, with
+// status 0; (exit n) quits with n.
+pub fn run(mut input: impl BufRead, mut output: impl Write) -> io::Result<i32> {
+    let mut repl = Repl::new();
+    let mut line = String::new();
+    loop {
+        write!(output, "{}", repl.prompt())?;
+        output.flush()?;
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            if repl.cancel() {
+                writeln!(output)?;
+                continue;
+            }
+            return writeln!(output).map(|()| 0);
+        }
+        if !repl.is_pending() && command(&mut repl, line.trim(), &mut output)? {
+            continue;
+        }
+        for result in repl
+            .feed(line.trim_end_matches(['\n', '\r']))
+            .unwrap_or_default()
+        {
+            writeln!(output, "{result}")?;
+        }
+        if let Some(status) = repl.exit_status() {
+            return Ok(status);
+        }
+    }
+}
+
+// The REPL's own commands: `:history` lists the inputs entered so far,
+// `:save path` writes the forms that ran to a file, `:replay path` runs
+// the forms in one, `:complete text` lists the completions of the word
+// text ends with and `:step forms` runs forms a step at a time. Returns
+// whether line was a command.
+fn command(repl: &mut Repl, line: &str, output: &mut impl Write) -> io::Result<bool> {
+    let (name, path) = line.split_once(' ').unwrap_or((line, ""));
+    let lines = match name {
+        ":complete" => Ok(repl.complete(path)),
+        ":history" => Ok(repl
+            .history()
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| format!("{:>4}  {entry}", i + 1))
+            .collect()),
+        ":save" => repl.save(path.trim()).map(|()| Vec::new()),
+        ":replay" => repl.replay(path.trim()),
+        ":step" => Ok(repl.step(path)),
+        _ => return Ok(false),
+    };
+    for line in lines.unwrap_or_else(|e| vec![format!("error: {e}")]) {
+        writeln!(output, "{line}")?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn feed_test() {
+        let mut repl = Repl::new();
+        assert_eq!(
+            repl.feed("(define x 1) x"),
+            Some(vec!["()".to_owned(), "1".to_owned()])
+        );
+        assert_eq!(repl.feed("(list x"), None);
+        assert_eq!(repl.prompt(), ".. ");
+        assert_eq!(repl.feed("  2)"), Some(vec!["(1 2)".to_owned()]));
+        assert_eq!(
+            repl.feed("missing"),
+            Some(vec!["error: unbound symbol `missing`".to_owned()])
+        );
+        assert_eq!(
+            repl.feed("(lenght '(a))"),
+            Some(vec![
+                "error: unbound symbol `lenght`\nhelp: did you mean `length`?".to_owned()
+            ])
+        );
+        assert_eq!(
+            repl.feed("(a))"),
+            Some(vec![
+                "read error: expected expression, found `)` at byte 4\n1 | (a))\n  |    ^"
+                    .to_owned()
+            ])
+        );
+        assert_eq!(repl.feed(""), Some(vec![]));
+        assert_eq!(
+            repl.history(),
+            [
+                "(define x 1) x",
+                "(list x\n  2)",
+                "missing",
+                "(lenght '(a))",
+                "(a))"
+            ]
+        );
+    }
+
+    #[test]
+    fn module_test() {
+        let mut repl = Repl::new();
+        assert_eq!(
+            repl.feed("(module m (export two) (define two 2))"),
+            Some(vec!["()".to_owned()])
+        );
+        assert_eq!(
+            repl.feed("(import m) two"),
+            Some(vec!["()".to_owned(), "2".to_owned()])
+        );
+        assert_eq!(repl.feed("m/two"), Some(vec!["2".to_owned()]));
+        let output = repl.feed("(load \"missing.scm\")").unwrap();
+        assert!(
+            output[0].starts_with("error: cannot load `missing.scm`"),
+            "{output:?}"
+        );
+    }
+
+    #[test]
+    fn cancel_test() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.feed("(list 1"), None);
+        assert!(repl.cancel());
+        assert!(!repl.cancel());
+        assert_eq!(repl.prompt(), "> ");
+        assert_eq!(repl.feed("(list 2)"), Some(vec!["(2)".to_owned()]));
+    }
+
+    #[test]
+    fn save_test() {
+        let path = std::env::temp_dir().join(format!("tuple-gd-repl-{}", std::process::id()));
+        let mut repl = Repl::new();
+        repl.feed("(define (sq x) (* x x))");
+        repl.feed("(sq 'oops)");
+        repl.feed("(define nine (sq 3)) \"saved\"");
+        assert_eq!(repl.session().len(), 3);
+        repl.save(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "(define (sq x) (* x x))\n(define nine (sq 3))\n\"saved\"\n"
+        );
+
+        let mut fresh = Repl::new();
+        assert_eq!(
+            fresh.replay(&path).unwrap(),
+            ["()", "()", "\"saved\""].map(str::to_owned)
+        );
+        assert_eq!(fresh.feed("nine"), Some(vec!["9".to_owned()]));
+        fs::write(&path, "(list 1").unwrap();
+        assert!(fresh.replay(&path).is_err());
+        assert!(!fresh.is_pending());
+        fs::remove_file(&path).unwrap();
+        assert!(fresh.replay(&path).is_err());
+    }
+
+    #[test]
+    fn complete_test() {
+        let mut repl = Repl::new();
+        repl.feed("(define string-count 1)");
+        assert_eq!(repl.complete("(defi"), ["define"]);
+        let strings = repl.complete("(+ 1 str");
+        assert!(strings.contains(&"string-count".to_owned()), "{strings:?}");
+        assert!(strings.contains(&"string-append".to_owned()), "{strings:?}");
+        assert_eq!(repl.complete("(list 'a ;; (defi"), ["define"]);
+        assert_eq!(repl.complete("(list \"defi"), Vec::<String>::new());
+
+        let dir = std::env::temp_dir().join(format!("tuple-gd-complete-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("lib.scm"), "").unwrap();
+        let dir = dir.to_str().unwrap();
+        assert_eq!(
+            repl.complete(&format!("(load \"{dir}/li")),
+            [format!("{dir}/lib.scm"), format!("{dir}/lib/")]
+        );
+        fs::remove_dir_all(dir).unwrap();
+        // The string the pending form left open is still open.
+        assert_eq!(repl.feed("(list \"two"), None);
+        assert_eq!(repl.complete("lines defi"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn step_test() {
+        let mut repl = Repl::new();
+        repl.feed("(define (inc x) (+ x 1))");
+        assert_eq!(
+            repl.step("(define y (if #t (inc 2) 0))"),
+            [
+                "   the test was true",
+                "1. (inc 2)\n   \
+                 1 | (define y (if #t (inc 2) 0))\n     \
+                 |                  ^^^^^^^\n   \
+                 + x = 2",
+                "2. (+ 2 1)",
+                "   (+ 2 1) => 3",
+                "   (inc 2) => 3",
+                "   + y = 3",
+                "()",
+            ]
+        );
+        assert_eq!(
+            repl.session().last().map(String::as_str),
+            Some("(define y (if #t (inc 2) 0))")
+        );
+        assert_eq!(
+            repl.step("(car 1)").last().unwrap(),
+            "error: expected list, found 1"
+        );
+        assert_eq!(repl.feed("y"), Some(vec!["3".to_owned()]));
+    }
+
+    #[test]
+    fn run_test() {
+        let mut output = Vec::new();
+        run("(list 1\n 2)\n:history\n".as_bytes(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "> .. (1 2)\n>    1  (list 1\n 2)\n> \n"
+        );
+        let mut output = Vec::new();
+        let input = "(+ 1 1) (exit 3) 4\n5\n";
+        assert_eq!(run(input.as_bytes(), &mut output).unwrap(), 3);
+        assert_eq!(String::from_utf8(output).unwrap(), "> 2\n");
+        let mut output = Vec::new();
+        run(":replay /nonexistent/file\n".as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("> error: "), "{output}");
+    }
+}
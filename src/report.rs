@@ -0,0 +1,116 @@
+// Errors shown with the source they're about, for people rather than
+// editors: the line a span starts on with a mark under the span,
+//
+//   3 | (defin x 1)
+//     |  ^^^^^
+//
+// and for a name that isn't bound, the bound name it was most likely meant
+// to be. The command line, the REPL and lint all show errors this way.
+use alloc::{format, string::String, vec::Vec};
+
+use crate::{
+    lexer::Span,
+    symbol::{SymbolId, SPECIAL_FORMS},
+};
+
+// The line span starts on, after its number, and a line marking the span
+// under it, each ending in a newline. A span that goes on past the end of
+// the line is marked to the end of it, and an empty one gets a single mark.
+pub fn snippet(text: &str, span: Span) -> String {
+    let (line, column) = span.line_col(text);
+    let start = span.start.min(text.len());
+    let line_start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[start..].find('\n').map_or(text.len(), |i| start + i);
+    let shown = text[line_start..line_end].trim_end_matches('\r');
+    let marked = text
+        .get(start..span.end.clamp(start, line_end))
+        .map_or(0, |marked| marked.chars().count());
+    let number = format!("{line}");
+    let gutter = " ".repeat(number.len());
+    let shown = format!("{number} | {shown}");
+    format!(
+        "{}\n{gutter} | {}{}\n",
+        shown.trim_end(),
+        " ".repeat(column - 1),
+        "^".repeat(marked.max(1))
+    )
+}
+
+// What an unbound name was most likely meant to be, out of the names bound
+// and the special forms: the nearest that a typo or two could have turned
+// into it, if any is that near.
+pub fn did_you_mean(name: &str, bound: &[SymbolId]) -> Option<String> {
+    let candidates = bound
+        .iter()
+        .map(|symbol| symbol.as_str())
+        .chain(SPECIAL_FORMS.iter().copied());
+    let mut best: Option<(usize, &str)> = None;
+    for candidate in candidates {
+        let distance = distance(name, candidate);
+        if distance > 0
+            && distance * 3 <= name.chars().count().max(3)
+            && best.is_none_or(|(nearest, _)| distance < nearest)
+        {
+            best = Some((distance, candidate));
+        }
+    }
+    best.map(|(_, candidate)| format!("did you mean `{candidate}`?"))
+}
+
+// How many characters have to be inserted, deleted or replaced to make one
+// string the other.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &b) in b.iter().enumerate() {
+            let replaced = diagonal + usize::from(a != b);
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn snippet_test() {
+        let text = "(define x 1)\n(defin y\n  2)";
+        assert_eq!(
+            snippet(text, Span::new(13, 19)),
+            "2 | (defin y\n  | ^^^^^^\n"
+        );
+        // To the end of the line, and one mark at the end of the text.
+        assert_eq!(
+            snippet(text, Span::new(13, 26)),
+            "2 | (defin y\n  | ^^^^^^^^\n"
+        );
+        assert_eq!(snippet(text, Span::new(26, 26)), "3 |   2)\n  |     ^\n");
+        let text = format!("{}é x", "\n".repeat(9));
+        assert_eq!(snippet(&text, Span::new(12, 13)), "10 | é x\n   |   ^\n");
+    }
+
+    #[test]
+    fn did_you_mean_test() {
+        let bound = ["length".into(), "list".into(), "last".into()];
+        assert_eq!(
+            did_you_mean("lenght", &bound).as_deref(),
+            Some("did you mean `length`?")
+        );
+        assert_eq!(
+            did_you_mean("defin", &bound).as_deref(),
+            Some("did you mean `define`?")
+        );
+        assert_eq!(
+            did_you_mean("lst", &bound).as_deref(),
+            Some("did you mean `list`?")
+        );
+        assert_eq!(did_you_mean("banana", &bound), None);
+        assert_eq!(did_you_mean("x", &bound), None);
+    }
+}
@@ -0,0 +1,209 @@
+use alloc::{boxed::Box, collections::BTreeMap, string::String, vec::Vec};
+#[cfg(not(feature = "std"))]
+use core::cell::RefCell;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::sync::{Mutex, OnceLock};
+
+use crate::limits;
+#[cfg(not(feature = "std"))]
+use crate::local::local;
+
+// An interned name. Two symbols with the same name always have the same
+// id, so comparing and hashing them never touches the string. Names are
+// interned for the life of the process, so a name new to a run counts
+// against its heap limit.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SymbolId(u32);
+
+struct Interner {
+    names: Vec<&'static str>,
+    ids: BTreeMap<&'static str, SymbolId>,
+}
+
+impl Interner {
+    fn new() -> Self {
+        let mut interner = Interner {
+            names: Vec::new(),
+            ids: BTreeMap::new(),
+        };
+        for name in KNOWN {
+            interner.intern(name);
+        }
+        interner
+    }
+
+    fn intern(&mut self, name: &str) -> SymbolId {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let name: &'static str = Box::leak(name.into());
+        let id = SymbolId(self.names.len() as u32);
+        self.names.push(name);
+        self.ids.insert(name, id);
+        id
+    }
+}
+
+// Names the evaluator looks for get fixed ids, in this order, so they can
+// be matched on as constants.
+macro_rules! known_symbols {
+    ($($id:ident = $name:expr,)*) => {
+        const KNOWN: &[&str] = &[$($name),*];
+
+        known_symbols!(@consts 0, $($id,)*);
+    };
+    (@consts $n:expr, $id:ident, $($rest:ident,)*) => {
+        pub const $id: SymbolId = SymbolId($n);
+        known_symbols!(@consts $n + 1, $($rest,)*);
+    };
+    (@consts $n:expr,) => {};
+}
+
+known_symbols! {
+    QUOTE = "quote",
+    QUASIQUOTE = "quasiquote",
+    UNQUOTE = "unquote",
+    UNQUOTE_SPLICING = "unquote-splicing",
+    DEFINE = "define",
+    LAMBDA = "lambda",
+    IF = "if",
+    LET = "let",
+    LET_STAR = "let*",
+    BEGIN = "begin",
+    DEFMACRO = "defmacro",
+    AND_KEY = "&key",
+    MATCH = "match",
+    WILDCARD = "_",
+    SET = "set!",
+    TRY = "try",
+    CATCH = "catch",
+    GENERATOR = "generator",
+    YIELD = "yield",
+    COND = "cond",
+    ELSE = "else",
+    AND = "and",
+    OR = "or",
+    WHEN = "when",
+    UNLESS = "unless",
+    LET_VALUES = "let-values",
+}
+
+// What a form can start with besides the names bound to values.
+pub const SPECIAL_FORMS: &[&str] = &[
+    "and",
+    "begin",
+    "cond",
+    "define",
+    "defmacro",
+    "generator",
+    "if",
+    "import",
+    "lambda",
+    "let",
+    "let*",
+    "let-values",
+    "match",
+    "module",
+    "or",
+    "quasiquote",
+    "quote",
+    "set!",
+    "try",
+    "unless",
+    "when",
+];
+
+// With std the interner is shared by every thread, so a symbol means the
+// same thing wherever an Expr is sent. Without it there is only the one.
+#[cfg(feature = "std")]
+fn with_interner<T>(f: impl FnOnce(&mut Interner) -> T) -> T {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    f(&mut INTERNER
+        .get_or_init(|| Mutex::new(Interner::new()))
+        .lock()
+        .unwrap())
+}
+
+#[cfg(not(feature = "std"))]
+fn with_interner<T>(f: impl FnOnce(&mut Interner) -> T) -> T {
+    local! {
+        static INTERNER: RefCell<Interner> = RefCell::new(Interner::new());
+    }
+    INTERNER.with(|interner| f(&mut interner.borrow_mut()))
+}
+
+impl SymbolId {
+    pub fn new(name: &str) -> Self {
+        let (id, new) = with_interner(|interner| {
+            let known = interner.names.len();
+            (interner.intern(name), interner.names.len() > known)
+        });
+        if new {
+            limits::interned(name.len());
+        }
+        id
+    }
+
+    pub fn as_str(self) -> &'static str {
+        with_interner(|interner| interner.names[self.0 as usize])
+    }
+}
+
+impl From<&str> for SymbolId {
+    fn from(name: &str) -> Self {
+        SymbolId::new(name)
+    }
+}
+
+impl From<String> for SymbolId {
+    fn from(name: String) -> Self {
+        SymbolId::new(&name)
+    }
+}
+
+impl fmt::Display for SymbolId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for SymbolId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+// Symbols are written out by name, since ids are only meaningful within
+// the process that interned them.
+#[cfg(feature = "serde")]
+impl serde::Serialize for SymbolId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SymbolId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name: alloc::borrow::Cow<str> = serde::Deserialize::deserialize(deserializer)?;
+        Ok(SymbolId::new(&name))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::format;
+
+    #[test]
+    fn intern_test() {
+        let a = SymbolId::new("interned-a");
+        assert_eq!(a, SymbolId::from("interned-a"));
+        assert_ne!(a, SymbolId::new("interned-b"));
+        assert_eq!(a.as_str(), "interned-a");
+        assert_eq!(SymbolId::new("quote"), QUOTE);
+        assert_eq!(DEFMACRO.as_str(), "defmacro");
+        assert_eq!(format!("{a} {a:?}"), "interned-a \"interned-a\"");
+    }
+}
@@ -0,0 +1,93 @@
+// What values, environments and interpreters share and mutate, so the
+// sync feature can make them Send + Sync. Without it sharing is an Rc and
+// mutation a RefCell; with it they are an Arc and a RwLock, and what a
+// host hands the interpreter, from native functions to I/O backends, has
+// to be Send + Sync as well.
+//
+// Lock keeps RefCell's borrow and borrow_mut, so code is written once for
+// both. A RefCell panics where a RwLock would wait, so nothing may hold a
+// borrow across code that could take another one of the same lock.
+//
+// The cycle collector still keeps track of frames and atoms per thread,
+// so the cycles among those an interpreter made before it moved are only
+// collected by the thread that made them. While any thread collects, the
+// others wait to read or change a frame or atom until it is done.
+
+#[cfg(not(feature = "sync"))]
+pub use alloc::rc::{Rc as Shared, Weak};
+#[cfg(feature = "sync")]
+pub use alloc::sync::{Arc as Shared, Weak};
+
+#[cfg(not(feature = "sync"))]
+pub use core::cell::{Ref, RefCell as Lock, RefMut};
+
+#[cfg(feature = "sync")]
+pub use std::sync::{RwLockReadGuard as Ref, RwLockWriteGuard as RefMut};
+
+// Send + Sync with the sync feature and nothing without it, as a bound on
+// what the interpreter keeps from a host. MaybeSend is the same for Send,
+// which is all a host's futures need.
+#[cfg(feature = "sync")]
+pub trait MaybeSync: Send + Sync {}
+#[cfg(feature = "sync")]
+impl<T: Send + Sync + ?Sized> MaybeSync for T {}
+#[cfg(feature = "sync")]
+pub trait MaybeSend: Send {}
+#[cfg(feature = "sync")]
+impl<T: Send + ?Sized> MaybeSend for T {}
+
+#[cfg(not(feature = "sync"))]
+pub trait MaybeSync {}
+#[cfg(not(feature = "sync"))]
+impl<T: ?Sized> MaybeSync for T {}
+#[cfg(not(feature = "sync"))]
+pub trait MaybeSend {}
+#[cfg(not(feature = "sync"))]
+impl<T: ?Sized> MaybeSend for T {}
+
+// A panic while a lock was held leaves the value as it was, which is what
+// a RefCell would do, so a poisoned lock is used all the same.
+#[cfg(feature = "sync")]
+#[derive(Debug, Default)]
+pub struct Lock<T: ?Sized>(std::sync::RwLock<T>);
+
+#[cfg(feature = "sync")]
+impl<T> Lock<T> {
+    pub const fn new(value: T) -> Self {
+        Lock(std::sync::RwLock::new(value))
+    }
+
+    pub fn replace(&self, value: T) -> T {
+        core::mem::replace(&mut *self.borrow_mut(), value)
+    }
+
+    pub fn take(&self) -> T
+    where
+        T: Default,
+    {
+        self.replace(T::default())
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<T: ?Sized> Lock<T> {
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.0
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        self.0
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    pub fn try_borrow(&self) -> std::sync::TryLockResult<Ref<'_, T>> {
+        self.0.try_read()
+    }
+
+    pub fn try_borrow_mut(&self) -> std::sync::TryLockResult<RefMut<'_, T>> {
+        self.0.try_write()
+    }
+}
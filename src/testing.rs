@@ -0,0 +1,196 @@
+// Tests written in the language itself, which `tuple-gd test` runs:
+//
+//   (deftest adds-up
+//     (assert-equal 3 (+ 1 2))
+//     (assert-raises error-object? (lambda () (car '()))))
+//
+// deftest only registers its body as a test, to be run once the whole file
+// has loaded. assert-equal raises an error naming the first place the two
+// values differ when they aren't equal?, and assert-raises one unless
+// calling the thunk raises something the predicate accepts; a test that
+// raises anything fails with that as its reason. Limits, cancelling and
+// exit aren't caught, any more than try catches them.
+use crate::{
+    eval::{apply, is_truthy, EvalError},
+    interpreter::Interpreter,
+    symbol::SymbolId,
+    sync::{Lock, Shared},
+    value::Value,
+};
+
+const DEFTEST: &str = "(defmacro deftest (name . body)
+  (list 'register-test (list 'quote name) (cons 'lambda (cons '() body))))";
+
+// What a test file looks like, when a directory is searched for them.
+pub const SUFFIXES: &[&str] = &["_test.lisp", "_test.scm", "_test.tasks"];
+
+// The tests of the files an interpreter has run since install, in the
+// order they were defined.
+#[derive(Clone, Default)]
+pub struct Tests(Shared<Lock<Vec<(SymbolId, Value)>>>);
+
+// How one test went: None if it passed, or why it failed.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Outcome {
+    pub name: SymbolId,
+    pub failure: Option<String>,
+}
+
+// Defines deftest, register-test, assert-equal and assert-raises in
+// interpreter.
+pub fn install(interpreter: &mut Interpreter) -> Tests {
+    let tests = Tests::default();
+    let registered = tests.clone();
+    interpreter.register_fn("register-test", move |args| match args {
+        [Value::Symbol(name), thunk] => {
+            registered.0.borrow_mut().push((*name, thunk.clone()));
+            Ok(Value::Nil)
+        }
+        [other, _] => Err(EvalError::TypeMismatch {
+            expected: "symbol",
+            found: other.clone(),
+        }),
+        _ => Err(EvalError::ArityMismatch {
+            expected: 2,
+            found: args.len(),
+        }),
+    });
+    interpreter.register_fn("assert-equal", assert_equal);
+    interpreter.register_fn("assert-raises", assert_raises);
+    interpreter
+        .eval_str(DEFTEST)
+        .expect("deftest is a well-formed macro");
+    tests
+}
+
+impl Tests {
+    // Runs each test registered so far and forgets it.
+    pub fn run(&self, interpreter: &mut Interpreter) -> Vec<Outcome> {
+        let tests = core::mem::take(&mut *self.0.borrow_mut());
+        tests
+            .into_iter()
+            .map(|(name, thunk)| Outcome {
+                name,
+                failure: interpreter.call(&thunk, &[]).err().map(|e| e.to_string()),
+            })
+            .collect()
+    }
+}
+
+fn assert_equal(args: &[Value]) -> Result<Value, EvalError> {
+    let [expected, actual] = args else {
+        return Err(EvalError::ArityMismatch {
+            expected: 2,
+            found: args.len(),
+        });
+    };
+    if expected.is_equal(actual) {
+        return Ok(Value::Nil);
+    }
+    let mut message = format!("expected {expected}, got {actual}");
+    if let Some((path, expected, actual)) = difference(expected, actual) {
+        message.push_str(&format!(": {path}: expected {expected}, got {actual}"));
+    }
+    Err(EvalError::Raised(Value::error(message, vec![])))
+}
+
+fn assert_raises(args: &[Value]) -> Result<Value, EvalError> {
+    let [predicate, thunk] = args else {
+        return Err(EvalError::ArityMismatch {
+            expected: 2,
+            found: args.len(),
+        });
+    };
+    let raised = match apply(thunk, vec![]) {
+        Ok(value) => {
+            let message = format!("expected an error, got {value}");
+            return Err(EvalError::Raised(Value::error(message, vec![])));
+        }
+        Err(error @ (EvalError::LimitExceeded(_) | EvalError::Cancelled | EvalError::Exit(_))) => {
+            return Err(error)
+        }
+        Err(error) => error.into_value(),
+    };
+    if is_truthy(&apply(predicate, vec![raised.clone()])?) {
+        return Ok(Value::Nil);
+    }
+    let message = format!("{predicate} doesn't accept what was raised: {raised}");
+    Err(EvalError::Raised(Value::error(message, vec![])))
+}
+
+// Where inside two unequal lists or vectors they first differ, as the
+// items that lead there, innermost first, with the values found there.
+// None if they differ at the top, or only in length.
+fn difference(expected: &Value, actual: &Value) -> Option<(String, Value, Value)> {
+    let (expected, actual) = match (expected, actual) {
+        (Value::List(expected), Value::List(actual))
+        | (Value::Vector(expected), Value::Vector(actual)) => (expected, actual),
+        _ => return None,
+    };
+    if expected.len() != actual.len() {
+        return None;
+    }
+    let (i, (expected, actual)) = expected
+        .iter()
+        .zip(actual)
+        .enumerate()
+        .find(|(_, (expected, actual))| !expected.is_equal(actual))?;
+    Some(match difference(expected, actual) {
+        Some((path, expected, actual)) => (format!("{path} of item {i}"), expected, actual),
+        None => (format!("item {i}"), expected.clone(), actual.clone()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn outcomes(source: &str) -> Vec<(String, Option<String>)> {
+        let mut interpreter = Interpreter::new();
+        let tests = install(&mut interpreter);
+        interpreter.eval_str(source).unwrap();
+        tests
+            .run(&mut interpreter)
+            .into_iter()
+            .map(|outcome| (outcome.name.to_string(), outcome.failure))
+            .collect()
+    }
+
+    #[test]
+    fn run_test() {
+        let source = "
+            (define (f x) (list 1 (list x 3)))
+            (deftest passes (assert-equal '(1 (2 3)) (f 2)))
+            (deftest differs (assert-equal '(1 (2 3)) (f 4)))
+            (deftest raises
+              (assert-raises error-object? (lambda () (car '())))
+              (assert-raises symbol? (lambda () (raise 'oops))))
+            (deftest no-error (assert-raises error-object? (lambda () 1)))
+            (deftest wrong-error (assert-raises symbol? (lambda () (error \"e\"))))
+            (deftest fails (car 1))";
+        let failure = |message: &str| Some(message.to_string());
+        assert_eq!(
+            outcomes(source),
+            [
+                ("passes".to_string(), None),
+                (
+                    "differs".to_string(),
+                    failure(
+                        "expected (1 (2 3)), got (1 (4 3)): item 0 of item 1: expected 2, got 4"
+                    )
+                ),
+                ("raises".to_string(), None),
+                ("no-error".to_string(), failure("expected an error, got 1")),
+                (
+                    "wrong-error".to_string(),
+                    failure("#<procedure symbol?> doesn't accept what was raised: #<error \"e\">")
+                ),
+                ("fails".to_string(), failure("expected list, found 1")),
+            ]
+        );
+        assert_eq!(
+            outcomes("(deftest exits (assert-raises symbol? (lambda () (exit 3))))"),
+            [("exits".to_string(), failure("exit with status 3"))]
+        );
+    }
+}
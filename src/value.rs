@@ -0,0 +1,631 @@
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+use core::{
+    fmt,
+    future::Future,
+    hash::{Hash, Hasher},
+    pin::Pin,
+};
+
+#[cfg(feature = "bigint")]
+use crate::bigint::BigInt;
+use crate::{
+    eval::{is_empty_list, Env, EvalError},
+    gc,
+    hooks::Spans,
+    io::Port,
+    parser::{Expr, Lambda},
+    rational::Rational,
+    symbol::SymbolId,
+    sync::{Lock, MaybeSend, MaybeSync, Shared, Weak},
+    vm,
+};
+
+// What the evaluator produces. Unlike Expr, which is only ever the syntax
+// that was read, a Value can hold things that have no written form:
+// procedures with a captured environment and functions implemented in Rust.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Integer(i64),
+    #[cfg(feature = "bigint")]
+    BigInteger(Box<BigInt>),
+    Rational(Rational),
+    Float(f64),
+    Char(char),
+    String(String),
+    Symbol(SymbolId),
+    Keyword(SymbolId),
+    List(Vec<Value>),
+    Pair(Box<Value>, Box<Value>),
+    Vector(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Closure(Shared<Closure>),
+    // A closure made by the bytecode backend.
+    Compiled(Shared<vm::Closure>),
+    Native(NativeFn),
+    AsyncNative(AsyncFn),
+    Macro(Shared<Closure>),
+    Atom(Atom),
+    Error(Shared<ErrorObject>),
+    Port(Port),
+    Generator(vm::Generator),
+    // What (values ...) returns for other than one value, for
+    // call-with-values or let-values to take apart again.
+    Values(Shared<[Value]>),
+}
+
+const _: () = assert!(core::mem::size_of::<Value>() <= 32);
+
+// A lambda together with the environment it was created in. Closures are
+// compared by identity, and Debug leaves out the environment since a
+// recursive function's environment contains the function itself.
+pub struct Closure {
+    pub lambda: Lambda,
+    pub env: Env,
+    // Where the body was read, for a hook.
+    pub(crate) spans: Option<Shared<Spans>>,
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        core::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Closure")
+            .field("params", &self.lambda.params)
+            .finish_non_exhaustive()
+    }
+}
+
+// A mutable cell, made by box or atom. Bindings themselves are only
+// changed by set!, so an atom is how a value is shared and updated between
+// the places that hold it. Atoms are compared by identity, like closures.
+#[derive(Clone)]
+pub struct Atom(Shared<Lock<Value>>);
+
+pub(crate) struct WeakAtom(Weak<Lock<Value>>);
+
+impl Atom {
+    pub fn new(value: Value) -> Self {
+        let atom = Atom(Shared::new(Lock::new(value)));
+        gc::track_atom(&atom);
+        atom
+    }
+
+    pub fn get(&self) -> Value {
+        gc::using(|| self.0.borrow().clone())
+    }
+
+    // Returns the value it replaced.
+    pub fn set(&self, value: Value) -> Value {
+        gc::using(|| self.0.replace(value))
+    }
+
+    // Sets the atom to new only if it still holds a value equal to current,
+    // checking and setting under one lock. Returns whether it did.
+    pub fn compare_and_set(&self, current: &Value, new: Value) -> bool {
+        gc::using(|| {
+            let mut value = self.0.borrow_mut();
+            if *value != *current {
+                return false;
+            }
+            *value = new;
+            true
+        })
+    }
+
+    // Empties an atom that is garbage, unless it is borrowed.
+    pub(crate) fn clear(&self) {
+        let value = self
+            .0
+            .try_borrow_mut()
+            .map(|mut value| core::mem::replace(&mut *value, Value::Nil));
+        drop(value);
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakAtom {
+        WeakAtom(Shared::downgrade(&self.0))
+    }
+
+    pub(crate) fn addr(&self) -> usize {
+        Shared::as_ptr(&self.0) as usize
+    }
+
+    pub(crate) fn strong_count(&self) -> usize {
+        Shared::strong_count(&self.0)
+    }
+
+    // None if the atom is borrowed.
+    pub(crate) fn visit(&self, f: impl FnOnce(&Value)) -> Option<()> {
+        f(&*self.0.try_borrow().ok()?);
+        Some(())
+    }
+}
+
+impl WeakAtom {
+    pub(crate) fn upgrade(&self) -> Option<Atom> {
+        self.0.upgrade().map(Atom)
+    }
+
+    pub(crate) fn is_live(&self) -> bool {
+        self.0.strong_count() > 0
+    }
+}
+
+impl PartialEq for Atom {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Atom").field(&self.0.borrow()).finish()
+    }
+}
+
+// What error raises: a message and the values it concerns, in the order
+// they were given.
+#[derive(PartialEq, Debug)]
+pub struct ErrorObject {
+    pub message: String,
+    pub irritants: Vec<Value>,
+}
+
+pub type Builtin = fn(&[Value]) -> Result<Value, EvalError>;
+#[cfg(not(feature = "sync"))]
+pub type Callback = dyn Fn(&[Value]) -> Result<Value, EvalError>;
+#[cfg(feature = "sync")]
+pub type Callback = dyn Fn(&[Value]) -> Result<Value, EvalError> + Send + Sync;
+
+// A function implemented in Rust: one of the builtins or a callback the
+// host registered. Natives are compared by name since comparing the
+// functions themselves isn't reliable.
+#[derive(Clone)]
+pub struct NativeFn {
+    pub name: SymbolId,
+    pub func: Shared<Callback>,
+}
+
+impl NativeFn {
+    pub fn new(
+        name: impl Into<SymbolId>,
+        func: impl Fn(&[Value]) -> Result<Value, EvalError> + MaybeSync + 'static,
+    ) -> Self {
+        NativeFn {
+            name: name.into(),
+            func: Shared::new(func),
+        }
+    }
+}
+
+impl PartialEq for NativeFn {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl fmt::Debug for NativeFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NativeFn({})", self.name)
+    }
+}
+
+#[cfg(not(feature = "sync"))]
+pub type HostFuture = Pin<Box<dyn Future<Output = Result<Value, EvalError>>>>;
+#[cfg(not(feature = "sync"))]
+pub type AsyncCallback = dyn Fn(Vec<Value>) -> HostFuture;
+// A machine stopped at a host's future is kept with it, and a machine
+// can end up in a generator, which is a Value.
+#[cfg(feature = "sync")]
+pub type HostFuture = Pin<Box<dyn Future<Output = Result<Value, EvalError>> + Send>>;
+#[cfg(feature = "sync")]
+pub type AsyncCallback = dyn Fn(Vec<Value>) -> HostFuture + Send + Sync;
+
+// A function implemented in Rust whose result is what the future it
+// returns resolves to. Only code run by Interpreter::eval_async can wait
+// for it; called from anywhere else it fails with EvalError::CannotAwait.
+#[derive(Clone)]
+pub struct AsyncFn {
+    pub name: SymbolId,
+    pub func: Shared<AsyncCallback>,
+}
+
+impl AsyncFn {
+    pub fn new<F>(
+        name: impl Into<SymbolId>,
+        func: impl Fn(Vec<Value>) -> F + MaybeSync + 'static,
+    ) -> Self
+    where
+        F: Future<Output = Result<Value, EvalError>> + MaybeSend + 'static,
+    {
+        AsyncFn {
+            name: name.into(),
+            func: Shared::new(move |args| Box::pin(func(args))),
+        }
+    }
+}
+
+impl PartialEq for AsyncFn {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl fmt::Debug for AsyncFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AsyncFn({})", self.name)
+    }
+}
+
+impl Value {
+    pub fn closure(lambda: Lambda, env: &Env) -> Self {
+        Value::Closure(Shared::new(Closure {
+            lambda,
+            env: env.clone(),
+            spans: None,
+        }))
+    }
+
+    // Like Expr::cons, a Pair is only ever an improper list.
+    pub fn cons(car: Value, cdr: Value) -> Self {
+        match cdr {
+            Value::Nil => Value::List(vec![car]),
+            Value::List(mut items) => {
+                items.insert(0, car);
+                Value::List(items)
+            }
+            cdr => Value::Pair(Box::new(car), Box::new(cdr)),
+        }
+    }
+
+    pub fn error(message: impl Into<String>, irritants: Vec<Value>) -> Self {
+        Value::Error(Shared::new(ErrorObject {
+            message: message.into(),
+            irritants,
+        }))
+    }
+
+    // The values a (values ...) returned, or this one value on its own.
+    pub fn into_values(self) -> Vec<Value> {
+        match self {
+            Value::Values(values) => values.to_vec(),
+            value => vec![value],
+        }
+    }
+
+    pub fn is_procedure(&self) -> bool {
+        matches!(
+            self,
+            Value::Closure(_) | Value::Compiled(_) | Value::Native(_) | Value::AsyncNative(_)
+        )
+    }
+
+    // eq?: whether two values are the same object. Procedures, macros,
+    // atoms, ports, generators and error objects are the same only when
+    // they are one object; nil, booleans, symbols, keywords, characters
+    // and fixnums are the same whenever they are equal. Strings, lists,
+    // pairs, vectors and maps are held by value rather than shared, so
+    // they have no identity to compare and only empty ones are the same.
+    pub fn is_eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Rational(_) | Value::Float(_), _) => false,
+            #[cfg(feature = "bigint")]
+            (Value::BigInteger(_), _) => false,
+            _ => self.is_eqv(other),
+        }
+    }
+
+    // eqv?: eq?, and also numbers that are both exact or both inexact and
+    // have the same value. Floats are compared by their bits, so a NaN is
+    // eqv? to itself and 0.0 isn't eqv? to -0.0.
+    pub fn is_eqv(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Float(a), Value::Float(b)) => a.to_bits() == b.to_bits(),
+            (Value::Nil | Value::List(_), Value::Nil | Value::List(_)) => {
+                is_empty_list(self) && is_empty_list(other)
+            }
+            (Value::String(a), Value::String(b)) => a.is_empty() && b.is_empty(),
+            (Value::Vector(a), Value::Vector(b)) => a.is_empty() && b.is_empty(),
+            (Value::Map(a), Value::Map(b)) => a.is_empty() && b.is_empty(),
+            (Value::Pair(..), _) => false,
+            (Value::Error(a), Value::Error(b)) => Shared::ptr_eq(a, b),
+            _ => self == other,
+        }
+    }
+
+    // equal?: eqv?, and also strings, lists, pairs, vectors and error
+    // objects whose contents are equal?, and maps that bind the same keys
+    // to equal? values in whatever order.
+    pub fn is_equal(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::List(a), Value::List(b)) | (Value::Vector(a), Value::Vector(b)) => {
+                all_equal(a, b)
+            }
+            (Value::Pair(a, b), Value::Pair(c, d)) => a.is_equal(c) && b.is_equal(d),
+            (Value::Map(a), Value::Map(b)) => {
+                a.len() == b.len()
+                    && a.iter().all(|(key, value)| {
+                        b.iter().any(|(k, v)| k.is_equal(key) && v.is_equal(value))
+                    })
+            }
+            (Value::Error(a), Value::Error(b)) => {
+                a.message == b.message && all_equal(&a.irritants, &b.irritants)
+            }
+            _ => self.is_eqv(other),
+        }
+    }
+}
+
+fn all_equal(a: &[Value], b: &[Value]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.is_equal(b))
+}
+
+// == is structural, like equal?, except that nil and () differ, as do maps
+// written in different orders. Floats are compared by their bits with
+// -0.0 taken as 0.0, so a NaN is == to itself and Eq holds. Hash agrees
+// with it, so values can be keys of a HashMap: things compared by
+// identity hash their address, natives their name, and floats those bits.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            #[cfg(feature = "bigint")]
+            (Value::BigInteger(a), Value::BigInteger(b)) => a == b,
+            (Value::Rational(a), Value::Rational(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => float_bits(*a) == float_bits(*b),
+            (Value::Char(a), Value::Char(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Symbol(a), Value::Symbol(b)) | (Value::Keyword(a), Value::Keyword(b)) => a == b,
+            (Value::List(a), Value::List(b)) | (Value::Vector(a), Value::Vector(b)) => a == b,
+            (Value::Pair(a, b), Value::Pair(c, d)) => a == c && b == d,
+            (Value::Map(a), Value::Map(b)) => a == b,
+            (Value::Closure(a), Value::Closure(b)) | (Value::Macro(a), Value::Macro(b)) => a == b,
+            (Value::Compiled(a), Value::Compiled(b)) => a == b,
+            (Value::Native(a), Value::Native(b)) => a == b,
+            (Value::AsyncNative(a), Value::AsyncNative(b)) => a == b,
+            (Value::Atom(a), Value::Atom(b)) => a == b,
+            (Value::Error(a), Value::Error(b)) => a == b,
+            (Value::Port(a), Value::Port(b)) => a == b,
+            (Value::Generator(a), Value::Generator(b)) => a == b,
+            (Value::Values(a), Value::Values(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value {}
+
+fn float_bits(f: f64) -> u64 {
+    if f == 0.0 {
+        0.0f64.to_bits()
+    } else {
+        f.to_bits()
+    }
+}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        core::mem::discriminant(self).hash(state);
+        match self {
+            Value::Nil => {}
+            Value::Bool(b) => b.hash(state),
+            Value::Integer(i) => i.hash(state),
+            #[cfg(feature = "bigint")]
+            Value::BigInteger(i) => i.hash(state),
+            Value::Rational(r) => r.hash(state),
+            Value::Float(f) => float_bits(*f).hash(state),
+            Value::Char(c) => c.hash(state),
+            Value::String(s) => s.hash(state),
+            Value::Symbol(name) | Value::Keyword(name) => name.hash(state),
+            Value::List(items) | Value::Vector(items) => items.hash(state),
+            Value::Pair(car, cdr) => {
+                car.hash(state);
+                cdr.hash(state);
+            }
+            Value::Map(entries) => entries.hash(state),
+            Value::Closure(closure) | Value::Macro(closure) => Shared::as_ptr(closure).hash(state),
+            Value::Compiled(closure) => Shared::as_ptr(closure).hash(state),
+            Value::Native(native) => native.name.hash(state),
+            Value::AsyncNative(native) => native.name.hash(state),
+            Value::Atom(atom) => Shared::as_ptr(&atom.0).hash(state),
+            Value::Error(error) => {
+                error.message.hash(state);
+                error.irritants.hash(state);
+            }
+            Value::Port(port) => port.hash(state),
+            Value::Generator(generator) => generator.hash(state),
+            Value::Values(values) => values.hash(state),
+        }
+    }
+}
+
+// Quoted syntax becomes data. An unevaluated lambda has no environment to
+// close over, so it turns back into the (lambda (params...) body...) list
+// it was read from.
+impl From<Expr> for Value {
+    fn from(expr: Expr) -> Self {
+        match expr {
+            Expr::Nil => Value::Nil,
+            Expr::Bool(b) => Value::Bool(b),
+            Expr::Integer(i) => Value::Integer(i),
+            #[cfg(feature = "bigint")]
+            Expr::BigInteger(i) => Value::BigInteger(i),
+            Expr::Rational(r) => Value::Rational(r),
+            Expr::Float(f) => Value::Float(f),
+            Expr::Char(c) => Value::Char(c),
+            Expr::String(s) => Value::String(s),
+            Expr::Symbol(s) => Value::Symbol(s),
+            Expr::Keyword(k) => Value::Keyword(k),
+            Expr::Lambda(lambda) => Value::from(lambda.to_expr()),
+            Expr::List(items) => Value::List(items.into_iter().map(Value::from).collect()),
+            Expr::Pair(car, cdr) => {
+                Value::Pair(Box::new(Value::from(*car)), Box::new(Value::from(*cdr)))
+            }
+            Expr::Vector(items) => Value::Vector(items.into_iter().map(Value::from).collect()),
+            Expr::Map(entries) => Value::Map(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (Value::from(k), Value::from(v)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<&Expr> for Value {
+    fn from(expr: &Expr) -> Self {
+        Value::from(expr.clone())
+    }
+}
+
+// Data turned back into code, as a macro's result is. Procedures and
+// macros have no written form, so they can't appear in code.
+impl TryFrom<Value> for Expr {
+    type Error = EvalError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Nil => Ok(Expr::Nil),
+            Value::Bool(b) => Ok(Expr::Bool(b)),
+            Value::Integer(i) => Ok(Expr::Integer(i)),
+            #[cfg(feature = "bigint")]
+            Value::BigInteger(i) => Ok(Expr::BigInteger(i)),
+            Value::Rational(r) => Ok(Expr::Rational(r)),
+            Value::Float(f) => Ok(Expr::Float(f)),
+            Value::Char(c) => Ok(Expr::Char(c)),
+            Value::String(s) => Ok(Expr::String(s)),
+            Value::Symbol(s) => Ok(Expr::Symbol(s)),
+            Value::Keyword(k) => Ok(Expr::Keyword(k)),
+            Value::List(items) => items
+                .into_iter()
+                .map(Expr::try_from)
+                .collect::<Result<_, _>>()
+                .map(Expr::List),
+            Value::Pair(car, cdr) => Ok(Expr::Pair(
+                Box::new(Expr::try_from(*car)?),
+                Box::new(Expr::try_from(*cdr)?),
+            )),
+            Value::Vector(items) => items
+                .into_iter()
+                .map(Expr::try_from)
+                .collect::<Result<_, _>>()
+                .map(Expr::Vector),
+            Value::Map(entries) => entries
+                .into_iter()
+                .map(|(k, v)| Ok((Expr::try_from(k)?, Expr::try_from(v)?)))
+                .collect::<Result<_, _>>()
+                .map(Expr::Map),
+            other => Err(EvalError::TypeMismatch {
+                expected: "syntax",
+                found: other,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::read;
+    use alloc::borrow::ToOwned;
+
+    #[test]
+    fn from_expr_test() {
+        assert_eq!(
+            Value::from(read("(a 1 \"b\" #t)").unwrap()),
+            Value::List(vec![
+                Value::Symbol("a".into()),
+                Value::Integer(1),
+                Value::String("b".to_owned()),
+                Value::Bool(true),
+            ])
+        );
+        let lambda = Expr::Lambda(Box::new(Lambda {
+            params: vec!["x".into()],
+            rest: None,
+            keys: vec![],
+            body: vec![Expr::Symbol("x".into())],
+        }));
+        assert_eq!(
+            Value::from(lambda),
+            Value::from(read("(lambda (x) x)").unwrap())
+        );
+    }
+
+    #[test]
+    fn try_into_expr_test() {
+        let expr = read("(if (a \"b\") 1.5 #f [x {:k y}])").unwrap();
+        assert_eq!(Expr::try_from(Value::from(expr.clone())), Ok(expr));
+        let closure = Value::closure(
+            Lambda {
+                params: vec![],
+                rest: None,
+                keys: vec![],
+                body: vec![],
+            },
+            &Env::new(),
+        );
+        assert!(Expr::try_from(Value::List(vec![closure])).is_err());
+    }
+
+    #[test]
+    fn closure_identity_test() {
+        let env = Env::new();
+        let lambda = Lambda {
+            params: vec![],
+            rest: None,
+            keys: vec![],
+            body: vec![],
+        };
+        let a = Value::closure(lambda.clone(), &env);
+        let b = Value::closure(lambda, &env);
+        assert_eq!(a, a.clone());
+        assert_ne!(a, b);
+        assert!(a.is_procedure());
+        assert!(!Value::Nil.is_procedure());
+    }
+
+    #[test]
+    fn hash_test() {
+        use std::collections::{hash_map::DefaultHasher, HashMap};
+
+        let hash = |value: &Value| {
+            let mut hasher = DefaultHasher::new();
+            value.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&Value::Float(0.0)), hash(&Value::Float(-0.0)));
+        assert_eq!(Value::Float(0.0), Value::Float(-0.0));
+        let nan = Value::Float(f64::NAN);
+        assert_eq!(nan, nan.clone());
+        assert_eq!(hash(&nan), hash(&nan.clone()));
+        let list = Value::from(read("(a [1 \"b\"] {:k 1/2})").unwrap());
+        assert_eq!(hash(&list), hash(&list.clone()));
+
+        let atom = Value::Atom(Atom::new(Value::Nil));
+        // What can change inside a value is only ever hashed by address.
+        #[allow(clippy::mutable_key_type)]
+        let mut map = HashMap::new();
+        map.insert(list.clone(), Value::Integer(1));
+        map.insert(atom.clone(), Value::Integer(2));
+        map.insert(Value::Symbol("a".into()), Value::Integer(3));
+        assert_eq!(map.get(&list), Some(&Value::Integer(1)));
+        assert_eq!(map.get(&atom), Some(&Value::Integer(2)));
+        assert_eq!(map.get(&Value::Atom(Atom::new(Value::Nil))), None);
+        assert_eq!(
+            map.get(&Value::Symbol("a".into())),
+            Some(&Value::Integer(3))
+        );
+        assert_eq!(map.get(&Value::Keyword("a".into())), None);
+        map.insert(nan.clone(), Value::Integer(4));
+        assert_eq!(map.get(&nan), Some(&Value::Integer(4)));
+    }
+}
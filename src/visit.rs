@@ -0,0 +1,357 @@
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::convert::Infallible;
+
+use crate::{
+    parser::{Expr, Lambda},
+    symbol::{self, SymbolId},
+};
+
+// Walks an Expr by reference. Every method has a default that recurses
+// into the node's children, so an implementation overrides only the nodes
+// it cares about and calls the matching free function to keep descending.
+// Quoted data is walked like code: a visitor that should skip it checks
+// for quote in visit_list.
+pub trait Visitor {
+    fn visit_expr(&mut self, expr: &Expr) {
+        visit_expr(self, expr)
+    }
+
+    fn visit_symbol(&mut self, _symbol: SymbolId) {}
+
+    fn visit_keyword(&mut self, _keyword: SymbolId) {}
+
+    // Nil, booleans, numbers, characters and strings.
+    fn visit_literal(&mut self, _literal: &Expr) {}
+
+    fn visit_list(&mut self, items: &[Expr]) {
+        visit_list(self, items)
+    }
+
+    fn visit_pair(&mut self, car: &Expr, cdr: &Expr) {
+        visit_pair(self, car, cdr)
+    }
+
+    fn visit_vector(&mut self, items: &[Expr]) {
+        visit_vector(self, items)
+    }
+
+    fn visit_map(&mut self, entries: &[(Expr, Expr)]) {
+        visit_map(self, entries)
+    }
+
+    fn visit_lambda(&mut self, lambda: &Lambda) {
+        visit_lambda(self, lambda)
+    }
+}
+
+pub fn visit_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Symbol(symbol) => visitor.visit_symbol(*symbol),
+        Expr::Keyword(keyword) => visitor.visit_keyword(*keyword),
+        Expr::List(items) => visitor.visit_list(items),
+        Expr::Pair(car, cdr) => visitor.visit_pair(car, cdr),
+        Expr::Vector(items) => visitor.visit_vector(items),
+        Expr::Map(entries) => visitor.visit_map(entries),
+        Expr::Lambda(lambda) => visitor.visit_lambda(lambda),
+        literal => visitor.visit_literal(literal),
+    }
+}
+
+pub fn visit_list<V: Visitor + ?Sized>(visitor: &mut V, items: &[Expr]) {
+    for item in items {
+        visitor.visit_expr(item);
+    }
+}
+
+pub fn visit_pair<V: Visitor + ?Sized>(visitor: &mut V, car: &Expr, cdr: &Expr) {
+    visitor.visit_expr(car);
+    visitor.visit_expr(cdr);
+}
+
+pub fn visit_vector<V: Visitor + ?Sized>(visitor: &mut V, items: &[Expr]) {
+    for item in items {
+        visitor.visit_expr(item);
+    }
+}
+
+pub fn visit_map<V: Visitor + ?Sized>(visitor: &mut V, entries: &[(Expr, Expr)]) {
+    for (key, value) in entries {
+        visitor.visit_expr(key);
+        visitor.visit_expr(value);
+    }
+}
+
+// The parameters are names being bound, not references, so only the key
+// defaults and the body are visited.
+pub fn visit_lambda<V: Visitor + ?Sized>(visitor: &mut V, lambda: &Lambda) {
+    for default in lambda.keys.iter().filter_map(|(_, default)| default.as_ref()) {
+        visitor.visit_expr(default);
+    }
+    for expr in &lambda.body {
+        visitor.visit_expr(expr);
+    }
+}
+
+// Rebuilds an Expr bottom-up, taking it by value so unchanged nodes are
+// moved rather than cloned. Defaults work as in Visitor: each returns the
+// node with its children folded and itself otherwise unchanged.
+pub trait Folder {
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        fold_expr(self, expr)
+    }
+
+    fn fold_symbol(&mut self, symbol: SymbolId) -> Expr {
+        Expr::Symbol(symbol)
+    }
+
+    fn fold_keyword(&mut self, keyword: SymbolId) -> Expr {
+        Expr::Keyword(keyword)
+    }
+
+    fn fold_literal(&mut self, literal: Expr) -> Expr {
+        literal
+    }
+
+    fn fold_list(&mut self, items: Vec<Expr>) -> Expr {
+        fold_list(self, items)
+    }
+
+    fn fold_pair(&mut self, car: Expr, cdr: Expr) -> Expr {
+        fold_pair(self, car, cdr)
+    }
+
+    fn fold_vector(&mut self, items: Vec<Expr>) -> Expr {
+        fold_vector(self, items)
+    }
+
+    fn fold_map(&mut self, entries: Vec<(Expr, Expr)>) -> Expr {
+        fold_map(self, entries)
+    }
+
+    fn fold_lambda(&mut self, lambda: Lambda) -> Expr {
+        fold_lambda(self, lambda)
+    }
+
+    // The items of a list with only the code in them folded, for a folder
+    // that rewrites code and should leave data alone.
+    fn fold_code(&mut self, items: Vec<Expr>) -> Vec<Expr> {
+        let Ok(items) = try_fold_code(items, |item| Ok::<_, Infallible>(self.fold_expr(item)));
+        items
+    }
+}
+
+pub fn fold_expr<F: Folder + ?Sized>(folder: &mut F, expr: Expr) -> Expr {
+    match expr {
+        Expr::Symbol(symbol) => folder.fold_symbol(symbol),
+        Expr::Keyword(keyword) => folder.fold_keyword(keyword),
+        Expr::List(items) => folder.fold_list(items),
+        Expr::Pair(car, cdr) => folder.fold_pair(*car, *cdr),
+        Expr::Vector(items) => folder.fold_vector(items),
+        Expr::Map(entries) => folder.fold_map(entries),
+        Expr::Lambda(lambda) => folder.fold_lambda(*lambda),
+        literal => folder.fold_literal(literal),
+    }
+}
+
+pub fn fold_list<F: Folder + ?Sized>(folder: &mut F, items: Vec<Expr>) -> Expr {
+    Expr::List(items.into_iter().map(|item| folder.fold_expr(item)).collect())
+}
+
+// Rebuilt with cons, since folding the cdr into a list or nil turns the
+// pair into a proper list.
+pub fn fold_pair<F: Folder + ?Sized>(folder: &mut F, car: Expr, cdr: Expr) -> Expr {
+    let car = folder.fold_expr(car);
+    Expr::cons(car, folder.fold_expr(cdr))
+}
+
+pub fn fold_vector<F: Folder + ?Sized>(folder: &mut F, items: Vec<Expr>) -> Expr {
+    Expr::Vector(items.into_iter().map(|item| folder.fold_expr(item)).collect())
+}
+
+pub fn fold_map<F: Folder + ?Sized>(folder: &mut F, entries: Vec<(Expr, Expr)>) -> Expr {
+    Expr::Map(
+        entries
+            .into_iter()
+            .map(|(key, value)| (folder.fold_expr(key), folder.fold_expr(value)))
+            .collect(),
+    )
+}
+
+pub fn fold_lambda<F: Folder + ?Sized>(folder: &mut F, lambda: Lambda) -> Expr {
+    Expr::Lambda(Box::new(Lambda {
+        keys: lambda
+            .keys
+            .into_iter()
+            .map(|(name, default)| (name, default.map(|d| folder.fold_expr(d))))
+            .collect(),
+        body: lambda
+            .body
+            .into_iter()
+            .map(|expr| folder.fold_expr(expr))
+            .collect(),
+        ..lambda
+    }))
+}
+
+// Which subforms of a list are code, as the special forms have it. Quoted
+// data, the names lambda, define, defmacro and let bind, and match
+// patterns are data; so is the whole of a quasiquote. Everything else,
+// the head of an ordinary call included, is code. Rebuilds the list with
+// f applied to each piece of code and the data moved over as it was, for
+// passes like macro expansion and optimization that mustn't touch data.
+pub fn try_fold_code<E>(
+    items: Vec<Expr>,
+    mut f: impl FnMut(Expr) -> Result<Expr, E>,
+) -> Result<Vec<Expr>, E> {
+    let head = match items.first() {
+        Some(Expr::Symbol(name)) => *name,
+        _ => return fold_after(items, 0, &mut f),
+    };
+    let kept = match (head, items.as_slice()) {
+        (symbol::QUOTE | symbol::QUASIQUOTE, _) => items.len(),
+        (symbol::LAMBDA, [_, _, ..]) => 2,
+        (symbol::DEFMACRO, [_, _, _, ..]) => 3,
+        (symbol::DEFINE, [_, _, ..]) => 2,
+        // Each binding is (name value), and each match clause
+        // (pattern body...), so only what comes after the first part is
+        // code; a match's subject is code too.
+        (symbol::LET | symbol::LET_STAR | symbol::LET_VALUES, [_, Expr::List(_), ..]) => {
+            let mut items = items.into_iter();
+            let mut result = vec![items.next().unwrap()];
+            if let Some(Expr::List(bindings)) = items.next() {
+                let bindings = bindings
+                    .into_iter()
+                    .map(|binding| fold_clause(binding, &mut f));
+                result.push(Expr::List(bindings.collect::<Result<_, _>>()?));
+            }
+            for item in items {
+                result.push(f(item)?);
+            }
+            return Ok(result);
+        }
+        (symbol::MATCH, [_, _, ..]) => {
+            let mut items = items.into_iter();
+            let mut result = vec![items.next().unwrap()];
+            result.extend(items.next().map(&mut f).transpose()?);
+            for clause in items {
+                result.push(fold_clause(clause, &mut f)?);
+            }
+            return Ok(result);
+        }
+        // A cond clause is a list of code, not a call.
+        (symbol::COND, _) => {
+            let mut items = items.into_iter();
+            let mut result = vec![items.next().unwrap()];
+            for clause in items {
+                result.push(match clause {
+                    Expr::List(parts) => Expr::List(fold_after(parts, 0, &mut f)?),
+                    other => other,
+                });
+            }
+            return Ok(result);
+        }
+        _ => 0,
+    };
+    fold_after(items, kept, &mut f)
+}
+
+fn fold_clause<E>(clause: Expr, f: &mut impl FnMut(Expr) -> Result<Expr, E>) -> Result<Expr, E> {
+    match clause {
+        Expr::List(parts) if !parts.is_empty() => fold_after(parts, 1, f).map(Expr::List),
+        other => Ok(other),
+    }
+}
+
+// Folds the items after the first kept ones.
+fn fold_after<E>(
+    items: Vec<Expr>,
+    kept: usize,
+    f: &mut impl FnMut(Expr) -> Result<Expr, E>,
+) -> Result<Vec<Expr>, E> {
+    items
+        .into_iter()
+        .enumerate()
+        .map(|(i, item)| if i < kept { Ok(item) } else { f(item) })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::read;
+
+    #[derive(Default)]
+    struct Symbols(Vec<SymbolId>);
+
+    impl Visitor for Symbols {
+        fn visit_symbol(&mut self, symbol: SymbolId) {
+            self.0.push(symbol);
+        }
+    }
+
+    #[test]
+    fn visitor_test() {
+        let mut symbols = Symbols::default();
+        symbols.visit_expr(&read("(f [a 1] {:k b} (c . d) \"e\")").unwrap());
+        let names = ["f", "a", "b", "c", "d"].map(SymbolId::new);
+        assert_eq!(symbols.0, names);
+
+        let mut symbols = Symbols::default();
+        let lambda = Lambda {
+            params: vec![SymbolId::new("x")],
+            keys: vec![(SymbolId::new("k"), Some(read("y").unwrap()))],
+            body: vec![read("(g x)").unwrap()],
+            ..Lambda::default()
+        };
+        symbols.visit_expr(&Expr::Lambda(Box::new(lambda)));
+        assert_eq!(symbols.0, ["y", "g", "x"].map(SymbolId::new));
+    }
+
+    struct Rename(SymbolId, Expr);
+
+    impl Folder for Rename {
+        fn fold_symbol(&mut self, symbol: SymbolId) -> Expr {
+            if symbol == self.0 {
+                self.1.clone()
+            } else {
+                Expr::Symbol(symbol)
+            }
+        }
+    }
+
+    #[test]
+    fn folder_test() {
+        let mut rename = Rename(SymbolId::new("x"), read("y").unwrap());
+        assert_eq!(
+            rename.fold_expr(read("(f x [x 1] {x :x})").unwrap()),
+            read("(f y [y 1] {y :x})").unwrap()
+        );
+
+        // A tail folded into a list makes the pair a proper list.
+        let mut rename = Rename(SymbolId::new("x"), read("(b)").unwrap());
+        assert_eq!(
+            rename.fold_expr(read("(a . x)").unwrap()),
+            read("(a b)").unwrap()
+        );
+    }
+
+    #[test]
+    fn fold_code_test() {
+        let mut rename = Rename(SymbolId::new("x"), read("y").unwrap());
+        let code = |source: &str, rename: &mut Rename| match read(source).unwrap() {
+            Expr::List(items) => Expr::List(rename.fold_code(items)),
+            other => panic!("expected a list, found {other:?}"),
+        };
+        for (source, folded) in [
+            ("(f x 'x)", "(f y 'y)"),
+            ("'(x x)", "'(x x)"),
+            ("(define x x)", "(define x y)"),
+            ("(lambda (x) x)", "(lambda (x) y)"),
+            ("(let ((x x) (z x)) x)", "(let ((x y) (z y)) y)"),
+            ("(match x ((x) x))", "(match y ((x) y))"),
+            ("(cond (x x) (else x))", "(cond (y y) (else y))"),
+        ] {
+            assert_eq!(code(source, &mut rename), read(folded).unwrap(), "{source}");
+        }
+    }
+}
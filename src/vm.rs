@@ -0,0 +1,584 @@
+use alloc::{string::ToString, vec::Vec};
+use core::{
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+use crate::{
+    compile::{CaptureSource, Function, Op},
+    eval::{self, is_truthy, Env, EvalError},
+    limits,
+    sync::{Lock, Shared},
+    value::{Atom, HostFuture, Value},
+};
+
+// A compiled lambda with the values it captured. Globals are looked up in
+// the environment it was made in, the same one tree-walker closures use,
+// so the two backends see each other's definitions.
+pub struct Closure {
+    pub function: Shared<Function>,
+    pub captured: Vec<Value>,
+    pub globals: Env,
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        core::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Closure")
+            .field("params", &self.function.params)
+            .finish_non_exhaustive()
+    }
+}
+
+// Runs a compiled top-level form.
+pub fn run(function: Shared<Function>, globals: &Env) -> Result<Value, EvalError> {
+    let closure = Shared::new(Closure {
+        function,
+        captured: Vec::new(),
+        globals: globals.clone(),
+    });
+    let mut machine = Machine::default();
+    machine.enter(closure, 0, Vec::new())?;
+    machine.execute()
+}
+
+// Calls a compiled closure from Rust, as eval::apply does for the others.
+pub fn call(closure: &Shared<Closure>, args: Vec<Value>) -> Result<Value, EvalError> {
+    let mut machine = Machine::default();
+    machine.enter(closure.clone(), 0, args)?;
+    machine.execute()
+}
+
+// A compiled top-level form run for eval_async, which stops instead of
+// waiting whenever it calls an async native. Once the host has the result
+// of the future it was handed it resumes the task, and the form carries on
+// as if the call had returned that.
+pub(crate) struct Task(Machine);
+
+pub(crate) enum Progress {
+    Done(Value),
+    Await(HostFuture),
+}
+
+impl Task {
+    pub(crate) fn new(function: Shared<Function>, globals: &Env) -> Result<Self, EvalError> {
+        let closure = Shared::new(Closure {
+            function,
+            captured: Vec::new(),
+            globals: globals.clone(),
+        });
+        let mut machine = Machine {
+            awaits: true,
+            ..Machine::default()
+        };
+        machine.enter(closure, 0, Vec::new())?;
+        Ok(Task(machine))
+    }
+
+    pub(crate) fn run(&mut self) -> Result<Progress, EvalError> {
+        let value = self.0.execute()?;
+        Ok(match self.0.pending.take() {
+            Some(future) => Progress::Await(into_future(future)),
+            None => Progress::Done(value),
+        })
+    }
+
+    pub(crate) fn resume(&mut self, value: Value) {
+        self.0.push(value);
+    }
+}
+
+// A call in progress. Its slots start at base on the stack, and once it
+// returns the stack goes back to how it was at callee, where the caller
+// had pushed the procedure and its arguments.
+struct Frame {
+    closure: Shared<Closure>,
+    pc: usize,
+    base: usize,
+    callee: usize,
+}
+
+#[derive(Default)]
+struct Machine {
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    // Whether the machine runs a generator, and set when execute returned
+    // because of a yield rather than a return.
+    generator: bool,
+    yielded: bool,
+    // Whether the machine runs a Task, and the future of the async native
+    // it stopped at.
+    awaits: bool,
+    pending: Option<Pending>,
+}
+
+// With the sync feature a machine has to be Sync, since a generator keeps
+// one, and a future needn't be. Only a task's machine ever waits for one.
+#[cfg(not(feature = "sync"))]
+type Pending = HostFuture;
+#[cfg(feature = "sync")]
+type Pending = std::sync::Mutex<HostFuture>;
+
+#[cfg(not(feature = "sync"))]
+fn pending(future: HostFuture) -> Pending {
+    future
+}
+
+#[cfg(feature = "sync")]
+fn pending(future: HostFuture) -> Pending {
+    std::sync::Mutex::new(future)
+}
+
+#[cfg(not(feature = "sync"))]
+fn into_future(pending: Pending) -> HostFuture {
+    pending
+}
+
+#[cfg(feature = "sync")]
+fn into_future(pending: Pending) -> HostFuture {
+    pending
+        .into_inner()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+// A body that runs a piece at a time: each resume runs it up to its next
+// yield, or to its end. Its machine is kept between resumes, which is what
+// lets the body carry on from where it stopped. Generators are compared by
+// identity.
+#[derive(Clone)]
+pub struct Generator(Shared<Lock<GeneratorState>>);
+
+enum GeneratorState {
+    Suspended(Machine),
+    Running,
+    Done,
+}
+
+impl Generator {
+    fn new(closure: Shared<Closure>) -> Result<Self, EvalError> {
+        let mut machine = Machine {
+            generator: true,
+            ..Machine::default()
+        };
+        machine.enter(closure, 0, Vec::new())?;
+        Ok(Generator(Shared::new(Lock::new(
+            GeneratorState::Suspended(machine),
+        ))))
+    }
+
+    // The next value the body yields, or None once it has finished. An
+    // error in the body finishes it too. A generator can't resume itself.
+    pub fn resume(&self) -> Result<Option<Value>, EvalError> {
+        let state = core::mem::replace(&mut *self.0.borrow_mut(), GeneratorState::Running);
+        let mut machine = match state {
+            GeneratorState::Suspended(machine) => machine,
+            GeneratorState::Running => {
+                return Err(EvalError::TypeMismatch {
+                    expected: "generator that isn't running",
+                    found: Value::Generator(self.clone()),
+                })
+            }
+            GeneratorState::Done => {
+                *self.0.borrow_mut() = GeneratorState::Done;
+                return Ok(None);
+            }
+        };
+        let result = machine.execute();
+        let yielded = core::mem::take(&mut machine.yielded);
+        *self.0.borrow_mut() = match (&result, yielded) {
+            (Ok(_), true) => GeneratorState::Suspended(machine),
+            _ => GeneratorState::Done,
+        };
+        match result {
+            Ok(value) if yielded => Ok(Some(value)),
+            Ok(_) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        matches!(*self.0.borrow(), GeneratorState::Done)
+    }
+}
+
+impl PartialEq for Generator {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Hash for Generator {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Shared::as_ptr(&self.0).hash(state);
+    }
+}
+
+impl fmt::Debug for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Generator")
+            .field("done", &self.is_done())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Machine {
+    // Binds args to the closure's parameters, the way the tree-walker
+    // binds a lambda's, and starts a frame for it.
+    fn enter(
+        &mut self,
+        closure: Shared<Closure>,
+        callee: usize,
+        mut args: Vec<Value>,
+    ) -> Result<(), EvalError> {
+        let function = &closure.function;
+        let too_many = !function.rest && args.len() > function.params;
+        if args.len() < function.params || too_many {
+            return Err(EvalError::ArityMismatch {
+                expected: function.params,
+                found: args.len(),
+            });
+        }
+        limits::check_depth(self.frames.len() + 1)?;
+        let base = self.stack.len();
+        let extra = args.split_off(function.params.min(args.len()));
+        self.stack.extend(args);
+        if function.rest {
+            self.stack.push(Value::List(extra));
+        }
+        self.stack.resize(base + function.slots, Value::Nil);
+        self.frames.push(Frame {
+            closure,
+            pc: 0,
+            base,
+            callee,
+        });
+        Ok(())
+    }
+
+    fn execute(&mut self) -> Result<Value, EvalError> {
+        loop {
+            limits::step()?;
+            let frame = self.frames.last_mut().unwrap();
+            let closure = frame.closure.clone();
+            let op = closure.function.code[frame.pc];
+            frame.pc += 1;
+            let base = frame.base;
+            match op {
+                Op::Const(i) => self.push(closure.function.constants[i as usize].clone()),
+                Op::Nil => self.push(Value::Nil),
+                Op::Local(slot) => self.push(self.stack[base + slot as usize].clone()),
+                Op::LocalBox(slot) => self.push(unbox(&self.stack[base + slot as usize])),
+                Op::SetLocal(slot) => self.stack[base + slot as usize] = self.pop_one()?,
+                Op::MakeBox(slot) => {
+                    let slot = &mut self.stack[base + slot as usize];
+                    *slot = Value::Atom(Atom::new(core::mem::replace(slot, Value::Nil)));
+                }
+                Op::SetBox(slot) => {
+                    let value = self.pop_one()?;
+                    set_box(&self.stack[base + slot as usize], value);
+                }
+                Op::Capture(i) => self.push(closure.captured[i as usize].clone()),
+                Op::CaptureBox(i) => self.push(unbox(&closure.captured[i as usize])),
+                Op::SetCaptureBox(i) => set_box(&closure.captured[i as usize], self.pop_one()?),
+                Op::Global(name) => match closure.globals.get(name) {
+                    Some(value) => self.push(value),
+                    None => return Err(EvalError::UnboundSymbol(name.to_string())),
+                },
+                Op::SetGlobal(name) => {
+                    if !closure.globals.set(name, self.pop_one()?) {
+                        return Err(EvalError::UnboundSymbol(name.to_string()));
+                    }
+                }
+                Op::DefineGlobal(name) => closure.globals.define(name, self.pop_one()?),
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Dup => {
+                    let value = self.stack.last().unwrap().clone();
+                    self.push(value);
+                }
+                Op::Jump(target) => self.jump(target),
+                Op::JumpIfFalse(target) => {
+                    if !is_truthy(&self.pop()) {
+                        self.jump(target);
+                    }
+                }
+                Op::Closure(i) => {
+                    let made = self.closure(&closure, base, i);
+                    self.push(Value::Compiled(made));
+                }
+                Op::Generator(i) => {
+                    let made = self.closure(&closure, base, i);
+                    self.push(Value::Generator(Generator::new(made)?));
+                }
+                // A lambda from a generator's body can yield only while the
+                // generator itself calls it, directly or through other
+                // compiled calls; anywhere else there is nothing to suspend.
+                Op::Yield if !self.generator => return Err(EvalError::NotInGenerator),
+                Op::Yield => {
+                    let value = self.pop();
+                    self.push(Value::Nil);
+                    self.yielded = true;
+                    return Ok(value);
+                }
+                Op::Call(argc) | Op::TailCall(argc) => {
+                    self.call(argc as usize, matches!(op, Op::TailCall(_)))?;
+                    if self.pending.is_some() {
+                        return Ok(Value::Nil);
+                    }
+                }
+                Op::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    self.stack.truncate(frame.callee);
+                    if self.frames.is_empty() {
+                        return Ok(result);
+                    }
+                    self.push(result);
+                }
+                Op::Vector(len) => {
+                    let items = self.stack.split_off(self.stack.len() - len as usize);
+                    eval::one_values(&items)?;
+                    self.push(Value::Vector(items));
+                }
+                Op::Map(len) => {
+                    let items = self.stack.split_off(self.stack.len() - 2 * len as usize);
+                    eval::one_values(&items)?;
+                    let mut items = items.into_iter();
+                    let mut entries = Vec::with_capacity(len as usize);
+                    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                        entries.push((key, value));
+                    }
+                    self.push(Value::Map(entries));
+                }
+            }
+        }
+    }
+
+    // A closure over the ith function nested in the running one.
+    fn closure(&self, closure: &Closure, base: usize, i: u32) -> Shared<Closure> {
+        let function = closure.function.functions[i as usize].clone();
+        let captured = function
+            .captures
+            .iter()
+            .map(|source| match *source {
+                CaptureSource::Local(slot) => self.stack[base + slot as usize].clone(),
+                CaptureSource::Capture(i) => closure.captured[i as usize].clone(),
+            })
+            .collect();
+        Shared::new(Closure {
+            function,
+            captured,
+            globals: closure.globals.clone(),
+        })
+    }
+
+    // A call to a compiled closure runs in this loop, and in tail position
+    // replaces the caller's frame, so neither kind of call grows the Rust
+    // stack. Natives and tree-walker closures are called directly, and an
+    // async native's future is left for the task to hand to the host.
+    fn call(&mut self, argc: usize, tail: bool) -> Result<(), EvalError> {
+        let callee = self.stack.len() - argc - 1;
+        let args = self.stack.split_off(callee + 1);
+        eval::one_values(&args)?;
+        let procedure = self.pop();
+        let result = match procedure {
+            Value::Compiled(closure) => {
+                let callee = match tail {
+                    true => {
+                        let frame = self.frames.pop().unwrap();
+                        self.stack.truncate(frame.base);
+                        frame.callee
+                    }
+                    false => callee,
+                };
+                return self.enter(closure, callee, args);
+            }
+            Value::Native(native) => (native.func)(&args)?,
+            Value::AsyncNative(native) if self.awaits => {
+                self.pending = Some(pending((native.func)(args)));
+                return Ok(());
+            }
+            Value::AsyncNative(native) => {
+                return Err(EvalError::CannotAwait(native.name.to_string()))
+            }
+            procedure @ Value::Closure(_) => eval::apply(&procedure, args)?,
+            procedure => return Err(EvalError::NotCallable(procedure)),
+        };
+        // A Return always follows a TailCall, which hands this result back.
+        self.push(result);
+        Ok(())
+    }
+
+    fn jump(&mut self, target: u32) {
+        self.frames.last_mut().unwrap().pc = target as usize;
+    }
+
+    fn push(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap()
+    }
+
+    // A value about to be stored, which can't be several.
+    fn pop_one(&mut self) -> Result<Value, EvalError> {
+        eval::one_value(self.pop())
+    }
+}
+
+fn unbox(value: &Value) -> Value {
+    match value {
+        Value::Atom(atom) => atom.get(),
+        _ => unreachable!(),
+    }
+}
+
+fn set_box(cell: &Value, value: Value) {
+    match cell {
+        Value::Atom(atom) => {
+            atom.set(value);
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{builtins::global_env, compile::compile, parser::read_all};
+    use alloc::borrow::ToOwned;
+
+    fn run_all(source: &str) -> Result<Value, EvalError> {
+        let env = global_env();
+        let mut result = Value::Nil;
+        for expr in read_all(source).unwrap() {
+            result = run(compile(&expr).unwrap(), &env)?;
+        }
+        Ok(result)
+    }
+
+    #[test]
+    fn run_test() {
+        assert_eq!(
+            run_all("(define (fact n) (if (= n 0) 1 (* n (fact (- n 1))))) (fact 10)"),
+            Ok(Value::Integer(3628800))
+        );
+        assert_eq!(
+            run_all("((lambda (a . rest) (list a rest)) 1 2 3)")
+                .unwrap()
+                .to_string(),
+            "(1 (2 3))"
+        );
+        assert_eq!(
+            run_all("((lambda (a b) a) 1)"),
+            Err(EvalError::ArityMismatch {
+                expected: 2,
+                found: 1
+            })
+        );
+        assert_eq!(
+            run_all("(set! missing 1)"),
+            Err(EvalError::UnboundSymbol("missing".to_owned()))
+        );
+        for source in [
+            "(list (values 1 2))",
+            "(define v (values 1 2))",
+            "(define v 0) (set! v (values))",
+            "(let ((v (values 1 2))) v)",
+            "((lambda (v) (set! v (values 1 2)) v) 0)",
+            "[(values 1 2)]",
+        ] {
+            assert!(
+                matches!(
+                    run_all(source),
+                    Err(EvalError::TypeMismatch {
+                        expected: "one value",
+                        ..
+                    })
+                ),
+                "{source}"
+            );
+        }
+        assert_eq!(
+            run_all("(call-with-values (lambda () (values 1 2)) +)"),
+            Ok(Value::Integer(3))
+        );
+    }
+
+    #[test]
+    fn generator_test() {
+        let source = "
+            (define (count-to n)
+              (generator
+                (define (loop i)
+                  (if (<= i n) (begin (yield i) (loop (+ i 1))) 'finished))
+                (loop 1)))
+            (define g (count-to 3))
+            (define first (next g))
+            (list first (generator? g) (generator-done? g) (generator->list g)
+                  (generator-done? g) (next g 'none))";
+        assert_eq!(
+            run_all(source).unwrap().to_string(),
+            "(1 #t #f (2 3) #t none)"
+        );
+        // Each generator has its own state, and a yield evaluates to ().
+        assert_eq!(
+            run_all(
+                "(define (naturals) (generator (define (from i) (yield i) (from (+ i 1))) (from 0)))
+                 (define a (naturals)) (define b (naturals))
+                 (next a) (next a)
+                 (list (next a) (next b) (generator->list (generator (list (yield 1)))))"
+            )
+            .unwrap()
+            .to_string(),
+            "(2 0 (1))"
+        );
+        assert_eq!(
+            run_all("(define g (generator (yield (car 1)))) (list (next g))"),
+            Err(EvalError::TypeMismatch {
+                expected: "list",
+                found: Value::Integer(1)
+            })
+        );
+        assert!(matches!(
+            run_all("(define g (generator (yield (next g)))) (next g)"),
+            Err(EvalError::TypeMismatch {
+                expected: "generator that isn't running",
+                ..
+            })
+        ));
+        // The lambda that yields has escaped its generator.
+        assert_eq!(
+            run_all(
+                "(define leak ())
+                 (define g (generator (set! leak (lambda () (yield 1))) (yield 0)))
+                 (next g)
+                 (leak)"
+            ),
+            Err(EvalError::NotInGenerator)
+        );
+    }
+
+    #[test]
+    fn deep_recursion_test() {
+        // Calls between compiled closures don't use the Rust stack, tail
+        // calls or not.
+        assert_eq!(
+            run_all(
+                "(define (count n) (if (= n 0) 0 (+ 1 (count (- n 1)))))
+                 (define (down n) (if (= n 0) 'done (down (- n 1))))
+                 (list (count 100000) (down 100000))"
+            )
+            .unwrap()
+            .to_string(),
+            "(100000 done)"
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use js_sys::{Error, Reflect, JSON};
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    interpreter::{Interpreter, InterpreterError},
+    json::expr_to_json,
+    parser::{lex_source, read_all, Expr, ReadError},
+    value::Value,
+};
+
+// Entry points for running the language from JavaScript. Each call stands
+// alone: eval_js starts from a fresh interpreter, so definitions don't
+// carry over from one call to the next. Failures are thrown as Error
+// objects; those the reader raises also have the 1-based line and column
+// of where it stopped.
+
+// The text of each token, in order.
+#[wasm_bindgen]
+pub fn lex_js(input: &str) -> Result<Vec<String>, JsValue> {
+    let tokens = lex_source(input).map_err(|e| read_error(input, &e))?;
+    Ok(tokens
+        .iter()
+        .map(|token| input[token.span.start..token.span.end].to_owned())
+        .collect())
+}
+
+// The forms in input as an array, each in the shape json::expr_to_json
+// gives it.
+#[wasm_bindgen]
+pub fn read_js(input: &str) -> Result<JsValue, JsValue> {
+    let forms = read_all(input).map_err(|e| read_error(input, &e))?;
+    let json: Vec<String> = forms.iter().map(expr_to_json).collect();
+    JSON::parse(&format!("[{}]", json.join(",")))
+}
+
+// The value of the last form, written as the REPL would print it.
+#[wasm_bindgen]
+pub fn eval_js(input: &str) -> Result<JsValue, JsValue> {
+    Ok(JsValue::from_str(&eval(input)?.to_string()))
+}
+
+// The value of the last form as JSON, in the shape json::expr_to_json
+// gives data, for a playground to show or take apart. A value that has no
+// written form, such as a procedure, is thrown.
+#[wasm_bindgen(js_name = evalToJson)]
+pub fn eval_to_json(input: &str) -> Result<JsValue, JsValue> {
+    let expr = Expr::try_from(eval(input)?).map_err(|e| Error::new(&e.to_string()))?;
+    JSON::parse(&expr_to_json(&expr))
+}
+
+fn eval(input: &str) -> Result<Value, JsValue> {
+    match Interpreter::new().eval_str(input) {
+        Ok(value) => Ok(value),
+        Err(InterpreterError::Read(e)) => Err(read_error(input, &e)),
+        Err(e) => Err(Error::new(&e.to_string()).into()),
+    }
+}
+
+fn read_error(source: &str, e: &ReadError) -> JsValue {
+    let error = Error::new(&e.to_string());
+    let (line, column) = e.span().line_col(source);
+    // Setting a property on a fresh Error can't fail.
+    let _ = Reflect::set(&error, &"line".into(), &(line as u32).into());
+    let _ = Reflect::set(&error, &"column".into(), &(column as u32).into());
+    error.into()
+}
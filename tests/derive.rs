@@ -0,0 +1,92 @@
+// Structs converted by #[derive(TasksValue)], on the far side of the
+// crate boundary where hosts use it. Without std the crate's global state
+// needs assume_single_threaded, which the test harness's threads rule out.
+#![cfg(all(feature = "derive", feature = "std"))]
+
+use tuple_gd::{
+    convert::{FromValue, IntoValue},
+    EvalError, Interpreter, TasksValue, Value,
+};
+
+#[derive(TasksValue, PartialEq, Debug)]
+struct Config {
+    name: String,
+    max_depth: i64,
+    tags: Vec<String>,
+    retry: Option<Retry>,
+}
+
+#[derive(TasksValue, PartialEq, Debug)]
+struct Retry {
+    times: i64,
+}
+
+#[derive(TasksValue, PartialEq, Debug)]
+struct Pair<T> {
+    first: T,
+    second: T,
+}
+
+#[test]
+fn round_trip_test() {
+    let config = Config {
+        name: "build".to_owned(),
+        max_depth: 3,
+        tags: vec!["ci".to_owned()],
+        retry: Some(Retry { times: 2 }),
+    };
+    assert_eq!(
+        Config::from_value(config.into_value()).unwrap().retry,
+        Some(Retry { times: 2 })
+    );
+    let pair = Pair {
+        first: 1,
+        second: 2,
+    }
+    .into_value();
+    assert_eq!(pair.to_string(), "{:first 1 :second 2}");
+    assert_eq!(
+        Pair::<i64>::from_value(pair),
+        Ok(Pair {
+            first: 1,
+            second: 2
+        })
+    );
+}
+
+#[test]
+fn script_test() {
+    let mut interpreter = Interpreter::new();
+    let config = interpreter
+        .eval_str("{:name \"build\" :max-depth (+ 1 2) :tags (list \"ci\")}")
+        .unwrap();
+    assert_eq!(
+        Config::from_value(config),
+        Ok(Config {
+            name: "build".to_owned(),
+            max_depth: 3,
+            tags: vec!["ci".to_owned()],
+            retry: None,
+        })
+    );
+    interpreter.register_typed_fn("deeper", |(config,): (Config,)| {
+        Ok(Config {
+            max_depth: config.max_depth + 1,
+            ..config
+        })
+    });
+    assert_eq!(
+        interpreter
+            .eval_str("(map-get (deeper {:name \"a\" :max-depth 1 :tags ()}) :max-depth)")
+            .unwrap(),
+        Value::Integer(2)
+    );
+    assert!(matches!(
+        Retry::from_value(Value::Integer(1)),
+        Err(EvalError::TypeMismatch {
+            expected: "Retry",
+            ..
+        })
+    ));
+    assert!(Retry::from_value(Value::Map(vec![])).is_err());
+}
@@ -0,0 +1,115 @@
+// Random programs should mean the same whichever way they're run: on the
+// tree-walker or the VM, optimized or not, and with garbage collected
+// between their forms or not. The properties run on several threads at
+// once, which the crate's global state only allows with std.
+#![cfg(feature = "std")]
+
+use proptest::prelude::*;
+use tuple_gd::{
+    gc::collect_garbage, interpreter::Backend, optimize::optimize, parser::read_all, Interpreter,
+};
+
+// An expression over x and y, which the program binds, and if calls, with
+// calls of f, the function of two arguments it defines. f's own body
+// doesn't call it, so every program ends. Division by zero, car of '() and
+// the like are left in, as errors both sides should raise alike.
+fn expr(calls: bool) -> impl Strategy<Value = String> {
+    let leaf = prop_oneof![
+        (-20i64..20).prop_map(|n| n.to_string()),
+        prop::sample::select(vec!["x", "y", "#t", "#f", "'()", "'(1 2)", "1.5"])
+            .prop_map(str::to_owned),
+    ];
+    leaf.prop_recursive(5, 64, 3, move |inner| {
+        let pair = (inner.clone(), inner.clone());
+        prop_oneof![
+            (
+                prop::sample::select(vec![
+                    "+", "-", "*", "quotient", "<", "=", "list", "cons", "max"
+                ]),
+                pair.clone()
+            )
+                .prop_map(|(op, (a, b))| format!("({op} {a} {b})")),
+            (
+                prop::sample::select(vec!["car", "cdr", "null?", "not", "length"]),
+                inner.clone()
+            )
+                .prop_map(|(op, a)| format!("({op} {a})")),
+            (inner.clone(), pair.clone()).prop_map(|(c, (a, b))| format!("(if {c} {a} {b})")),
+            pair.clone()
+                .prop_map(|(value, body)| format!("(let ((x {value})) {body})")),
+            pair.clone()
+                .prop_map(|(arg, body)| format!("((lambda (y) {body}) {arg})")),
+            pair.clone().prop_map(move |(a, b)| match calls {
+                true => format!("(f {a} {b})"),
+                false => format!("(list {a} {b})"),
+            }),
+            pair.clone().prop_map(|(a, b)| format!("(begin {a} {b})")),
+            pair.prop_map(|(a, b)| format!("(and {a} {b})")),
+        ]
+    })
+}
+
+// A definition of f and the forms that use it.
+fn program() -> impl Strategy<Value = String> {
+    (expr(false), prop::collection::vec(expr(true), 1..4)).prop_map(|(body, forms)| {
+        let mut program = format!("(define x 3)\n(define y 4)\n(define (f x y) {body})\n");
+        for form in forms {
+            program.push_str(&form);
+            program.push('\n');
+        }
+        program
+    })
+}
+
+// What each form of program gave, one to a line, as text, so that values
+// and errors compare whatever ran them. A form that fails doesn't stop
+// the ones after it. With collect, garbage is collected after each.
+fn run(backend: Backend, program: &str, collect: bool) -> Vec<Result<String, String>> {
+    let mut interpreter = Interpreter::with_backend(backend);
+    let mut results = Vec::new();
+    for form in program.lines() {
+        let result = interpreter.eval_str(form);
+        results.push(
+            result
+                .map(|value| value.to_string())
+                .map_err(|e| e.to_string()),
+        );
+        if collect {
+            collect_garbage();
+        }
+    }
+    results
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(2_000))]
+
+    #[test]
+    fn vm_runs_like_the_tree_walker(program in program()) {
+        prop_assert_eq!(
+            run(Backend::Vm, &program, false),
+            run(Backend::TreeWalker, &program, false)
+        );
+    }
+
+    #[test]
+    fn optimizing_keeps_results(program in program()) {
+        let optimized: Vec<String> = read_all(&program)
+            .unwrap()
+            .into_iter()
+            .map(|form| optimize(form).to_string())
+            .collect();
+        prop_assert_eq!(
+            run(Backend::TreeWalker, &optimized.join("\n"), false),
+            run(Backend::TreeWalker, &program, false)
+        );
+    }
+
+    #[test]
+    fn collecting_keeps_results(program in program()) {
+        prop_assert_eq!(
+            run(Backend::TreeWalker, &program, true),
+            run(Backend::TreeWalker, &program, false)
+        );
+    }
+}
@@ -0,0 +1,54 @@
+// Counts heap allocations made while lexing. This lives in its own test
+// binary because it installs a global allocator.
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+use tuple_gd::lexer::lex;
+
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get().map(|n| n + 1)));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn count_allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    ALLOCATIONS.with(|count| count.set(Some(0)));
+    let result = f();
+    let count = ALLOCATIONS.with(|count| count.take()).unwrap();
+    (result, count)
+}
+
+#[test]
+fn lex_allocations_test() {
+    let form = "(define (greet name) (display \"hello there\") (list name 'name 42 1.5 #t))\n";
+    let source = form.repeat(1000);
+    // Without std the crate's state is in statics; this binary's one test
+    // is the only thread that touches them.
+    #[cfg(not(feature = "std"))]
+    unsafe {
+        tuple_gd::assume_single_threaded();
+    }
+    // Warm up the symbol interner so only lexing itself is counted.
+    lex(form).unwrap();
+    let (tokens, allocations) = count_allocations(|| lex(&source).unwrap().1);
+    assert_eq!(tokens.len(), 20 * 1000);
+    println!("{allocations} allocations for {} tokens", tokens.len());
+    // The token vector grows by doubling; nothing else should allocate.
+    assert!(allocations < 64, "{allocations} allocations");
+}
@@ -0,0 +1,148 @@
+// The properties run on several threads at once, which the crate's
+// global state only allows with std.
+#![cfg(feature = "std")]
+
+use proptest::prelude::*;
+use tuple_gd::{
+    analysis::Document,
+    arena::ExprArena,
+    format::format_source,
+    fuzz_check,
+    lexer::lex,
+    lexer::Span,
+    parser::{read, read_all, read_all_recover, Expr, MAX_DEPTH},
+    rational::Rational,
+    reader::Reader,
+    symbol::SymbolId,
+};
+
+// Mostly the characters that mean something to the lexer, so that random
+// strings get past the first token.
+fn source() -> impl Strategy<Value = String> {
+    prop::collection::vec(
+        prop_oneof![
+            4 => prop::sample::select(
+                "()[]{}'`,@.#;|\\\":-+/ \n\tabx019eE&λ".chars().collect::<Vec<_>>()
+            ),
+            1 => any::<char>(),
+        ],
+        0..64,
+    )
+    .prop_map(|chars| chars.into_iter().collect())
+}
+
+fn name() -> impl Strategy<Value = SymbolId> {
+    "[a-z*<>=!?][a-z0-9*<>=!?/-]{0,8}".prop_map(|name| SymbolId::new(&name))
+}
+
+// Expressions the reader can produce. Nil is left out since it is written
+// (), which reads as the empty list.
+fn expr() -> impl Strategy<Value = Expr> {
+    let leaf = prop_oneof![
+        any::<bool>().prop_map(Expr::Bool),
+        any::<i64>().prop_map(Expr::Integer),
+        (any::<i64>(), 2..i64::MAX).prop_filter_map("whole number", |(n, d)| {
+            Rational::new(n, d).map(Expr::Rational)
+        }),
+        // NaN reads back as NaN, but isn't equal to itself.
+        any::<f64>()
+            .prop_filter("NaN", |x| !x.is_nan())
+            .prop_map(Expr::Float),
+        any::<char>().prop_map(Expr::Char),
+        any::<String>().prop_map(Expr::String),
+        name().prop_map(Expr::Symbol),
+        any::<String>().prop_map(|name| Expr::Symbol(SymbolId::new(&name))),
+        name().prop_map(Expr::Keyword),
+    ];
+    leaf.prop_recursive(4, 64, 6, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..6).prop_map(Expr::List),
+            prop::collection::vec(inner.clone(), 0..6).prop_map(Expr::Vector),
+            prop::collection::vec((inner.clone(), inner.clone()), 0..4).prop_map(Expr::Map),
+            (inner.clone(), inner.clone()).prop_map(|(car, cdr)| Expr::cons(car, cdr)),
+        ]
+    })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(20_000))]
+
+    #[test]
+    fn reading_never_panics(input in source()) {
+        let _ = lex(&input);
+        let _ = read(&input);
+        let _ = read_all(&input);
+        let _ = read_all_recover(&input);
+        let _ = format_source(&input);
+        let mut reader = Reader::new();
+        for chunk in input.split_inclusive(' ') {
+            let _ = reader.feed(chunk);
+        }
+        let _ = reader.finish();
+    }
+
+    #[test]
+    fn fuzz_check_holds(input in source()) {
+        fuzz_check(input.as_bytes());
+    }
+
+    #[test]
+    fn arena_reads_like_the_parser(input in source()) {
+        let read = ExprArena::read(&input)
+            .map(|arena| arena.forms().iter().map(|&id| arena.to_expr(id)).collect::<Vec<_>>());
+        prop_assert_eq!(read, read_all(&input));
+    }
+}
+
+proptest! {
+    #[test]
+    fn nesting_never_overflows(
+        open in prop::sample::select(vec!["(", "[", "{", "'", "#;", "`(", ",@["]),
+        depth in 0..4 * MAX_DEPTH,
+    ) {
+        let input = open.repeat(depth) + "x" + &")".repeat(depth);
+        let _ = read_all(&input);
+        let _ = format_source(&input);
+    }
+
+    #[test]
+    fn printed_exprs_read_back(expr in expr()) {
+        prop_assert_eq!(read(&expr.to_string()), Ok(expr.clone()));
+        let formatted = format_source(&expr.to_string()).unwrap();
+        prop_assert_eq!(read(&formatted), Ok(expr));
+    }
+}
+
+// A byte offset on a character boundary, a fraction of the way through text.
+fn boundary(text: &str, at: f64) -> usize {
+    let boundaries: Vec<usize> = text
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([text.len()])
+        .collect();
+    boundaries[(at * (boundaries.len() - 1) as f64) as usize]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(2_000))]
+
+    // Reading again only what an edit could have changed gives what
+    // reading the new text from scratch does.
+    #[test]
+    fn edited_documents_read_like_new(
+        input in source(),
+        edits in prop::collection::vec((0.0..1.0f64, 0.0..1.0f64, source()), 1..6),
+    ) {
+        let mut document = Document::new(input);
+        for (from, to, replacement) in edits {
+            let from = boundary(document.text(), from);
+            let to = boundary(document.text(), to);
+            let cut = boundary(&replacement, 0.25);
+            document.edit(Span::new(from.min(to), from.max(to)), &replacement[..cut]);
+            let fresh = Document::new(document.text());
+            prop_assert_eq!(document.forms().collect::<Vec<_>>(), fresh.forms().collect::<Vec<_>>());
+            prop_assert_eq!(document.diagnostics(), fresh.diagnostics());
+            prop_assert_eq!(document.symbols(), fresh.symbols());
+        }
+    }
+}